@@ -0,0 +1,131 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr, Type};
+
+// field attributes:
+//   #[fp(key)]             the field is the user key
+//   #[fp(rename = "name")] attribute name used in rules, defaults to the field name
+//   #[fp(private)]         used in rules but kept out of serialized users
+//   #[fp(skip)]            not part of the user
+// fields are converted with ToString, None options are left out
+#[proc_macro_derive(IntoEvalContext, attributes(fp))]
+pub fn derive_into_eval_context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct FieldOpts {
+    key: bool,
+    private: bool,
+    skip: bool,
+    rename: Option<String>,
+}
+
+fn field_opts(field: &syn::Field) -> syn::Result<FieldOpts> {
+    let mut opts = FieldOpts::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("fp")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("key") {
+                opts.key = true;
+            } else if meta.path.is_ident("private") {
+                opts.private = true;
+            } else if meta.path.is_ident("skip") {
+                opts.skip = true;
+            } else if meta.path.is_ident("rename") {
+                opts.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected key, private, skip or rename"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(opts)
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => p.path.segments.last().is_some_and(|s| s.ident == "Option"),
+        _ => false,
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(named) => &named.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "IntoEvalContext needs named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "IntoEvalContext can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut key = None;
+    let mut steps = vec![];
+    for field in fields {
+        let opts = field_opts(field)?;
+        if opts.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().expect("named field");
+        let option = is_option(&field.ty);
+        if opts.key {
+            if key.is_some() {
+                return Err(Error::new_spanned(ident, "only one field can be the key"));
+            }
+            key = Some(match option {
+                true => quote! { self.#ident.as_ref().map(|v| v.to_string()) },
+                false => quote! { Some(self.#ident.to_string()) },
+            });
+            continue;
+        }
+        let name = opts.rename.unwrap_or_else(|| ident.to_string());
+        let with = match opts.private {
+            true => quote! { with_private },
+            false => quote! { with },
+        };
+        steps.push(match option {
+            true => quote! {
+                if let Some(v) = &self.#ident {
+                    user = user.#with(#name.to_owned(), v.to_string());
+                }
+            },
+            false => quote! {
+                user = user.#with(#name.to_owned(), self.#ident.to_string());
+            },
+        });
+    }
+
+    let key = match key {
+        Some(key) => quote! {
+            if let Some(key) = #key {
+                user = user.stable_rollout(key);
+            }
+        },
+        None => quote! {},
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::feature_probe_server_sdk::IntoEvalContext for #name #ty_generics #where_clause {
+            fn to_user(&self) -> ::feature_probe_server_sdk::FPUser {
+                let mut user = ::feature_probe_server_sdk::FPUser::new();
+                #key
+                #(#steps)*
+                user
+            }
+        }
+    })
+}
@@ -11,7 +11,7 @@ async fn main() -> Result<(), FPError> {
     // let remote_url = Url::parse("http://localhost:4009/server").unwrap(); // for local docker
     let remote_url = Url::parse("https://featureprobe.io/server").expect("invalid url");
     // Replace Server SDK key in your Project List Page.
-    let server_sdk_key = "server-7fa2f771259cb7235b96433d70b91e99abcf6ff8".to_owned();
+    let server_sdk_key = "server-7fa2f771259cb7235b96433d70b91e99abcf6ff8".into();
     let refresh_interval = Duration::from_secs(20);
 
     let config = FPConfig {
@@ -0,0 +1,232 @@
+use crate::diff::{Change, ChangeKind};
+use crate::evaluate::{Repository, Segment, Toggle};
+use crate::sync::SyncType;
+use serde::{Deserialize, Serialize};
+
+pub type AuditCallback = Box<dyn Fn(AuditRecord) + Send>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditItem {
+    Toggle,
+    Segment,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditChange {
+    pub item: AuditItem,
+    pub key: String,
+    pub kind: ChangeKind,
+    pub old_version: Option<u64>,
+    pub new_version: Option<u64>,
+    pub fields: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub time: u128,
+    pub sync_type: SyncType,
+    pub old_version: Option<u128>,
+    pub new_version: Option<u128>,
+    pub changes: Vec<AuditChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub salt_rotations: Vec<SaltRotation>,
+}
+
+// a changed salt reshuffles every user of the split
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SaltRotation {
+    pub toggle: String,
+    pub old_salt: String,
+    pub new_salt: String,
+}
+
+impl AuditRecord {
+    pub fn new(old: &Repository, new: &Repository, sync_type: SyncType, time: u128) -> Self {
+        let diff = old.diff(new);
+        let toggles = diff.toggles.into_iter().map(|c| {
+            let fields = match (old.toggles.get(&c.key), new.toggles.get(&c.key)) {
+                (Some(o), Some(n)) => toggle_fields(o, n),
+                _ => vec![],
+            };
+            audit_change(AuditItem::Toggle, c, fields)
+        });
+        let segments = diff.segments.into_iter().map(|c| {
+            let fields = match (old.segments.get(&c.key), new.segments.get(&c.key)) {
+                (Some(o), Some(n)) => segment_fields(o, n),
+                _ => vec![],
+            };
+            audit_change(AuditItem::Segment, c, fields)
+        });
+
+        let changes: Vec<AuditChange> = toggles.chain(segments).collect();
+        let salt_rotations = changes
+            .iter()
+            .filter(|c| c.item == AuditItem::Toggle && c.kind == ChangeKind::Changed)
+            .filter_map(|c| {
+                let old_salt = old.toggles.get(&c.key)?.split_salt()?;
+                let new_salt = new.toggles.get(&c.key)?.split_salt()?;
+                (old_salt != new_salt).then(|| SaltRotation {
+                    toggle: c.key.clone(),
+                    old_salt,
+                    new_salt,
+                })
+            })
+            .collect();
+
+        Self {
+            time,
+            sync_type,
+            old_version: diff.old_version,
+            new_version: diff.new_version,
+            changes,
+            salt_rotations,
+        }
+    }
+}
+
+impl std::fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "repository {:?}→{:?} received via {:?} at {}",
+            self.old_version, self.new_version, self.sync_type, self.time
+        )?;
+        for c in &self.changes {
+            write!(
+                f,
+                "; {:?} {} {:?} version {:?}→{:?}",
+                c.item, c.key, c.kind, c.old_version, c.new_version
+            )?;
+            if !c.fields.is_empty() {
+                write!(f, ", {} changed", c.fields.join(", "))?;
+            }
+        }
+        for r in &self.salt_rotations {
+            write!(
+                f,
+                "; {} salt rotated {}→{}",
+                r.toggle, r.old_salt, r.new_salt
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn audit_change(item: AuditItem, change: Change, fields: Vec<String>) -> AuditChange {
+    AuditChange {
+        item,
+        key: change.key,
+        kind: change.kind,
+        old_version: change.old_version,
+        new_version: change.new_version,
+        fields,
+    }
+}
+
+fn toggle_fields(old: &Toggle, new: &Toggle) -> Vec<String> {
+    let mut fields = vec![];
+    let mut check = |changed: bool, name: &str| {
+        if changed {
+            fields.push(name.to_owned());
+        }
+    };
+    check(old.enabled != new.enabled, "enabled");
+    check(old.rules != new.rules, "rules");
+    check(old.variations != new.variations, "variations");
+    check(old.default_serve != new.default_serve, "defaultServe");
+    check(old.disabled_serve != new.disabled_serve, "disabledServe");
+    check(old.prerequisites != new.prerequisites, "prerequisites");
+    check(
+        old.track_access_events != new.track_access_events,
+        "trackAccessEvents",
+    );
+    check(old.for_client != new.for_client, "forClient");
+    check(old.archived != new.archived, "archived");
+    check(old.deprecated != new.deprecated, "deprecated");
+    check(old.archived_serve != new.archived_serve, "archivedServe");
+    check(
+        old.rollout_schedule != new.rollout_schedule,
+        "rolloutSchedule",
+    );
+    fields
+}
+
+fn segment_fields(old: &Segment, new: &Segment) -> Vec<String> {
+    let mut fields = vec![];
+    if old.rules != new.rules {
+        fields.push("rules".to_owned());
+    }
+    if (old.start, old.end) != (new.start, new.end) {
+        fields.push("window".to_owned());
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::Serve;
+    use serde_json::json;
+
+    #[test]
+    fn test_audit_record() {
+        let mut old = Repository::default();
+        old.toggles.insert(
+            "t1".to_owned(),
+            Toggle::new_for_test("t1".to_owned(), json!(true)),
+        );
+        let mut new = old.clone();
+        new.version = Some(1);
+        let t = new.toggles.get_mut("t1").unwrap();
+        t.version = 1;
+        t.enabled = false;
+        t.variations = vec![json!(false)];
+
+        let record = AuditRecord::new(&old, &new, SyncType::Realtime, 100);
+        assert_eq!(record.changes.len(), 1);
+        let change = &record.changes[0];
+        assert_eq!(change.item, AuditItem::Toggle);
+        assert_eq!(change.kind, ChangeKind::Changed);
+        assert_eq!(change.fields, vec!["enabled", "variations"]);
+        assert!(record
+            .to_string()
+            .contains("t1 Changed version Some(0)→Some(1), enabled, variations changed"));
+        assert!(record.salt_rotations.is_empty());
+    }
+
+    #[test]
+    fn test_salt_rotation() {
+        let split = |salt: &str| {
+            Serve::Split(
+                serde_json::from_value(json!({
+                    "distribution": [[[0, 5000]], [[5000, 10000]]],
+                    "salt": salt
+                }))
+                .unwrap(),
+            )
+        };
+        let mut old = Repository::default();
+        let mut toggle = Toggle::new_for_test("t1".to_owned(), json!(true));
+        toggle.default_serve = split("a");
+        old.toggles.insert("t1".to_owned(), toggle);
+        let mut new = old.clone();
+        let t = new.toggles.get_mut("t1").unwrap();
+        t.version = 1;
+        t.default_serve = split("b");
+
+        let record = AuditRecord::new(&old, &new, SyncType::Polling, 100);
+        assert_eq!(
+            record.salt_rotations,
+            vec![SaltRotation {
+                toggle: "t1".to_owned(),
+                old_salt: "a".to_owned(),
+                new_salt: "b".to_owned(),
+            }]
+        );
+        assert!(record.to_string().contains("t1 salt rotated a→b"));
+    }
+}
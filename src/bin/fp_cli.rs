@@ -0,0 +1,220 @@
+use clap::{Args, Parser, Subcommand};
+use feature_probe_server_sdk::{
+    lint, load_json, ChangeKind, FPConfig, FPDetail, FPError, FPUser, FeatureProbe, LintOptions,
+    Repository, Url,
+};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+// Local evaluation and inspection of FeatureProbe repositories.
+// cargo run --features cli --bin fp-cli -- eval --repo repo.json --toggle t --attr city=1
+
+#[derive(Parser)]
+#[command(name = "fp-cli", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Evaluate a toggle for a user and explain the result
+    Eval(EvalArgs),
+    /// Show toggles and segments changed between two repository files
+    Diff { old: PathBuf, new: PathBuf },
+    /// Flag suspicious toggle and segment configurations
+    Lint {
+        repo: PathBuf,
+        /// Json object of evaluations per toggle, enables the usage checks
+        #[arg(long)]
+        evaluations: Option<PathBuf>,
+        #[arg(long, default_value_t = 1000)]
+        heavy_evaluations: u64,
+    },
+}
+
+#[derive(Args)]
+struct EvalArgs {
+    /// Repository json file
+    #[arg(long, conflicts_with = "sdk_key")]
+    repo: Option<PathBuf>,
+    /// Fetch the repository with this server sdk key instead
+    #[arg(long, required_unless_present = "repo")]
+    sdk_key: Option<String>,
+    #[arg(long, default_value = "https://featureprobe.io/server")]
+    remote_url: Url,
+    #[arg(long)]
+    toggle: String,
+    /// Stable rollout key, a random key is used when missing
+    #[arg(long)]
+    user: Option<String>,
+    /// User attribute as k=v, may be repeated
+    #[arg(long = "attr", value_parser = parse_attr)]
+    attrs: Vec<(String, String)>,
+}
+
+fn parse_attr(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.to_owned(), v.to_owned())),
+        _ => Err(format!("expected k=v, got {s}")),
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Eval(args) => eval(args).await,
+        Command::Diff { old, new } => diff(&old, &new),
+        Command::Lint {
+            repo,
+            evaluations,
+            heavy_evaluations,
+        } => lint_file(&repo, evaluations.as_deref(), heavy_evaluations),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn eval(args: EvalArgs) -> Result<ExitCode, FPError> {
+    let fp = match (&args.repo, args.sdk_key) {
+        (Some(path), _) => FeatureProbe::from_fixture(path)?,
+        (None, Some(key)) => {
+            let fp = FeatureProbe::try_new(FPConfig {
+                remote_url: args.remote_url,
+                server_sdk_key: key.into(),
+                ..Default::default()
+            })?;
+            let timeout = Duration::from_secs(10);
+            if !fp.wait_until_initialized(timeout).await {
+                return Err(FPError::InitTimeout(timeout));
+            }
+            fp
+        }
+        (None, None) => unreachable!("clap requires --repo or --sdk-key"),
+    };
+
+    let mut user = FPUser::new();
+    if let Some(key) = args.user {
+        user = user.stable_rollout(key);
+    }
+    for (k, v) in args.attrs {
+        user = user.with(k, v);
+    }
+    let detail = fp.json_detail(&args.toggle, &user, Value::Null);
+    fp.close();
+    print_detail(&args.toggle, &user, &detail);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_detail(toggle: &str, user: &FPUser, detail: &FPDetail<Value>) {
+    let opt = |v: Option<String>| v.unwrap_or_else(|| "-".to_owned());
+    println!("toggle:    {toggle}");
+    println!("user:      {}", user.key());
+    println!("value:     {}", detail.value);
+    println!("reason:    {}", detail.reason);
+    println!(
+        "code:      {}",
+        opt(detail.reason_code.map(|c| c.to_string()))
+    );
+    println!(
+        "rule:      {}",
+        opt(detail.rule_index.map(|i| i.to_string()))
+    );
+    println!(
+        "variation: {}",
+        opt(detail.variation_index.map(|i| i.to_string()))
+    );
+    println!("version:   {}", opt(detail.version.map(|v| v.to_string())));
+    if detail.prerequisites.is_empty() {
+        return;
+    }
+    println!("prerequisites:");
+    let last = detail.prerequisites.len() - 1;
+    for (i, p) in detail.prerequisites.iter().enumerate() {
+        let branch = if i == last { "└─" } else { "├─" };
+        let got = p.value.as_ref().map(|v| v.to_string());
+        let status = if p.passed { "passed" } else { "failed" };
+        println!(
+            "  {branch} {}: expected {}, got {} ({status})",
+            p.key,
+            p.expected,
+            opt(got)
+        );
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, FPError> {
+    std::fs::read_to_string(path)
+        .map_err(|e| FPError::InternalError(format!("read {}: {}", path.display(), e)))
+}
+
+fn load_file(path: &Path) -> Result<Repository, FPError> {
+    load_json(&read_file(path)?)
+}
+
+// exits with 1 when the repositories differ, for use in CI
+fn diff(old: &Path, new: &Path) -> Result<ExitCode, FPError> {
+    let diff = load_file(old)?.diff(&load_file(new)?);
+    let version = |v: Option<u128>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_owned());
+    println!(
+        "version {} -> {}",
+        version(diff.old_version),
+        version(diff.new_version)
+    );
+    for (kind, changes) in [("toggle", &diff.toggles), ("segment", &diff.segments)] {
+        for change in changes {
+            let mark = match change.kind {
+                ChangeKind::Added => "+",
+                ChangeKind::Changed => "~",
+                ChangeKind::Removed => "-",
+            };
+            println!("{mark} {kind} {}", change.key);
+        }
+    }
+    Ok(if diff.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    })
+}
+
+// exits with 1 when anything is flagged
+fn lint_file(
+    repo: &Path,
+    evaluations: Option<&Path>,
+    heavy_evaluations: u64,
+) -> Result<ExitCode, FPError> {
+    let repo = load_file(repo)?;
+    let mut options = LintOptions {
+        heavy_evaluations,
+        ..Default::default()
+    };
+    if let Some(path) = evaluations {
+        let json_str = read_file(path)?;
+        options.evaluations =
+            serde_json::from_str(&json_str).map_err(|e| FPError::JsonError(json_str, e))?;
+    }
+    let issues = lint(&repo, &options);
+    for issue in &issues {
+        let subject = issue
+            .toggle
+            .as_deref()
+            .map(|t| format!("toggle {t}"))
+            .or_else(|| issue.segment.as_deref().map(|s| format!("segment {s}")))
+            .unwrap_or_default();
+        println!("{:?} {subject}: {}", issue.kind, issue.message);
+        println!("  hint: {}", issue.hint);
+    }
+    Ok(if issues.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    })
+}
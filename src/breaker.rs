@@ -0,0 +1,103 @@
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    // consecutive evaluation errors before the breaker trips
+    pub threshold: u32,
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<u128>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreakers {
+    states: RwLock<HashMap<String, BreakerState>>,
+}
+
+impl CircuitBreakers {
+    // after cooldown one evaluation is let through to retry
+    pub fn allow(&self, toggle: &str, now: u128) -> bool {
+        let states = self.states.read();
+        match states.get(toggle).and_then(|s| s.open_until) {
+            Some(until) => now >= until,
+            None => true,
+        }
+    }
+
+    pub fn record(&self, config: &CircuitBreakerConfig, toggle: &str, ok: bool, now: u128) {
+        if ok {
+            if self.states.read().contains_key(toggle) {
+                self.states.write().remove(toggle);
+            }
+            return;
+        }
+
+        let mut states = self.states.write();
+        let state = states.entry(toggle.to_owned()).or_default();
+        state.failures += 1;
+        if state.failures >= config.threshold || state.open_until.is_some() {
+            error!(
+                "toggle {} failed {} times, circuit open for {:?}",
+                toggle, state.failures, config.cooldown
+            );
+            state.open_until = Some(now + config.cooldown.as_millis());
+        }
+    }
+
+    pub fn open_circuits(&self, now: u128) -> Vec<String> {
+        let states = self.states.read();
+        let mut open: Vec<String> = states
+            .iter()
+            .filter(|(_, s)| matches!(s.open_until, Some(until) if now < until))
+            .map(|(k, _)| k.clone())
+            .collect();
+        open.sort();
+        open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let config = CircuitBreakerConfig {
+            threshold: 2,
+            cooldown: Duration::from_millis(100),
+        };
+        let breakers = CircuitBreakers::default();
+        breakers.record(&config, "t", false, 0);
+        assert!(breakers.allow("t", 0));
+        breakers.record(&config, "t", false, 0);
+        assert!(!breakers.allow("t", 50));
+        assert_eq!(breakers.open_circuits(50), vec!["t"]);
+
+        // retry after cooldown fails, trip again immediately
+        assert!(breakers.allow("t", 100));
+        breakers.record(&config, "t", false, 100);
+        assert!(!breakers.allow("t", 150));
+
+        assert!(breakers.allow("t", 200));
+        breakers.record(&config, "t", true, 200);
+        assert!(breakers.open_circuits(200).is_empty());
+        breakers.record(&config, "t", false, 200);
+        assert!(breakers.allow("t", 200));
+    }
+}
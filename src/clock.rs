@@ -0,0 +1,70 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub trait Clock: Debug + Send + Sync {
+    // milliseconds since unix epoch
+    fn now(&self) -> u128;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        crate::unix_timestamp()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MockClock {
+    millis: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    pub fn new(millis: u64) -> Self {
+        Self {
+            millis: Arc::new(AtomicU64::new(millis)),
+        }
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.millis
+            .fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u128 {
+        self.millis.load(Ordering::SeqCst) as u128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1000);
+        let shared = clock.clone();
+        assert_eq!(clock.now(), 1000);
+
+        shared.advance(Duration::from_secs(2));
+        assert_eq!(clock.now(), 3000);
+
+        shared.set(10);
+        assert_eq!(clock.now(), 10);
+    }
+
+    #[test]
+    fn test_system_clock() {
+        let now = crate::unix_timestamp();
+        assert!(SystemClock.now() >= now);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{Repository, ValueType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::info;
+
+// flag hygiene stats, logged after every sync that changes the repository
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoComposition {
+    pub version: Option<u128>,
+    pub toggles: usize,
+    pub segments: usize,
+    // by the type of the first variation
+    pub toggles_by_type: BTreeMap<String, usize>,
+    // rule count -> number of toggles with that many rules
+    pub rules_distribution: BTreeMap<usize, usize>,
+    // segment key -> objects across all its conditions
+    pub segment_sizes: BTreeMap<String, usize>,
+    pub toggles_with_prerequisites: Vec<String>,
+    // directly or through a segment
+    pub toggles_using_regex: Vec<String>,
+}
+
+impl RepoComposition {
+    pub fn of(repo: &Repository) -> Self {
+        let mut composition = RepoComposition {
+            version: repo.version,
+            toggles: repo.toggles.len(),
+            segments: repo.segments.len(),
+            ..Default::default()
+        };
+        for (key, toggle) in &repo.toggles {
+            let value_type = toggle
+                .variations
+                .first()
+                .map(ValueType::of)
+                .unwrap_or(ValueType::Json);
+            *composition
+                .toggles_by_type
+                .entry(value_type.to_string())
+                .or_default() += 1;
+            *composition
+                .rules_distribution
+                .entry(toggle.rules.len())
+                .or_default() += 1;
+            if toggle.prerequisites.as_ref().is_some_and(|p| !p.is_empty()) {
+                composition.toggles_with_prerequisites.push(key.clone());
+            }
+            if toggle.regex_conditions(&repo.segments).next().is_some() {
+                composition.toggles_using_regex.push(key.clone());
+            }
+        }
+        composition.toggles_with_prerequisites.sort();
+        composition.toggles_using_regex.sort();
+        composition.segment_sizes = repo
+            .segments
+            .iter()
+            .map(|(key, segment)| {
+                let size = segment
+                    .rules
+                    .iter()
+                    .flat_map(|r| r.conditions.iter())
+                    .map(|c| c.objects.len())
+                    .sum();
+                (key.clone(), size)
+            })
+            .collect();
+        composition
+    }
+
+    pub(crate) fn log(&self) {
+        info!(
+            version = ?self.version,
+            toggles = self.toggles,
+            segments = self.segments,
+            toggles_by_type = ?self.toggles_by_type,
+            rules_distribution = ?self.rules_distribution,
+            largest_segment = self.segment_sizes.values().max().copied().unwrap_or_default(),
+            toggles_with_prerequisites = self.toggles_with_prerequisites.len(),
+            toggles_using_regex = self.toggles_using_regex.len(),
+            "repository composition"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Toggle;
+    use serde_json::json;
+
+    #[test]
+    fn test_composition_fixture() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = std::fs::read_to_string(path).unwrap();
+        let repo = crate::load_json(&json_str).unwrap();
+        let composition = RepoComposition::of(&repo);
+
+        assert_eq!(composition.toggles, repo.toggles.len());
+        assert_eq!(
+            composition.toggles_by_type.values().sum::<usize>(),
+            repo.toggles.len()
+        );
+        assert_eq!(
+            composition.rules_distribution.values().sum::<usize>(),
+            repo.toggles.len()
+        );
+        assert_eq!(composition.segment_sizes.len(), repo.segments.len());
+    }
+
+    #[test]
+    fn test_composition_regex_and_prerequisites() {
+        let mut toggle = Toggle::new_for_test("r".to_owned(), json!("a"));
+        toggle.rules = serde_json::from_value(json!([
+            {"serve": {"select": 0}, "conditions": [{
+                "type": "segment", "predicate": "is in", "objects": ["s"]
+            }]}
+        ]))
+        .unwrap();
+        toggle.prerequisites =
+            serde_json::from_value(json!([{"key": "p", "value": true}])).unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("r".to_owned(), toggle);
+        repo.toggles.insert(
+            "p".to_owned(),
+            Toggle::new_for_test("p".to_owned(), json!(true)),
+        );
+        repo.segments.insert(
+            "s".to_owned(),
+            serde_json::from_value(json!({"uniqueId": "s", "version": 1, "rules": [
+                {"conditions": [{"type": "string", "subject": "email",
+                    "predicate": "matches regex", "objects": [".*@corp", ".*@test"]}]}
+            ]}))
+            .unwrap(),
+        );
+
+        let composition = RepoComposition::of(&repo);
+        assert_eq!(composition.toggles_using_regex, vec!["r".to_owned()]);
+        assert_eq!(composition.toggles_with_prerequisites, vec!["r".to_owned()]);
+        assert_eq!(composition.segment_sizes["s"], 2);
+        assert_eq!(composition.toggles_by_type["string"], 1);
+        assert_eq!(composition.toggles_by_type["bool"], 1);
+        assert_eq!(
+            composition.rules_distribution,
+            BTreeMap::from([(0, 1), (1, 1)])
+        );
+    }
+}
@@ -1,7 +1,20 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::breaker::CircuitBreakerConfig;
+use crate::clock::{Clock, SystemClock};
+use crate::events::EventEndpoints;
+use crate::user::{InstanceKeyGenerator, KeyGenerator, UserLimits, UuidKeyGenerator};
+use crate::{
+    EvalSoftLimits, EventSchema, EventSink, FPError, HttpTransport, RepositoryLimits, Secret,
+    StickyBucketStore, UnicodeNormalization,
+};
 use reqwest::Client;
-use tracing::info;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 use url::Url;
 
 #[derive(Debug, Clone)]
@@ -9,51 +22,200 @@ pub struct FPConfig {
     pub remote_url: Url,
     pub toggles_url: Option<Url>,
     pub events_url: Option<Url>,
-    pub server_sdk_key: String,
+    // per event type overrides of events_url
+    pub access_events_url: Option<Url>,
+    pub debug_events_url: Option<Url>,
+    pub custom_events_url: Option<Url>,
+    pub server_sdk_key: Secret,
     pub refresh_interval: Duration,
     pub http_client: Option<Client>,
+    pub http_transport: Option<Arc<dyn HttpTransport>>,
     pub start_wait: Option<Duration>,
+    // takes precedence over start_wait when set
+    pub init_policy: Option<InitPolicy>,
 
     #[cfg(feature = "realtime")]
     pub realtime_url: Option<Url>,
     #[cfg(feature = "realtime")]
     pub realtime_path: Option<String>,
+    #[cfg(feature = "grpc")]
+    pub grpc_url: Option<Url>,
     pub track_events: bool,
+    // newest event schema offered to the events endpoint
+    pub event_schema: EventSchema,
+    // larger event posts are split into several requests
+    pub max_event_payload_bytes: Option<usize>,
+    // events are recorded for sinks even when track_events is off
+    pub event_sinks: Vec<Arc<dyn EventSink>>,
+    // cancelling it stops the client's background tasks, close() leaves it untouched
+    pub cancellation_token: Option<CancellationToken>,
+    // rule processing longer than this serves the default variation
+    pub eval_budget: Option<Duration>,
+    pub clock: Option<Arc<dyn Clock>>,
+    pub key_generator: Option<Arc<dyn KeyGenerator>>,
+    // keeps the instance key across restarts, generated per client when unset
+    pub instance_key_path: Option<PathBuf>,
+    // keyless users get the instance key instead of key_generator's
+    pub bucket_keyless_by_instance: bool,
+    pub max_staleness: Option<Duration>,
+    pub stale_policy: StalePolicy,
+    pub warning_interval: Duration,
+    // evaluations see users cut to these limits, the caller's FPUser is untouched
+    pub user_limits: Option<UserLimits>,
+    pub app_name: Option<String>,
+    pub app_version: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub missing_toggle_report_interval: Option<Duration>,
+    // usage_report() is posted here every usage_report_interval
+    pub usage_report_url: Option<Url>,
+    pub usage_report_interval: Duration,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub eval_soft_limits: Option<EvalSoftLimits>,
+    pub sticky_buckets: Option<Arc<dyn StickyBucketStore>>,
+    pub track_prerequisite_events: bool,
+    // signs sync requests, event posts and relayed events with X-FP-Signature
+    pub signing_secret: Option<Secret>,
+    pub unicode_normalization: Option<UnicodeNormalization>,
+    // synced repositories over these caps are rejected, for untrusted sources
+    pub repository_limits: Option<RepositoryLimits>,
+    pub suppressed_events: BTreeSet<String>,
+    pub background_validation: bool,
+    pub dns_overrides: BTreeMap<String, Vec<SocketAddr>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum InitPolicy {
+    // start serving defaults right away and sync in the background
+    #[default]
+    NoWait,
+    WaitFor(Duration),
+    // like WaitFor, FeatureProbe::try_new fails when not initialized in time
+    RequireSuccess(Duration),
+    // serve the snapshot saved at the path by the last close, then sync
+    UseCacheThenSync(PathBuf),
+}
+
+impl InitPolicy {
+    pub fn wait(&self) -> Option<Duration> {
+        match self {
+            InitPolicy::WaitFor(d) | InitPolicy::RequireSuccess(d) => Some(*d),
+            InitPolicy::NoWait | InitPolicy::UseCacheThenSync(_) => None,
+        }
+    }
+
+    pub fn cache_path(&self) -> Option<&Path> {
+        match self {
+            InitPolicy::UseCacheThenSync(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StalePolicy {
+    #[default]
+    Annotate,
+    ServeDefault,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct Config {
     pub toggles_url: Url,
     pub events_url: Url,
-    pub server_sdk_key: String,
+    pub event_endpoints: EventEndpoints,
+    pub server_sdk_key: Secret,
     pub refresh_interval: Duration,
-    pub http_client: Option<Client>,
-    pub start_wait: Option<Duration>,
+    pub http_transport: Arc<dyn HttpTransport>,
+    pub init_policy: InitPolicy,
     pub track_events: bool,
+    pub event_schema: EventSchema,
+    pub max_event_payload_bytes: Option<usize>,
+    pub event_sinks: Vec<Arc<dyn EventSink>>,
+    pub cancellation_token: Option<CancellationToken>,
+    pub eval_budget: Option<Duration>,
 
     #[cfg(feature = "realtime")]
     pub realtime_url: Url,
     #[cfg(feature = "realtime")]
     pub realtime_path: String,
+    #[cfg(feature = "grpc")]
+    pub grpc_url: Option<Url>,
     pub max_prerequisites_deep: u8,
+    pub clock: Arc<dyn Clock>,
+    pub key_generator: Arc<dyn KeyGenerator>,
+    pub instance_key: Arc<InstanceKeyGenerator>,
+    pub max_staleness: Option<Duration>,
+    pub stale_policy: StalePolicy,
+    pub warning_interval: Duration,
+    pub user_limits: Option<UserLimits>,
+    pub user_agent: String,
+    pub tags: BTreeMap<String, String>,
+    pub missing_toggle_report_interval: Option<Duration>,
+    pub usage_report_url: Option<Url>,
+    pub usage_report_interval: Duration,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub eval_soft_limits: Option<EvalSoftLimits>,
+    pub sticky_buckets: Option<Arc<dyn StickyBucketStore>>,
+    pub track_prerequisite_events: bool,
+    pub signing_secret: Option<Secret>,
+    pub unicode_normalization: Option<UnicodeNormalization>,
+    pub repository_limits: Option<RepositoryLimits>,
+    pub suppressed_events: BTreeSet<String>,
+    pub background_validation: bool,
 }
 
 impl Default for FPConfig {
     fn default() -> Self {
         Self {
-            server_sdk_key: "".to_owned(),
+            server_sdk_key: Secret::default(),
             remote_url: Url::parse("https://featureprobe.io/server").unwrap(),
             toggles_url: None,
             events_url: None,
+            access_events_url: None,
+            debug_events_url: None,
+            custom_events_url: None,
             refresh_interval: Duration::from_secs(5),
             start_wait: None,
+            init_policy: None,
             http_client: None,
+            http_transport: None,
 
             #[cfg(feature = "realtime")]
             realtime_url: None,
             #[cfg(feature = "realtime")]
             realtime_path: None,
+            #[cfg(feature = "grpc")]
+            grpc_url: None,
             track_events: true,
+            event_schema: EventSchema::default(),
+            max_event_payload_bytes: None,
+            event_sinks: vec![],
+            cancellation_token: None,
+            eval_budget: None,
+            clock: None,
+            key_generator: None,
+            instance_key_path: None,
+            bucket_keyless_by_instance: false,
+            max_staleness: None,
+            stale_policy: StalePolicy::Annotate,
+            warning_interval: Duration::from_secs(60),
+            user_limits: None,
+            app_name: None,
+            app_version: None,
+            tags: BTreeMap::new(),
+            missing_toggle_report_interval: None,
+            usage_report_url: None,
+            usage_report_interval: Duration::from_secs(300),
+            circuit_breaker: None,
+            eval_soft_limits: None,
+            sticky_buckets: None,
+            track_prerequisite_events: false,
+            signing_secret: None,
+            unicode_normalization: None,
+            repository_limits: None,
+            suppressed_events: BTreeSet::new(),
+            background_validation: true,
+            dns_overrides: BTreeMap::new(),
         }
     }
 }
@@ -61,26 +223,71 @@ impl Default for FPConfig {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            server_sdk_key: "".to_owned(),
+            server_sdk_key: Secret::default(),
             toggles_url: Url::parse("https://featureprobe.io/server/api/server-sdk/toggles")
                 .unwrap(),
             events_url: Url::parse("https://featureprobe.io/server/api/events").unwrap(),
+            event_endpoints: EventEndpoints::single(
+                Url::parse("https://featureprobe.io/server/api/events").unwrap(),
+            ),
             track_events: true,
+            event_schema: EventSchema::default(),
+            max_event_payload_bytes: None,
+            event_sinks: vec![],
+            cancellation_token: None,
+            eval_budget: None,
             refresh_interval: Duration::from_secs(60),
-            start_wait: None,
-            http_client: None,
+            init_policy: InitPolicy::NoWait,
+            http_transport: Arc::new(Client::default()),
 
             #[cfg(feature = "realtime")]
             realtime_url: Url::parse("https://featureprobe.io/server/realtime").unwrap(),
             #[cfg(feature = "realtime")]
             realtime_path: "/server/realtime".to_owned(),
+            #[cfg(feature = "grpc")]
+            grpc_url: None,
             max_prerequisites_deep: 20,
+            clock: Arc::new(SystemClock),
+            key_generator: Arc::new(UuidKeyGenerator),
+            instance_key: Arc::new(InstanceKeyGenerator::new(UuidKeyGenerator.generate())),
+            max_staleness: None,
+            stale_policy: StalePolicy::Annotate,
+            warning_interval: Duration::from_secs(60),
+            user_limits: None,
+            user_agent: crate::USER_AGENT.clone(),
+            tags: BTreeMap::new(),
+            missing_toggle_report_interval: None,
+            usage_report_url: None,
+            usage_report_interval: Duration::from_secs(300),
+            circuit_breaker: None,
+            eval_soft_limits: None,
+            sticky_buckets: None,
+            track_prerequisite_events: false,
+            signing_secret: None,
+            unicode_normalization: None,
+            repository_limits: None,
+            suppressed_events: BTreeSet::new(),
+            background_validation: true,
         }
     }
 }
 
 impl FPConfig {
-    pub(crate) fn build(&self) -> Config {
+    // a broken key file falls back to a key for this process only
+    fn instance_key(&self) -> InstanceKeyGenerator {
+        self.instance_key_path
+            .as_ref()
+            .and_then(|path| match InstanceKeyGenerator::load_or_create(path) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    warn!("instance key not persisted: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_else(|| InstanceKeyGenerator::new(UuidKeyGenerator.generate()))
+    }
+
+    pub(crate) fn build(&self) -> Result<Config, FPError> {
         info!("build_config from {:?}", self);
         let remote_url = self.remote_url.to_string();
         let remote_url = match remote_url.ends_with('/') {
@@ -90,7 +297,7 @@ impl FPConfig {
 
         #[cfg(feature = "realtime")]
         let realtime_url = match &self.realtime_url {
-            None => Url::parse(&(remote_url.clone() + "realtime")).expect("invalid realtime url"),
+            None => parse_url(remote_url.clone() + "realtime")?,
             Some(url) => url.to_owned(),
         };
 
@@ -101,29 +308,215 @@ impl FPConfig {
         };
 
         let toggles_url = match &self.toggles_url {
-            None => Url::parse(&(remote_url.clone() + "api/server-sdk/toggles"))
-                .expect("invalid toggles url"),
+            None => parse_url(remote_url.clone() + "api/server-sdk/toggles")?,
             Some(url) => url.to_owned(),
         };
 
         let events_url = match &self.events_url {
-            None => Url::parse(&(remote_url + "api/events")).expect("invalid events url"),
+            None => parse_url(remote_url + "api/events")?,
             Some(url) => url.to_owned(),
         };
+        let or_events_url = |url: &Option<Url>| url.clone().unwrap_or_else(|| events_url.clone());
+        let event_endpoints = EventEndpoints {
+            access: or_events_url(&self.access_events_url),
+            debug: or_events_url(&self.debug_events_url),
+            custom: or_events_url(&self.custom_events_url),
+        };
 
-        Config {
+        let instance_key = Arc::new(self.instance_key());
+        let key_generator: Arc<dyn KeyGenerator> = match &self.key_generator {
+            Some(generator) => generator.clone(),
+            None if self.bucket_keyless_by_instance => instance_key.clone(),
+            None => Arc::new(UuidKeyGenerator),
+        };
+
+        Ok(Config {
             toggles_url,
             events_url,
+            event_endpoints,
             server_sdk_key: self.server_sdk_key.clone(),
             refresh_interval: self.refresh_interval,
-            start_wait: self.start_wait,
-            http_client: self.http_client.clone(),
+            init_policy: self.init_policy.clone().unwrap_or(match self.start_wait {
+                Some(wait) => InitPolicy::WaitFor(wait),
+                None => InitPolicy::NoWait,
+            }),
+            http_transport: self.http_transport()?,
             track_events: self.track_events,
+            event_schema: self.event_schema,
+            max_event_payload_bytes: self.max_event_payload_bytes,
+            event_sinks: self.event_sinks.clone(),
+            cancellation_token: self.cancellation_token.clone(),
+            eval_budget: self.eval_budget,
             #[cfg(feature = "realtime")]
             realtime_url,
             #[cfg(feature = "realtime")]
             realtime_path,
+            #[cfg(feature = "grpc")]
+            grpc_url: self.grpc_url.clone(),
+            clock: self.clock.clone().unwrap_or_else(|| Arc::new(SystemClock)),
+            key_generator,
+            instance_key,
+            max_staleness: self.max_staleness,
+            stale_policy: self.stale_policy,
+            warning_interval: self.warning_interval,
+            user_limits: self.user_limits,
+            user_agent: self.user_agent()?,
+            tags: self.tags.clone(),
+            missing_toggle_report_interval: self.missing_toggle_report_interval,
+            usage_report_url: self.usage_report_url.clone(),
+            usage_report_interval: self.usage_report_interval,
+            circuit_breaker: self.circuit_breaker,
+            eval_soft_limits: self.eval_soft_limits.clone(),
+            sticky_buckets: self.sticky_buckets.clone(),
+            track_prerequisite_events: self.track_prerequisite_events,
+            signing_secret: self.signing_secret.clone(),
+            unicode_normalization: self.unicode_normalization,
+            repository_limits: self.repository_limits,
+            suppressed_events: self.suppressed_events.clone(),
+            background_validation: self.background_validation,
             ..Default::default()
+        })
+    }
+}
+
+impl FPConfig {
+    fn http_transport(&self) -> Result<Arc<dyn HttpTransport>, FPError> {
+        match &self.http_transport {
+            Some(transport) if self.dns_overrides.is_empty() => Ok(transport.clone()),
+            Some(transport) => transport
+                .with_dns_overrides(&self.dns_overrides)
+                .ok_or_else(|| {
+                    FPError::InternalError(
+                        "dns_overrides are not supported by the provided http_transport".to_owned(),
+                    )
+                }),
+            None => Ok(Arc::new(self.http_client()?.unwrap_or_default())),
+        }
+    }
+
+    // ports in the override addresses are ignored, the url port is used
+    fn http_client(&self) -> Result<Option<Client>, FPError> {
+        if self.dns_overrides.is_empty() {
+            return Ok(self.http_client.clone());
+        }
+        if let Some(client) = &self.http_client {
+            warn!("dns_overrides ignored, resolve them on the provided http_client");
+            return Ok(Some(client.clone()));
         }
+        let builder = self
+            .dns_overrides
+            .iter()
+            .fold(Client::builder(), |builder, (host, addrs)| {
+                builder.resolve_to_addrs(host, addrs)
+            });
+        builder
+            .build()
+            .map(Some)
+            .map_err(|e| FPError::HttpError(e.to_string()))
+    }
+
+    fn user_agent(&self) -> Result<String, FPError> {
+        let mut ua = crate::USER_AGENT.clone();
+        if let Some(name) = &self.app_name {
+            ua.push(' ');
+            ua.push_str(name);
+            if let Some(version) = &self.app_version {
+                ua.push('/');
+                ua.push_str(version);
+            }
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            ua.push_str(&format!(" ({})", tags.join("; ")));
+        }
+        match http::HeaderValue::from_str(&ua) {
+            Ok(_) => Ok(ua),
+            Err(_) => Err(FPError::InternalError(format!(
+                "invalid application metadata: {ua}"
+            ))),
+        }
+    }
+}
+
+fn parse_url(url: String) -> Result<Url, FPError> {
+    Url::parse(&url).map_err(|e| FPError::UrlError(format!("{url}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent() {
+        let mut config = FPConfig {
+            app_name: Some("order-service".to_owned()),
+            app_version: Some("1.2.0".to_owned()),
+            ..Default::default()
+        };
+        config.tags.insert("env".to_owned(), "prod".to_owned());
+        config.tags.insert("region".to_owned(), "us".to_owned());
+        let ua = config.build().unwrap().user_agent;
+        assert!(ua.starts_with("Rust/"));
+        assert!(ua.ends_with(" order-service/1.2.0 (env=prod; region=us)"));
+
+        config.app_name = Some("bad\nname".to_owned());
+        assert!(config.build().is_err());
+    }
+
+    #[test]
+    fn test_event_endpoints() {
+        let config = FPConfig {
+            remote_url: Url::parse("http://fp.test/").unwrap(),
+            debug_events_url: Some(Url::parse("http://debug.test/events").unwrap()),
+            ..Default::default()
+        };
+        let endpoints = config.build().unwrap().event_endpoints;
+        assert_eq!(endpoints.access.as_str(), "http://fp.test/api/events");
+        assert_eq!(endpoints.custom, endpoints.access);
+        assert_eq!(endpoints.debug.as_str(), "http://debug.test/events");
+    }
+
+    #[test]
+    fn test_instance_key() {
+        let path = std::env::temp_dir().join(format!("fp-config-instance-{}", std::process::id()));
+        let config = FPConfig {
+            instance_key_path: Some(path.clone()),
+            bucket_keyless_by_instance: true,
+            ..Default::default()
+        };
+        let first = config.build().unwrap();
+        let second = config.build().unwrap();
+        assert_eq!(first.instance_key.key(), second.instance_key.key());
+        assert_eq!(first.key_generator.generate(), first.instance_key.key());
+        std::fs::remove_file(path).unwrap();
+
+        let config = FPConfig::default().build().unwrap();
+        assert_ne!(config.key_generator.generate(), config.instance_key.key());
+    }
+
+    #[tokio::test]
+    async fn test_dns_overrides() {
+        use axum::{routing::get, Router};
+
+        let port = 9012;
+        let app = Router::new().route("/ping", get(|| async { "pong" }));
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        tokio::spawn(axum::Server::bind(&addr).serve(app.into_make_service()));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let mut config = FPConfig::default();
+        config
+            .dns_overrides
+            .insert("featureprobe.test".to_owned(), vec![addr]);
+        let client = config.http_client().unwrap().unwrap();
+        let body = client
+            .get(format!("http://featureprobe.test:{port}/ping"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "pong");
     }
 }
@@ -0,0 +1,47 @@
+use crate::FPUser;
+
+// how request or session types turn into the user a toggle is evaluated for,
+// derive it with the derive feature instead of hand-writing the mapping
+pub trait IntoEvalContext {
+    fn to_user(&self) -> FPUser;
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+
+    #[derive(crate::IntoEvalContext)]
+    struct Session {
+        #[fp(key)]
+        user_id: u64,
+        #[fp(rename = "city")]
+        city_name: String,
+        plan: Option<String>,
+        referrer: Option<String>,
+        #[fp(private)]
+        email: String,
+        #[fp(skip)]
+        #[allow(dead_code)]
+        token: String,
+    }
+
+    #[test]
+    fn test_derive_into_eval_context() {
+        let session = Session {
+            user_id: 42,
+            city_name: "paris".to_owned(),
+            plan: Some("pro".to_owned()),
+            referrer: None,
+            email: "a@b.c".to_owned(),
+            token: "secret".to_owned(),
+        };
+        let user = session.to_user();
+        assert_eq!(user.key(), "42");
+        assert_eq!(user.get("city").unwrap(), "paris");
+        assert_eq!(user.get("plan").unwrap(), "pro");
+        assert!(user.get("referrer").is_none());
+        assert!(user.get("token").is_none());
+        assert!(user.is_private("email"));
+        assert_eq!(user.get_all().len(), 3);
+    }
+}
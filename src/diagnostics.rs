@@ -0,0 +1,134 @@
+use crate::secret::REDACTED;
+use crate::{
+    EventSchema, FeatureProbe, Health, MissingToggle, SoftLimitBreach, ValidationReport,
+    WarningCount,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+// secrets are never included, only whether they are set
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigDiagnostics {
+    pub server_sdk_key: &'static str,
+    pub toggles_url: String,
+    pub events_url: String,
+    pub refresh_interval_ms: u128,
+    pub init_policy: String,
+    pub track_events: bool,
+    pub max_staleness_ms: Option<u128>,
+    pub stale_policy: String,
+    pub user_agent: String,
+    pub tags: BTreeMap<String, String>,
+    pub signing: bool,
+    pub sticky_buckets: bool,
+    pub circuit_breaker: bool,
+    pub unicode_normalization: Option<String>,
+    pub suppressed_events: BTreeSet<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoDiagnostics {
+    pub version: Option<u128>,
+    pub toggles: usize,
+    pub segments: usize,
+    pub debug_until_time: Option<u64>,
+    pub suppressed_events: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsSnapshot {
+    pub sdk_version: &'static str,
+    pub time: u128,
+    pub config: ConfigDiagnostics,
+    pub repository: RepoDiagnostics,
+    // sync, event and realtime state
+    pub health: Health,
+    pub event_schema: Option<EventSchema>,
+    pub validation: Option<ValidationReport>,
+    pub warnings: Vec<WarningCount>,
+    pub missing_toggles: Vec<MissingToggle>,
+    pub soft_limit_breaches: Vec<SoftLimitBreach>,
+    pub retired_toggles_requested: Vec<String>,
+}
+
+impl DiagnosticsSnapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl FeatureProbe {
+    pub fn diagnostics(&self) -> DiagnosticsSnapshot {
+        let config = &self.config;
+        let repository = {
+            let repo = self.repo.read();
+            RepoDiagnostics {
+                version: repo.version,
+                toggles: repo.toggles.len(),
+                segments: repo.segments.len(),
+                debug_until_time: repo.debug_until_time,
+                suppressed_events: repo.suppressed_events.len(),
+            }
+        };
+        DiagnosticsSnapshot {
+            sdk_version: crate::VERSION,
+            time: config.clock.now(),
+            config: ConfigDiagnostics {
+                server_sdk_key: REDACTED,
+                toggles_url: config.toggles_url.to_string(),
+                events_url: config.events_url.to_string(),
+                refresh_interval_ms: config.refresh_interval.as_millis(),
+                init_policy: format!("{:?}", config.init_policy),
+                track_events: config.track_events,
+                max_staleness_ms: config.max_staleness.map(|d| d.as_millis()),
+                stale_policy: format!("{:?}", config.stale_policy),
+                user_agent: config.user_agent.clone(),
+                tags: config.tags.clone(),
+                signing: config.signing_secret.is_some(),
+                sticky_buckets: config.sticky_buckets.is_some(),
+                circuit_breaker: config.circuit_breaker.is_some(),
+                unicode_normalization: config.unicode_normalization.map(|n| format!("{n:?}")),
+                suppressed_events: config.suppressed_events.clone(),
+            },
+            repository,
+            health: self.health(),
+            event_schema: self.event_schema(),
+            validation: self.validation_report(),
+            warnings: self.warning_counts(),
+            missing_toggles: self.missing_toggles(),
+            soft_limit_breaches: self.soft_limit_breaches(),
+            retired_toggles_requested: self.retired_toggles_requested(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FPConfig, FeatureProbe};
+    use serde_json::json;
+
+    #[test]
+    fn test_diagnostics_redacted() {
+        let config = FPConfig {
+            server_sdk_key: "server-secret-key".into(),
+            signing_secret: Some("signing-secret".into()),
+            ..Default::default()
+        };
+        let mut fp = FeatureProbe::new_for_test("toggle", json!(true));
+        fp.config = config.build().unwrap();
+        let diagnostics = fp.diagnostics();
+        assert_eq!(diagnostics.repository.toggles, 1);
+        assert!(diagnostics.config.signing);
+
+        let dump = diagnostics.to_json();
+        assert!(!dump.contains("server-secret-key"));
+        assert!(!dump.contains("signing-secret"));
+        let value: serde_json::Value = serde_json::from_str(&dump).unwrap();
+        assert_eq!(value["config"]["serverSdkKey"], "***");
+        assert_eq!(value["repository"]["toggles"], 1);
+        assert_eq!(value["health"]["initialized"], false);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::evaluate::{Repository, Segment, Toggle};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Added,
+    Changed,
+    Removed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub key: String,
+    pub kind: ChangeKind,
+    pub old_version: Option<u64>,
+    pub new_version: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoDiff {
+    pub old_version: Option<u128>,
+    pub new_version: Option<u128>,
+    pub toggles: Vec<Change>,
+    pub segments: Vec<Change>,
+}
+
+impl RepoDiff {
+    pub fn is_empty(&self) -> bool {
+        self.toggles.is_empty() && self.segments.is_empty()
+    }
+
+    pub fn changed_toggles(&self) -> impl Iterator<Item = &str> {
+        self.toggles.iter().map(|c| c.key.as_str())
+    }
+
+    pub fn changed_segments(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().map(|c| c.key.as_str())
+    }
+}
+
+trait Versioned: PartialEq {
+    fn version(&self) -> u64;
+}
+
+impl Versioned for Toggle {
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl Versioned for Segment {
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl Repository {
+    pub fn diff(&self, other: &Repository) -> RepoDiff {
+        RepoDiff {
+            old_version: self.version,
+            new_version: other.version,
+            toggles: diff_map(&self.toggles, &other.toggles),
+            segments: diff_map(&self.segments, &other.segments),
+        }
+    }
+}
+
+fn diff_map<T: Versioned>(old: &HashMap<String, T>, new: &HashMap<String, T>) -> Vec<Change> {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (old, new) = (old.get(key), new.get(key));
+            let kind = match (old, new) {
+                (None, Some(_)) => ChangeKind::Added,
+                (Some(_), None) => ChangeKind::Removed,
+                (Some(o), Some(n)) if o != n => ChangeKind::Changed,
+                _ => return None,
+            };
+            Some(Change {
+                key: key.clone(),
+                kind,
+                old_version: old.map(|t| t.version()),
+                new_version: new.map(|t| t.version()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::load_json;
+    use serde_json::json;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_repo_diff() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let old = load_json(&json_str).unwrap();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.version = Some(2);
+        new.toggles.remove("bool_toggle");
+        new.toggles.insert(
+            "new_toggle".to_owned(),
+            Toggle::new_for_test("new_toggle".to_owned(), json!(true)),
+        );
+        let t = new.toggles.get_mut("number_toggle").unwrap();
+        t.version += 1;
+        t.enabled = false;
+        new.segments.clear();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.new_version, Some(2));
+        assert_eq!(
+            diff.changed_toggles().collect::<Vec<_>>(),
+            vec!["bool_toggle", "new_toggle", "number_toggle"]
+        );
+        assert_eq!(diff.toggles[0].kind, ChangeKind::Removed);
+        assert_eq!(diff.toggles[1].kind, ChangeKind::Added);
+        assert_eq!(diff.toggles[2].kind, ChangeKind::Changed);
+        assert_eq!(
+            diff.toggles[2].old_version.unwrap() + 1,
+            diff.toggles[2].new_version.unwrap()
+        );
+        assert_eq!(diff.segments.len(), 1);
+        assert_eq!(diff.segments[0].kind, ChangeKind::Removed);
+    }
+}
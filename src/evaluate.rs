@@ -1,15 +1,23 @@
+use crate::hashing::{salt_hash, BUCKET_SIZE};
+use crate::normalization::UnicodeNormalization;
+use crate::reason::ReasonCode;
+use crate::unix_timestamp;
 use crate::user::FPUser;
+use crate::warning::{warn_throttled, WarningKind};
 use crate::FPError;
-use crate::{unix_timestamp, PrerequisiteError};
-use byteorder::{BigEndian, ReadBytesExt};
-use regex::Regex;
+use crate::RepositoryLimits;
+use regex::{Regex, RegexBuilder};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sha1::Digest;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufReader, Read};
+use std::str::FromStr;
 use std::string::String;
-use std::{collections::HashMap, str::FromStr};
-use tracing::{info, warn};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::io::AsyncRead;
+use tokio_util::io::SyncIoBridge;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -48,18 +56,23 @@ impl Serve {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-struct BucketRange((u32, u32));
+pub(crate) struct BucketRange(pub(crate) (u32, u32));
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Distribution {
-    distribution: Vec<Vec<BucketRange>>,
-    bucket_by: Option<String>,
-    salt: Option<String>,
+    pub(crate) distribution: Vec<Vec<BucketRange>>,
+    #[serde(alias = "bucket_by")]
+    pub(crate) bucket_by: Option<String>,
+    pub(crate) salt: Option<String>,
 }
 
 impl Distribution {
     pub fn find_index(&self, eval_param: &EvalParams) -> Result<usize, FPError> {
+        self.find_bucket(eval_param).map(|(_, index)| index)
+    }
+
+    pub fn find_bucket(&self, eval_param: &EvalParams) -> Result<(u32, usize), FPError> {
         let user = eval_param.user;
 
         let hash_key = match &self.bucket_by {
@@ -77,12 +90,8 @@ impl Distribution {
             },
         };
 
-        let salt = match &self.salt {
-            Some(s) if !s.is_empty() => s,
-            _ => eval_param.key,
-        };
-
-        let bucket_index = salt_hash(&hash_key, salt, 10000);
+        let salt = effective_salt(&self.salt, eval_param.key);
+        let bucket_index = salt_hash(&hash_key, salt, BUCKET_SIZE);
 
         let variation = self.distribution.iter().position(|ranges| {
             ranges.iter().any(|pair| {
@@ -96,24 +105,63 @@ impl Distribution {
                 "not find hash_bucket in distribution.".to_string(),
             )),
             None => Err(FPError::EvalError),
-            Some(index) => Ok(index),
+            Some(index) => Ok((bucket_index, index)),
+        }
+    }
+}
+
+// ramps the default serve towards one variation, the percentage of the
+// latest step not after now is taken and users bucket as in splits
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutSchedule {
+    pub(crate) variation: usize,
+    pub(crate) steps: Vec<RolloutStep>,
+    #[serde(default, alias = "bucket_by")]
+    pub(crate) bucket_by: Option<String>,
+    #[serde(default)]
+    pub(crate) salt: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct RolloutStep {
+    pub(crate) time: u64,
+    pub(crate) percentage: u32,
+}
+
+impl RolloutSchedule {
+    pub fn percentage_at(&self, now: u128) -> u32 {
+        self.steps
+            .iter()
+            .filter(|s| s.time as u128 <= now)
+            .max_by_key(|s| s.time)
+            .map(|s| s.percentage.min(100))
+            .unwrap_or(0)
+    }
+
+    // users without the bucket_by attribute stay on the default serve
+    fn ramped(&self, eval_param: &EvalParams) -> Option<u32> {
+        let percentage = self.percentage_at(eval_param.now);
+        if percentage == 0 {
+            return None;
         }
+        let hash_key = match &self.bucket_by {
+            None => eval_param.user.key(),
+            Some(custom_key) => eval_param.user.get(custom_key)?.to_owned(),
+        };
+        let salt = effective_salt(&self.salt, eval_param.key);
+        let bucket = salt_hash(&hash_key, salt, BUCKET_SIZE);
+        let threshold = percentage * (BUCKET_SIZE / 100) as u32;
+        (bucket < threshold).then_some(percentage)
     }
 }
 
-fn salt_hash(key: &str, salt: &str, bucket_size: u64) -> u32 {
-    let size = 4;
-    let mut hasher = sha1::Sha1::new();
-    let data = format!("{key}{salt}");
-    hasher.update(data);
-    let hax_value = hasher.finalize();
-    let mut v = Vec::with_capacity(size);
-    for i in (hax_value.len() - size)..hax_value.len() {
-        v.push(hax_value[i]);
-    }
-    let mut v = v.as_slice();
-    let value = v.read_u32::<BigEndian>().expect("can not be here");
-    value % bucket_size as u32
+// splits, schedules and bucket_of must agree on this or users change buckets
+fn effective_salt<'a>(salt: &'a Option<String>, toggle_key: &'a str) -> &'a str {
+    match salt {
+        Some(s) if !s.is_empty() => s,
+        _ => toggle_key,
+    }
 }
 
 pub struct EvalParams<'a> {
@@ -124,6 +172,18 @@ pub struct EvalParams<'a> {
     segment_repo: &'a HashMap<String, Segment>,
     toggle_repo: &'a HashMap<String, Toggle>,
     debug_until_time: Option<u64>,
+    now: u128,
+    // rule processing stops with EvalTimeout once passed
+    deadline: Option<Instant>,
+}
+
+impl EvalParams<'_> {
+    fn check_deadline(&self) -> Result<(), FPError> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(FPError::EvalTimeout),
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
@@ -137,6 +197,19 @@ pub struct EvalDetail<T> {
     pub variation_index: Option<usize>,
     pub version: Option<u64>,
     pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<ReasonCode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prerequisites: Vec<PrerequisiteResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PrerequisiteResult {
+    pub key: String,
+    pub value: Option<Value>,
+    pub expected: Value,
+    pub passed: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -149,17 +222,39 @@ pub struct Prerequisites {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Toggle {
-    key: String,
-    enabled: bool,
-    track_access_events: Option<bool>,
-    last_modified: Option<u64>,
-    version: u64,
-    for_client: bool,
-    disabled_serve: Serve,
-    default_serve: Serve,
-    rules: Vec<Rule>,
-    variations: Vec<Value>,
-    prerequisites: Option<Vec<Prerequisites>>,
+    pub(crate) key: String,
+    pub(crate) enabled: bool,
+    #[serde(alias = "track_access_events")]
+    pub(crate) track_access_events: Option<bool>,
+    #[serde(alias = "last_modified")]
+    pub(crate) last_modified: Option<u64>,
+    pub(crate) version: u64,
+    #[serde(alias = "for_client")]
+    pub(crate) for_client: bool,
+    #[serde(alias = "disabled_serve")]
+    pub(crate) disabled_serve: Serve,
+    #[serde(alias = "default_serve")]
+    pub(crate) default_serve: Serve,
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) variations: Vec<Value>,
+    pub(crate) prerequisites: Option<Vec<Prerequisites>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) archived: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) deprecated: bool,
+    // served while archived, falls back to the disabled serve
+    #[serde(
+        default,
+        alias = "archived_serve",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) archived_serve: Option<Serve>,
+    #[serde(
+        default,
+        alias = "rollout_schedule",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) rollout_schedule: Option<RolloutSchedule>,
 }
 
 impl Toggle {
@@ -172,6 +267,58 @@ impl Toggle {
         deep: u8,
         debug_until_time: Option<u64>,
     ) -> EvalDetail<Value> {
+        self.eval_at(
+            user,
+            segment_repo,
+            toggle_repo,
+            is_detail,
+            deep,
+            debug_until_time,
+            unix_timestamp(),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn eval_at(
+        &self,
+        user: &FPUser,
+        segment_repo: &HashMap<String, Segment>,
+        toggle_repo: &HashMap<String, Toggle>,
+        is_detail: bool,
+        deep: u8,
+        debug_until_time: Option<u64>,
+        now: u128,
+    ) -> EvalDetail<Value> {
+        self.eval_traced(
+            user,
+            segment_repo,
+            toggle_repo,
+            is_detail,
+            deep,
+            debug_until_time,
+            now,
+            None,
+        )
+        .0
+    }
+
+    // also returns the prerequisites evaluated on the way, in evaluation order,
+    // which are attached to the detail when is_detail is set
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn eval_traced(
+        &self,
+        user: &FPUser,
+        segment_repo: &HashMap<String, Segment>,
+        toggle_repo: &HashMap<String, Toggle>,
+        is_detail: bool,
+        deep: u8,
+        debug_until_time: Option<u64>,
+        now: u128,
+        deadline: Option<Instant>,
+    ) -> (
+        EvalDetail<Value>,
+        Vec<(PrerequisiteResult, EvalDetail<Value>)>,
+    ) {
         let eval_param = EvalParams {
             user,
             segment_repo,
@@ -180,29 +327,170 @@ impl Toggle {
             is_detail,
             variations: &self.variations,
             debug_until_time,
+            now,
+            deadline,
         };
 
-        match self.do_eval(&eval_param, deep) {
+        let mut prerequisites = vec![];
+        let mut detail = match self.do_eval(&eval_param, deep, &mut prerequisites) {
             Ok(eval) => eval,
-            Err(e) => self.disabled_variation(&eval_param, Some(e.to_string())),
+            Err(FPError::EvalTimeout) => self.default_variation(
+                &eval_param,
+                Some("evaluation budget exceeded".to_owned()),
+                ReasonCode::EvalTimeout,
+            ),
+            Err(e) => self.disabled_variation(
+                &eval_param,
+                Some(e.to_string()),
+                ReasonCode::PrerequisiteError,
+            ),
+        };
+        if is_detail {
+            detail.prerequisites = prerequisites.iter().map(|(r, _)| r.clone()).collect();
         }
+        (detail, prerequisites)
+    }
+
+    // errors are returned instead of serving the disabled variation
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn try_eval_at(
+        &self,
+        user: &FPUser,
+        segment_repo: &HashMap<String, Segment>,
+        toggle_repo: &HashMap<String, Toggle>,
+        is_detail: bool,
+        deep: u8,
+        debug_until_time: Option<u64>,
+        now: u128,
+        deadline: Option<Instant>,
+    ) -> Result<EvalDetail<Value>, FPError> {
+        let eval_param = EvalParams {
+            user,
+            segment_repo,
+            toggle_repo,
+            key: &self.key,
+            is_detail,
+            variations: &self.variations,
+            debug_until_time,
+            now,
+            deadline,
+        };
+
+        let mut prerequisites = vec![];
+        let mut detail = self.do_eval(&eval_param, deep, &mut prerequisites)?;
+        if is_detail {
+            detail.prerequisites = prerequisites.into_iter().map(|(r, _)| r).collect();
+        }
+        Ok(detail)
+    }
+
+    // effective salt of the first split, which is what bucket_of hashes with
+    pub fn split_salt(&self) -> Option<String> {
+        std::iter::once(&self.default_serve)
+            .chain(self.rules.iter().map(|r| &r.serve))
+            .find_map(|serve| match serve {
+                Serve::Split(d) => Some(self.salt_of(d)),
+                Serve::Select(_) => None,
+            })
+    }
+
+    // salt of the split that produced the detail, none when no split served it
+    pub(crate) fn served_split_salt(&self, detail: &EvalDetail<Value>) -> Option<String> {
+        let serve = match (detail.reason_code, detail.rule_index) {
+            (Some(ReasonCode::RuleMatch), Some(i)) => &self.rules.get(i)?.serve,
+            (Some(ReasonCode::DefaultServe), _) => &self.default_serve,
+            _ => return None,
+        };
+        match serve {
+            Serve::Split(d) => Some(self.salt_of(d)),
+            Serve::Select(_) => None,
+        }
+    }
+
+    fn salt_of(&self, distribution: &Distribution) -> String {
+        effective_salt(&distribution.salt, &self.key).to_owned()
+    }
+
+    pub fn bucket_of(&self, user: &FPUser) -> Option<(u32, usize)> {
+        let distribution = std::iter::once(&self.default_serve)
+            .chain(self.rules.iter().map(|r| &r.serve))
+            .find_map(|serve| match serve {
+                Serve::Split(d) => Some(d),
+                Serve::Select(_) => None,
+            })?;
+        let eval_param = EvalParams {
+            user,
+            segment_repo: &HashMap::new(),
+            toggle_repo: &HashMap::new(),
+            key: &self.key,
+            is_detail: false,
+            variations: &self.variations,
+            debug_until_time: None,
+            now: 0,
+            deadline: None,
+        };
+        distribution.find_bucket(&eval_param).ok()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn eval_disabled(
+        &self,
+        user: &FPUser,
+        segment_repo: &HashMap<String, Segment>,
+        toggle_repo: &HashMap<String, Toggle>,
+        is_detail: bool,
+        debug_until_time: Option<u64>,
+        now: u128,
+        reason: &str,
+    ) -> EvalDetail<Value> {
+        let eval_param = EvalParams {
+            user,
+            segment_repo,
+            toggle_repo,
+            key: &self.key,
+            is_detail,
+            variations: &self.variations,
+            debug_until_time,
+            now,
+            deadline: None,
+        };
+        self.disabled_variation(
+            &eval_param,
+            Some(reason.to_owned()),
+            ReasonCode::EmergencyDisabled,
+        )
     }
 
     fn do_eval(
         &self,
         eval_param: &EvalParams,
         max_depth: u8,
-    ) -> Result<EvalDetail<Value>, PrerequisiteError> {
+        evaluated: &mut Vec<(PrerequisiteResult, EvalDetail<Value>)>,
+    ) -> Result<EvalDetail<Value>, FPError> {
+        if self.archived {
+            return Ok(self.fixed_variation(
+                self.archived_serve.as_ref().unwrap_or(&self.disabled_serve),
+                eval_param,
+                "archived".to_owned(),
+                None,
+                ReasonCode::Archived,
+            ));
+        }
+
         if !self.enabled {
-            return Ok(self.disabled_variation(eval_param, None))
+            return Ok(self.disabled_variation(eval_param, None, ReasonCode::Disabled));
         }
 
-        if !self.meet_prerequisite(eval_param, max_depth)? {
-            return Ok(self.disabled_variation(eval_param, Some(
-                "Prerequisite not match".to_owned())));
+        if !self.meet_prerequisite(eval_param, max_depth, evaluated)? {
+            return Ok(self.disabled_variation(
+                eval_param,
+                Some("Prerequisite not match".to_owned()),
+                ReasonCode::PrerequisiteNotMatch,
+            ));
         }
 
         for (i, rule) in self.rules.iter().enumerate() {
+            eval_param.check_deadline()?;
             match rule.serve_variation(eval_param) {
                 Ok(v) => {
                     if v.is_some() {
@@ -211,6 +499,7 @@ impl Toggle {
                             format!("rule {i}"),
                             Some(i),
                             eval_param.debug_until_time,
+                            ReasonCode::RuleMatch,
                         ));
                     }
                 }
@@ -220,28 +509,50 @@ impl Toggle {
                         format!("{e:?}"),
                         Some(i),
                         eval_param.debug_until_time,
+                        ReasonCode::ServeError,
                     ));
                 }
             }
         }
 
-        Ok(self.default_variation(eval_param, None))
+        if let Some(schedule) = &self.rollout_schedule {
+            if let Some(percentage) = schedule.ramped(eval_param) {
+                return Ok(self.fixed_variation(
+                    &Serve::Select(schedule.variation),
+                    eval_param,
+                    format!("rollout schedule {percentage}%"),
+                    None,
+                    ReasonCode::ScheduledRollout,
+                ));
+            }
+        }
+
+        Ok(self.default_variation(eval_param, None, ReasonCode::DefaultServe))
     }
 
     fn meet_prerequisite(
         &self,
         eval_param: &EvalParams,
         deep: u8,
-    ) -> Result<bool, PrerequisiteError> {
+        evaluated: &mut Vec<(PrerequisiteResult, EvalDetail<Value>)>,
+    ) -> Result<bool, FPError> {
         if deep == 0 {
-            return Err(PrerequisiteError::DepthOverflow);
+            return Err(FPError::PrerequisiteDepthOverflow);
         }
 
         if let Some(ref prerequisites) = self.prerequisites {
             for pre in prerequisites {
+                eval_param.check_deadline()?;
                 let eval = match eval_param.toggle_repo.get(&pre.key) {
                     None => {
-                        return Err(PrerequisiteError::NotExist(pre.key.to_string()));
+                        let result = PrerequisiteResult {
+                            key: pre.key.clone(),
+                            value: None,
+                            expected: pre.value.clone(),
+                            passed: false,
+                        };
+                        evaluated.push((result, EvalDetail::default()));
+                        return Err(FPError::PrerequisiteNotExist(pre.key.to_string()));
                     }
                     Some(t) => t.do_eval(
                         &EvalParams {
@@ -252,14 +563,23 @@ impl Toggle {
                             segment_repo: eval_param.segment_repo,
                             toggle_repo: eval_param.toggle_repo,
                             debug_until_time: eval_param.debug_until_time,
+                            now: eval_param.now,
+                            deadline: eval_param.deadline,
                         },
                         deep - 1,
+                        evaluated,
                     )?,
                 };
-
-                match eval.value {
-                    Some(v) if v == pre.value => continue,
-                    _ => return Ok(false),
+                let passed = eval.value.as_ref() == Some(&pre.value);
+                let result = PrerequisiteResult {
+                    key: pre.key.clone(),
+                    value: eval.value.clone(),
+                    expected: pre.value.clone(),
+                    passed,
+                };
+                evaluated.push((result, eval));
+                if !passed {
+                    return Ok(false);
                 }
             }
             return Ok(true);
@@ -273,7 +593,12 @@ impl Toggle {
         reason: String,
         rule_index: Option<usize>,
         debug_until_time: Option<u64>,
+        code: ReasonCode,
     ) -> EvalDetail<Value> {
+        let code = match v {
+            Some(_) => code,
+            None => ReasonCode::ServeError,
+        };
         EvalDetail {
             variation_index: v.as_ref().map(|v| v.index),
             value: v.map(|v| v.value),
@@ -283,6 +608,8 @@ impl Toggle {
             last_modified: self.last_modified,
             rule_index,
             reason,
+            reason_code: Some(code),
+            prerequisites: vec![],
         }
     }
 
@@ -290,12 +617,14 @@ impl Toggle {
         &self,
         eval_param: &EvalParams,
         reason: Option<String>,
+        code: ReasonCode,
     ) -> EvalDetail<Value> {
         return self.fixed_variation(
             &self.default_serve,
             eval_param,
             "default.".to_owned(),
             reason,
+            code,
         );
     }
 
@@ -303,12 +632,14 @@ impl Toggle {
         &self,
         eval_param: &EvalParams,
         reason: Option<String>,
+        code: ReasonCode,
     ) -> EvalDetail<Value> {
         return self.fixed_variation(
             &self.disabled_serve,
             eval_param,
             "disabled.".to_owned(),
             reason,
+            code,
         );
     }
 
@@ -318,6 +649,7 @@ impl Toggle {
         eval_param: &EvalParams,
         default_reason: String,
         reason: Option<String>,
+        code: ReasonCode,
     ) -> EvalDetail<Value> {
         match serve.select_variation(eval_param) {
             Ok(v) => self.serve_variation(
@@ -325,12 +657,14 @@ impl Toggle {
                 concat_reason(default_reason, reason),
                 None,
                 eval_param.debug_until_time,
+                code,
             ),
             Err(e) => self.serve_variation(
                 None,
                 concat_reason(format!("{e:?}"), reason),
                 None,
                 eval_param.debug_until_time,
+                ReasonCode::ServeError,
             ),
         }
     }
@@ -339,24 +673,58 @@ impl Toggle {
         self.track_access_events.unwrap_or(false)
     }
 
-    #[cfg(feature = "internal")]
     pub fn is_for_client(&self) -> bool {
         self.for_client
     }
 
-    #[cfg(feature = "internal")]
+    pub fn is_archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    pub fn rollout_schedule(&self) -> Option<&RolloutSchedule> {
+        self.rollout_schedule.as_ref()
+    }
+
     pub fn all_segment_ids(&self) -> Vec<&str> {
         let mut sids: Vec<&str> = Vec::new();
         for r in &self.rules {
             for c in &r.conditions {
                 if c.r#type == ConditionType::Segment {
-                    sids.push(&c.subject)
+                    sids.extend(c.objects.iter().map(|o| o.as_str()))
                 }
             }
         }
         sids
     }
 
+    // regex conditions of this toggle and of the segments it uses
+    pub(crate) fn regex_conditions<'a>(
+        &'a self,
+        segments: &'a HashMap<String, Segment>,
+    ) -> impl Iterator<Item = &'a Condition> {
+        let segment_conditions = self
+            .all_segment_ids()
+            .into_iter()
+            .filter_map(|id| segments.get(id))
+            .flat_map(|s| s.rules.iter().flat_map(|r| r.conditions.iter()));
+        self.rules
+            .iter()
+            .flat_map(|r| r.conditions.iter())
+            .chain(segment_conditions)
+            .filter(|c| c.r#type == ConditionType::String && c.predicate.ends_with("regex"))
+    }
+
+    // compiles the regex patterns, returns the number seen
+    pub(crate) fn warm_up(&self, segments: &HashMap<String, Segment>) -> usize {
+        self.regex_conditions(segments)
+            .map(|c| c.regexes().len())
+            .sum()
+    }
+
     pub fn new_for_test(key: String, val: Value) -> Self {
         Self {
             key,
@@ -370,19 +738,30 @@ impl Toggle {
             for_client: false,
             rules: vec![],
             prerequisites: None,
+            archived: false,
+            deprecated: false,
+            archived_serve: None,
+            rollout_schedule: None,
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
-struct SegmentRule {
-    conditions: Vec<Condition>,
+pub(crate) struct SegmentRule {
+    pub(crate) conditions: Vec<Condition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) start: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) end: Option<u64>,
 }
 
 impl SegmentRule {
-    pub fn allow(&self, user: &FPUser) -> bool {
+    pub fn allow(&self, user: &FPUser, now: u128) -> bool {
+        if !within_window(self.start, self.end, now) {
+            return false;
+        }
         for c in &self.conditions {
-            if c.meet(user, None) {
+            if c.meet(user, None, now) {
                 return true;
             }
         }
@@ -396,9 +775,9 @@ struct DefaultRule {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-struct Rule {
-    serve: Serve,
-    conditions: Vec<Condition>,
+pub(crate) struct Rule {
+    pub(crate) serve: Serve,
+    pub(crate) conditions: Vec<Condition>,
 }
 
 impl Rule {
@@ -408,7 +787,7 @@ impl Rule {
         match self
             .conditions
             .iter()
-            .all(|c| c.meet(user, Some(segment_repo)))
+            .all(|c| c.meet(user, Some(segment_repo), eval_param.now))
         {
             true => Ok(Some(self.serve.select_variation(eval_param)?)),
             false => Ok(None),
@@ -418,33 +797,132 @@ impl Rule {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "camelCase")]
-enum ConditionType {
+pub(crate) enum ConditionType {
     String,
     Segment,
     Datetime,
     Number,
     Semver,
+    Version,
+    Locale,
     #[serde(other)]
     Unknown,
 }
 
+// 2 to 4 numeric segments such as 1.2 or 1.2.3.4, missing segments count as 0
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) struct LooseVersion([u64; 4]);
+
+impl FromStr for LooseVersion {
+    type Err = FPError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || FPError::InternalError(format!("invalid version: {s}"));
+        let parts: Vec<&str> = s.trim().split('.').collect();
+        if !(2..=4).contains(&parts.len()) {
+            return Err(invalid());
+        }
+        let mut segments = [0; 4];
+        for (i, part) in parts.iter().enumerate() {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            segments[i] = part.parse().map_err(|_| invalid())?;
+        }
+        Ok(LooseVersion(segments))
+    }
+}
+
+// lowercased subtags, posix style zh_CN is accepted as well
+fn locale_subtags(tag: &str) -> Vec<String> {
+    tag.trim()
+        .split(['-', '_'])
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_ascii_lowercase())
+        .collect()
+}
+
+// region is the 2 letter or 3 digit subtag following language and optional script
+fn locale_region(subtags: &[String]) -> Option<&str> {
+    subtags
+        .iter()
+        .skip(1)
+        .find(|s| {
+            (s.len() == 2 && s.bytes().all(|b| b.is_ascii_alphabetic()))
+                || (s.len() == 3 && s.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(|s| s.as_str())
+}
+
+// string conditions on this subject compare the user key, it is reserved so
+// no attribute can shadow it
+pub(crate) const KEY_SUBJECT: &str = "key";
+
+// compiled size cap for every pattern, repository limits can set a lower one
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+// compiled objects of a regex condition, built on first use and dropped with
+// the repository, invalid or oversized patterns are kept as None
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Regexes(OnceLock<Vec<Option<Regex>>>);
+
+impl PartialEq for Regexes {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Regexes {}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-struct Condition {
-    r#type: ConditionType,
+pub(crate) struct Condition {
+    pub(crate) r#type: ConditionType,
     #[serde(default)]
-    subject: String,
-    predicate: String,
-    objects: Vec<String>,
+    pub(crate) subject: String,
+    pub(crate) predicate: String,
+    pub(crate) objects: Vec<String>,
+    #[serde(skip)]
+    pub(crate) regexes: Regexes,
 }
 
 impl Condition {
-    pub fn meet(&self, user: &FPUser, segment_repo: Option<&HashMap<String, Segment>>) -> bool {
+    pub(crate) fn regexes(&self) -> &[Option<Regex>] {
+        self.regexes.0.get_or_init(|| {
+            self.objects
+                .iter()
+                .map(|o| {
+                    RegexBuilder::new(o)
+                        .size_limit(REGEX_SIZE_LIMIT)
+                        .build()
+                        .ok()
+                })
+                .collect()
+        })
+    }
+
+    pub fn meet(
+        &self,
+        user: &FPUser,
+        segment_repo: Option<&HashMap<String, Segment>>,
+        now: u128,
+    ) -> bool {
+        // existence only looks at the attribute, whatever the condition type
+        match self.predicate.as_str() {
+            "exists" => return user.get(&self.subject).is_some(),
+            "does not exist" => return user.get(&self.subject).is_none(),
+            _ => {}
+        }
         match &self.r#type {
             ConditionType::String => self.match_string(user, &self.predicate),
-            ConditionType::Segment => self.match_segment(user, &self.predicate, segment_repo),
+            ConditionType::Segment => self.match_segment(user, &self.predicate, segment_repo, now),
+            ConditionType::Number if self.predicate.starts_with('%') => {
+                self.match_modulo(user, &self.predicate)
+            }
             ConditionType::Number => self.match_ordering::<f64>(user, &self.predicate),
             ConditionType::Semver => self.match_ordering::<Version>(user, &self.predicate),
-            ConditionType::Datetime => self.match_timestamp(user, &self.predicate),
+            ConditionType::Version => self.match_ordering::<LooseVersion>(user, &self.predicate),
+            ConditionType::Datetime => self.match_timestamp(user, &self.predicate, now),
+            ConditionType::Locale => self.match_locale(user, &self.predicate),
             _ => false,
         }
     }
@@ -454,42 +932,43 @@ impl Condition {
         user: &FPUser,
         predicate: &str,
         segment_repo: Option<&HashMap<String, Segment>>,
+        now: u128,
     ) -> bool {
         match segment_repo {
             None => false,
             Some(repo) => match predicate {
-                "is in" => self.user_in_segments(user, repo),
-                "is not in" => !self.user_in_segments(user, repo),
+                "is in" => self.user_in_segments(user, repo, now),
+                "is not in" => !self.user_in_segments(user, repo, now),
                 _ => false,
             },
         }
     }
 
     fn match_string(&self, user: &FPUser, predicate: &str) -> bool {
-        if let Some(c) = user.get(&self.subject) {
+        let key = match user.get(&self.subject) {
+            None if self.subject == KEY_SUBJECT => Some(user.key()),
+            _ => None,
+        };
+        if let Some(c) = user.get(&self.subject).or(key.as_ref()) {
             return match predicate {
                 "is one of" => self.do_match::<String>(c, |c, o| c.eq(o)),
                 "ends with" => self.do_match::<String>(c, |c, o| c.ends_with(o)),
                 "starts with" => self.do_match::<String>(c, |c, o| c.starts_with(o)),
                 "contains" => self.do_match::<String>(c, |c, o| c.contains(o)),
-                "matches regex" => {
-                    self.do_match::<String>(c, |c, o| match Regex::new(o) {
-                        Ok(re) => re.is_match(c),
-                        Err(_) => false, // invalid regex should be checked when load config
-                    })
-                }
+                // invalid regex should be checked when load config
+                "matches regex" => self.regexes().iter().flatten().any(|re| re.is_match(c)),
                 "is not any of" => !self.match_string(user, "is one of"),
                 "does not end with" => !self.match_string(user, "ends with"),
                 "does not start with" => !self.match_string(user, "starts with"),
                 "does not contain" => !self.match_string(user, "contains"),
                 "does not match regex" => !self.match_string(user, "matches regex"),
                 _ => {
-                    info!("unknown predicate {}", predicate);
+                    warn_throttled(WarningKind::UnknownPredicate, predicate);
                     false
                 }
             };
         }
-        info!("user attr missing: {}", self.subject);
+        warn_throttled(WarningKind::UserAttrMissing, &self.subject);
         false
     }
 
@@ -507,28 +986,85 @@ impl Condition {
                 "<" => self.do_match::<T>(&c, |c, o| c.lt(o)),
                 "<=" => self.do_match::<T>(&c, |c, o| c.le(o)),
                 _ => {
-                    info!("unknown predicate {}", predicate);
+                    warn_throttled(WarningKind::UnknownPredicate, predicate);
                     false
                 }
             };
         }
-        info!("user attr missing: {}", self.subject);
+        warn_throttled(WarningKind::UserAttrMissing, &self.subject);
         false
     }
 
-    fn match_timestamp(&self, user: &FPUser, predicate: &str) -> bool {
+    // "% n ==" matches when the integer attribute modulo n is one of the objects,
+    // "% n !=" when it is none of them
+    fn match_modulo(&self, user: &FPUser, predicate: &str) -> bool {
+        let parsed = predicate[1..]
+            .trim()
+            .split_once(' ')
+            .and_then(|(n, op)| Some((n.parse::<i64>().ok()?, op.trim())))
+            .filter(|(n, op)| *n > 0 && (*op == "==" || *op == "!="));
+        let (modulus, op) = match parsed {
+            Some(p) => p,
+            None => {
+                warn_throttled(WarningKind::UnknownPredicate, predicate);
+                return false;
+            }
+        };
+        let c: i64 = match user.get(&self.subject) {
+            Some(v) => match v.trim().parse() {
+                Ok(v) => v,
+                Err(_) => return false,
+            },
+            None => {
+                warn_throttled(WarningKind::UserAttrMissing, &self.subject);
+                return false;
+            }
+        };
+        let hit = self.do_match::<i64>(&c.rem_euclid(modulus), |c, o| c.eq(o));
+        match op {
+            "==" => hit,
+            _ => !hit,
+        }
+    }
+
+    // bcp-47 tags compared case insensitively by subtag, so zh matches zh-Hans-CN
+    fn match_locale(&self, user: &FPUser, predicate: &str) -> bool {
+        if let Some(c) = user.get(&self.subject) {
+            let tag = locale_subtags(c);
+            return match predicate {
+                "is one of" => self
+                    .objects
+                    .iter()
+                    .any(|o| tag.starts_with(&locale_subtags(o))),
+                "in region" => match locale_region(&tag) {
+                    Some(region) => self.objects.iter().any(|o| o.eq_ignore_ascii_case(region)),
+                    None => false,
+                },
+                "is not any of" => !self.match_locale(user, "is one of"),
+                "not in region" => !self.match_locale(user, "in region"),
+                _ => {
+                    warn_throttled(WarningKind::UnknownPredicate, predicate);
+                    false
+                }
+            };
+        }
+        warn_throttled(WarningKind::UserAttrMissing, &self.subject);
+        false
+    }
+
+    fn match_timestamp(&self, user: &FPUser, predicate: &str, now: u128) -> bool {
         let c: u128 = match user.get(&self.subject) {
             Some(v) => match v.parse() {
                 Ok(v) => v,
                 Err(_) => return false,
             },
-            None => unix_timestamp() / 1000,
+            None => now / 1000,
         };
         match predicate {
             "after" => self.do_match::<u128>(&c, |c, o| c.ge(o)),
             "before" => self.do_match::<u128>(&c, |c, o| c.lt(o)),
             _ => {
-                info!("unknown predicate {}", predicate);
+                warn_throttled(WarningKind::UnknownPredicate, predicate);
                 false
             }
         }
@@ -544,15 +1080,15 @@ impl Condition {
             .any(|x| x)
     }
 
-    fn user_in_segments(&self, user: &FPUser, repo: &HashMap<String, Segment>) -> bool {
+    fn user_in_segments(&self, user: &FPUser, repo: &HashMap<String, Segment>, now: u128) -> bool {
         for segment_key in &self.objects {
             match repo.get(segment_key) {
                 Some(segment) => {
-                    if segment.contains(user) {
+                    if segment.contains_at(user, now) {
                         return true;
                     }
                 }
-                None => warn!("segment not found {}", segment_key),
+                None => warn_throttled(WarningKind::SegmentNotFound, segment_key),
             }
         }
         false
@@ -562,15 +1098,32 @@ impl Condition {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Segment {
-    unique_id: String,
-    version: u64,
-    rules: Vec<SegmentRule>,
+    #[serde(alias = "unique_id")]
+    pub(crate) unique_id: String,
+    pub(crate) version: u64,
+    pub(crate) rules: Vec<SegmentRule>,
+    // validity window in unix ms, start inclusive and end exclusive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) start: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) end: Option<u64>,
 }
 
 impl Segment {
     pub fn contains(&self, user: &FPUser) -> bool {
+        self.contains_at(user, unix_timestamp())
+    }
+
+    pub fn is_active_at(&self, now: u128) -> bool {
+        within_window(self.start, self.end, now)
+    }
+
+    pub(crate) fn contains_at(&self, user: &FPUser, now: u128) -> bool {
+        if !self.is_active_at(now) {
+            return false;
+        }
         for rule in &self.rules {
-            if rule.allow(user) {
+            if rule.allow(user, now) {
                 return true;
             }
         }
@@ -578,8 +1131,8 @@ impl Segment {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", from = "RepositoryData")]
 pub struct Repository {
     pub segments: HashMap<String, Segment>,
     pub toggles: HashMap<String, Toggle>,
@@ -587,6 +1140,119 @@ pub struct Repository {
     // TODO: remove option next release
     pub version: Option<u128>,
     pub debug_until_time: Option<u64>,
+    // toggles whose access and debug events are never recorded
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub suppressed_events: BTreeSet<String>,
+    #[serde(skip)]
+    segment_index: HashMap<String, BTreeSet<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RepositoryData {
+    segments: HashMap<String, Segment>,
+    toggles: HashMap<String, Toggle>,
+    events: Option<Value>,
+    version: Option<u128>,
+    #[serde(alias = "debug_until_time")]
+    debug_until_time: Option<u64>,
+    #[serde(default, alias = "suppressed_events")]
+    suppressed_events: BTreeSet<String>,
+}
+
+impl From<RepositoryData> for Repository {
+    fn from(data: RepositoryData) -> Self {
+        let mut repo = Repository {
+            segments: data.segments,
+            toggles: data.toggles,
+            events: data.events,
+            version: data.version,
+            debug_until_time: data.debug_until_time,
+            suppressed_events: data.suppressed_events,
+            segment_index: Default::default(),
+        };
+        repo.rebuild_index();
+        repo
+    }
+}
+
+// segment_index is derived from toggles, so it is not compared.
+impl PartialEq for Repository {
+    fn eq(&self, other: &Self) -> bool {
+        self.segments == other.segments
+            && self.toggles == other.toggles
+            && self.events == other.events
+            && self.version == other.version
+            && self.debug_until_time == other.debug_until_time
+            && self.suppressed_events == other.suppressed_events
+    }
+}
+
+impl Repository {
+    // normalizes the objects of string conditions in toggle and segment rules
+    pub fn normalize_strings(&mut self, form: UnicodeNormalization) {
+        let conditions = self
+            .toggles
+            .values_mut()
+            .flat_map(|t| t.rules.iter_mut().flat_map(|r| r.conditions.iter_mut()))
+            .chain(
+                self.segments
+                    .values_mut()
+                    .flat_map(|s| s.rules.iter_mut().flat_map(|r| r.conditions.iter_mut())),
+            );
+        for condition in conditions {
+            if condition.r#type == ConditionType::String {
+                for object in condition.objects.iter_mut() {
+                    *object = form.normalize(object);
+                }
+            }
+        }
+    }
+
+    pub fn rebuild_index(&mut self) {
+        let mut index: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for (key, toggle) in &self.toggles {
+            for segment in toggle.all_segment_ids() {
+                index
+                    .entry(segment.to_owned())
+                    .or_default()
+                    .insert(key.clone());
+            }
+        }
+        self.segment_index = index;
+    }
+
+    pub fn dependent_toggles(&self, segment: &str) -> Vec<&str> {
+        match self.segment_index.get(segment) {
+            Some(toggles) => toggles.iter().map(|t| t.as_str()).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn client_repository(&self) -> Repository {
+        let toggles: HashMap<String, Toggle> = self
+            .toggles
+            .iter()
+            .filter(|(_, t)| t.is_for_client())
+            .map(|(k, t)| (k.clone(), t.clone()))
+            .collect();
+        let segments = toggles
+            .values()
+            .flat_map(|t| t.all_segment_ids())
+            .filter_map(|id| self.segments.get(id).map(|s| (id.to_owned(), s.clone())))
+            .collect();
+        let mut repo = Repository {
+            segments,
+            toggles,
+            events: None,
+            version: self.version,
+            debug_until_time: self.debug_until_time,
+            suppressed_events: self.suppressed_events.clone(),
+            segment_index: Default::default(),
+        };
+        repo.rebuild_index();
+        repo
+    }
 }
 
 impl Default for Repository {
@@ -597,6 +1263,8 @@ impl Default for Repository {
             events: Default::default(),
             version: Some(0),
             debug_until_time: None,
+            suppressed_events: Default::default(),
+            segment_index: Default::default(),
         }
     }
 }
@@ -614,13 +1282,113 @@ pub fn load_json(json_str: &str) -> Result<Repository, FPError> {
     let repo = serde_json::from_str::<Repository>(json_str)
         .map_err(|e| FPError::JsonError(json_str.to_owned(), e));
     if let Ok(repo) = &repo {
-        for t in repo.toggles.values() {
-            validate_toggle(t)?
-        }
+        validate_repository(repo)?;
+        check_legacy_format(json_str.as_bytes());
     }
     repo
 }
 
+pub fn load_bytes(bytes: &[u8]) -> Result<Repository, FPError> {
+    let repo = serde_json::from_slice::<Repository>(bytes)
+        .map_err(|e| FPError::JsonError(String::from_utf8_lossy(bytes).into_owned(), e))?;
+    validate_repository(&repo)?;
+    check_legacy_format(bytes);
+    Ok(repo)
+}
+
+// for untrusted sources, the payload is not kept for the error
+pub fn load_bytes_with_limits(
+    bytes: &[u8],
+    limits: &RepositoryLimits,
+) -> Result<Repository, FPError> {
+    limits.check_payload(bytes.len())?;
+    let repo = serde_json::from_slice::<Repository>(bytes)
+        .map_err(|e| FPError::JsonError("<untrusted>".to_owned(), e))?;
+    limits.check(&repo)?;
+    validate_repository(&repo)?;
+    check_legacy_format(bytes);
+    Ok(repo)
+}
+
+// the payload is not kept for the error, it may be arbitrarily large
+pub fn load_reader<R: Read>(reader: R) -> Result<Repository, FPError> {
+    let mut reader = BufReader::new(LegacyScan::new(reader));
+    let repo = serde_json::from_reader::<_, Repository>(&mut reader)
+        .map_err(|e| FPError::JsonError("<reader>".to_owned(), e))?;
+    validate_repository(&repo)?;
+    if reader.get_ref().legacy {
+        warn_throttled(WarningKind::LegacyFormat, "repository");
+    }
+    Ok(repo)
+}
+
+// streams like load_reader, parsing runs on a blocking thread
+pub async fn load_async_reader<R>(reader: R) -> Result<Repository, FPError>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || load_reader(SyncIoBridge::new(reader)))
+        .await
+        .map_err(|e| FPError::InternalError(format!("read repository: {e}")))?
+}
+
+// keys only emitted by older servers, aliases accept them while loading
+const LEGACY_KEYS: [&str; 3] = ["\"disabled_serve\"", "\"default_serve\"", "\"unique_id\""];
+
+fn has_legacy_keys(bytes: &[u8]) -> bool {
+    LEGACY_KEYS
+        .iter()
+        .any(|k| bytes.windows(k.len()).any(|w| w == k.as_bytes()))
+}
+
+pub(crate) fn check_legacy_format(bytes: &[u8]) {
+    if has_legacy_keys(bytes) {
+        warn_throttled(WarningKind::LegacyFormat, "repository");
+    }
+}
+
+// scans streamed bytes for legacy keys, keeping a tail for keys split across reads
+struct LegacyScan<R> {
+    inner: R,
+    tail: Vec<u8>,
+    legacy: bool,
+}
+
+impl<R: Read> LegacyScan<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            tail: vec![],
+            legacy: false,
+        }
+    }
+}
+
+impl<R: Read> Read for LegacyScan<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if !self.legacy && n > 0 {
+            self.tail.extend_from_slice(&buf[..n]);
+            self.legacy = has_legacy_keys(&self.tail);
+            let keep = LEGACY_KEYS.iter().map(|k| k.len()).max().unwrap_or(0);
+            let start = self.tail.len().saturating_sub(keep);
+            self.tail.drain(..start);
+        }
+        Ok(n)
+    }
+}
+
+fn validate_repository(repo: &Repository) -> Result<(), FPError> {
+    for t in repo.toggles.values() {
+        validate_toggle(t)?
+    }
+    Ok(())
+}
+
+fn within_window(start: Option<u64>, end: Option<u64>, now: u128) -> bool {
+    start.is_none_or(|s| s as u128 <= now) && end.is_none_or(|e| now < e as u128)
+}
+
 fn concat_reason(reason1: String, reason2: Option<String>) -> String {
     if let Some(reason2) = reason2 {
         return format!("{reason1}. {reason2}.");
@@ -646,6 +1414,53 @@ mod tests {
         assert!(repo.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_load_from_bytes_and_readers() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let bytes = fs::read(&path).unwrap();
+        let repo = load_bytes(&bytes).unwrap();
+        assert_eq!(load_reader(fs::File::open(&path).unwrap()).unwrap(), repo);
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        assert_eq!(load_async_reader(file).await.unwrap(), repo);
+
+        assert!(load_bytes(b"{invalid_json}").is_err());
+        assert!(load_reader(&b"{invalid_json}"[..]).is_err());
+    }
+
+    #[test]
+    fn test_load_snake_case() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = load_json(&json_str).unwrap();
+        assert!(!has_legacy_keys(json_str.as_bytes()));
+
+        let mut legacy = json_str.clone();
+        for (camel, snake) in [
+            ("debugUntilTime", "debug_until_time"),
+            ("uniqueId", "unique_id"),
+            ("trackAccessEvents", "track_access_events"),
+            ("lastModified", "last_modified"),
+            ("forClient", "for_client"),
+            ("disabledServe", "disabled_serve"),
+            ("defaultServe", "default_serve"),
+            ("bucketBy", "bucket_by"),
+        ] {
+            legacy = legacy.replace(camel, snake);
+        }
+        assert!(has_legacy_keys(legacy.as_bytes()));
+        assert_eq!(load_json(&legacy).unwrap(), repo);
+
+        let split = legacy.find("disabled_serve").unwrap() + 4;
+        let (head, tail) = legacy.as_bytes().split_at(split);
+        let mut scan = LegacyScan::new(Read::chain(head, tail));
+        let mut bytes = vec![];
+        Read::read_to_end(&mut scan, &mut bytes).unwrap();
+        assert!(scan.legacy);
+        assert_eq!(load_reader(legacy.as_bytes()).unwrap(), repo);
+    }
+
     #[test]
     fn test_load_invalid_json() {
         let json_str = "{invalid_json}";
@@ -654,9 +1469,74 @@ mod tests {
     }
 
     #[test]
-    fn test_salt_hash() {
-        let bucket = salt_hash("key", "salt", 10000);
-        assert_eq!(2647, bucket);
+    fn test_bucket_of() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = load_json(&json_str).unwrap();
+        let user = FPUser::new().stable_rollout("key".to_owned());
+
+        let toggle = repo.toggles.get("json_toggle").unwrap();
+        let (bucket, index) = toggle.bucket_of(&user).unwrap();
+        assert_eq!(bucket, salt_hash("key", "some_salt", 10000));
+        assert!(index < toggle.variations.len());
+
+        let toggle = Toggle::new_for_test("t".to_owned(), Value::Bool(true));
+        assert!(toggle.bucket_of(&user).is_none());
+    }
+
+    #[test]
+    fn test_rollout_schedule() {
+        let toggle: Toggle = serde_json::from_value(serde_json::json!({
+            "key": "ramp", "enabled": true, "version": 1, "forClient": false,
+            "disabledServe": {"select": 0}, "defaultServe": {"select": 0},
+            "rules": [], "variations": [false, true],
+            "rolloutSchedule": {"variation": 1, "steps": [
+                {"time": 1000, "percentage": 0},
+                {"time": 3000, "percentage": 100},
+                {"time": 2000, "percentage": 50}
+            ]}
+        }))
+        .unwrap();
+        let schedule = toggle.rollout_schedule().unwrap();
+        assert_eq!(schedule.percentage_at(500), 0);
+        assert_eq!(schedule.percentage_at(2500), 50);
+        assert_eq!(schedule.percentage_at(3000), 100);
+
+        let ramped = |now: u128| {
+            (0..1000)
+                .filter(|i| {
+                    let user = FPUser::new().stable_rollout(format!("user{i}"));
+                    let detail = toggle.eval_at(
+                        &user,
+                        &HashMap::new(),
+                        &HashMap::new(),
+                        true,
+                        10,
+                        None,
+                        now,
+                    );
+                    detail.value == Some(Value::Bool(true))
+                })
+                .count()
+        };
+        assert_eq!(ramped(1500), 0);
+        let half = ramped(2500);
+        assert!(400 < half && half < 600, "{half}");
+        assert_eq!(ramped(3500), 1000);
+
+        let user = FPUser::new().stable_rollout("u".to_owned());
+        let detail = toggle.eval_at(
+            &user,
+            &HashMap::new(),
+            &HashMap::new(),
+            true,
+            10,
+            None,
+            3500,
+        );
+        assert_eq!(detail.reason_code, Some(ReasonCode::ScheduledRollout));
+        assert_eq!(detail.reason, "rollout schedule 100%.");
     }
 
     #[test]
@@ -676,6 +1556,27 @@ mod tests {
         assert!(r.get("variation_1").is_some());
     }
 
+    #[test]
+    fn test_segment_window() {
+        let segment: Segment = serde_json::from_value(serde_json::json!({
+            "uniqueId": "beta", "version": 1, "start": 1000, "end": 2000,
+            "rules": [
+                {"conditions": [{"type": "string", "subject": "city", "predicate": "is one of", "objects": ["4"]}]},
+                {"conditions": [{"type": "string", "subject": "city", "predicate": "is one of", "objects": ["5"]}], "end": 1500}
+            ]
+        }))
+        .unwrap();
+        let user = FPUser::new().with("city", "4");
+        assert!(!segment.contains_at(&user, 999));
+        assert!(segment.contains_at(&user, 1000));
+        assert!(!segment.contains_at(&user, 2000));
+
+        let user = FPUser::new().with("city", "5");
+        assert!(segment.contains_at(&user, 1499));
+        assert!(!segment.contains_at(&user, 1500));
+        assert!(segment.is_active_at(1500));
+    }
+
     #[test]
     fn test_not_in_segment_condition() {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -794,6 +1695,52 @@ mod tests {
         assert!(r.value.unwrap().as_object().unwrap().get("2").is_some());
     }
 
+    #[test]
+    fn test_prerequisite_trace() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = load_json(&json_str).unwrap();
+        let user = FPUser::new().with("city", "4");
+
+        let toggle = repo.toggles.get("prerequisite_toggle").unwrap();
+        let (_, trace) = toggle.eval_traced(
+            &user,
+            &repo.segments,
+            &repo.toggles,
+            false,
+            MAX_DEEP,
+            None,
+            0,
+            None,
+        );
+        let keys: Vec<&str> = trace.iter().map(|(r, _)| r.key.as_str()).collect();
+        assert_eq!(keys, vec!["bool_toggle", "string_toggle", "number_toggle"]);
+
+        let toggle = repo.toggles.get("prerequisite_toggle_not_match").unwrap();
+        let (_, trace) = toggle.eval_traced(
+            &user,
+            &repo.segments,
+            &repo.toggles,
+            false,
+            MAX_DEEP,
+            None,
+            0,
+            None,
+        );
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].1.value, Some(Value::Bool(false)));
+        assert!(!trace[0].0.passed);
+
+        let toggle = repo.toggles.get("prerequisite_toggle_not_exist").unwrap();
+        let r = toggle.eval(&user, &repo.segments, &repo.toggles, true, MAX_DEEP, None);
+        assert_eq!(r.prerequisites.len(), 1);
+        assert_eq!(r.prerequisites[0].key, "bool_toggle_not_exist");
+        assert_eq!(r.prerequisites[0].value, None);
+        let r = toggle.eval(&user, &repo.segments, &repo.toggles, false, MAX_DEEP, None);
+        assert!(r.prerequisites.is_empty());
+    }
+
     #[test]
     fn test_prerequisite_not_exist_should_return_disabled_variation() {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -848,6 +1795,42 @@ mod tests {
         assert!(r.reason.contains("depth overflow"));
     }
 
+    #[test]
+    fn test_client_repository() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let mut repo = load_json(&json_str).unwrap();
+        for t in repo.toggles.values_mut() {
+            t.for_client = t.key == "bool_toggle" || t.key == "disabled_toggle";
+        }
+
+        let client = repo.client_repository();
+        assert_eq!(client.toggles.len(), 2);
+        assert!(client.toggles.contains_key("bool_toggle"));
+        assert_eq!(client.segments.len(), 1);
+        assert_eq!(client.version, repo.version);
+    }
+
+    #[test]
+    fn test_segment_index() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let mut repo = load_json(&json_str).unwrap();
+
+        let toggles = repo.dependent_toggles("some_segment1-fjoaefjaam");
+        assert!(toggles.contains(&"bool_toggle"));
+        assert!(toggles.contains(&"not_in_segment"));
+        assert!(!toggles.contains(&"disabled_toggle"));
+        assert!(repo.dependent_toggles("none_exist_segment").is_empty());
+
+        repo.toggles.remove("bool_toggle");
+        repo.rebuild_index();
+        let toggles = repo.dependent_toggles("some_segment1-fjoaefjaam");
+        assert!(!toggles.contains(&"bool_toggle"));
+    }
+
     fn gen_users(num: usize, random: bool) -> Vec<FPUser> {
         let mut users = Vec::with_capacity(num);
         for i in 0..num {
@@ -887,6 +1870,8 @@ mod distribution_tests {
             segment_repo: &Default::default(),
             toggle_repo: &Default::default(),
             debug_until_time: None,
+            now: 0,
+            deadline: None,
         };
         let result = distribution.find_index(&params);
 
@@ -914,6 +1899,8 @@ mod distribution_tests {
             segment_repo: &Default::default(),
             toggle_repo: &Default::default(),
             debug_until_time: None,
+            now: 0,
+            deadline: None,
         };
         let result = distribution.find_index(&params);
 
@@ -927,6 +1914,8 @@ mod distribution_tests {
             segment_repo: &Default::default(),
             toggle_repo: &Default::default(),
             debug_until_time: None,
+            now: 0,
+            deadline: None,
         };
         let result = distribution.find_index(&params_no_detail);
         assert!(result.is_err());
@@ -957,6 +1946,8 @@ mod distribution_tests {
             segment_repo: &Default::default(),
             toggle_repo: &Default::default(),
             debug_until_time: None,
+            now: 0,
+            deadline: None,
         };
 
         let result = serve.select_variation(&params).expect_err("e");
@@ -990,6 +1981,22 @@ mod condition_tests {
         assert_eq!(condition.r#type, ConditionType::Unknown);
     }
 
+    #[test]
+    fn test_match_key_subject() {
+        let condition = Condition {
+            r#type: ConditionType::String,
+            subject: KEY_SUBJECT.to_owned(),
+            predicate: "is one of".to_string(),
+            objects: vec![String::from("u1")],
+            regexes: Default::default(),
+        };
+
+        let user = FPUser::new().stable_rollout("u1".to_owned());
+        assert!(condition.match_string(&user, &condition.predicate));
+        let user = FPUser::new().stable_rollout("u2".to_owned());
+        assert!(!condition.match_string(&user, &condition.predicate));
+    }
+
     #[test]
     fn test_match_is_one_of() {
         let condition = Condition {
@@ -997,6 +2004,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "is one of".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "world");
@@ -1010,6 +2018,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "is one of".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "not_in");
@@ -1024,6 +2033,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "is not one of".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new();
@@ -1038,6 +2048,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "is not any of".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "welcome");
@@ -1051,6 +2062,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "is not any of".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "not_in");
@@ -1065,6 +2077,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "ends with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "bob world");
@@ -1079,6 +2092,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "ends with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "bob");
@@ -1093,6 +2107,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not end with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "bob");
@@ -1107,6 +2122,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not end with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "bob world");
@@ -1121,6 +2137,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "starts with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "world bob");
@@ -1135,6 +2152,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "ends with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "bob");
@@ -1149,6 +2167,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not start with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "bob");
@@ -1163,6 +2182,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not start with".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "world bob");
@@ -1177,6 +2197,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "contains".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice world bob");
@@ -1191,6 +2212,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "contains".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice bob");
@@ -1205,6 +2227,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not contain".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice bob");
@@ -1219,6 +2242,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not contain".to_string(),
             objects: vec![String::from("hello"), String::from("world")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice world bob");
@@ -1233,6 +2257,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "matches regex".to_string(),
             objects: vec![String::from("hello"), String::from("world.*")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice world bob");
@@ -1247,6 +2272,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "matches regex".to_string(),
             objects: vec![String::from(r"hello\d"), String::from("world.*")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice orld bob hello3");
@@ -1261,6 +2287,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "matches regex".to_string(),
             objects: vec![String::from(r"hello\d"), String::from("world.*")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice orld bob hello");
@@ -1275,6 +2302,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "does not match regex".to_string(),
             objects: vec![String::from(r"hello\d"), String::from("world.*")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "alice orld bob hello");
@@ -1289,6 +2317,7 @@ mod condition_tests {
             subject: "name".to_string(),
             predicate: "matches regex".to_string(),
             objects: vec![String::from("\\\\\\")],
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("name", "\\\\\\");
@@ -1335,59 +2364,94 @@ mod condition_tests {
             subject: "version".to_owned(),
             objects: vec!["1.0.0".to_owned(), "2.0.0".to_owned()],
             predicate: "=".to_owned(),
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("version".to_owned(), "1.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "2.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "3.0.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = "!=".to_owned();
         let user = FPUser::new().with("version".to_owned(), "1.0.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "2.0.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "0.1.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
 
         condition.predicate = ">".to_owned();
         let user = FPUser::new().with("version".to_owned(), "2.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "3.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "0.1.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = ">=".to_owned();
         let user = FPUser::new().with("version".to_owned(), "1.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "2.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "3.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "0.1.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = "<".to_owned();
         let user = FPUser::new().with("version".to_owned(), "1.0.0".to_owned()); // < 2.0.0
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "2.0.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "3.0.0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = "<=".to_owned();
         let user = FPUser::new().with("version".to_owned(), "1.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "2.0.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("version".to_owned(), "0.1.0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
 
         let user = FPUser::new().with("version".to_owned(), "a".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
+    }
+
+    #[test]
+    fn test_loose_version_condition() {
+        let mut condition = Condition {
+            r#type: ConditionType::Version,
+            subject: "version".to_owned(),
+            objects: vec!["1.2".to_owned()],
+            predicate: "=".to_owned(),
+            regexes: Default::default(),
+        };
+        let meet = |condition: &Condition, v: &str| {
+            condition.meet(&FPUser::new().with("version", v), None, 0)
+        };
+        assert!(meet(&condition, "1.2.0"));
+        assert!(meet(&condition, "1.2.0.0"));
+        assert!(!meet(&condition, "1.2.1"));
+
+        condition.predicate = ">".to_owned();
+        assert!(meet(&condition, "1.2.0.1"));
+        assert!(meet(&condition, "1.10"));
+        assert!(!meet(&condition, "1.1.9.9"));
+
+        condition.predicate = "<".to_owned();
+        condition.objects = vec!["2.0.0.10".to_owned()];
+        assert!(meet(&condition, "2.0.0.9"));
+        assert!(!meet(&condition, "2.0.1"));
+        assert!(!meet(&condition, "1"));
+        assert!(!meet(&condition, "1.2.3.4.5"));
+        assert!(!meet(&condition, "1.2-beta"));
+
+        let json = r#"{"type":"version","subject":"v","predicate":">=","objects":["1.0"]}"#;
+        let condition: Condition = serde_json::from_str(json).unwrap();
+        assert_eq!(condition.r#type, ConditionType::Version);
     }
 
     #[test]
@@ -1397,79 +2461,214 @@ mod condition_tests {
             subject: "price".to_owned(),
             objects: vec!["10".to_owned(), "100".to_owned()],
             predicate: "=".to_owned(),
+            regexes: Default::default(),
         };
 
         let user = FPUser::new().with("price".to_owned(), "10".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "100".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = "!=".to_owned();
         let user = FPUser::new().with("price".to_owned(), "10".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "100".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "0".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
 
         condition.predicate = ">".to_owned();
         let user = FPUser::new().with("price".to_owned(), "11".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "10".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = ">=".to_owned();
         let user = FPUser::new().with("price".to_owned(), "10".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "11".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "100".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "0".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = "<".to_owned();
         let user = FPUser::new().with("price".to_owned(), "1".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "10".to_owned()); // < 100
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "100".to_owned()); // < 100
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
 
         condition.predicate = "<=".to_owned();
         let user = FPUser::new().with("price".to_owned(), "1".to_owned());
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "10".to_owned()); // < 100
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
         let user = FPUser::new().with("price".to_owned(), "100".to_owned()); // < 100
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, 0));
 
         let user = FPUser::new().with("price".to_owned(), "a".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, 0));
+    }
+
+    #[test]
+    fn test_modulo_condition() {
+        let mut condition = Condition {
+            r#type: ConditionType::Number,
+            subject: "customer".to_owned(),
+            objects: vec!["3".to_owned(), "4".to_owned()],
+            predicate: "% 10 ==".to_owned(),
+            regexes: Default::default(),
+        };
+        let meet = |condition: &Condition, v: &str| {
+            condition.meet(&FPUser::new().with("customer", v), None, 0)
+        };
+        assert!(meet(&condition, "13"));
+        assert!(meet(&condition, "1004"));
+        assert!(meet(&condition, "-7"));
+        assert!(!meet(&condition, "15"));
+        assert!(!meet(&condition, "13.5"));
+        assert!(!condition.meet(&FPUser::new(), None, 0));
+
+        condition.predicate = "% 10 !=".to_owned();
+        assert!(!meet(&condition, "13"));
+        assert!(meet(&condition, "15"));
+
+        for predicate in ["% 0 ==", "% x ==", "% 10 >", "%"] {
+            condition.predicate = predicate.to_owned();
+            assert!(!meet(&condition, "13"));
+        }
+    }
+
+    #[test]
+    fn test_exists_condition() {
+        let mut condition = Condition {
+            r#type: ConditionType::String,
+            subject: "locale".to_owned(),
+            objects: vec![],
+            predicate: "exists".to_owned(),
+            regexes: Default::default(),
+        };
+        let user = FPUser::new().with("locale", "");
+        let anonymous = FPUser::new();
+        for t in [
+            ConditionType::String,
+            ConditionType::Number,
+            ConditionType::Semver,
+            ConditionType::Datetime,
+        ] {
+            condition.r#type = t;
+            condition.predicate = "exists".to_owned();
+            assert!(condition.meet(&user, None, 0));
+            assert!(!condition.meet(&anonymous, None, 0));
+            condition.predicate = "does not exist".to_owned();
+            assert!(!condition.meet(&user, None, 0));
+            assert!(condition.meet(&anonymous, None, 0));
+        }
+    }
+
+    #[test]
+    fn test_locale_condition() {
+        let mut condition = Condition {
+            r#type: ConditionType::Locale,
+            subject: "locale".to_owned(),
+            objects: vec!["zh".to_owned(), "en-GB".to_owned()],
+            predicate: "is one of".to_owned(),
+            regexes: Default::default(),
+        };
+        let meet = |condition: &Condition, v: &str| {
+            condition.meet(&FPUser::new().with("locale", v), None, 0)
+        };
+        assert!(meet(&condition, "zh-Hans-CN"));
+        assert!(meet(&condition, "ZH_tw"));
+        assert!(meet(&condition, "en-gb"));
+        assert!(!meet(&condition, "en-US"));
+        assert!(!meet(&condition, "zha"));
+
+        condition.predicate = "is not any of".to_owned();
+        assert!(meet(&condition, "en-US"));
+        assert!(!meet(&condition, "zh"));
+
+        condition.predicate = "in region".to_owned();
+        condition.objects = vec!["cn".to_owned(), "419".to_owned()];
+        assert!(meet(&condition, "zh-Hans-CN"));
+        assert!(meet(&condition, "es-419"));
+        assert!(!meet(&condition, "zh-TW"));
+        assert!(!meet(&condition, "zh"));
+
+        condition.predicate = "not in region".to_owned();
+        assert!(meet(&condition, "zh-TW"));
+        assert!(!condition.meet(&FPUser::new(), None, 0));
     }
 
     #[test]
     fn test_datetime_condition() {
-        let now_ts = unix_timestamp() / 1000;
+        let now = unix_timestamp();
+        let now_ts = now / 1000;
         let mut condition = Condition {
             r#type: ConditionType::Datetime,
             subject: "ts".to_owned(),
             objects: vec![format!("{}", now_ts)],
             predicate: "after".to_owned(),
+            regexes: Default::default(),
         };
 
         let user = FPUser::new();
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, now));
         let user = FPUser::new().with("ts".to_owned(), format!("{}", now_ts));
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, now));
 
         condition.predicate = "before".to_owned();
         condition.objects = vec![format!("{}", now_ts + 2)];
-        assert!(condition.meet(&user, None));
+        assert!(condition.meet(&user, None, now));
 
         let user = FPUser::new().with("ts".to_owned(), "a".to_owned());
-        assert!(!condition.meet(&user, None));
+        assert!(!condition.meet(&user, None, now));
+
+        let user = FPUser::new();
+        assert!(condition.meet(&user, None, now));
+        assert!(!condition.meet(&user, None, (now_ts + 2) * 1000));
+    }
+
+    #[test]
+    fn test_eval_timeout() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = load_json(&json_str).unwrap();
+        let user = FPUser::new().with("city", "1");
+        let toggle = repo.toggles.get("bool_toggle").unwrap();
+
+        let expired = Some(Instant::now());
+        let (detail, _) = toggle.eval_traced(
+            &user,
+            &repo.segments,
+            &repo.toggles,
+            true,
+            MAX_DEEP,
+            None,
+            0,
+            expired,
+        );
+        assert_eq!(detail.reason_code, Some(ReasonCode::EvalTimeout));
+        assert_eq!(detail.rule_index, None);
+        let default = toggle.eval(&user, &repo.segments, &repo.toggles, true, MAX_DEEP, None);
+        assert_ne!(default.reason_code, Some(ReasonCode::EvalTimeout));
+
+        let r = toggle.try_eval_at(
+            &user,
+            &repo.segments,
+            &repo.toggles,
+            true,
+            MAX_DEEP,
+            None,
+            0,
+            expired,
+        );
+        assert!(matches!(r, Err(FPError::EvalTimeout)));
     }
 }
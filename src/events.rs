@@ -0,0 +1,756 @@
+use crate::clock::Clock;
+use crate::{FPError, HttpTransport, Secret};
+use async_trait::async_trait;
+use feature_probe_event::event::{Access, CountValue, Event, PackedData, ToggleCounter, Variation};
+use headers::HeaderValue;
+use http::header::{HeaderMap, HeaderName, AUTHORIZATION, USER_AGENT};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+use url::Url;
+
+// sent with every post, servers echo back the newest schema they accept
+pub const EVENT_SCHEMA_HEADER: &str = "x-fp-event-schema";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum EventSchema {
+    // a json array of packed data, understood by every events endpoint
+    Legacy = 1,
+    // batches wrapped with a schema version and recorder diagnostics
+    #[default]
+    Summary = 2,
+}
+
+impl EventSchema {
+    pub fn version(&self) -> u8 {
+        *self as u8
+    }
+
+    fn from_version(version: u8) -> Option<Self> {
+        match version {
+            1 => Some(EventSchema::Legacy),
+            2 => Some(EventSchema::Summary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EventDiagnostics {
+    pub dropped_batches: u64,
+    pub retried_batches: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SummaryPayload<'a> {
+    schema_version: u8,
+    batches: &'a VecDeque<PackedData>,
+    diagnostics: EventDiagnostics,
+}
+
+// receives every recorded event, including untracked access events, on each flush
+#[async_trait]
+pub trait EventSink: Debug + Send + Sync {
+    async fn send(&self, events: &[Event]) -> Result<(), FPError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EventEndpoints {
+    pub access: Url,
+    pub debug: Url,
+    pub custom: Url,
+}
+
+impl EventEndpoints {
+    pub fn single(url: Url) -> Self {
+        Self {
+            access: url.clone(),
+            debug: url.clone(),
+            custom: url,
+        }
+    }
+
+    fn route(&self, event: &Event) -> &Url {
+        match event {
+            Event::AccessEvent(_) => &self.access,
+            Event::DebugEvent(_) => &self.debug,
+            Event::CustomEvent(_) => &self.custom,
+        }
+    }
+
+    #[cfg(feature = "relay")]
+    // relayed events stay raw json, the untagged Event can't tell access and
+    // debug events apart, so they are routed by kind
+    pub fn route_kind(&self, kind: &str) -> &Url {
+        match kind {
+            "debug" => &self.debug,
+            "custom" => &self.custom,
+            _ => &self.access,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct EventConfig {
+    pub endpoints: EventEndpoints,
+    pub auth: HeaderValue,
+    pub user_agent: String,
+    pub transport: Arc<dyn HttpTransport>,
+    pub flush_interval: Duration,
+    pub capacity: usize,
+    pub max_payload: Option<usize>,
+    // newest schema the recorder may negotiate
+    pub schema: EventSchema,
+    // false when events only go to sinks
+    pub post_events: bool,
+    pub sinks: Vec<Arc<dyn EventSink>>,
+    pub signing_secret: Option<Secret>,
+    pub clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct EventRecorder {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: EventConfig,
+    incoming: Mutex<Vec<Event>>,
+    endpoints: Mutex<BTreeMap<Url, Endpoint>>,
+    diagnostics: Mutex<EventDiagnostics>,
+    shutdown: CancellationToken,
+}
+
+// each url keeps its own queue and negotiates its own schema
+#[derive(Debug)]
+struct Endpoint {
+    batches: VecDeque<PackedData>,
+    schema: EventSchema,
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self {
+            batches: VecDeque::new(),
+            schema: EventSchema::Legacy,
+        }
+    }
+}
+
+impl EventRecorder {
+    pub fn new(config: EventConfig, shutdown: CancellationToken) -> Self {
+        let slf = Self::idle(config, shutdown);
+        slf.start();
+        slf
+    }
+
+    // a recorder without the periodic flush task
+    fn idle(config: EventConfig, shutdown: CancellationToken) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                incoming: Default::default(),
+                endpoints: Default::default(),
+                diagnostics: Default::default(),
+                shutdown,
+            }),
+        }
+    }
+
+    pub fn record_event(&self, event: Event) {
+        self.inner.incoming.lock().push(event);
+    }
+
+    // sends pending events in the background when called inside a runtime
+    pub fn flush(&self) {
+        let inner = self.inner.clone();
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(async move { inner.flush().await });
+        }
+    }
+
+    pub async fn flush_now(&self) {
+        self.inner.flush().await
+    }
+
+    // schema of the access events endpoint
+    pub fn schema(&self) -> EventSchema {
+        let access = &self.inner.config.endpoints.access;
+        self.inner
+            .endpoints
+            .lock()
+            .get(access)
+            .map(|e| e.schema)
+            .unwrap_or(EventSchema::Legacy)
+    }
+
+    fn start(&self) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(inner.config.flush_interval);
+            loop {
+                tokio::select! {
+                    _ = inner.shutdown.cancelled() => {
+                        // last chance to send what was recorded before the stop
+                        inner.flush().await;
+                        break;
+                    }
+                    _ = interval.tick() => inner.flush().await,
+                }
+            }
+        });
+    }
+}
+
+impl Inner {
+    async fn flush(&self) {
+        let events = std::mem::take(&mut *self.incoming.lock());
+        if !events.is_empty() {
+            for sink in &self.config.sinks {
+                if let Err(e) = sink.send(&events).await {
+                    error!(?sink, error = %e, "event sink failed");
+                }
+            }
+        }
+        if !self.config.post_events {
+            return;
+        }
+
+        let mut routed: BTreeMap<&Url, Vec<Event>> = BTreeMap::new();
+        for event in events {
+            let url = self.config.endpoints.route(&event);
+            routed.entry(url).or_default().push(event);
+        }
+        for (url, events) in routed {
+            self.push_batch(url, pack(events));
+        }
+
+        let pending: Vec<(Url, VecDeque<PackedData>, EventSchema)> = self
+            .endpoints
+            .lock()
+            .iter_mut()
+            .filter(|(_, e)| !e.batches.is_empty())
+            .map(|(url, e)| (url.clone(), std::mem::take(&mut e.batches), e.schema))
+            .collect();
+        for (url, batches, schema) in pending {
+            self.post(url, batches, schema).await;
+        }
+    }
+
+    // oversized bodies are split in halves until they fit max_payload
+    async fn post(&self, url: Url, batches: VecDeque<PackedData>, schema: EventSchema) {
+        let mut groups = vec![batches];
+        let mut failed = VecDeque::new();
+        while let Some(mut group) = groups.pop() {
+            let body = match self.body(&group, schema) {
+                Some(body) => body,
+                None => continue,
+            };
+            if let Some(max) = self.config.max_payload {
+                if body.len() > max {
+                    if let Some(rest) = split_group(&mut group) {
+                        groups.push(rest);
+                        groups.push(group);
+                        continue;
+                    }
+                    warn!(%url, size = body.len(), max, "event payload can not be split further");
+                }
+            }
+            if !self.send(&url, body, schema).await {
+                failed.extend(group);
+            }
+        }
+        if !failed.is_empty() {
+            self.put_back(&url, failed);
+        }
+    }
+
+    fn body(&self, batches: &VecDeque<PackedData>, schema: EventSchema) -> Option<Vec<u8>> {
+        let body = match schema {
+            EventSchema::Legacy => serde_json::to_vec(&batches),
+            EventSchema::Summary => serde_json::to_vec(&SummaryPayload {
+                schema_version: schema.version(),
+                batches,
+                diagnostics: self.diagnostics.lock().clone(),
+            }),
+        };
+        match body {
+            Ok(body) => Some(body),
+            Err(e) => {
+                error!(error = %e, "serialize events failed");
+                None
+            }
+        }
+    }
+
+    async fn send(&self, url: &Url, body: Vec<u8>, schema: EventSchema) -> bool {
+        let result = self
+            .config
+            .transport
+            .post_json(
+                url.clone(),
+                self.headers(&body),
+                body,
+                self.config.flush_interval,
+            )
+            .await;
+        match result {
+            Ok(resp) if resp.is_success() => {
+                debug!(%url, status = resp.status, schema = ?schema, "events posted");
+                self.negotiate(url, &resp.headers);
+                true
+            }
+            Ok(resp) => {
+                error!(%url, status = resp.status, "event post rejected");
+                false
+            }
+            Err(e) => {
+                error!(%url, error = %e, "event post error");
+                false
+            }
+        }
+    }
+
+    fn headers(&self, body: &[u8]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, self.config.auth.clone());
+        if let Ok(ua) = HeaderValue::from_str(&self.config.user_agent) {
+            headers.insert(USER_AGENT, ua);
+        }
+        headers.insert(
+            HeaderName::from_static(EVENT_SCHEMA_HEADER),
+            HeaderValue::from(self.config.schema.version() as u16),
+        );
+        if let Some(secret) = &self.config.signing_secret {
+            let now = self.config.clock.now();
+            let signature = crate::signing::sign(secret.expose().as_bytes(), now, body);
+            if let Ok(signature) = HeaderValue::from_str(&signature) {
+                headers.insert(crate::signing::SIGNATURE_HEADER, signature);
+            }
+        }
+        headers
+    }
+
+    // servers without schema support don't answer the header, keep them on legacy
+    fn negotiate(&self, url: &Url, headers: &HeaderMap) {
+        let accepted = headers
+            .get(EVENT_SCHEMA_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u8>().ok())
+            .unwrap_or(EventSchema::Legacy.version());
+        let schema = (1..=accepted.min(self.config.schema.version()))
+            .rev()
+            .find_map(EventSchema::from_version)
+            .unwrap_or(EventSchema::Legacy);
+        let mut endpoints = self.endpoints.lock();
+        let endpoint = endpoints.entry(url.clone()).or_default();
+        if endpoint.schema != schema {
+            debug!(%url, schema = ?schema, "event schema negotiated");
+            endpoint.schema = schema;
+        }
+    }
+
+    fn push_batch(&self, url: &Url, batch: PackedData) {
+        let mut endpoints = self.endpoints.lock();
+        let batches = &mut endpoints.entry(url.clone()).or_default().batches;
+        if batches.len() >= self.config.capacity {
+            batches.pop_front();
+            self.diagnostics.lock().dropped_batches += 1;
+        }
+        batches.push_back(batch);
+    }
+
+    // failed batches go before the ones recorded while posting
+    fn put_back(&self, url: &Url, mut failed: VecDeque<PackedData>) {
+        let mut endpoints = self.endpoints.lock();
+        let batches = &mut endpoints.entry(url.clone()).or_default().batches;
+        self.diagnostics.lock().retried_batches += failed.len() as u64;
+        failed.append(batches);
+        while failed.len() > self.config.capacity {
+            failed.pop_front();
+            self.diagnostics.lock().dropped_batches += 1;
+        }
+        *batches = failed;
+    }
+}
+
+// leaves the first half in group and returns the second
+fn split_group(group: &mut VecDeque<PackedData>) -> Option<VecDeque<PackedData>> {
+    match group.len() {
+        0 => None,
+        1 => split_batch(&mut group[0]).map(|rest| VecDeque::from([rest])),
+        n => Some(group.split_off(n / 2)),
+    }
+}
+
+// splits counters by toggle and events in halves, both keep the access window
+fn split_batch(batch: &mut PackedData) -> Option<PackedData> {
+    if batch.access.counters.len() < 2 && batch.events.len() < 2 {
+        return None;
+    }
+    let mut keys: Vec<String> = batch.access.counters.keys().cloned().collect();
+    keys.sort();
+    let counters = keys[keys.len() / 2..]
+        .iter()
+        .filter_map(|k| batch.access.counters.remove_entry(k))
+        .collect();
+    Some(PackedData {
+        events: batch.events.split_off(batch.events.len() / 2),
+        access: Access {
+            start_time: batch.access.start_time,
+            end_time: batch.access.end_time,
+            counters,
+        },
+    })
+}
+
+fn pack(events: Vec<Event>) -> PackedData {
+    let access = build_access(&events);
+    let events = events
+        .into_iter()
+        .filter(|e| match e {
+            Event::AccessEvent(access) => access.track_access_events,
+            _ => true,
+        })
+        .collect();
+    PackedData { events, access }
+}
+
+fn build_access(events: &[Event]) -> Access {
+    let mut start_time = u128::MAX;
+    let mut end_time = 0;
+    let mut counters: HashMap<Variation, CountValue> = HashMap::new();
+
+    for e in events {
+        if let Event::AccessEvent(access_event) = e {
+            start_time = start_time.min(access_event.time);
+            end_time = end_time.max(access_event.time);
+            let variation = Variation {
+                key: access_event.key.clone(),
+                version: access_event.version,
+                index: access_event.variation_index,
+            };
+            let count_value = counters.entry(variation).or_insert(CountValue {
+                count: 0,
+                value: access_event.value.clone(),
+            });
+            count_value.count += 1;
+        }
+    }
+
+    let mut access = Access {
+        start_time,
+        end_time,
+        counters: Default::default(),
+    };
+    for (k, v) in counters {
+        access
+            .counters
+            .entry(k.key)
+            .or_default()
+            .push(ToggleCounter {
+                index: k.index,
+                version: k.version,
+                value: v.value,
+                count: v.count,
+            });
+    }
+    access
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HttpResponse;
+    use async_trait::async_trait;
+    use feature_probe_event::event::{AccessEvent, CustomEvent};
+    use serde_json::{json, Value};
+
+    #[derive(Debug, Default)]
+    struct Server {
+        accept: Option<u8>,
+        fail: bool,
+        posts: Mutex<Vec<(Url, HeaderMap, Value)>>,
+        bodies: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl HttpTransport for Server {
+        async fn get(
+            &self,
+            _url: Url,
+            _headers: HeaderMap,
+            _timeout: Duration,
+        ) -> Result<HttpResponse, FPError> {
+            Err(FPError::HttpError("events only".to_owned()))
+        }
+
+        async fn post_json(
+            &self,
+            url: Url,
+            headers: HeaderMap,
+            body: Vec<u8>,
+            _timeout: Duration,
+        ) -> Result<HttpResponse, FPError> {
+            self.bodies.lock().push(body.clone());
+            let body = serde_json::from_slice(&body).unwrap();
+            self.posts.lock().push((url, headers, body));
+            if self.fail {
+                return Err(FPError::HttpError("down".to_owned()));
+            }
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = self.accept {
+                headers.insert(EVENT_SCHEMA_HEADER, HeaderValue::from(accept as u16));
+            }
+            Ok(HttpResponse {
+                status: 200,
+                headers,
+                body: vec![],
+            })
+        }
+    }
+
+    fn recorder(server: Arc<Server>, schema: EventSchema) -> EventRecorder {
+        EventRecorder::idle(config(server, schema), Default::default())
+    }
+
+    fn config(server: Arc<Server>, schema: EventSchema) -> EventConfig {
+        EventConfig {
+            endpoints: EventEndpoints::single("http://127.0.0.1/api/events".parse().unwrap()),
+            auth: HeaderValue::from_static("sdk-key"),
+            user_agent: "Rust/test".to_owned(),
+            transport: server,
+            flush_interval: Duration::from_secs(1),
+            capacity: 2,
+            max_payload: None,
+            schema,
+            post_events: true,
+            sinks: vec![],
+            signing_secret: None,
+            clock: Arc::new(crate::clock::SystemClock),
+        }
+    }
+
+    fn access(key: &str, index: usize, track: bool) -> Event {
+        Event::AccessEvent(AccessEvent {
+            kind: "access".to_owned(),
+            time: 10,
+            key: key.to_owned(),
+            user: "u".to_owned(),
+            value: json!(index),
+            variation_index: index,
+            version: Some(1),
+            rule_index: None,
+            track_access_events: track,
+        })
+    }
+
+    #[test]
+    fn test_pack_counts_access() {
+        let packed = pack(vec![
+            access("a", 0, false),
+            access("a", 0, true),
+            access("a", 1, false),
+            Event::CustomEvent(CustomEvent {
+                kind: "custom".to_owned(),
+                time: 12,
+                user: "u".to_owned(),
+                name: "buy".to_owned(),
+                value: None,
+            }),
+        ]);
+        assert_eq!(packed.events.len(), 2);
+        let mut counts: Vec<(usize, u128)> = packed.access.counters["a"]
+            .iter()
+            .map(|c| (c.index, c.count))
+            .collect();
+        counts.sort();
+        assert_eq!(counts, vec![(0, 2), (1, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_schema_negotiation() {
+        let server = Arc::new(Server {
+            accept: Some(2),
+            ..Default::default()
+        });
+        let recorder = recorder(server.clone(), EventSchema::Summary);
+        assert_eq!(recorder.schema(), EventSchema::Legacy);
+
+        recorder.record_event(access("a", 0, false));
+        recorder.flush_now().await;
+        recorder.record_event(access("a", 0, false));
+        recorder.flush_now().await;
+
+        let posts = server.posts.lock();
+        assert_eq!(posts[0].1.get(EVENT_SCHEMA_HEADER).unwrap(), "2");
+        assert!(posts[0].2.is_array());
+        assert_eq!(posts[1].2["schemaVersion"], 2);
+        assert_eq!(posts[1].2["batches"].as_array().unwrap().len(), 1);
+        assert_eq!(recorder.schema(), EventSchema::Summary);
+    }
+
+    #[tokio::test]
+    async fn test_schema_fallback() {
+        let server = Arc::new(Server::default());
+        let recorder = recorder(server.clone(), EventSchema::Summary);
+        recorder.record_event(access("a", 0, false));
+        recorder.flush_now().await;
+        assert_eq!(recorder.schema(), EventSchema::Legacy);
+
+        // a server newer than the sdk still gets the newest shape the sdk knows
+        let server = Arc::new(Server {
+            accept: Some(9),
+            ..Default::default()
+        });
+        let recorder = self::recorder(server, EventSchema::Legacy);
+        recorder.record_event(access("a", 0, false));
+        recorder.flush_now().await;
+        assert_eq!(recorder.schema(), EventSchema::Legacy);
+    }
+
+    #[tokio::test]
+    async fn test_failed_batches_retried() {
+        let server = Arc::new(Server {
+            fail: true,
+            ..Default::default()
+        });
+        let recorder = recorder(server.clone(), EventSchema::Summary);
+        for _ in 0..3 {
+            recorder.record_event(access("a", 0, false));
+            recorder.flush_now().await;
+        }
+        let posts = server.posts.lock();
+        assert_eq!(posts.len(), 3);
+        assert_eq!(posts[2].2.as_array().unwrap().len(), 2);
+        let endpoints = recorder.inner.endpoints.lock();
+        assert_eq!(endpoints.values().next().unwrap().batches.len(), 2);
+        assert_eq!(recorder.inner.diagnostics.lock().dropped_batches, 1);
+    }
+
+    #[tokio::test]
+    async fn test_split_endpoints() {
+        let server = Arc::new(Server::default());
+        let mut config = config(server.clone(), EventSchema::Legacy);
+        config.endpoints.debug = "http://127.0.0.1/api/debug".parse().unwrap();
+        config.endpoints.custom = "http://127.0.0.1/api/custom".parse().unwrap();
+        let recorder = EventRecorder::idle(config, Default::default());
+        recorder.record_event(access("a", 0, false));
+        recorder.record_event(Event::CustomEvent(CustomEvent {
+            kind: "custom".to_owned(),
+            time: 12,
+            user: "u".to_owned(),
+            name: "buy".to_owned(),
+            value: None,
+        }));
+        recorder.flush_now().await;
+
+        let posts = server.posts.lock();
+        let paths: Vec<&str> = posts.iter().map(|p| p.0.path()).collect();
+        assert_eq!(paths, vec!["/api/custom", "/api/events"]);
+        assert_eq!(posts[0].2[0]["events"][0]["name"], "buy");
+        assert_eq!(posts[1].2[0]["access"]["counters"]["a"][0]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_signed_posts() {
+        let server = Arc::new(Server::default());
+        let mut config = config(server.clone(), EventSchema::Legacy);
+        config.signing_secret = Some("signing".into());
+        config.clock = Arc::new(crate::MockClock::new(42));
+        let recorder = EventRecorder::idle(config, Default::default());
+        recorder.record_event(access("a", 0, false));
+        recorder.flush_now().await;
+
+        let posts = server.posts.lock();
+        let header = posts[0].1[crate::signing::SIGNATURE_HEADER]
+            .to_str()
+            .unwrap();
+        assert!(header.starts_with("t=42,"));
+        let body = &server.bodies.lock()[0];
+        assert!(crate::signing::verify(b"signing", header, body));
+        assert!(!crate::signing::verify(b"other", header, body));
+    }
+
+    #[tokio::test]
+    async fn test_max_payload_split() {
+        let server = Arc::new(Server::default());
+        let mut config = config(server.clone(), EventSchema::Legacy);
+        config.max_payload = Some(400);
+        let recorder = EventRecorder::idle(config, Default::default());
+        for i in 0..6 {
+            recorder.record_event(access(&format!("toggle_{i}"), 0, true));
+        }
+        recorder.flush_now().await;
+
+        let posts = server.posts.lock();
+        assert!(posts.len() > 1);
+        let mut counted = 0;
+        let mut tracked = 0;
+        for (_, _, body) in posts.iter() {
+            assert!(serde_json::to_vec(body).unwrap().len() <= 400);
+            let batch = &body[0];
+            counted += batch["access"]["counters"].as_object().unwrap().len();
+            tracked += batch["events"].as_array().unwrap().len();
+        }
+        assert_eq!((counted, tracked), (6, 6));
+    }
+
+    #[derive(Debug, Default)]
+    struct Collect(Mutex<Vec<Event>>);
+
+    #[async_trait]
+    impl EventSink for Collect {
+        async fn send(&self, events: &[Event]) -> Result<(), FPError> {
+            self.0.lock().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sink_without_post() {
+        let server = Arc::new(Server::default());
+        let sink = Arc::new(Collect::default());
+        let mut config = config(server.clone(), EventSchema::Legacy);
+        config.post_events = false;
+        config.sinks = vec![sink.clone()];
+        let recorder = EventRecorder::idle(config, Default::default());
+        recorder.record_event(access("a", 0, false));
+        recorder.flush_now().await;
+
+        assert_eq!(sink.0.lock().len(), 1);
+        assert!(server.posts.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_cancel() {
+        let sink = Arc::new(Collect::default());
+        let mut config = config(Arc::new(Server::default()), EventSchema::Legacy);
+        config.flush_interval = Duration::from_secs(60);
+        config.post_events = false;
+        config.sinks = vec![sink.clone()];
+        let shutdown = CancellationToken::new();
+        let recorder = EventRecorder::new(config, shutdown.clone());
+        tokio::task::yield_now().await;
+
+        recorder.record_event(access("a", 0, false));
+        shutdown.cancel();
+        for _ in 0..100 {
+            if !sink.0.lock().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(sink.0.lock().len(), 1);
+    }
+}
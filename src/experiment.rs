@@ -0,0 +1,126 @@
+use crate::{FPError, FPUser, FeatureProbe};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+const DEFAULT_CAPACITY: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Exposure {
+    pub exposure_id: String,
+    pub toggle: String,
+    pub user: String,
+    pub value: Value,
+    pub variation_index: Option<usize>,
+    pub version: Option<u64>,
+    pub time: u128,
+}
+
+#[derive(Debug, Default)]
+struct Exposures {
+    capacity: usize,
+    by_id: HashMap<String, Exposure>,
+    order: VecDeque<String>,
+}
+
+// event payloads carry no correlation field, so exposure ids are resolved
+// locally and metrics are tracked against the exposed user
+#[derive(Debug, Clone)]
+pub struct ExperimentClient {
+    fp: FeatureProbe,
+    exposures: Arc<RwLock<Exposures>>,
+}
+
+impl ExperimentClient {
+    pub fn new(fp: FeatureProbe) -> Self {
+        Self::with_capacity(fp, DEFAULT_CAPACITY)
+    }
+
+    // oldest exposures are forgotten once capacity is reached
+    pub fn with_capacity(fp: FeatureProbe, capacity: usize) -> Self {
+        Self {
+            fp,
+            exposures: Arc::new(RwLock::new(Exposures {
+                capacity: capacity.max(1),
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn exposure(&self, toggle: &str, user: &FPUser, default: Value) -> Exposure {
+        let detail = self.fp.json_detail(toggle, user, default);
+        let exposure = Exposure {
+            exposure_id: uuid::Uuid::new_v4().to_string(),
+            toggle: toggle.to_owned(),
+            user: user.key_with(&*self.fp.config.key_generator),
+            value: detail.value,
+            variation_index: detail.variation_index,
+            version: detail.version,
+            time: self.fp.config.clock.now(),
+        };
+
+        let mut exposures = self.exposures.write();
+        if exposures.order.len() >= exposures.capacity {
+            if let Some(oldest) = exposures.order.pop_front() {
+                exposures.by_id.remove(&oldest);
+            }
+        }
+        exposures.order.push_back(exposure.exposure_id.clone());
+        exposures
+            .by_id
+            .insert(exposure.exposure_id.clone(), exposure.clone());
+        exposure
+    }
+
+    pub fn get_exposure(&self, exposure_id: &str) -> Option<Exposure> {
+        self.exposures.read().by_id.get(exposure_id).cloned()
+    }
+
+    pub fn track_with_exposure(
+        &self,
+        event_name: &str,
+        exposure_id: &str,
+        value: Option<f64>,
+    ) -> Result<Exposure, FPError> {
+        let exposure = self
+            .get_exposure(exposure_id)
+            .ok_or_else(|| FPError::InternalError(format!("exposure {exposure_id} not found")))?;
+        let user = FPUser::new().stable_rollout(exposure.user.clone());
+        self.fp.track(event_name, &user, value);
+        Ok(exposure)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_exposure() {
+        let fp = FeatureProbe::new_for_test("toggle_1", json!("a"));
+        let client = ExperimentClient::with_capacity(fp, 2);
+        let u = FPUser::new().stable_rollout("u1".to_owned());
+        let e1 = client.exposure("toggle_1", &u, json!("b"));
+        assert_eq!(e1.value, json!("a"));
+        assert_eq!(e1.user, "u1");
+        assert_eq!(e1.variation_index, Some(0));
+        assert_eq!(client.get_exposure(&e1.exposure_id), Some(e1.clone()));
+
+        let e2 = client.exposure("toggle_1", &u, json!("b"));
+        assert_ne!(e1.exposure_id, e2.exposure_id);
+        let tracked = client
+            .track_with_exposure("purchase", &e2.exposure_id, Some(1.0))
+            .unwrap();
+        assert_eq!(tracked, e2);
+
+        client.exposure("not_exist", &u, json!("b"));
+        assert!(client.get_exposure(&e1.exposure_id).is_none());
+        assert!(client
+            .track_with_exposure("purchase", &e1.exposure_id, None)
+            .is_err());
+    }
+}
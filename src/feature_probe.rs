@@ -1,41 +1,112 @@
+use crate::audit::AuditCallback;
+use crate::breaker::CircuitBreakers;
+use crate::clock::Clock;
+use crate::composition::RepoComposition;
+use crate::events::{EventConfig, EventRecorder, EventSchema};
+use crate::golden::{GoldenRecorder, GoldenReport};
+use crate::limits::{EvalRates, SoftLimitBreach};
+use crate::shadow::{Shadow, ShadowCallback, ShadowStats};
+use crate::stats::{MissingToggle, MissingToggles, WarmUpReport};
 use crate::sync::SyncType;
+#[cfg(feature = "realtime")]
+use crate::sync::WeakSynchronizer;
+use crate::usage::{UsageReport, UsageTracker};
+use crate::validation::{ReportSlot, ValidationReport};
+use crate::value_type::parse_duration;
+use crate::warning::{warn_throttled, WarningKind, WarningThrottle};
+#[cfg(feature = "realtime")]
+use crate::Secret;
 use crate::{
     config::Config,
-    evaluate::{EvalDetail, Repository},
+    evaluate::{load_json, EvalDetail, PrerequisiteResult, Repository},
+};
+use crate::{sync::Synchronizer, FPConfig, InitPolicy, StalePolicy};
+use crate::{sync::UpdateCallback, user::FPUser, user::KeyGenerator};
+use crate::{FPDetail, FPError, SdkAuthorization, Snapshot, Toggle, WarningCount};
+use crate::{
+    ReasonCode, ScopedEvaluator, Simulation, StickyAssignment, StickyBucketStore, TypeMismatch,
+    ValueType,
 };
-use crate::{sync::Synchronizer, FPConfig};
-use crate::{sync::UpdateCallback, user::FPUser};
-use crate::{FPDetail, SdkAuthorization, Toggle};
 use event::event::AccessEvent;
 use event::event::CustomEvent;
 use event::event::DebugEvent;
 use event::event::Event;
-use event::recorder::unix_timestamp;
-use event::recorder::EventRecorder;
 use feature_probe_event as event;
 #[cfg(feature = "realtime")]
 use futures_util::FutureExt;
+#[cfg(feature = "realtime")]
+use parking_lot::Mutex;
 use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 #[cfg(feature = "realtime")]
 use socketio_rs::Client;
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
-use tracing::{trace, warn};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, field, info, trace, trace_span, warn};
+
+const BATCH_CHUNK_SIZE: usize = 1024;
 
 #[cfg(feature = "realtime")]
 type SocketCallback = std::pin::Pin<Box<dyn futures_util::Future<Output = ()> + Send>>;
 
+#[derive(Debug, Default)]
+struct KillSwitch {
+    all: bool,
+    toggles: HashSet<String>,
+}
+
 #[derive(Default, Clone)]
 pub struct FeatureProbe {
-    repo: Arc<RwLock<Repository>>,
+    pub(crate) repo: Arc<RwLock<Repository>>,
     syncer: Option<Synchronizer>,
-    event_recorder: Option<EventRecorder>,
-    config: Config,
-    should_stop: Arc<RwLock<bool>>,
+    pub(crate) event_recorder: Option<EventRecorder>,
+    pub(crate) config: Config,
+    cancellation: CancellationToken,
+    pub(crate) last_synced: Arc<RwLock<Option<u128>>>,
+    overrides: Arc<RwLock<HashMap<String, Value>>>,
+    snapshot: Arc<RwLock<Option<Arc<Repository>>>>,
+    defaults: Arc<RwLock<HashMap<String, Value>>>,
+    expected_types: Arc<RwLock<HashMap<String, ValueType>>>,
+    reported_mismatches: Arc<RwLock<HashSet<(String, u64)>>>,
+    missing_toggles: Arc<MissingToggles>,
+    usage: Arc<UsageTracker>,
+    retired_requested: Arc<RwLock<BTreeSet<String>>>,
+    breakers: Arc<CircuitBreakers>,
+    eval_rates: Arc<EvalRates>,
+    validation: ReportSlot,
+    kill_switch: Arc<RwLock<KillSwitch>>,
+    shadow: Arc<Shadow>,
+    warnings: Arc<WarningThrottle>,
+    golden: Arc<RwLock<Option<GoldenRecorder>>>,
+    close_guard: Option<Arc<CloseGuard>>,
     #[cfg(feature = "realtime")]
-    socket: Option<Client>,
+    socket: Arc<Mutex<Option<Client>>>,
+}
+
+// Shared by every clone of a started client, closes it when the last one is dropped.
+#[derive(Debug)]
+struct CloseGuard {
+    cancellation: CancellationToken,
+    event_recorder: Option<EventRecorder>,
+}
+
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        trace!("last featureprobe client dropped, closing");
+        if let Some(recorder) = &self.event_recorder {
+            recorder.flush();
+        }
+        self.cancellation.cancel();
+    }
 }
 
 impl Debug for FeatureProbe {
@@ -44,23 +115,60 @@ impl Debug for FeatureProbe {
             .field(&self.repo)
             .field(&self.syncer)
             .field(&self.config)
-            .field(&self.should_stop)
+            .field(&self.cancellation)
             .finish()
     }
 }
 
 impl FeatureProbe {
     pub fn new(config: FPConfig) -> Self {
-        let config = config.build();
+        let config = match config.build() {
+            Ok(config) => config,
+            Err(e) => {
+                // serve defaults rather than taking down the host
+                error!("invalid config, featureprobe client not started: {}", e);
+                return Self::default();
+            }
+        };
         let mut slf = Self {
+            warnings: Arc::new(WarningThrottle::new(
+                config.warning_interval,
+                config.clock.clone(),
+            )),
             config,
             ..Default::default()
         };
 
         slf.start();
+        if matches!(slf.config.init_policy, InitPolicy::RequireSuccess(_)) && !slf.initialized() {
+            error!("featureprobe client not initialized, use try_new to fail instead");
+        }
         slf
     }
 
+    // fails instead of serving defaults when the first sync does not finish
+    // within the init policy wait, the client is closed before returning the error
+    pub fn try_new(config: FPConfig) -> Result<Self, FPError> {
+        let config = config.build()?;
+        let timeout = config.init_policy.wait().ok_or_else(|| {
+            FPError::InternalError("try_new requires an init policy that waits".to_owned())
+        })?;
+        let mut slf = Self {
+            warnings: Arc::new(WarningThrottle::new(
+                config.warning_interval,
+                config.clock.clone(),
+            )),
+            config,
+            ..Default::default()
+        };
+        slf.start();
+        if !slf.initialized() {
+            slf.close();
+            return Err(FPError::InitTimeout(timeout));
+        }
+        Ok(slf)
+    }
+
     pub fn new_for_test(toggle: &str, value: Value) -> Self {
         let mut toggles = HashMap::new();
         toggles.insert(toggle.to_owned(), value);
@@ -73,6 +181,7 @@ impl FeatureProbe {
             repo.toggles
                 .insert(key.clone(), Toggle::new_for_test(key, val));
         }
+        repo.rebuild_index();
 
         Self {
             repo: Arc::new(RwLock::new(repo)),
@@ -81,322 +190,1732 @@ impl FeatureProbe {
     }
 
     pub fn bool_value(&self, toggle: &str, user: &FPUser, default: bool) -> bool {
-        self.generic_eval(toggle, user, default, false, |v| v.as_bool())
-            .value
+        self.generic_eval(toggle, user, default, false, ValueType::Bool, |v| {
+            v.as_bool()
+        })
+        .value
+    }
+
+    // false for missing toggles and toggles serving anything but a boolean
+    pub fn is_enabled(&self, toggle: &str, user: &FPUser) -> bool {
+        self.bool_value(toggle, user, false)
+    }
+
+    // sorted keys of every toggle serving true to the user, no events are recorded
+    pub fn enabled_toggles(&self, user: &FPUser) -> Vec<String> {
+        user.key_with(&*self.config.key_generator);
+        let repo = self.repo.read();
+        let now = self.config.clock.now();
+        let mut enabled: Vec<String> = repo
+            .toggles
+            .iter()
+            .filter(|(key, t)| {
+                let detail = match self.eval_override(key) {
+                    Some(d) => d,
+                    None => self.eval_toggle(t, &repo, user, false, now),
+                };
+                detail.value == Some(Value::Bool(true))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        enabled.sort();
+        enabled
     }
 
     pub fn string_value(&self, toggle: &str, user: &FPUser, default: String) -> String {
-        self.generic_eval(toggle, user, default, false, |v| {
+        self.generic_eval(toggle, user, default, false, ValueType::String, |v| {
             v.as_str().map(|s| s.to_owned())
         })
         .value
     }
 
     pub fn number_value(&self, toggle: &str, user: &FPUser, default: f64) -> f64 {
-        self.generic_eval(toggle, user, default, false, |v| v.as_f64())
-            .value
+        self.generic_eval(toggle, user, default, false, ValueType::Number, |v| {
+            v.as_f64()
+        })
+        .value
     }
 
     pub fn json_value(&self, toggle: &str, user: &FPUser, default: Value) -> Value {
-        self.generic_eval(toggle, user, default, false, Some).value
+        self.generic_eval(toggle, user, default, false, ValueType::Json, Some)
+            .value
     }
 
-    pub fn bool_detail(&self, toggle: &str, user: &FPUser, default: bool) -> FPDetail<bool> {
-        self.generic_eval(toggle, user, default, true, |v| v.as_bool())
+    pub fn register_default(&self, toggle: &str, default: Value) {
+        self.expect_type(toggle, ValueType::of(&default));
+        let mut defaults = self.defaults.write();
+        defaults.insert(toggle.to_owned(), default);
     }
 
-    pub fn string_detail(&self, toggle: &str, user: &FPUser, default: String) -> FPDetail<String> {
-        self.generic_eval(toggle, user, default, true, |v| {
-            v.as_str().map(|x| x.to_owned())
-        })
+    pub fn register_defaults<I: IntoIterator<Item = (String, Value)>>(&self, defaults: I) {
+        for (toggle, default) in defaults {
+            self.register_default(&toggle, default);
+        }
     }
 
-    pub fn number_detail(&self, toggle: &str, user: &FPUser, default: f64) -> FPDetail<f64> {
-        self.generic_eval(toggle, user, default, true, |v| v.as_f64())
+    pub fn expect_type(&self, toggle: &str, value_type: ValueType) {
+        let mut expected_types = self.expected_types.write();
+        expected_types.insert(toggle.to_owned(), value_type);
     }
 
-    pub fn json_detail(&self, toggle: &str, user: &FPUser, default: Value) -> FPDetail<Value> {
-        self.generic_eval(toggle, user, default, true, Some)
+    pub fn type_mismatches(&self) -> Vec<TypeMismatch> {
+        let expected_types = self.expected_types.read().clone();
+        let repo = self.repo.read();
+        let mut mismatches: Vec<TypeMismatch> = expected_types
+            .into_iter()
+            .filter_map(|(key, expected)| {
+                let toggle = repo.toggles.get(&key)?;
+                let found = toggle.variations.iter().find(|v| !expected.accepts(v))?;
+                Some(TypeMismatch {
+                    toggle: key,
+                    version: toggle.version,
+                    expected,
+                    found: ValueType::of(found),
+                })
+            })
+            .collect();
+        mismatches.sort_by(|a, b| a.toggle.cmp(&b.toggle));
+        for m in &mismatches {
+            self.report_mismatch(m);
+        }
+        mismatches
     }
 
-    pub fn track(&self, event_name: &str, user: &FPUser, value: Option<f64>) {
-        let recorder = match self.event_recorder.as_ref() {
-            None => {
-                warn!("Event Recorder no ready.");
-                return;
-            }
-            Some(recorder) => recorder,
-        };
-        let event = CustomEvent {
-            kind: "custom".to_string(),
-            time: unix_timestamp(),
-            user: user.key(),
-            name: event_name.to_string(),
-            value,
-        };
-        recorder.record_event(Event::CustomEvent(event));
+    fn report_mismatch(&self, mismatch: &TypeMismatch) {
+        let key = (mismatch.toggle.clone(), mismatch.version);
+        if self.reported_mismatches.read().contains(&key) {
+            return;
+        }
+        if self.reported_mismatches.write().insert(key) {
+            warn!(toggle = %mismatch.toggle, version = mismatch.version, "type mismatch: {}", mismatch);
+        }
     }
 
-    pub fn new_with(server_key: String, repo: Repository) -> Self {
-        Self {
-            config: Config {
-                server_sdk_key: server_key,
-                ..Default::default()
-            },
-            repo: Arc::new(RwLock::new(repo)),
-            syncer: None,
-            event_recorder: None,
-            should_stop: Arc::new(RwLock::new(false)),
-            #[cfg(feature = "realtime")]
-            socket: None,
+    // warns once per archived or deprecated toggle, listing all seen so far
+    fn report_retired(&self, toggle: &Toggle) {
+        if self.retired_requested.read().contains(&toggle.key) {
+            return;
+        }
+        let mut requested = self.retired_requested.write();
+        if requested.insert(toggle.key.clone()) {
+            let keys: Vec<&str> = requested.iter().map(|k| k.as_str()).collect();
+            warn!(
+                toggle = %toggle.key,
+                "archived or deprecated toggles still requested: {}",
+                keys.join(", ")
+            );
         }
     }
 
-    pub fn close(&self) {
-        trace!("closing featureprobe client");
-        if let Some(recorder) = &self.event_recorder {
-            recorder.flush();
-        }
-        let mut should_stop = self.should_stop.write();
-        *should_stop = true;
+    pub fn retired_toggles_requested(&self) -> Vec<String> {
+        self.retired_requested.read().iter().cloned().collect()
     }
 
-    pub fn initialized(&self) -> bool {
-        match &self.syncer {
-            Some(s) => s.initialized(),
-            None => false,
-        }
+    pub fn registered_default(&self, toggle: &str) -> Option<Value> {
+        let defaults = self.defaults.read();
+        defaults.get(toggle).cloned()
     }
 
-    pub fn set_update_callback(&mut self, update_callback: UpdateCallback) {
-        if let Some(syncer) = &mut self.syncer {
-            syncer.set_update_callback(update_callback)
-        }
+    pub fn registered_bool_value(&self, toggle: &str, user: &FPUser) -> bool {
+        let default = self.registered_or_default(toggle, |v| v.as_bool());
+        self.bool_value(toggle, user, default)
     }
 
-    pub fn version(&self) -> Option<u128> {
-        self.syncer.as_ref().map(|s| s.version()).flatten()
+    pub fn registered_string_value(&self, toggle: &str, user: &FPUser) -> String {
+        let default = self.registered_or_default(toggle, |v| v.as_str().map(|s| s.to_owned()));
+        self.string_value(toggle, user, default)
     }
 
-    fn generic_eval<T: Default + Debug>(
+    pub fn registered_number_value(&self, toggle: &str, user: &FPUser) -> f64 {
+        let default = self.registered_or_default(toggle, |v| v.as_f64());
+        self.number_value(toggle, user, default)
+    }
+
+    pub fn registered_json_value(&self, toggle: &str, user: &FPUser) -> Value {
+        let default = self.registered_or_default(toggle, Some);
+        self.json_value(toggle, user, default)
+    }
+
+    fn registered_or_default<T: Default>(
         &self,
         toggle: &str,
-        user: &FPUser,
-        default: T,
-        is_detail: bool,
         transform: fn(Value) -> Option<T>,
-    ) -> FPDetail<T> {
-        let (value, reason, detail) = match self.eval(toggle, user, is_detail) {
-            None => (
-                default,
-                Some(format!("Toggle:[{toggle}] not exist")),
-                Default::default(),
-            ),
-            Some(mut d) => match d.value.take() {
-                None => (default, None, d), // Serve error.
-                Some(v) => match transform(v) {
-                    None => (default, Some("Value type mismatch.".to_string()), d), // Transform error.
-                    Some(typed_v) => (typed_v, None, d),
-                },
-            },
-        };
-
-        FPDetail {
-            value,
-            reason: reason.unwrap_or(detail.reason),
-            rule_index: detail.rule_index,
-            variation_index: detail.variation_index,
-            version: detail.version,
+    ) -> T {
+        match self.registered_default(toggle) {
+            Some(v) => transform(v).unwrap_or_else(|| {
+                warn!(toggle, "registered default has unexpected type");
+                T::default()
+            }),
+            None => {
+                warn!(toggle, "no default registered");
+                T::default()
+            }
         }
     }
 
-    fn eval(&self, toggle: &str, user: &FPUser, is_detail: bool) -> Option<EvalDetail<Value>> {
-        let repo = self.repo.read();
-        let debug_until_time = repo.debug_until_time;
-        let detail = repo.toggles.get(toggle).map(|toggle| {
-            toggle.eval(
-                user,
-                &repo.segments,
-                &repo.toggles,
-                is_detail,
-                self.config.max_prerequisites_deep,
-                debug_until_time,
-            )
-        });
+    pub fn bool_detail(&self, toggle: &str, user: &FPUser, default: bool) -> FPDetail<bool> {
+        self.generic_eval(toggle, user, default, true, ValueType::Bool, |v| {
+            v.as_bool()
+        })
+    }
 
-        if let Some(recorder) = &self.event_recorder {
-            let track_access_events = repo
-                .toggles
-                .get(toggle)
-                .map(|t| t.track_access_events())
-                .unwrap_or(false);
-            record_event(
-                recorder.clone(),
-                track_access_events,
-                toggle,
-                user,
-                detail.clone(),
-                debug_until_time,
-            )
-        }
+    pub fn string_detail(&self, toggle: &str, user: &FPUser, default: String) -> FPDetail<String> {
+        self.generic_eval(toggle, user, default, true, ValueType::String, |v| {
+            v.as_str().map(|x| x.to_owned())
+        })
+    }
 
-        detail.map(|mut d| {
-            d.debug_until_time = debug_until_time;
-            d
+    pub fn number_detail(&self, toggle: &str, user: &FPUser, default: f64) -> FPDetail<f64> {
+        self.generic_eval(toggle, user, default, true, ValueType::Number, |v| {
+            v.as_f64()
         })
     }
 
-    fn start(&mut self) {
-        self.sync();
+    pub fn json_detail(&self, toggle: &str, user: &FPUser, default: Value) -> FPDetail<Value> {
+        self.generic_eval(toggle, user, default, true, ValueType::Json, Some)
+    }
 
-        #[cfg(feature = "realtime")]
-        self.connect_socket();
+    // keeps track_access_events, debug_until_time and last_modified, None for missing toggles
+    pub fn json_detail_raw(&self, toggle: &str, user: &FPUser) -> Option<EvalDetail<Value>> {
+        self.eval(toggle, user, true)
+    }
 
-        if self.config.track_events {
-            self.flush_events();
-        }
+    // string variations like "500ms" or "2s", see value_type::parse_duration
+    pub fn duration_value(&self, toggle: &str, user: &FPUser, default: Duration) -> Duration {
+        self.parsed_eval(toggle, user, default, false, parse_duration)
+            .value
     }
 
-    fn sync(&mut self) {
-        trace!("sync url {}", &self.config.toggles_url);
-        let toggles_url = self.config.toggles_url.clone();
-        let refresh_interval = self.config.refresh_interval;
-        let auth = SdkAuthorization(self.config.server_sdk_key.clone()).encode();
-        let repo = self.repo.clone();
-        let syncer = Synchronizer::new(
-            toggles_url,
-            refresh_interval,
-            auth,
-            self.config.http_client.clone().unwrap_or_default(),
-            repo,
-        );
-        self.syncer = Some(syncer.clone());
-        syncer.start_sync(self.config.start_wait, self.should_stop.clone());
+    pub fn duration_detail(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        default: Duration,
+    ) -> FPDetail<Duration> {
+        self.parsed_eval(toggle, user, default, true, parse_duration)
     }
 
-    pub fn sync_now(&self, t: SyncType) {
-        trace!("sync now url {}", &self.config.toggles_url);
-        let syncer = match &self.syncer {
-            Some(syncer) => syncer.clone(),
-            None => return,
-        };
-        syncer.sync_now(t);
+    // string variations parsed with FromStr
+    pub fn enum_value<E>(&self, toggle: &str, user: &FPUser, default: E) -> E
+    where
+        E: FromStr + Default + Debug,
+        E::Err: Display,
+    {
+        self.parsed_eval(toggle, user, default, false, |s| {
+            s.parse().map_err(|e: E::Err| e.to_string())
+        })
+        .value
     }
 
-    #[cfg(feature = "realtime")]
-    fn connect_socket(&mut self) {
-        let mut slf = self.clone();
-        let slf2 = self.clone();
-        let nsp = self.config.realtime_path.clone();
-        tokio::spawn(async move {
-            let url = slf.config.realtime_url;
-            let server_sdk_key = slf.config.server_sdk_key.clone();
-            trace!("connect_socket {}", url);
-            let client = socketio_rs::ClientBuilder::new(url.clone())
-                .namespace(&nsp)
-                .on(socketio_rs::Event::Connect, move |_, socket, _| {
-                    Self::socket_on_connect(socket, server_sdk_key.clone())
-                })
-                .on(
-                    "update",
-                    move |payload: Option<socketio_rs::Payload>, _, _| {
-                        Self::socket_on_update(slf2.clone(), payload)
-                    },
-                )
-                .on("error", |err, _, _| {
-                    async move { tracing::error!("socket on error: {:#?}", err) }.boxed()
-                })
-                .connect()
-                .await;
-            match client {
-                Err(e) => tracing::error!("connect_socket error: {:?}", e),
-                Ok(client) => slf.socket = Some(client),
-            };
-        });
+    pub fn enum_detail<E>(&self, toggle: &str, user: &FPUser, default: E) -> FPDetail<E>
+    where
+        E: FromStr + Default + Debug,
+        E::Err: Display,
+    {
+        self.parsed_eval(toggle, user, default, true, |s| {
+            s.parse().map_err(|e: E::Err| e.to_string())
+        })
     }
 
-    #[cfg(feature = "realtime")]
-    fn socket_on_connect(socket: socketio_rs::Socket, server_sdk_key: String) -> SocketCallback {
-        let sdk_key = server_sdk_key;
-        trace!("socket_on_connect: {:?}", sdk_key);
-        async move {
-            if let Err(e) = socket
-                .emit("register", serde_json::json!({ "key": sdk_key }))
-                .await
-            {
-                tracing::error!("register error: {:?}", e);
-            }
-        }
-        .boxed()
+    // dynamic config without a user, rules needing attributes never match and
+    // splits bucket the whole client instance the same way
+    pub fn config_value(&self, toggle: &str, default: Value) -> Value {
+        self.json_value(toggle, &self.instance_user(), default)
     }
 
-    #[cfg(feature = "realtime")]
-    fn socket_on_update(slf: Self, payload: Option<socketio_rs::Payload>) -> SocketCallback {
-        trace!("socket_on_update: {:?}", payload);
-        async move {
-            if let Some(syncer) = &slf.syncer {
-                syncer.sync_now(SyncType::Realtime);
-            } else {
-                warn!("socket receive update event, but no synchronizer");
-            }
-        }
-        .boxed()
+    pub fn config_detail(&self, toggle: &str, default: Value) -> FPDetail<Value> {
+        self.json_detail(toggle, &self.instance_user(), default)
     }
 
-    fn flush_events(&mut self) {
-        trace!("flush_events");
-        let events_url = self.config.events_url.clone();
-        let flush_interval = self.config.refresh_interval;
-        let auth = SdkAuthorization(self.config.server_sdk_key.clone()).encode();
-        let should_stop = self.should_stop.clone();
-        let event_recorder = EventRecorder::new(
-            events_url,
-            auth,
-            (*crate::USER_AGENT).clone(),
-            flush_interval,
-            100,
-            should_stop,
-        );
-        self.event_recorder = Some(event_recorder);
+    // stable for the client, and across restarts with instance_key_path
+    pub fn instance_key(&self) -> &str {
+        self.config.instance_key.key()
     }
 
-    #[cfg(feature = "internal")]
-    pub fn repo(&self) -> Arc<RwLock<Repository>> {
-        self.repo.clone()
+    fn instance_user(&self) -> FPUser {
+        FPUser::new().stable_rollout(self.instance_key().to_owned())
     }
-}
 
-fn record_event(
-    recorder: EventRecorder,
-    track_access_events: bool,
-    toggle: &str,
-    user: &FPUser,
-    detail: Option<EvalDetail<Value>>,
-    debug_until_time: Option<u64>,
-) {
-    let toggle = toggle.to_owned();
-    let user = user.key();
-    let user_detail = serde_json::to_value(user.clone()).unwrap_or_default();
+    // evaluation failures are returned as errors instead of a served default,
+    // no events are recorded
+    pub fn try_detail(&self, toggle: &str, user: &FPUser) -> Result<FPDetail<Value>, FPError> {
+        user.key_with(&*self.config.key_generator);
+        let detail = match self.eval_override(toggle) {
+            Some(detail) => detail,
+            None => {
+                let now = self.config.clock.now();
+                if self.is_stale_at(now) {
+                    let age_ms = now.saturating_sub(self.last_synced().unwrap_or_default());
+                    return Err(FPError::StaleData { age_ms });
+                }
+                let repo = self.repo.read();
+                let t = repo
+                    .toggles
+                    .get(toggle)
+                    .ok_or_else(|| FPError::ToggleNotExist(toggle.to_owned()))?;
+                match self.is_emergency_disabled(toggle) {
+                    true => self.eval_toggle(t, &repo, user, true, now),
+                    false => crate::warning::scoped(&self.warnings, || {
+                        t.try_eval_at(
+                            self.prepared_user(user).as_ref().unwrap_or(user),
+                            &repo.segments,
+                            &repo.toggles,
+                            true,
+                            self.config.max_prerequisites_deep,
+                            repo.debug_until_time,
+                            now,
+                            self.eval_deadline(),
+                        )
+                    })?,
+                }
+            }
+        };
+        let value = detail
+            .value
+            .ok_or_else(|| FPError::EvalDetailError(detail.reason.clone()))?;
+        Ok(FPDetail {
+            value,
+            reason: detail.reason,
+            reason_code: detail.reason_code,
+            rule_index: detail.rule_index,
+            variation_index: detail.variation_index,
+            version: detail.version,
+            prerequisites: detail.prerequisites,
+        })
+    }
 
-    tokio::spawn(async move {
-        let ts = unix_timestamp();
-        record_access(
-            &recorder,
-            &toggle,
-            user.clone(),
-            track_access_events,
-            &detail,
-            ts,
+    pub fn bool_value_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: bool,
+    ) -> bool {
+        self.bool_detail_with_attrs(toggle, user, extra_attrs, default)
+            .value
+    }
+
+    pub fn string_value_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: String,
+    ) -> String {
+        self.string_detail_with_attrs(toggle, user, extra_attrs, default)
+            .value
+    }
+
+    pub fn number_value_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: f64,
+    ) -> f64 {
+        self.number_detail_with_attrs(toggle, user, extra_attrs, default)
+            .value
+    }
+
+    pub fn json_value_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: Value,
+    ) -> Value {
+        self.json_detail_with_attrs(toggle, user, extra_attrs, default)
+            .value
+    }
+
+    pub fn bool_detail_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: bool,
+    ) -> FPDetail<bool> {
+        let user = self.merge_attrs(user, extra_attrs);
+        self.bool_detail(toggle, &user, default)
+    }
+
+    pub fn string_detail_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: String,
+    ) -> FPDetail<String> {
+        let user = self.merge_attrs(user, extra_attrs);
+        self.string_detail(toggle, &user, default)
+    }
+
+    pub fn number_detail_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: f64,
+    ) -> FPDetail<f64> {
+        let user = self.merge_attrs(user, extra_attrs);
+        self.number_detail(toggle, &user, default)
+    }
+
+    pub fn json_detail_with_attrs(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        extra_attrs: &HashMap<String, String>,
+        default: Value,
+    ) -> FPDetail<Value> {
+        let user = self.merge_attrs(user, extra_attrs);
+        self.json_detail(toggle, &user, default)
+    }
+
+    pub fn values_for_users(&self, toggle: &str, users: &[FPUser], default: Value) -> Vec<Value> {
+        self.details_for_users(toggle, users, default)
+            .into_iter()
+            .map(|d| d.value)
+            .collect()
+    }
+
+    // repository lock is taken once and events are recorded by a single task,
+    // soft limits, circuit breakers, shadow comparison and golden recording are
+    // skipped for batches
+    pub fn details_for_users(
+        &self,
+        toggle: &str,
+        users: &[FPUser],
+        default: Value,
+    ) -> Vec<FPDetail<Value>> {
+        let details = match self.eval_override(toggle) {
+            Some(detail) => vec![Some(detail); users.len()],
+            None => self.eval_batch(toggle, users),
+        };
+        details
+            .into_iter()
+            .map(|d| self.typed_detail(toggle, d, default.clone(), ValueType::Json, Some))
+            .collect()
+    }
+
+    fn eval_batch(&self, toggle: &str, users: &[FPUser]) -> Vec<Option<EvalDetail<Value>>> {
+        let repo = self.repo.read();
+        let now = self.config.clock.now();
+        let t = match repo.toggles.get(toggle) {
+            Some(t) => t,
+            None => {
+                self.missing_toggles.record(toggle, now);
+                return vec![None; users.len()];
+            }
+        };
+        let is_stale = self.is_stale_at(now);
+        if is_stale && self.config.stale_policy == StalePolicy::ServeDefault {
+            let detail = EvalDetail {
+                version: Some(t.version),
+                reason: format!("Stale: Toggle:[{toggle}] serve default."),
+                reason_code: Some(ReasonCode::StaleDefault),
+                ..Default::default()
+            };
+            return vec![Some(detail); users.len()];
+        }
+
+        // events are aggregated per chunk, one recording task per chunk
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        let eval_chunk = |users: &[FPUser]| -> Vec<EvalDetail<Value>> {
+            let _guard = runtime.as_ref().map(|r| r.enter());
+            let details: Vec<EvalDetail<Value>> = users
+                .iter()
+                .map(|user| {
+                    catch_unwind(AssertUnwindSafe(|| {
+                        user.key_with(&*self.config.key_generator);
+                        self.eval_toggle(t, &repo, user, true, now)
+                    }))
+                    .unwrap_or_else(|_| {
+                        error!(toggle, "evaluate toggle panicked");
+                        Self::panicked_detail(toggle)
+                    })
+                })
+                .collect();
+            for detail in &details {
+                self.usage.record(toggle, detail.variation_index, now);
+            }
+            let recorder = self
+                .event_recorder
+                .as_ref()
+                .filter(|_| !self.events_suppressed(&repo, toggle));
+            if let Some(recorder) = recorder {
+                record_events(
+                    recorder.clone(),
+                    t.track_access_events(),
+                    toggle,
+                    users.iter().zip(details.iter().cloned().map(Some)),
+                    repo.debug_until_time,
+                    now,
+                );
+            }
+            details
+        };
+
+        #[cfg(feature = "rayon")]
+        let details: Vec<EvalDetail<Value>> = {
+            use rayon::prelude::*;
+            users
+                .par_chunks(BATCH_CHUNK_SIZE)
+                .flat_map_iter(eval_chunk)
+                .collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let details: Vec<EvalDetail<Value>> = users
+            .chunks(BATCH_CHUNK_SIZE)
+            .flat_map(eval_chunk)
+            .collect();
+
+        details
+            .into_iter()
+            .map(|mut d| {
+                d.debug_until_time = repo.debug_until_time;
+                if is_stale {
+                    d.reason = format!("Stale: {}", d.reason);
+                }
+                Some(d)
+            })
+            .collect()
+    }
+
+    // values are indexed by toggle, then in the order of users
+    pub fn bulk_values(
+        &self,
+        toggles: &[&str],
+        users: &[FPUser],
+        default: Value,
+    ) -> HashMap<String, Vec<Value>> {
+        toggles
+            .iter()
+            .map(|toggle| {
+                let values = self.values_for_users(toggle, users, default.clone());
+                (toggle.to_string(), values)
+            })
+            .collect()
+    }
+
+    // a per-call copy, the shared user is left untouched
+    fn merge_attrs(&self, user: &FPUser, extra_attrs: &HashMap<String, String>) -> FPUser {
+        user.key_with(&*self.config.key_generator);
+        user.copy_with(extra_attrs.clone())
+    }
+
+    pub fn value_with_fallback(&self, toggles: &[&str], user: &FPUser, default: Value) -> Value {
+        self.detail_with_fallback(toggles, user, default).value
+    }
+
+    pub fn detail_with_fallback(
+        &self,
+        toggles: &[&str],
+        user: &FPUser,
+        default: Value,
+    ) -> FPDetail<Value> {
+        for toggle in toggles {
+            if let Some(d) = self.eval(toggle, user, true) {
+                if let Some(value) = d.value {
+                    return FPDetail {
+                        value,
+                        reason: format!("Toggle:[{toggle}] {}", d.reason),
+                        reason_code: d.reason_code,
+                        rule_index: d.rule_index,
+                        variation_index: d.variation_index,
+                        version: d.version,
+                        prerequisites: d.prerequisites,
+                    };
+                }
+            }
+        }
+        FPDetail {
+            value: default,
+            reason: format!("Toggles:{toggles:?} none serve a value"),
+            ..Default::default()
+        }
+    }
+
+    // re-evaluated whenever a sync changes the toggle's version, the watch ends
+    // when every receiver is dropped or the client is closed, needs a tokio runtime
+    pub fn watch_value<T>(&self, toggle: &str, user: FPUser, default: T) -> watch::Receiver<T>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        let version =
+            |fp: &FeatureProbe, toggle: &str| fp.repo.read().toggles.get(toggle).map(|t| t.version);
+        // subscribe and read before evaluating, so a sync in between is not missed
+        let updates = self.syncer.as_ref().map(|s| s.subscribe());
+        let mut last = version(self, toggle);
+        let (tx, rx) = watch::channel(self.watched_value(toggle, &user, &default));
+        let Some(mut updates) = updates else {
+            return rx;
+        };
+        // without the close guard, so watching does not keep the client open
+        let fp = FeatureProbe {
+            close_guard: None,
+            ..self.clone()
+        };
+        let toggle = toggle.to_owned();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = fp.cancellation.cancelled() => break,
+                    _ = tx.closed() => break,
+                    changed = updates.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        let current = version(&fp, &toggle);
+                        if current != last {
+                            last = current;
+                            tx.send_replace(fp.watched_value(&toggle, &user, &default));
+                        }
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    fn watched_value<T: DeserializeOwned + Clone>(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        default: &T,
+    ) -> T {
+        let value = match self.eval(toggle, user, false).and_then(|d| d.value) {
+            Some(value) => value,
+            None => return default.clone(),
+        };
+        serde_json::from_value(value).unwrap_or_else(|e| {
+            warn!(toggle, "watched value not deserialized: {}", e);
+            default.clone()
+        })
+    }
+
+    pub fn client_toggles(&self, user: &FPUser) -> HashMap<String, EvalDetail<Value>> {
+        user.key_with(&*self.config.key_generator);
+        let repo = self.repo.read();
+        let now = self.config.clock.now();
+        repo.toggles
+            .iter()
+            .filter(|(_, t)| t.is_for_client())
+            .map(|(key, t)| {
+                let detail = match self.eval_override(key) {
+                    Some(d) => d,
+                    None => self.eval_toggle(t, &repo, user, true, now),
+                };
+                (key.clone(), detail)
+            })
+            .collect()
+    }
+
+    pub fn client_toggles_json(&self, user: &FPUser) -> Result<String, FPError> {
+        serde_json::to_string(&self.client_toggles(user))
+            .map_err(|e| FPError::InternalError(e.to_string()))
+    }
+
+    pub fn track(&self, event_name: &str, user: &FPUser, value: Option<f64>) {
+        let recorder = match self.event_recorder.as_ref() {
+            None => {
+                warn!("Event Recorder no ready.");
+                return;
+            }
+            Some(recorder) => recorder,
+        };
+        let event = CustomEvent {
+            kind: "custom".to_string(),
+            time: self.config.clock.now(),
+            user: user.key_with(&*self.config.key_generator),
+            name: event_name.to_string(),
+            value,
+        };
+        recorder.record_event(Event::CustomEvent(event));
+    }
+
+    pub fn new_with(server_key: String, mut repo: Repository) -> Self {
+        repo.rebuild_index();
+        Self {
+            config: Config {
+                server_sdk_key: server_key.into(),
+                ..Default::default()
+            },
+            repo: Arc::new(RwLock::new(repo)),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_fixture<P: AsRef<Path>>(path: P) -> Result<Self, FPError> {
+        let path = path.as_ref();
+        let json_str = fs::read_to_string(path)
+            .map_err(|e| FPError::InternalError(format!("read {}: {}", path.display(), e)))?;
+        let repo = load_json(&json_str)?;
+        Ok(Self::new_with_repo(Arc::new(RwLock::new(repo))))
+    }
+
+    pub fn raw_toggle(&self, toggle: &str) -> Option<String> {
+        let repo = self.repo.read();
+        let toggle = repo.toggles.get(toggle)?;
+        serde_json::to_string(toggle).ok()
+    }
+
+    pub fn raw_segment(&self, segment: &str) -> Option<String> {
+        let repo = self.repo.read();
+        let segment = repo.segments.get(segment)?;
+        serde_json::to_string(segment).ok()
+    }
+
+    pub fn segment_dependents(&self, segment: &str) -> Vec<String> {
+        let repo = self.repo.read();
+        repo.dependent_toggles(segment)
+            .into_iter()
+            .map(|t| t.to_owned())
+            .collect()
+    }
+
+    pub fn bucket_of(&self, toggle: &str, user: &FPUser) -> Option<(u32, usize)> {
+        user.key_with(&*self.config.key_generator);
+        let repo = self.repo.read();
+        repo.toggles.get(toggle)?.bucket_of(user)
+    }
+
+    pub fn simulate(&self, toggle: &str, sample_size: usize) -> Option<Simulation> {
+        let repo = self.repo.read();
+        let t = repo.toggles.get(toggle)?;
+        Some(t.simulate(&repo.segments, &repo.toggles, sample_size))
+    }
+
+    pub fn export_snapshot(&self) -> Vec<u8> {
+        let repo = self.repo.read().clone();
+        Snapshot::new(repo, self.config.clock.now()).to_bytes()
+    }
+
+    pub fn import_snapshot(&self, bytes: &[u8]) -> Result<Snapshot, FPError> {
+        let snapshot = Snapshot::from_bytes(bytes)?;
+        let mut repo = self.repo.write();
+        *repo = snapshot.repository.clone();
+        if let Some(form) = self.config.unicode_normalization {
+            repo.normalize_strings(form);
+        }
+        *self.snapshot.write() = None;
+        Ok(snapshot)
+    }
+
+    pub fn scoped(&self, user: &FPUser) -> ScopedEvaluator {
+        ScopedEvaluator::new(self.clone(), user, self.repo_snapshot())
+    }
+
+    // repository is cloned once per version and shared by all scopes
+    pub(crate) fn repo_snapshot(&self) -> Arc<Repository> {
+        let repo = self.repo.read();
+        if let Some(snapshot) = &*self.snapshot.read() {
+            if snapshot.version == repo.version {
+                return snapshot.clone();
+            }
+        }
+        let snapshot = Arc::new(repo.clone());
+        *self.snapshot.write() = Some(snapshot.clone());
+        snapshot
+    }
+
+    pub(crate) fn new_with_repo(repo: Arc<RwLock<Repository>>) -> Self {
+        Self {
+            repo,
+            ..Default::default()
+        }
+    }
+
+    // waits up to grace for pending events to be sent, then closes,
+    // returns false when the flush did not finish in time
+    pub async fn shutdown(&self, grace: Duration) -> bool {
+        let flushed = match &self.event_recorder {
+            Some(recorder) => tokio::time::timeout(grace, recorder.flush_now())
+                .await
+                .is_ok(),
+            None => true,
+        };
+        if !flushed {
+            warn!(
+                grace_ms = grace.as_millis() as u64,
+                "events not flushed before shutdown"
+            );
+        }
+        self.close();
+        flushed
+    }
+
+    // cancelled by close(), or by the token passed in FPConfig
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn close(&self) {
+        trace!("closing featureprobe client");
+        if let Some(path) = self.config.init_policy.cache_path() {
+            if let Err(e) = fs::write(path, self.export_snapshot()) {
+                warn!(path = %path.display(), error = %e, "save cache failed");
+            }
+        }
+        if let Some(recorder) = &self.event_recorder {
+            recorder.flush();
+        }
+        self.cancellation.cancel();
+    }
+
+    pub fn initialized(&self) -> bool {
+        match &self.syncer {
+            Some(s) => s.initialized(),
+            None => false,
+        }
+    }
+
+    pub async fn wait_until_initialized(&self, timeout: Duration) -> bool {
+        match &self.syncer {
+            Some(s) => s.wait_until_initialized(timeout).await,
+            None => false,
+        }
+    }
+
+    pub fn wait_until_initialized_blocking(&self, timeout: Duration) -> bool {
+        match &self.syncer {
+            Some(s) => s.wait_until_initialized_blocking(timeout),
+            None => false,
+        }
+    }
+
+    pub fn set_update_callback(&mut self, update_callback: UpdateCallback) {
+        if let Some(syncer) = &mut self.syncer {
+            syncer.set_update_callback(update_callback)
+        }
+    }
+
+    pub fn override_toggle(&self, toggle: &str, value: Value) {
+        let mut overrides = self.overrides.write();
+        overrides.insert(toggle.to_owned(), value);
+    }
+
+    pub fn clear_override(&self, toggle: &str) {
+        let mut overrides = self.overrides.write();
+        overrides.remove(toggle);
+    }
+
+    pub fn clear_all_overrides(&self) {
+        let mut overrides = self.overrides.write();
+        overrides.clear();
+    }
+
+    pub fn emergency_disable_all(&self) {
+        warn!("emergency disable all toggles");
+        self.kill_switch.write().all = true;
+    }
+
+    pub fn emergency_restore_all(&self) {
+        let mut kill_switch = self.kill_switch.write();
+        kill_switch.all = false;
+        kill_switch.toggles.clear();
+    }
+
+    pub fn emergency_disable(&self, toggle: &str) {
+        warn!(toggle, "emergency disable toggle");
+        self.kill_switch.write().toggles.insert(toggle.to_owned());
+    }
+
+    pub fn emergency_restore(&self, toggle: &str) {
+        self.kill_switch.write().toggles.remove(toggle);
+    }
+
+    pub fn is_emergency_disabled(&self, toggle: &str) -> bool {
+        let kill_switch = self.kill_switch.read();
+        kill_switch.all || kill_switch.toggles.contains(toggle)
+    }
+
+    pub fn set_shadow_repository(&self, mut repo: Repository) {
+        repo.rebuild_index();
+        let mut shadow = self.shadow.repo.write();
+        *shadow = Some(repo);
+    }
+
+    pub fn clear_shadow_repository(&self) {
+        let mut shadow = self.shadow.repo.write();
+        *shadow = None;
+    }
+
+    pub fn on_shadow_mismatch(&mut self, callback: ShadowCallback) {
+        self.shadow.set_callback(callback)
+    }
+
+    pub fn shadow_stats(&self) -> ShadowStats {
+        self.shadow.stats()
+    }
+
+    // appends every repository evaluation to a jsonl file for replay_golden,
+    // private attributes are left out so rules on them may replay differently
+    pub fn start_golden_recording(&self, path: impl AsRef<Path>) -> Result<(), FPError> {
+        let recorder = GoldenRecorder::create(path.as_ref(), false)?;
+        *self.golden.write() = Some(recorder);
+        Ok(())
+    }
+
+    // like start_golden_recording but private attribute values are written to the file
+    pub fn start_golden_recording_with_private(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(), FPError> {
+        let recorder = GoldenRecorder::create(path.as_ref(), true)?;
+        *self.golden.write() = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_golden_recording(&self) {
+        *self.golden.write() = None;
+    }
+
+    // re-evaluates recorded evaluations against the current repository
+    pub fn replay_golden(&self, path: impl AsRef<Path>) -> Result<GoldenReport, FPError> {
+        let records = crate::golden::load_golden(path.as_ref())?;
+        let repo = self.repo.read();
+        Ok(crate::golden::replay(
+            &records,
+            &repo,
+            self.config.max_prerequisites_deep,
+        ))
+    }
+
+    pub fn user_agent(&self) -> &str {
+        &self.config.user_agent
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.config.tags
+    }
+
+    pub fn open_circuits(&self) -> Vec<String> {
+        self.breakers.open_circuits(self.config.clock.now())
+    }
+
+    // compiles regex patterns of the listed toggles ahead of traffic, the compiled
+    // patterns live until the repository is replaced by a sync
+    pub fn warm_up(&self, toggles: &[&str]) -> WarmUpReport {
+        let repo = self.repo.read();
+        let mut report = WarmUpReport::default();
+        for key in toggles {
+            let toggle = match repo.toggles.get(*key) {
+                Some(t) => t,
+                None => {
+                    report.missing.push(key.to_string());
+                    continue;
+                }
+            };
+            report.toggles += 1;
+            report.patterns += toggle.warm_up(&repo.segments);
+        }
+        report
+    }
+
+    pub fn repo_composition(&self) -> RepoComposition {
+        RepoComposition::of(&self.repo.read())
+    }
+
+    // latest report of the background validation run after each sync
+    pub fn validation_report(&self) -> Option<ValidationReport> {
+        self.validation.read().clone()
+    }
+
+    pub fn soft_limit_breaches(&self) -> Vec<SoftLimitBreach> {
+        self.eval_rates.breaches()
+    }
+
+    pub fn missing_toggles(&self) -> Vec<MissingToggle> {
+        self.missing_toggles.snapshot()
+    }
+
+    pub fn clear_missing_toggles(&self) {
+        self.missing_toggles.clear()
+    }
+
+    // toggles and variations served since startup, overrides are not counted
+    pub fn usage_report(&self) -> UsageReport {
+        self.usage.report(self.config.clock.now())
+    }
+
+    pub fn warning_counts(&self) -> Vec<WarningCount> {
+        self.warnings.counts()
+    }
+
+    pub fn on_change(&mut self, audit_callback: AuditCallback) {
+        if let Some(syncer) = &mut self.syncer {
+            syncer.set_audit_callback(audit_callback)
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.warnings = Arc::new(WarningThrottle::new(
+            self.config.warning_interval,
+            clock.clone(),
+        ));
+        self.config.clock = clock;
+    }
+
+    pub fn set_key_generator(&mut self, key_generator: Arc<dyn KeyGenerator>) {
+        self.config.key_generator = key_generator;
+    }
+
+    pub fn version(&self) -> Option<u128> {
+        self.syncer.as_ref().map(|s| s.version()).flatten()
+    }
+
+    // version of the repository being served, also set by snapshots and fixtures
+    pub fn repo_version(&self) -> Option<u128> {
+        self.repo.read().version
+    }
+
+    // debug_until_time while the debug window is still open
+    pub fn debug_active(&self) -> Option<u64> {
+        let now = self.config.clock.now();
+        self.repo
+            .read()
+            .debug_until_time
+            .filter(|until| *until as u128 >= now)
+    }
+
+    // schema agreed with the events endpoint, legacy until the first post succeeds
+    pub fn event_schema(&self) -> Option<EventSchema> {
+        self.event_recorder.as_ref().map(|r| r.schema())
+    }
+
+    #[cfg(feature = "realtime")]
+    pub(crate) fn realtime_connected(&self) -> bool {
+        self.socket.lock().is_some()
+    }
+
+    #[cfg(not(feature = "realtime"))]
+    pub(crate) fn realtime_connected(&self) -> bool {
+        false
+    }
+
+    pub fn last_synced(&self) -> Option<u128> {
+        *self.last_synced.read()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.is_stale_at(self.config.clock.now())
+    }
+
+    fn is_stale_at(&self, now: u128) -> bool {
+        let max_staleness = match self.config.max_staleness {
+            Some(d) => d.as_millis(),
+            None => return false,
+        };
+        match self.last_synced() {
+            Some(t) => now.saturating_sub(t) > max_staleness,
+            None => false,
+        }
+    }
+
+    fn generic_eval<T: Default + Debug>(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        default: T,
+        is_detail: bool,
+        expected: ValueType,
+        transform: fn(Value) -> Option<T>,
+    ) -> FPDetail<T> {
+        let detail = self.eval(toggle, user, is_detail);
+        self.typed_detail(toggle, detail, default, expected, transform)
+    }
+
+    // string variations the caller parses, parse errors serve the default
+    fn parsed_eval<T: Default + Debug>(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        default: T,
+        is_detail: bool,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> FPDetail<T> {
+        let detail = self.generic_eval(toggle, user, None, is_detail, ValueType::String, |v| {
+            v.as_str().map(|s| Some(s.to_owned()))
+        });
+        let (value, reason, reason_code) = match detail.value.as_deref().map(&parse) {
+            None => (default, detail.reason, detail.reason_code),
+            Some(Ok(value)) => (value, detail.reason, detail.reason_code),
+            Some(Err(e)) => (
+                default,
+                format!("Value parse error. Toggle:[{toggle}] {e}."),
+                Some(ReasonCode::TypeMismatch),
+            ),
+        };
+        FPDetail {
+            value,
+            reason,
+            reason_code,
+            rule_index: detail.rule_index,
+            variation_index: detail.variation_index,
+            version: detail.version,
+            prerequisites: detail.prerequisites,
+        }
+    }
+
+    pub(crate) fn typed_detail<T: Default + Debug>(
+        &self,
+        toggle: &str,
+        detail: Option<EvalDetail<Value>>,
+        default: T,
+        expected: ValueType,
+        transform: fn(Value) -> Option<T>,
+    ) -> FPDetail<T> {
+        if !self.expected_types.read().contains_key(toggle) {
+            self.expect_type(toggle, expected);
+        }
+        let (value, reason, detail) = match detail {
+            None => (
+                default,
+                Some((
+                    format!("Toggle:[{toggle}] not exist"),
+                    ReasonCode::ToggleNotExist,
+                )),
+                Default::default(),
+            ),
+            Some(mut d) => match d.value.take() {
+                None => (default, None, d), // Serve error.
+                Some(v) => {
+                    let found = ValueType::of(&v);
+                    match transform(v) {
+                        None => {
+                            // Transform error.
+                            let mismatch = TypeMismatch {
+                                toggle: toggle.to_owned(),
+                                version: d.version.unwrap_or_default(),
+                                expected,
+                                found,
+                            };
+                            self.report_mismatch(&mismatch);
+                            (
+                                default,
+                                Some((
+                                    format!("Value type mismatch. {mismatch}."),
+                                    ReasonCode::TypeMismatch,
+                                )),
+                                d,
+                            )
+                        }
+                        Some(typed_v) => (typed_v, None, d),
+                    }
+                }
+            },
+        };
+
+        let (reason, reason_code) = match reason {
+            Some((reason, code)) => (reason, Some(code)),
+            None => (detail.reason, detail.reason_code),
+        };
+        FPDetail {
+            value,
+            reason,
+            reason_code,
+            rule_index: detail.rule_index,
+            variation_index: detail.variation_index,
+            version: detail.version,
+            prerequisites: detail.prerequisites,
+        }
+    }
+
+    fn eval(&self, toggle: &str, user: &FPUser, is_detail: bool) -> Option<EvalDetail<Value>> {
+        let span = trace_span!(
+            "fp.eval",
+            toggle,
+            is_detail,
+            version = field::Empty,
+            reason_code = field::Empty,
         );
-        record_debug(
-            &recorder,
-            &toggle,
+        let _entered = span.enter();
+        match catch_unwind(AssertUnwindSafe(|| {
+            crate::warning::scoped(&self.warnings, || self.eval_inner(toggle, user, is_detail))
+        })) {
+            Ok(detail) => {
+                if let Some(d) = &detail {
+                    span.record("version", d.version);
+                    span.record("reason_code", d.reason_code.map(|c| c.as_str()));
+                }
+                detail
+            }
+            Err(_) => {
+                error!(toggle, "evaluate toggle panicked");
+                Some(Self::panicked_detail(toggle))
+            }
+        }
+    }
+
+    pub(crate) fn panicked_detail(toggle: &str) -> EvalDetail<Value> {
+        EvalDetail {
+            reason: format!("Toggle:[{toggle}] evaluation panicked"),
+            reason_code: Some(ReasonCode::EvaluationPanicked),
+            ..Default::default()
+        }
+    }
+
+    fn eval_inner(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        is_detail: bool,
+    ) -> Option<EvalDetail<Value>> {
+        // assign generated key before evaluating, so bucketing and events share it
+        user.key_with(&*self.config.key_generator);
+
+        if let Some(detail) = self.eval_override(toggle) {
+            return Some(detail);
+        }
+
+        let repo = self.repo.read();
+        let debug_until_time = repo.debug_until_time;
+        let now = self.config.clock.now();
+        let detail = self.eval_checked(toggle, &repo, user, is_detail, now);
+        self.eval_shadow(toggle, user, is_detail, now, detail.as_ref());
+
+        let recorder = self
+            .event_recorder
+            .as_ref()
+            .filter(|_| !self.events_suppressed(&repo, toggle));
+        if let Some(recorder) = recorder {
+            let track_access_events = repo
+                .toggles
+                .get(toggle)
+                .map(|t| t.track_access_events())
+                .unwrap_or(false);
+            record_event(
+                recorder.clone(),
+                track_access_events,
+                toggle,
+                user,
+                detail.clone(),
+                debug_until_time,
+                now,
+            )
+        }
+        drop(repo);
+        if let Some(golden) = &*self.golden.read() {
+            if let Err(e) = golden.record(toggle, user, now, detail.as_ref()) {
+                warn!("golden recording failed: {}", e);
+            }
+        }
+        detail
+    }
+
+    // stale policy, circuit breaker, soft limits, usage and missing toggle tracking
+    // around one evaluation against repo, shared by eval_inner and scoped evaluators
+    pub(crate) fn eval_checked(
+        &self,
+        toggle: &str,
+        repo: &Repository,
+        user: &FPUser,
+        is_detail: bool,
+        now: u128,
+    ) -> Option<EvalDetail<Value>> {
+        let is_stale = self.is_stale_at(now);
+        if is_stale && self.config.stale_policy == StalePolicy::ServeDefault {
+            return repo.toggles.get(toggle).map(|t| EvalDetail {
+                version: Some(t.version),
+                reason: format!("Stale: Toggle:[{toggle}] serve default."),
+                reason_code: Some(ReasonCode::StaleDefault),
+                ..Default::default()
+            });
+        }
+        if self.config.circuit_breaker.is_some() && !self.breakers.allow(toggle, now) {
+            return repo.toggles.get(toggle).map(|t| EvalDetail {
+                version: Some(t.version),
+                reason: format!("Toggle:[{toggle}] circuit open, serve default."),
+                reason_code: Some(ReasonCode::CircuitOpen),
+                ..Default::default()
+            });
+        }
+        if let Some(limits) = &self.config.eval_soft_limits {
+            self.eval_rates.record(limits, toggle, now);
+        }
+        let detail = repo
+            .toggles
+            .get(toggle)
+            .map(|toggle| self.eval_toggle(toggle, repo, user, is_detail, now));
+        if let Some(d) = &detail {
+            self.usage.record(toggle, d.variation_index, now);
+        }
+        match (&detail, &self.config.circuit_breaker) {
+            (None, _) => self.missing_toggles.record(toggle, now),
+            (Some(d), Some(config)) => self.breakers.record(config, toggle, d.value.is_some(), now),
+            _ => {}
+        }
+        detail.map(|mut d| {
+            d.debug_until_time = repo.debug_until_time;
+            if is_stale {
+                d.reason = format!("Stale: {}", d.reason);
+            }
+            d
+        })
+    }
+
+    pub(crate) fn eval_toggle(
+        &self,
+        toggle: &Toggle,
+        repo: &Repository,
+        user: &FPUser,
+        is_detail: bool,
+        now: u128,
+    ) -> EvalDetail<Value> {
+        crate::warning::scoped(&self.warnings, || {
+            self.eval_toggle_unscoped(toggle, repo, user, is_detail, now)
+        })
+    }
+
+    fn eval_toggle_unscoped(
+        &self,
+        toggle: &Toggle,
+        repo: &Repository,
+        user: &FPUser,
+        is_detail: bool,
+        now: u128,
+    ) -> EvalDetail<Value> {
+        if toggle.is_archived() || toggle.is_deprecated() {
+            self.report_retired(toggle);
+        }
+        let prepared = self.prepared_user(user);
+        let user = prepared.as_ref().unwrap_or(user);
+        if self.is_emergency_disabled(&toggle.key) {
+            return toggle.eval_disabled(
+                user,
+                &repo.segments,
+                &repo.toggles,
+                is_detail,
+                repo.debug_until_time,
+                now,
+                "Emergency disabled",
+            );
+        }
+        let (detail, prerequisites) = toggle.eval_traced(
             user,
-            user_detail,
-            debug_until_time,
-            &detail,
-            ts,
+            &repo.segments,
+            &repo.toggles,
+            is_detail,
+            self.config.max_prerequisites_deep,
+            repo.debug_until_time,
+            now,
+            self.eval_deadline(),
+        );
+        if detail.reason_code == Some(ReasonCode::EvalTimeout) {
+            warn_throttled(WarningKind::EvalTimeout, &toggle.key);
+        }
+        if self.config.track_prerequisite_events {
+            self.record_prerequisite_events(repo, user, prerequisites, now);
+        }
+        match &self.config.sticky_buckets {
+            Some(store) => Self::pin_bucket(&**store, toggle, user, detail),
+            None => detail,
+        }
+    }
+
+    fn eval_deadline(&self) -> Option<Instant> {
+        self.config
+            .eval_budget
+            .map(|budget| Instant::now() + budget)
+    }
+
+    // the user as this client evaluates it, none when it is used unchanged
+    fn prepared_user(&self, user: &FPUser) -> Option<FPUser> {
+        let normalized = self
+            .config
+            .unicode_normalization
+            .map(|form| user.normalized(form));
+        match &self.config.user_limits {
+            Some(limits) => normalized
+                .as_ref()
+                .unwrap_or(user)
+                .limited(limits)
+                .or(normalized),
+            None => normalized,
+        }
+    }
+
+    pub(crate) fn events_suppressed(&self, repo: &Repository, toggle: &str) -> bool {
+        self.config.suppressed_events.contains(toggle) || repo.suppressed_events.contains(toggle)
+    }
+
+    // keeps users on the variation they were split into before a salt rotation
+    fn pin_bucket(
+        store: &dyn StickyBucketStore,
+        toggle: &Toggle,
+        user: &FPUser,
+        mut detail: EvalDetail<Value>,
+    ) -> EvalDetail<Value> {
+        let (salt, variation) = match (toggle.served_split_salt(&detail), detail.variation_index) {
+            (Some(salt), Some(variation)) => (salt, variation),
+            _ => return detail,
+        };
+        let user_key = user.key();
+        match store.get(&toggle.key, &user_key) {
+            None => store.set(&toggle.key, &user_key, StickyAssignment { variation, salt }),
+            Some(pinned) if pinned.salt != salt => {
+                if let Some(value) = toggle.variations.get(pinned.variation) {
+                    detail.value = Some(value.clone());
+                    detail.variation_index = Some(pinned.variation);
+                    detail.reason = format!("{} Pinned to salt {}.", detail.reason, pinned.salt);
+                }
+            }
+            Some(_) => {}
+        }
+        detail
+    }
+
+    fn record_prerequisite_events(
+        &self,
+        repo: &Repository,
+        user: &FPUser,
+        prerequisites: Vec<(PrerequisiteResult, EvalDetail<Value>)>,
+        now: u128,
+    ) {
+        let recorder = match &self.event_recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        for (result, detail) in prerequisites {
+            let key = result.key;
+            if self.events_suppressed(repo, &key) {
+                continue;
+            }
+            let track_access_events = repo
+                .toggles
+                .get(&key)
+                .map(|t| t.track_access_events())
+                .unwrap_or(false);
+            record_event(
+                recorder.clone(),
+                track_access_events,
+                &key,
+                user,
+                Some(detail),
+                repo.debug_until_time,
+                now,
+            );
+        }
+    }
+
+    fn eval_shadow(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        is_detail: bool,
+        now: u128,
+        primary: Option<&EvalDetail<Value>>,
+    ) {
+        let shadow_repo = self.shadow.repo.read();
+        let repo = match &*shadow_repo {
+            Some(repo) => repo,
+            None => return,
+        };
+        let detail = repo.toggles.get(toggle).map(|t| {
+            t.eval_at(
+                user,
+                &repo.segments,
+                &repo.toggles,
+                is_detail,
+                self.config.max_prerequisites_deep,
+                repo.debug_until_time,
+                now,
+            )
+        });
+        self.shadow.compare(toggle, user, primary, detail);
+    }
+
+    pub(crate) fn eval_override(&self, toggle: &str) -> Option<EvalDetail<Value>> {
+        let value = {
+            let overrides = self.overrides.read();
+            overrides.get(toggle)?.clone()
+        };
+        let repo = self.repo.read();
+        let origin = repo.toggles.get(toggle);
+        Some(EvalDetail {
+            variation_index: origin.and_then(|t| t.variations.iter().position(|v| v == &value)),
+            version: origin.map(|t| t.version),
+            value: Some(value),
+            debug_until_time: repo.debug_until_time,
+            reason: format!("Toggle:[{toggle}] overridden locally."),
+            reason_code: Some(ReasonCode::Overridden),
+            ..Default::default()
+        })
+    }
+
+    fn start(&mut self) {
+        // background work also stops when the application's token is cancelled
+        if let Some(token) = &self.config.cancellation_token {
+            self.cancellation = token.child_token();
+        }
+        if let Some(path) = self.config.init_policy.cache_path() {
+            self.load_cache(path);
+        }
+        self.sync();
+
+        #[cfg(feature = "realtime")]
+        self.connect_socket();
+
+        #[cfg(feature = "grpc")]
+        self.connect_grpc();
+
+        if self.config.track_events || !self.config.event_sinks.is_empty() {
+            self.flush_events();
+        }
+
+        if let Some(interval) = self.config.missing_toggle_report_interval {
+            MissingToggles::start_report(
+                Arc::downgrade(&self.missing_toggles),
+                interval,
+                self.cancellation.clone(),
+            );
+        }
+
+        if let Some(url) = &self.config.usage_report_url {
+            let mut headers = http::HeaderMap::new();
+            let auth = SdkAuthorization(self.config.server_sdk_key.expose().to_owned());
+            headers.insert(http::header::AUTHORIZATION, auth.encode());
+            if let Ok(ua) = http::HeaderValue::from_str(&self.config.user_agent) {
+                headers.insert(http::header::USER_AGENT, ua);
+            }
+            UsageTracker::start_export(
+                Arc::downgrade(&self.usage),
+                url.clone(),
+                self.config.usage_report_interval,
+                self.config.http_transport.clone(),
+                headers,
+                self.cancellation.clone(),
+            );
+        }
+
+        self.arm_close_guard();
+    }
+
+    fn load_cache(&self, path: &Path) {
+        let loaded = fs::read(path)
+            .map_err(|e| FPError::InternalError(e.to_string()))
+            .and_then(|bytes| self.import_snapshot(&bytes));
+        match loaded {
+            Ok(snapshot) => info!(version = ?snapshot.version(), "serving cache until synced"),
+            Err(e) => warn!(path = %path.display(), error = %e, "load cache failed"),
+        }
+    }
+
+    fn arm_close_guard(&mut self) {
+        self.close_guard = Some(Arc::new(CloseGuard {
+            cancellation: self.cancellation.clone(),
+            event_recorder: self.event_recorder.clone(),
+        }));
+    }
+
+    fn sync(&mut self) {
+        trace!(url = %self.config.toggles_url, "start sync");
+        let toggles_url = self.config.toggles_url.clone();
+        let refresh_interval = self.config.refresh_interval;
+        let auth = SdkAuthorization(self.config.server_sdk_key.expose().to_owned()).encode();
+        let repo = self.repo.clone();
+        let mut syncer = Synchronizer::new(
+            toggles_url,
+            refresh_interval,
+            auth,
+            self.config.user_agent.clone(),
+            self.config.http_transport.clone(),
+            repo,
+            self.last_synced.clone(),
+            self.config.clock.clone(),
         );
+        if let Some(secret) = &self.config.signing_secret {
+            syncer.set_signing_secret(secret.clone());
+        }
+        if let Some(form) = self.config.unicode_normalization {
+            syncer.set_unicode_normalization(form);
+        }
+        if let Some(limits) = self.config.repository_limits {
+            syncer.set_repository_limits(limits);
+        }
+        if self.config.background_validation {
+            syncer.set_validation(self.validation.clone());
+        }
+        self.syncer = Some(syncer.clone());
+        syncer.start_sync(self.config.init_policy.wait(), self.cancellation.clone());
+    }
+
+    pub fn sync_now(&self, t: SyncType) {
+        trace!(url = %self.config.toggles_url, sync_type = ?t, "sync now");
+        let syncer = match &self.syncer {
+            Some(syncer) => syncer.clone(),
+            None => return,
+        };
+        syncer.sync_now(t);
+    }
+
+    #[cfg(feature = "grpc")]
+    fn connect_grpc(&mut self) {
+        let (url, syncer) = match (&self.config.grpc_url, &self.syncer) {
+            (Some(url), Some(syncer)) => (url.clone(), syncer.downgrade()),
+            _ => return,
+        };
+        trace!(url = %url, "connect grpc");
+        tokio::spawn(crate::grpc::run_stream(
+            url,
+            self.config.server_sdk_key.clone(),
+            syncer,
+            self.config.refresh_interval,
+            self.cancellation.clone(),
+        ));
+    }
+
+    #[cfg(feature = "realtime")]
+    fn connect_socket(&mut self) {
+        let socket_slot = self.socket.clone();
+        let syncer = self.syncer.as_ref().map(|s| s.downgrade());
+        let url = self.config.realtime_url.clone();
+        let server_sdk_key = self.config.server_sdk_key.clone();
+        let nsp = self.config.realtime_path.clone();
+        tokio::spawn(async move {
+            trace!(url = %url, "connect socket");
+            let client = socketio_rs::ClientBuilder::new(url.clone())
+                .namespace(&nsp)
+                .on(socketio_rs::Event::Connect, move |_, socket, _| {
+                    Self::socket_on_connect(socket, server_sdk_key.clone())
+                })
+                .on(
+                    "update",
+                    move |payload: Option<socketio_rs::Payload>, _, _| {
+                        Self::socket_on_update(syncer.clone(), payload)
+                    },
+                )
+                .on("error", |err, _, _| {
+                    async move { tracing::error!("socket on error: {:#?}", err) }.boxed()
+                })
+                .connect()
+                .await;
+            match client {
+                Err(e) => tracing::error!("connect_socket error: {:?}", e),
+                Ok(client) => *socket_slot.lock() = Some(client),
+            };
+        });
+    }
+
+    #[cfg(feature = "realtime")]
+    fn socket_on_connect(socket: socketio_rs::Socket, server_sdk_key: Secret) -> SocketCallback {
+        trace!("socket_on_connect: {:?}", server_sdk_key);
+        async move {
+            if let Err(e) = socket
+                .emit(
+                    "register",
+                    serde_json::json!({ "key": server_sdk_key.expose() }),
+                )
+                .await
+            {
+                tracing::error!("register error: {:?}", e);
+            }
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "realtime")]
+    fn socket_on_update(
+        syncer: Option<WeakSynchronizer>,
+        payload: Option<socketio_rs::Payload>,
+    ) -> SocketCallback {
+        trace!("socket_on_update: {:?}", payload);
+        async move {
+            if let Some(syncer) = syncer.and_then(|s| s.upgrade()) {
+                syncer.sync_now(SyncType::Realtime);
+            } else {
+                warn!("socket receive update event, but no synchronizer");
+            }
+        }
+        .boxed()
+    }
+
+    fn flush_events(&mut self) {
+        trace!("flush_events");
+        let flush_interval = self.config.refresh_interval;
+        let auth = SdkAuthorization(self.config.server_sdk_key.expose().to_owned()).encode();
+        let shutdown = self.cancellation.clone();
+        let config = EventConfig {
+            endpoints: self.config.event_endpoints.clone(),
+            auth,
+            user_agent: self.config.user_agent.clone(),
+            transport: self.config.http_transport.clone(),
+            flush_interval,
+            capacity: 100,
+            max_payload: self.config.max_event_payload_bytes,
+            schema: self.config.event_schema,
+            post_events: self.config.track_events,
+            sinks: self.config.event_sinks.clone(),
+            signing_secret: self.config.signing_secret.clone(),
+            clock: self.config.clock.clone(),
+        };
+        let event_recorder = EventRecorder::new(config, shutdown);
+        self.event_recorder = Some(event_recorder);
+    }
+
+    #[cfg(feature = "internal")]
+    pub fn repo(&self) -> Arc<RwLock<Repository>> {
+        self.repo.clone()
+    }
+}
+
+pub(crate) fn record_event(
+    recorder: EventRecorder,
+    track_access_events: bool,
+    toggle: &str,
+    user: &FPUser,
+    detail: Option<EvalDetail<Value>>,
+    debug_until_time: Option<u64>,
+    ts: u128,
+) {
+    record_events(
+        recorder,
+        track_access_events,
+        toggle,
+        std::iter::once((user, detail)),
+        debug_until_time,
+        ts,
+    )
+}
+
+pub(crate) fn record_events<'a>(
+    recorder: EventRecorder,
+    track_access_events: bool,
+    toggle: &str,
+    evaluations: impl Iterator<Item = (&'a FPUser, Option<EvalDetail<Value>>)>,
+    debug_until_time: Option<u64>,
+    ts: u128,
+) {
+    let toggle = toggle.to_owned();
+    let evaluations: Vec<(String, Value, Option<EvalDetail<Value>>)> = evaluations
+        .map(|(user, detail)| {
+            let user = user.key();
+            let user_detail = serde_json::to_value(user.clone()).unwrap_or_default();
+            (user, user_detail, detail)
+        })
+        .collect();
+
+    tokio::spawn(async move {
+        for (user, user_detail, detail) in evaluations {
+            record_access(
+                &recorder,
+                &toggle,
+                user.clone(),
+                track_access_events,
+                &detail,
+                ts,
+            );
+            record_debug(
+                &recorder,
+                &toggle,
+                user,
+                user_detail,
+                debug_until_time,
+                &detail,
+                ts,
+            );
+        }
     });
 }
 
@@ -449,7 +1968,10 @@ fn record_debug(
                 variation_index: detail.variation_index?,
                 version: detail.version,
                 rule_index: detail.rule_index,
-                reason: Some(detail.reason.to_string()),
+                reason: Some(match detail.reason_code {
+                    Some(code) => format!("{code}: {}", detail.reason),
+                    None => detail.reason.to_string(),
+                }),
             };
             recorder.record_event(Event::DebugEvent(debug));
         }
@@ -457,331 +1979,1249 @@ fn record_debug(
     None
 }
 
-#[cfg(test)]
-mod tests {
-    use serde_json::json;
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_feature_probe_bool() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let u = FPUser::new().with("name", "bob").with("city", "1");
+
+        assert!(fp.bool_value("bool_toggle", &u, false));
+        assert!(fp.bool_detail("bool_toggle", &u, false).value);
+    }
+
+    #[test]
+    fn test_feature_probe_number() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let u = FPUser::new().with("name", "bob").with("city", "1");
+
+        assert_eq!(fp.number_value("number_toggle", &u, 0.0), 1.0);
+        assert_eq!(fp.number_detail("number_toggle", &u, 0.0).value, 1.0);
+    }
+
+    #[test]
+    fn test_feature_probe_string() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let u = FPUser::new().with("name", "bob").with("city", "1");
+
+        assert_eq!(
+            fp.string_value("string_toggle", &u, "".to_string()),
+            "1".to_owned()
+        );
+        assert_eq!(
+            fp.string_detail("string_toggle", &u, "".to_owned()).value,
+            "1".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_feature_probe_json() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let u = FPUser::new().with("name", "bob").with("city", "1");
+
+        assert!(fp
+            .json_value("json_toggle", &u, json!(""))
+            .get("variation_0")
+            .is_some());
+        assert!(fp
+            .json_detail("json_toggle", &u, json!(""))
+            .value
+            .get("variation_0")
+            .is_some());
+    }
+
+    #[test]
+    fn test_feature_probe_none_exist_toggle() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let u = FPUser::new();
+
+        assert!(fp.bool_value("none_exist_toggle", &u, true));
+        let d = fp.bool_detail("none_exist_toggle", &u, true);
+        assert!(d.value);
+        assert_eq!(d.rule_index, None);
+    }
+
+    #[test]
+    fn test_for_ut() {
+        let fp = FeatureProbe::new_for_test("toggle_1", Value::Bool(false));
+        let u = FPUser::new();
+        assert!(!fp.bool_value("toggle_1", &u, true));
+
+        let mut toggles: HashMap<String, Value> = HashMap::new();
+        toggles.insert("toggle_2".to_owned(), json!(12.5));
+        toggles.insert("toggle_3".to_owned(), json!("value"));
+        let fp = FeatureProbe::new_for_tests(toggles);
+        assert_eq!(fp.number_value("toggle_2", &u, 20.0), 12.5);
+        assert_eq!(fp.string_value("toggle_3", &u, "val".to_owned()), "value");
+    }
+
+    #[test]
+    fn test_from_fixture() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let u = FPUser::new().with("city", "1");
+        assert!(fp.bool_value("bool_toggle", &u, false));
+
+        let r = FeatureProbe::from_fixture("resources/fixtures/not_exist.json");
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_snapshot_export_import() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let bytes = fp.export_snapshot();
+
+        let fp2 = FeatureProbe::new_with("secret key".to_string(), Repository::default());
+        let u = FPUser::new().with("city", "1");
+        assert!(!fp2.bool_value("bool_toggle", &u, false));
+        let snapshot = fp2.import_snapshot(&bytes).unwrap();
+        assert_eq!(snapshot.version(), fp.repo.read().version);
+        assert!(fp2.bool_value("bool_toggle", &u, false));
+
+        assert!(fp2.import_snapshot(b"not a snapshot").is_err());
+        assert!(fp2.bool_value("bool_toggle", &u, false));
+    }
+
+    #[test]
+    fn test_client_toggles() {
+        let mut repo = load_local_json("resources/fixtures/repo.json").unwrap();
+        for t in repo.toggles.values_mut() {
+            t.for_client = t.key == "bool_toggle";
+        }
+        let fp = FeatureProbe::new_with("secret key".to_string(), repo);
+        let u = FPUser::new().with("city", "1");
+
+        let toggles = fp.client_toggles(&u);
+        assert_eq!(toggles.len(), 1);
+        assert_eq!(toggles["bool_toggle"].value, Some(json!(true)));
+        assert_eq!(toggles["bool_toggle"].rule_index, Some(0));
+
+        let payload = fp.client_toggles_json(&u).unwrap();
+        assert!(payload.contains("\"ruleIndex\":0"));
+    }
+
+    #[test]
+    fn test_is_enabled() {
+        let fp = FeatureProbe::new_for_tests(HashMap::from([
+            ("on".to_owned(), json!(true)),
+            ("off".to_owned(), json!(false)),
+            ("text".to_owned(), json!("true")),
+        ]));
+        let u = FPUser::new();
+        assert!(fp.is_enabled("on", &u));
+        assert!(!fp.is_enabled("off", &u));
+        assert!(!fp.is_enabled("text", &u));
+        assert!(!fp.is_enabled("missing", &u));
+        assert_eq!(fp.enabled_toggles(&u), vec!["on".to_owned()]);
+
+        fp.override_toggle("off", json!(true));
+        assert_eq!(
+            fp.enabled_toggles(&u),
+            vec!["off".to_owned(), "on".to_owned()]
+        );
+    }
+
+    #[derive(Debug, Default, PartialEq)]
+    enum Tier {
+        #[default]
+        Free,
+        Pro,
+    }
+
+    impl FromStr for Tier {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "free" => Ok(Tier::Free),
+                "pro" => Ok(Tier::Pro),
+                _ => Err(format!("unknown tier {s}")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_duration_and_enum_value() {
+        let fp = FeatureProbe::new_for_tests(HashMap::from([
+            ("timeout".to_owned(), json!("500ms")),
+            ("bad_timeout".to_owned(), json!("soon")),
+            ("tier".to_owned(), json!("pro")),
+            ("bad_tier".to_owned(), json!("gold")),
+            ("number".to_owned(), json!(1)),
+        ]));
+        let u = FPUser::new();
+        let default = Duration::from_secs(1);
+        assert_eq!(
+            fp.duration_value("timeout", &u, default),
+            Duration::from_millis(500)
+        );
+        assert_eq!(fp.duration_value("missing", &u, default), default);
+        let detail = fp.duration_detail("bad_timeout", &u, default);
+        assert_eq!(detail.value, default);
+        assert_eq!(detail.reason_code, Some(ReasonCode::TypeMismatch));
+        assert!(detail.reason.contains("duration \"soon\""));
+
+        assert_eq!(fp.enum_value("tier", &u, Tier::Free), Tier::Pro);
+        let detail = fp.enum_detail("bad_tier", &u, Tier::Free);
+        assert_eq!(detail.value, Tier::Free);
+        assert!(detail.reason.contains("unknown tier gold"));
+        let detail = fp.enum_detail("number", &u, Tier::Free);
+        assert_eq!(detail.reason_code, Some(ReasonCode::TypeMismatch));
+    }
+
+    #[test]
+    fn test_config_value() {
+        let mut toggle = Toggle::new_for_test("pool".to_owned(), json!(1));
+        toggle.variations = vec![json!(1), json!(2), json!(3)];
+        toggle.default_serve = serde_json::from_value(json!({"split": {
+            "distribution": [[[0, 5000]], [[5000, 10000]]]
+        }}))
+        .unwrap();
+        toggle.rules = serde_json::from_value(json!([
+            {"serve": {"select": 2}, "conditions": [{
+                "type": "string", "subject": "city", "predicate": "is one of", "objects": ["1"]
+            }]}
+        ]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("pool".to_owned(), toggle);
+        let fp = FeatureProbe::new_with("secret key".to_string(), repo);
+
+        let first = fp.config_value("pool", json!(0));
+        assert!(first == json!(1) || first == json!(2));
+        for _ in 0..20 {
+            assert_eq!(fp.clone().config_value("pool", json!(0)), first);
+        }
+        let detail = fp.config_detail("pool", json!(0));
+        assert_eq!(detail.rule_index, None);
+        assert_eq!(fp.config_value("missing", json!(0)), json!(0));
+    }
+
+    #[test]
+    fn test_raw_toggle_and_segment() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+
+        let raw = fp.raw_toggle("bool_toggle").unwrap();
+        let toggle: Toggle = serde_json::from_str(&raw).unwrap();
+        assert_eq!(Some(&toggle), fp.repo.read().toggles.get("bool_toggle"));
+        assert!(fp.raw_toggle("none_exist_toggle").is_none());
 
-    use super::*;
-    use crate::FPError;
-    use std::fs;
-    use std::path::PathBuf;
+        let raw = fp.raw_segment("some_segment1-fjoaefjaam").unwrap();
+        assert!(raw.contains("\"uniqueId\""));
+        assert!(fp.raw_segment("none_exist_segment").is_none());
+    }
 
     #[test]
-    fn test_feature_probe_bool() {
+    fn test_override_toggle() {
         let json = load_local_json("resources/fixtures/repo.json");
         let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
         let u = FPUser::new().with("name", "bob").with("city", "1");
+        assert!(fp.bool_value("bool_toggle", &u, false));
+
+        fp.override_toggle("bool_toggle", json!(false));
+        let d = fp.bool_detail("bool_toggle", &u, true);
+        assert!(!d.value);
+        assert!(d.reason.contains("overridden"));
+
+        fp.override_toggle("none_exist_toggle", json!(true));
+        assert!(fp.bool_value("none_exist_toggle", &u, false));
 
+        fp.clear_override("bool_toggle");
         assert!(fp.bool_value("bool_toggle", &u, false));
-        assert!(fp.bool_detail("bool_toggle", &u, false).value);
+        fp.clear_all_overrides();
+        assert!(!fp.bool_value("none_exist_toggle", &u, false));
     }
 
     #[test]
-    fn test_feature_probe_number() {
+    fn test_set_clock() {
+        use crate::clock::MockClock;
+
         let json = load_local_json("resources/fixtures/repo.json");
-        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
-        let u = FPUser::new().with("name", "bob").with("city", "1");
+        let mut fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        let clock = MockClock::new(0);
+        fp.set_clock(Arc::new(clock.clone()));
+        assert_eq!(fp.config.clock.now(), 0);
 
-        assert_eq!(fp.number_value("number_toggle", &u, 0.0), 1.0);
-        assert_eq!(fp.number_detail("number_toggle", &u, 0.0).value, 1.0);
+        clock.advance(std::time::Duration::from_secs(1));
+        assert_eq!(fp.config.clock.now(), 1000);
     }
 
     #[test]
-    fn test_feature_probe_string() {
-        let json = load_local_json("resources/fixtures/repo.json");
-        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
-        let u = FPUser::new().with("name", "bob").with("city", "1");
+    fn test_clock_flips_datetime_toggle() {
+        let mut toggle = Toggle::new_for_test("launch".to_owned(), json!(false));
+        toggle.variations = vec![json!(false), json!(true)];
+        toggle.rules = serde_json::from_value(json!([{
+            "serve": {"select": 1},
+            "conditions": [{
+                "type": "datetime",
+                "subject": "",
+                "predicate": "after",
+                "objects": ["100"]
+            }]
+        }]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("launch".to_owned(), toggle);
+        let mut fp = FeatureProbe::new_with("secret".to_string(), repo);
+        let clock = crate::MockClock::new(99_000);
+        fp.set_clock(Arc::new(clock.clone()));
+        let user = FPUser::new();
+        assert!(!fp.bool_value("launch", &user, true));
+
+        // datetime objects are in seconds, the clock in milliseconds
+        clock.advance(std::time::Duration::from_millis(999));
+        assert!(!fp.bool_value("launch", &user, true));
+        clock.advance(std::time::Duration::from_millis(1));
+        assert!(fp.bool_value("launch", &user, false));
+    }
 
-        assert_eq!(
-            fp.string_value("string_toggle", &u, "".to_string()),
-            "1".to_owned()
-        );
-        assert_eq!(
-            fp.string_detail("string_toggle", &u, "".to_owned()).value,
-            "1".to_owned()
-        );
+    #[test]
+    fn test_max_staleness() {
+        let clock = crate::MockClock::new(10_000);
+        let mut fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        fp.set_clock(Arc::new(clock.clone()));
+        fp.config.max_staleness = Some(Duration::from_secs(5));
+        *fp.last_synced.write() = Some(10_000);
+        let u = FPUser::new();
+
+        assert!(!fp.is_stale());
+        let detail = fp.bool_detail("toggle_1", &u, false);
+        assert!(!detail.reason.starts_with("Stale"));
+
+        clock.advance(Duration::from_secs(6));
+        assert!(fp.is_stale());
+        let detail = fp.bool_detail("toggle_1", &u, false);
+        assert!(detail.value);
+        assert!(detail.reason.starts_with("Stale"));
+
+        fp.config.stale_policy = StalePolicy::ServeDefault;
+        let detail = fp.bool_detail("toggle_1", &u, false);
+        assert!(!detail.value);
+        assert!(detail.reason.starts_with("Stale"));
     }
 
     #[test]
-    fn test_feature_probe_json() {
-        let json = load_local_json("resources/fixtures/repo.json");
-        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
-        let u = FPUser::new().with("name", "bob").with("city", "1");
+    fn test_value_with_fallback() {
+        let mut toggles = HashMap::new();
+        toggles.insert("new_pricing".to_owned(), json!("v1"));
+        toggles.insert("new_pricing_v2".to_owned(), json!("v2"));
+        let fp = FeatureProbe::new_for_tests(toggles);
+        let u = FPUser::new();
 
-        assert!(fp
-            .json_value("json_toggle", &u, json!(""))
-            .get("variation_0")
-            .is_some());
-        assert!(fp
-            .json_detail("json_toggle", &u, json!(""))
-            .value
-            .get("variation_0")
-            .is_some());
+        let v = fp.value_with_fallback(&["new_pricing_v2", "new_pricing"], &u, json!("none"));
+        assert_eq!(v, json!("v2"));
+        let v = fp.value_with_fallback(&["new_pricing_v3", "new_pricing"], &u, json!("none"));
+        assert_eq!(v, json!("v1"));
+
+        let d = fp.detail_with_fallback(&["not_exist"], &u, json!("none"));
+        assert_eq!(d.value, json!("none"));
+        assert!(d.reason.contains("none serve"));
     }
 
     #[test]
-    fn test_feature_probe_none_exist_toggle() {
-        let json = load_local_json("resources/fixtures/repo.json");
-        let fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+    fn test_shadow_evaluation() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        fp.on_shadow_mismatch(Box::new(move |m| tx.send(m).unwrap()));
         let u = FPUser::new();
 
-        assert!(fp.bool_value("none_exist_toggle", &u, true));
-        let d = fp.bool_detail("none_exist_toggle", &u, true);
-        assert!(d.value);
-        assert_eq!(d.rule_index, None);
+        assert!(fp.bool_value("toggle_1", &u, false));
+        assert_eq!(fp.shadow_stats(), ShadowStats::default());
+
+        let mut candidate = Repository::default();
+        candidate.toggles.insert(
+            "toggle_1".to_owned(),
+            Toggle::new_for_test("toggle_1".to_owned(), json!(false)),
+        );
+        fp.set_shadow_repository(candidate);
+        assert!(fp.bool_value("toggle_1", &u, false));
+
+        let mismatch = rx.try_recv().unwrap();
+        assert_eq!(mismatch.toggle, "toggle_1");
+        assert_eq!(mismatch.primary, Some(json!(true)));
+        assert_eq!(mismatch.shadow, Some(json!(false)));
+        assert_eq!(fp.shadow_stats().evaluations, 1);
+        assert_eq!(fp.shadow_stats().mismatches, 1);
+
+        fp.clear_shadow_repository();
+        fp.bool_value("toggle_1", &u, false);
+        assert_eq!(fp.shadow_stats().evaluations, 1);
     }
 
     #[test]
-    fn test_for_ut() {
-        let fp = FeatureProbe::new_for_test("toggle_1", Value::Bool(false));
+    fn test_json_detail_raw() {
+        let mut toggle = Toggle::new_for_test("toggle_1".to_owned(), json!(7));
+        toggle.last_modified = Some(1700000000000);
+        toggle.track_access_events = Some(true);
+        let mut repo = Repository::default();
+        repo.toggles.insert("toggle_1".to_owned(), toggle);
+        repo.debug_until_time = Some(42);
+        let fp = FeatureProbe::new_with("secret key".to_owned(), repo);
         let u = FPUser::new();
-        assert!(!fp.bool_value("toggle_1", &u, true));
 
-        let mut toggles: HashMap<String, Value> = HashMap::new();
-        toggles.insert("toggle_2".to_owned(), json!(12.5));
-        toggles.insert("toggle_3".to_owned(), json!("value"));
+        let detail = fp.json_detail_raw("toggle_1", &u).unwrap();
+        assert_eq!(detail.value, Some(json!(7)));
+        assert_eq!(detail.last_modified, Some(1700000000000));
+        assert_eq!(detail.track_access_events, Some(true));
+        assert_eq!(detail.debug_until_time, Some(42));
+        assert!(fp.json_detail_raw("missing", &u).is_none());
+    }
+
+    #[test]
+    fn test_golden_replay() {
+        let path = std::env::temp_dir().join(format!("fp-golden-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        let u = FPUser::anonymous()
+            .with("city", "1")
+            .with_private("email", "a@b.c");
+        fp.start_golden_recording(&path).unwrap();
+        assert!(fp.bool_value("toggle_1", &u, false));
+        assert!(!fp.bool_value("missing", &u, false));
+        fp.stop_golden_recording();
+        fp.bool_value("toggle_1", &u, false);
+
+        let records = crate::load_golden(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].user.key, u.key());
+        assert_eq!(records[0].user.attrs.get("city"), Some(&"1".to_owned()));
+        assert_eq!(records[0].user.attrs.get("email"), None);
+        assert!(records[0].user.to_user().is_anonymous());
+        assert_eq!(records[1].result, None);
+        assert!(fp.replay_golden(&path).unwrap().is_clean());
+
+        let upgraded = FeatureProbe::new_for_test("toggle_1", json!(false));
+        let report = upgraded.replay_golden(&path).unwrap();
+        assert_eq!(report.records, 2);
+        assert_eq!(report.diffs.len(), 1);
+        let diff = &report.diffs[0];
+        assert_eq!(diff.index, 0);
+        assert_eq!(diff.recorded.as_ref().unwrap().value, Some(json!(true)));
+        assert_eq!(diff.replayed.as_ref().unwrap().value, Some(json!(false)));
+        fs::remove_file(&path).unwrap();
+
+        fp.start_golden_recording_with_private(&path).unwrap();
+        fp.bool_value("toggle_1", &u, false);
+        fp.stop_golden_recording();
+        let records = crate::load_golden(&path).unwrap();
+        let replayed = records[0].user.to_user();
+        assert_eq!(replayed.get("email"), Some(&"a@b.c".to_owned()));
+        assert!(replayed.is_private("email"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_register_default() {
+        let fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        let u = FPUser::new();
+        fp.register_default("not_exist", json!(true));
+        fp.register_defaults(vec![
+            ("toggle_1".to_owned(), json!(false)),
+            ("name".to_owned(), json!("fp")),
+        ]);
+
+        assert!(fp.registered_bool_value("toggle_1", &u));
+        assert!(fp.registered_bool_value("not_exist", &u));
+        assert_eq!(fp.registered_string_value("name", &u), "fp");
+        assert_eq!(fp.registered_number_value("unregistered", &u), 0.0);
+        assert_eq!(fp.registered_default("name"), Some(json!("fp")));
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let mut toggles = HashMap::new();
+        toggles.insert("toggle_1".to_owned(), json!("on"));
+        toggles.insert("toggle_2".to_owned(), json!(1.0));
         let fp = FeatureProbe::new_for_tests(toggles);
-        assert_eq!(fp.number_value("toggle_2", &u, 20.0), 12.5);
-        assert_eq!(fp.string_value("toggle_3", &u, "val".to_owned()), "value");
+        let u = FPUser::new();
+
+        let detail = fp.bool_detail("toggle_1", &u, false);
+        assert!(!detail.value);
+        assert!(detail
+            .reason
+            .contains("Toggle:[toggle_1] version 0 serves string, expected bool"));
+
+        fp.register_default("toggle_2", json!("off"));
+        let mismatches = fp.type_mismatches();
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[1].toggle, "toggle_2");
+        assert_eq!(mismatches[1].expected, ValueType::String);
+        assert_eq!(mismatches[1].found, ValueType::Number);
+        assert_eq!(fp.reported_mismatches.read().len(), 2);
     }
 
     #[test]
-    fn test_feature_probe_record_debug() {
-        let json = load_local_json("resources/fixtures/repo.json");
-        let mut repo = json.unwrap();
-        repo.debug_until_time = Some(unix_timestamp() as u64 + 60 * 1000);
-        let fp = FeatureProbe::new_with("secret key".to_string(), repo);
-        let u = FPUser::new().with("name", "bob").with("city", "1");
-        fp.bool_value("bool_toggle", &u, false);
+    fn test_archived_toggles() {
+        let mut repo = Repository::default();
+        let archived: Toggle = serde_json::from_value(json!({
+            "key": "old", "enabled": true, "version": 1, "forClient": false,
+            "disabledServe": {"select": 0}, "defaultServe": {"select": 0},
+            "rules": [], "variations": [false, true],
+            "archived": true, "archivedServe": {"select": 1}
+        }))
+        .unwrap();
+        assert!(archived.is_archived());
+        repo.toggles.insert("old".to_owned(), archived);
+        let mut deprecated = Toggle::new_for_test("dep".to_owned(), json!(true));
+        deprecated.deprecated = true;
+        repo.toggles.insert("dep".to_owned(), deprecated);
+        let fp = FeatureProbe::new_with("secret".to_string(), repo);
+
+        let u = FPUser::new();
+        let detail = fp.bool_detail("old", &u, false);
+        assert!(detail.value);
+        assert_eq!(detail.reason_code, Some(ReasonCode::Archived));
+        assert!(fp.bool_value("dep", &u, false));
+        fp.bool_value("old", &u, false);
+        assert_eq!(fp.retired_toggles_requested(), vec!["dep", "old"]);
     }
 
-    fn load_local_json(file: &str) -> Result<Repository, FPError> {
-        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        path.push(file);
-        let json_str = fs::read_to_string(path).unwrap();
-        let repo = crate::evaluate::load_json(&json_str);
-        assert!(repo.is_ok(), "err is {:?}", repo);
-        repo
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_try_new_unreachable() {
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            server_sdk_key: "sdk-key".into(),
+            start_wait: Some(Duration::from_millis(200)),
+            track_events: false,
+            ..Default::default()
+        };
+        let err = FeatureProbe::try_new(config.clone()).unwrap_err();
+        assert!(matches!(err, FPError::InitTimeout(d) if d == Duration::from_millis(200)));
+
+        let config = FPConfig {
+            start_wait: None,
+            ..config
+        };
+        assert!(matches!(
+            FeatureProbe::try_new(config),
+            Err(FPError::InternalError(_))
+        ));
     }
-}
 
-#[cfg(test)]
-mod server_sdk_contract_tests {
-    use crate::{FPDetail, FPError, FPUser, FeatureProbe, Repository};
-    use serde::{Deserialize, Serialize};
-    use serde_json::Value;
-    use std::fmt::Debug;
-    use std::fs;
-    use std::path::PathBuf;
-    use std::string::String;
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_init_policy_cache() {
+        let path = std::env::temp_dir().join(format!("fp-cache-{}.json", std::process::id()));
+        let cached = FeatureProbe::new_for_test("cached", json!(true));
+        fs::write(&path, cached.export_snapshot()).unwrap();
+
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            server_sdk_key: "sdk-key".into(),
+            init_policy: Some(InitPolicy::UseCacheThenSync(path.clone())),
+            track_events: false,
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config.clone());
+        assert!(!fp.initialized());
+        assert!(fp.bool_value("cached", &FPUser::new(), false));
+        fs::remove_file(&path).unwrap();
+        fp.close();
+        assert!(Snapshot::from_bytes(&fs::read(&path).unwrap()).is_ok());
+        fs::remove_file(&path).unwrap();
+
+        let config = FPConfig {
+            init_policy: Some(InitPolicy::RequireSuccess(Duration::from_millis(100))),
+            start_wait: Some(Duration::from_secs(60)),
+            ..config
+        };
+        assert!(matches!(
+            FeatureProbe::try_new(config),
+            Err(FPError::InitTimeout(d)) if d == Duration::from_millis(100)
+        ));
+    }
+
+    #[test]
+    fn test_repo_version_and_debug_active() {
+        let mut repo = Repository::default();
+        repo.version = Some(42);
+        repo.debug_until_time = Some(2_000);
+        let mut fp = FeatureProbe::new_with("secret".to_string(), repo);
+        let clock = Arc::new(crate::MockClock::new(1_000));
+        fp.config.clock = clock.clone();
+        assert_eq!(fp.version(), None);
+        assert_eq!(fp.repo_version(), Some(42));
+        assert_eq!(fp.debug_active(), Some(2_000));
+        // the last millisecond still records debug events
+        clock.set(2_000);
+        assert_eq!(fp.debug_active(), Some(2_000));
+        clock.set(2_001);
+        assert_eq!(fp.debug_active(), None);
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let mut toggle = Toggle::new_for_test("mail".to_owned(), json!(false));
+        toggle.variations = vec![json!(false), json!(true)];
+        toggle.rules = serde_json::from_value(json!([{
+            "serve": {"select": 1},
+            "conditions": [{
+                "type": "string",
+                "subject": "email",
+                "predicate": "matches regex",
+                "objects": [".*@featureprobe\\.com$", "("]
+            }]
+        }]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("mail".to_owned(), toggle);
+        let fp = FeatureProbe::new_with("secret".to_string(), repo);
+        let users = [
+            FPUser::new().with("email", "a@featureprobe.com"),
+            FPUser::new().with("email", "b@example.com"),
+        ];
+        let report = fp.warm_up(&["mail", "gone"]);
+        assert_eq!(
+            report,
+            WarmUpReport {
+                toggles: 1,
+                patterns: 2,
+                missing: vec!["gone".to_owned()],
+            }
+        );
+        assert!(fp.bool_value("mail", &users[0], false));
+        assert!(!fp.bool_value("mail", &users[1], false));
+    }
+
+    #[test]
+    fn test_eval_soft_limits() {
+        let mut repo = Repository::default();
+        repo.toggles.insert(
+            "loop".to_owned(),
+            Toggle::new_for_test("loop".to_owned(), json!(true)),
+        );
+        let clock = Arc::new(crate::MockClock::new(5_000));
+        let mut fp = FeatureProbe::new_with("secret".to_string(), repo);
+        fp.config.clock = clock.clone();
+        fp.config.eval_soft_limits = Some(crate::EvalSoftLimits {
+            per_second: Some(10),
+            ..Default::default()
+        });
+        let user = FPUser::new();
+        for _ in 0..10 {
+            fp.bool_value("loop", &user, false);
+        }
+        assert!(fp.soft_limit_breaches().is_empty());
+        for _ in 0..5 {
+            fp.bool_value("loop", &user, false);
+        }
+        let breaches = fp.soft_limit_breaches();
+        assert_eq!(breaches[0].toggle, "loop");
+        assert_eq!((breaches[0].peak, breaches[0].seconds), (15, 1));
+        assert!(fp
+            .warning_counts()
+            .iter()
+            .any(|w| w.kind == crate::WarningKind::EvalSoftLimit && w.subject == "loop"));
 
-    #[allow(dead_code)]
-    pub(crate) fn load_tests_json(json_str: &str) -> Result<Tests, FPError> {
-        serde_json::from_str::<Tests>(json_str)
-            .map_err(|e| FPError::JsonError(json_str.to_owned(), e))
+        let other = FeatureProbe::new_with("secret".to_string(), Repository::default());
+        assert!(other.warning_counts().is_empty());
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Tests {
-        pub(crate) tests: Vec<Scenario>,
+    #[test]
+    fn test_suppressed_events() {
+        let repo: Repository = serde_json::from_value(json!({
+            "segments": {},
+            "toggles": {},
+            "version": 1,
+            "suppressedEvents": ["hot"]
+        }))
+        .unwrap();
+        assert!(repo.suppressed_events.contains("hot"));
+        let mut fp = FeatureProbe::new_with("secret".to_string(), repo.clone());
+        fp.config.suppressed_events.insert("local".to_owned());
+        assert!(fp.events_suppressed(&repo, "hot"));
+        assert!(fp.events_suppressed(&repo, "local"));
+        assert!(!fp.events_suppressed(&repo, "other"));
+
+        let json = serde_json::to_value(&repo).unwrap();
+        assert_eq!(json["suppressedEvents"], json!(["hot"]));
+        let json = serde_json::to_value(Repository::default()).unwrap();
+        assert!(json.get("suppressedEvents").is_none());
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-    pub struct Scenario {
-        pub(crate) scenario: String,
-        pub(crate) cases: Vec<Case>,
-        pub(crate) fixture: Repository,
+    #[test]
+    fn test_unicode_normalization() {
+        let mut toggle = Toggle::new_for_test("cafe".to_owned(), json!(false));
+        toggle.variations = vec![json!(false), json!(true)];
+        toggle.rules = serde_json::from_value(json!([{
+            "serve": {"select": 1},
+            "conditions": [{
+                "type": "string",
+                "subject": "name",
+                "predicate": "is one of",
+                "objects": ["cafe\u{301}"]
+            }]
+        }]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("cafe".to_owned(), toggle);
+        let mut fp = FeatureProbe::new_with("secret".to_string(), repo);
+        let composed = FPUser::new().with("name", "caf\u{e9}");
+        let decomposed = FPUser::new().with("name", "cafe\u{301}");
+        assert!(!fp.bool_value("cafe", &composed, false));
+
+        fp.config.unicode_normalization = Some(crate::UnicodeNormalization::Nfc);
+        fp.import_snapshot(&fp.export_snapshot()).unwrap();
+        assert!(fp.bool_value("cafe", &composed, false));
+        assert!(fp.bool_value("cafe", &decomposed, false));
+        assert_eq!(
+            fp.try_detail("cafe", &decomposed).unwrap().value,
+            json!(true)
+        );
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-    #[serde(rename_all = "camelCase")]
-    pub struct Case {
-        pub(crate) name: String,
-        pub(crate) user: User,
-        pub(crate) function: Function,
-        pub(crate) expect_result: ExpectResult,
+    #[tokio::test]
+    async fn test_configured_user_limits() {
+        let mut toggle = Toggle::new_for_test("plan".to_owned(), json!(false));
+        toggle.variations = vec![json!(false), json!(true)];
+        toggle.rules = serde_json::from_value(json!([{
+            "serve": {"select": 1},
+            "conditions": [{
+                "type": "string",
+                "subject": "plan",
+                "predicate": "is one of",
+                "objects": ["gold"]
+            }]
+        }]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("plan".to_owned(), toggle);
+        repo.version = Some(1);
+        let client = |user_limits| {
+            FeatureProbe::new(FPConfig {
+                remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+                track_events: false,
+                refresh_interval: Duration::from_secs(60),
+                http_transport: Some(Arc::new(Posts(
+                    repo.clone(),
+                    parking_lot::Mutex::new(vec![]),
+                ))),
+                user_limits,
+                ..Default::default()
+            })
+        };
+        let limited = client(Some(crate::UserLimits {
+            max_attrs: 10,
+            max_value_len: 4,
+            policy: crate::LimitPolicy::Truncate,
+        }));
+        let unlimited = client(None);
+        assert!(limited.wait_until_initialized(Duration::from_secs(1)).await);
+        assert!(
+            unlimited
+                .wait_until_initialized(Duration::from_secs(1))
+                .await
+        );
+
+        let u = FPUser::new().with("plan", "goldish");
+        assert!(limited.bool_value("plan", &u, false));
+        assert!(!unlimited.bool_value("plan", &u, false));
+        assert_eq!(u.get("plan").unwrap(), "goldish");
+        limited.close();
+        unlimited.close();
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-    #[serde(rename_all = "camelCase")]
-    pub struct User {
-        pub(crate) key: String,
-        pub(crate) custom_values: Vec<KeyValue>,
+    #[test]
+    fn test_sticky_buckets_across_salt_rotation() {
+        let split = |salt: &str| {
+            let mut toggle = Toggle::new_for_test("exp".to_owned(), json!(false));
+            toggle.variations = vec![json!(false), json!(true)];
+            toggle.default_serve = serde_json::from_value(json!({"split": {
+                "distribution": [[[0, 5000]], [[5000, 10000]]],
+                "salt": salt
+            }}))
+            .unwrap();
+            toggle
+        };
+        let mut repo = Repository::default();
+        repo.toggles.insert("exp".to_owned(), split("a"));
+        let store = Arc::new(crate::InMemoryStickyBuckets::default());
+        let mut fp = FeatureProbe::new_with("secret".to_string(), repo);
+        fp.config.sticky_buckets = Some(store.clone());
+
+        let users: Vec<FPUser> = (0..50)
+            .map(|i| FPUser::new().stable_rollout(format!("u{i}")))
+            .collect();
+        let before: Vec<bool> = users
+            .iter()
+            .map(|u| fp.bool_value("exp", u, false))
+            .collect();
+        assert_eq!(store.len(), 50);
+
+        fp.repo.write().toggles.insert("exp".to_owned(), split("b"));
+        let reshuffled = users
+            .iter()
+            .zip(&before)
+            .filter(|(u, v)| split("b").bucket_of(u).unwrap().1 != **v as usize)
+            .count();
+        assert!(reshuffled > 0);
+        for (u, v) in users.iter().zip(&before) {
+            let detail = fp.bool_detail("exp", u, false);
+            assert_eq!(detail.value, *v);
+        }
+        let fresh = FPUser::new().stable_rollout("fresh".to_owned());
+        let detail = fp.bool_detail("exp", &fresh, false);
+        assert!(!detail.reason.contains("Pinned"));
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-    pub struct KeyValue {
-        pub(crate) key: String,
-        pub(crate) value: String,
+    #[test]
+    fn test_missing_toggles() {
+        let fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        let u = FPUser::new();
+        fp.bool_value("toggle_1", &u, false);
+        fp.bool_value("toggle_typo", &u, false);
+        fp.string_value("toggle_typo", &u, "".to_owned());
+
+        let missing = fp.missing_toggles();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].key, "toggle_typo");
+        assert_eq!(missing[0].count, 2);
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-    pub struct Function {
-        pub(crate) name: String,
-        pub(crate) toggle: String,
-        pub(crate) default: Value,
+    #[test]
+    fn test_simulate() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        // disabled toggles are simulated as enabled
+        let simulation = fp.simulate("disabled_toggle", 100).unwrap();
+        assert_eq!(simulation.variations.iter().sum::<u64>(), 100);
+        assert!(fp.simulate("not_exist", 100).is_none());
     }
 
-    #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-    #[serde(rename_all = "camelCase")]
-    pub struct ExpectResult {
-        pub(crate) value: Value,
-        pub(crate) reason: Option<String>,
-        pub(crate) rule_index: Option<usize>,
-        pub(crate) no_rule_index: Option<bool>,
-        pub(crate) version: Option<u64>,
+    #[test]
+    fn test_circuit_breaker() {
+        let mut repo = Repository::default();
+        let mut toggle = Toggle::new_for_test("broken".to_owned(), json!(true));
+        toggle.default_serve = crate::evaluate::Serve::Select(9);
+        repo.toggles.insert("broken".to_owned(), toggle);
+        let mut fp = FeatureProbe::new_with("key".to_owned(), repo);
+        let clock = crate::MockClock::new(0);
+        fp.set_clock(Arc::new(clock.clone()));
+        fp.config.circuit_breaker = Some(crate::CircuitBreakerConfig {
+            threshold: 2,
+            cooldown: Duration::from_secs(10),
+        });
+        let u = FPUser::new();
+
+        fp.bool_detail("broken", &u, false);
+        fp.bool_detail("broken", &u, false);
+        assert_eq!(fp.open_circuits(), vec!["broken"]);
+        let detail = fp.bool_detail("broken", &u, false);
+        assert!(detail.reason.contains("circuit open"));
+
+        clock.advance(Duration::from_secs(10));
+        assert!(fp.open_circuits().is_empty());
+        let detail = fp.bool_detail("broken", &u, false);
+        assert!(!detail.reason.contains("circuit open"));
+        assert_eq!(fp.open_circuits(), vec!["broken"]);
     }
 
     #[test]
-    fn test_contract() {
-        let root = load_test_json("resources/fixtures/spec/spec/toggle_simple_spec.json");
-        assert!(root.is_ok());
+    fn test_values_for_users() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let users = vec![
+            FPUser::new().with("city", "1"),
+            FPUser::new().with("city", "4"),
+            FPUser::new(),
+        ];
+        let values = fp.values_for_users("bool_toggle", &users, json!(null));
+        assert_eq!(values, vec![json!(true), json!(false), json!(true)]);
+        for (user, value) in users.iter().zip(values.iter()) {
+            assert_eq!(&fp.json_value("bool_toggle", user, json!(null)), value);
+        }
+
+        let details = fp.details_for_users("not_exist", &users, json!(1));
+        assert!(details.iter().all(|d| d.value == json!(1)));
 
-        for scenario in root.unwrap().tests {
-            println!("scenario: {}", scenario.scenario);
-            assert!(!scenario.cases.is_empty());
+        fp.override_toggle("bool_toggle", json!(false));
+        let values = fp.values_for_users("bool_toggle", &users, json!(null));
+        assert!(values.iter().all(|v| v == &json!(false)));
+    }
 
-            let fp = FeatureProbe::new_with("secret key".to_string(), scenario.fixture);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_bulk_values() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let users: Vec<FPUser> = (0..BATCH_CHUNK_SIZE * 2 + 1)
+            .map(|i| FPUser::new().with("city", &(i % 5).to_string()))
+            .collect();
+        let values = fp.bulk_values(&["bool_toggle", "not_exist"], &users, json!(null));
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["not_exist"].len(), users.len());
+        let bools = &values["bool_toggle"];
+        assert_eq!(bools.len(), users.len());
+        for (user, value) in users.iter().zip(bools.iter()) {
+            assert_eq!(&fp.json_value("bool_toggle", user, json!(null)), value);
+        }
+    }
 
-            for case in scenario.cases {
-                println!("  case: {}", case.name);
+    #[test]
+    fn test_try_detail() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let u = FPUser::new().with("city", "4");
+
+        let detail = fp.try_detail("prerequisite_toggle", &u).unwrap();
+        assert_eq!(detail.prerequisites.len(), 3);
+        assert!(matches!(
+            fp.try_detail("prerequisite_toggle_not_exist", &u),
+            Err(FPError::PrerequisiteNotExist(key)) if key == "bool_toggle_not_exist"
+        ));
+        assert!(matches!(
+            fp.try_detail("not_exist", &u),
+            Err(FPError::ToggleNotExist(_))
+        ));
+
+        let mut toggles = fp.repo.read().toggles.clone();
+        let mut deep = toggles.get("prerequisite_toggle").unwrap().clone();
+        deep.key = "deep".to_owned();
+        deep.prerequisites = Some(vec![crate::evaluate::Prerequisites {
+            key: "deep".to_owned(),
+            value: json!(true),
+        }]);
+        toggles.insert("deep".to_owned(), deep);
+        fp.repo.write().toggles = toggles;
+        assert!(matches!(
+            fp.try_detail("deep", &u),
+            Err(FPError::PrerequisiteDepthOverflow)
+        ));
+    }
 
-                let mut user = FPUser::new().stable_rollout(case.user.key.clone());
-                for custom_value in &case.user.custom_values {
-                    user = user.with(custom_value.key.clone(), custom_value.value.clone());
-                }
+    #[test]
+    fn test_reason_codes() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let code = |toggle: &str, city: &str| {
+            let u = FPUser::new().with("city", city);
+            fp.bool_detail(toggle, &u, false).reason_code
+        };
+        assert_eq!(code("bool_toggle", "1"), Some(ReasonCode::RuleMatch));
+        assert_eq!(code("bool_toggle", "100"), Some(ReasonCode::DefaultServe));
+        assert_eq!(code("not_exist", "1"), Some(ReasonCode::ToggleNotExist));
+        assert_eq!(code("string_toggle", "1"), Some(ReasonCode::TypeMismatch));
+        let u = FPUser::new();
+        let detail = fp.json_detail("prerequisite_toggle_not_exist", &u, json!(null));
+        assert_eq!(detail.reason_code, Some(ReasonCode::PrerequisiteError));
 
-                macro_rules! validate_value {
-                    ( $fun:ident, $default:expr, $expect:expr) => {
-                        let ret = fp.$fun(case.function.toggle.as_str(), &user, $default);
-                        assert_eq!(ret, $expect);
-                    };
-                }
+        fp.override_toggle("bool_toggle", json!(false));
+        assert_eq!(code("bool_toggle", "1"), Some(ReasonCode::Overridden));
+    }
 
-                macro_rules! validate_detail {
-                    ( $fun:ident, $default:expr, $expect:expr) => {
-                        let ret = fp.$fun(case.function.toggle.as_str(), &user, $default);
-                        assert_eq!(ret.value, $expect);
-                        assert_detail(&case, ret);
-                    };
-                }
+    #[test]
+    fn test_extra_attrs() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let u = FPUser::new().with("city", "100");
+        assert!(fp.bool_value("bool_toggle", &u, false));
 
-                match case.function.name.as_str() {
-                    "bool_value" => {
-                        validate_value!(
-                            bool_value,
-                            case.function.default.as_bool().unwrap(),
-                            case.expect_result.value.as_bool().unwrap()
-                        );
-                    }
-                    "string_value" => {
-                        validate_value!(
-                            string_value,
-                            case.function.default.as_str().unwrap().to_string(),
-                            case.expect_result.value.as_str().unwrap().to_string()
-                        );
-                    }
-                    "number_value" => {
-                        validate_value!(
-                            number_value,
-                            case.function.default.as_f64().unwrap(),
-                            case.expect_result.value.as_f64().unwrap()
-                        );
-                    }
-                    "json_value" => {
-                        validate_value!(
-                            json_value,
-                            case.function.default,
-                            case.expect_result.value
-                        );
-                    }
-                    "bool_detail" => {
-                        validate_detail!(
-                            bool_detail,
-                            case.function.default.as_bool().unwrap(),
-                            case.expect_result.value
-                        );
-                    }
-                    "string_detail" => {
-                        validate_detail!(
-                            string_detail,
-                            case.function.default.as_str().unwrap().to_string(),
-                            case.expect_result.value
-                        );
-                    }
-                    "number_detail" => {
-                        validate_detail!(
-                            number_detail,
-                            case.function.default.as_f64().unwrap(),
-                            case.expect_result.value
-                        );
-                    }
-                    "json_detail" => {
-                        validate_detail!(
-                            json_detail,
-                            case.function.default.clone(),
-                            case.expect_result.value
-                        );
-                    }
-                    _ => panic!("function name {} not found.", case.function.name),
-                }
+        let mut extra = HashMap::new();
+        extra.insert("city".to_owned(), "4".to_owned());
+        assert!(!fp.bool_value_with_attrs("bool_toggle", &u, &extra, true));
+        let detail = fp.bool_detail_with_attrs("bool_toggle", &u, &extra, true);
+        assert_eq!(detail.rule_index, Some(1));
+        assert_eq!(u.get("city"), Some(&"100".to_owned()));
+    }
+
+    #[test]
+    fn test_emergency_disable() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+        let u = FPUser::new().with("city", "1");
+        assert!(fp.bool_value("bool_toggle", &u, false));
+
+        fp.emergency_disable("bool_toggle");
+        let detail = fp.bool_detail("bool_toggle", &u, true);
+        assert!(!detail.value);
+        assert!(detail.reason.contains("Emergency disabled"));
+
+        fp.emergency_restore("bool_toggle");
+        assert!(fp.bool_value("bool_toggle", &u, false));
+
+        fp.emergency_disable_all();
+        assert!(fp.is_emergency_disabled("number_toggle"));
+        assert!(!fp.bool_value("bool_toggle", &u, true));
+
+        fp.emergency_restore_all();
+        assert!(!fp.is_emergency_disabled("number_toggle"));
+        assert!(fp.bool_value("bool_toggle", &u, false));
+    }
+
+    #[test]
+    fn test_close_on_last_drop() {
+        let mut fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        fp.arm_close_guard();
+        let shutdown = fp.cancellation.clone();
+        let fp2 = fp.clone();
+
+        drop(fp);
+        assert!(!shutdown.is_cancelled());
+        assert!(fp2.bool_value("toggle_1", &FPUser::new(), false));
+
+        drop(fp2);
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[test]
+    fn test_eval_panic_serves_default() {
+        #[derive(Debug)]
+        struct PanicKeyGenerator;
+
+        impl KeyGenerator for PanicKeyGenerator {
+            fn generate(&self) -> String {
+                panic!("generate key")
             }
         }
+
+        let mut fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        fp.set_key_generator(Arc::new(PanicKeyGenerator));
+        let detail = fp.bool_detail("toggle_1", &FPUser::new(), false);
+        assert!(!detail.value);
+        assert!(detail.reason.contains("panicked"));
+
+        let users = [FPUser::new(), FPUser::new().stable_rollout("u".to_owned())];
+        let details = fp.details_for_users("toggle_1", &users, json!(false));
+        assert_eq!(details[0].value, json!(false));
+        assert_eq!(details[0].reason_code, Some(ReasonCode::EvaluationPanicked));
+        assert_eq!(details[1].value, json!(true));
     }
 
-    fn assert_detail<T: Default + Debug>(case: &Case, ret: FPDetail<T>) {
-        match &case.expect_result.reason {
-            None => (),
-            Some(r) => {
-                assert!(
-                    ret.reason.contains(r.as_str()),
-                    "reason: \"{}\" does not contains \"{}\"",
-                    ret.reason.as_str(),
-                    r.as_str()
-                );
-            }
+    #[test]
+    fn test_set_key_generator() {
+        use crate::user::SequenceKeyGenerator;
+
+        let json = load_local_json("resources/fixtures/repo.json");
+        let mut fp = FeatureProbe::new_with("secret key".to_string(), json.unwrap());
+        fp.set_key_generator(Arc::new(SequenceKeyGenerator::new("anonymous-")));
+        let u = FPUser::new();
+        fp.json_value("json_toggle", &u, json!(""));
+        assert_eq!(u.key(), "anonymous-0");
+    }
+
+    #[test]
+    fn test_feature_probe_record_debug() {
+        let json = load_local_json("resources/fixtures/repo.json");
+        let mut repo = json.unwrap();
+        repo.debug_until_time = Some(crate::unix_timestamp() as u64 + 60 * 1000);
+        let fp = FeatureProbe::new_with("secret key".to_string(), repo);
+        let u = FPUser::new().with("name", "bob").with("city", "1");
+        fp.bool_value("bool_toggle", &u, false);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shutdown_flushes_events() {
+        let path = std::env::temp_dir().join(format!("fp-shutdown-{}.jsonl", std::process::id()));
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            track_events: false,
+            event_sinks: vec![Arc::new(crate::FileSink::new(&path))],
+            refresh_interval: Duration::from_secs(60),
+            ..Default::default()
         };
+        let fp = FeatureProbe::new(config);
+        fp.track("checkout", &FPUser::new(), None);
+        assert!(fp.shutdown(Duration::from_secs(1)).await);
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("checkout"));
+        fs::remove_file(path).unwrap();
+    }
 
-        if case.expect_result.rule_index.is_some() {
-            assert_eq!(
-                case.expect_result.rule_index, ret.rule_index,
-                "rule index not match"
-            );
+    #[derive(Debug)]
+    struct Versions(parking_lot::Mutex<Vec<Repository>>);
+
+    #[async_trait::async_trait]
+    impl crate::HttpTransport for Versions {
+        async fn get(
+            &self,
+            _url: url::Url,
+            _headers: http::HeaderMap,
+            _timeout: Duration,
+        ) -> Result<crate::HttpResponse, FPError> {
+            let mut repos = self.0.lock();
+            let repo = match repos.len() {
+                1 => repos[0].clone(),
+                _ => repos.remove(0),
+            };
+            Ok(crate::HttpResponse {
+                status: 200,
+                headers: Default::default(),
+                body: serde_json::to_vec(&repo).unwrap(),
+            })
         }
 
-        if case.expect_result.no_rule_index.is_some() {
-            assert!(
-                case.expect_result.rule_index.is_none(),
-                "should not have rule index."
-            );
+        async fn post_json(
+            &self,
+            _url: url::Url,
+            _headers: http::HeaderMap,
+            _body: Vec<u8>,
+            _timeout: Duration,
+        ) -> Result<crate::HttpResponse, FPError> {
+            Err(FPError::HttpError("toggles only".to_owned()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_value() {
+        let repo = |version: u128, toggle_version: u64, value: Value| {
+            let mut toggle = Toggle::new_for_test("pool_size".to_owned(), value);
+            toggle.version = toggle_version;
+            let mut repo = Repository::default();
+            repo.toggles.insert("pool_size".to_owned(), toggle);
+            repo.version = Some(version);
+            repo
+        };
+        let repos = vec![
+            repo(1, 1, json!(4)),
+            repo(2, 1, json!(4)),
+            repo(3, 2, json!(8)),
+        ];
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            track_events: false,
+            refresh_interval: Duration::from_millis(20),
+            http_transport: Some(Arc::new(Versions(parking_lot::Mutex::new(repos)))),
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config);
+        assert!(fp.wait_until_initialized(Duration::from_secs(1)).await);
+        let mut rx = fp.watch_value("pool_size", FPUser::new(), 1u32);
+        assert_eq!(*rx.borrow(), 4);
+        tokio::time::timeout(Duration::from_secs(2), rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(*rx.borrow(), 8);
+
+        let missing = fp.watch_value("missing", FPUser::new(), 1u32);
+        assert_eq!(*missing.borrow(), 1);
+        fp.close();
+    }
+
+    #[derive(Debug)]
+    struct Posts(Repository, parking_lot::Mutex<Vec<(url::Url, Vec<u8>)>>);
+
+    #[async_trait::async_trait]
+    impl crate::HttpTransport for Posts {
+        async fn get(
+            &self,
+            _url: url::Url,
+            _headers: http::HeaderMap,
+            _timeout: Duration,
+        ) -> Result<crate::HttpResponse, FPError> {
+            Ok(crate::HttpResponse {
+                status: 200,
+                headers: Default::default(),
+                body: serde_json::to_vec(&self.0).unwrap(),
+            })
         }
 
-        if case.expect_result.version.is_some() {
-            assert_eq!(case.expect_result.version, ret.version, "version not match");
+        async fn post_json(
+            &self,
+            url: url::Url,
+            _headers: http::HeaderMap,
+            body: Vec<u8>,
+            _timeout: Duration,
+        ) -> Result<crate::HttpResponse, FPError> {
+            self.1.lock().push((url, body));
+            Ok(crate::HttpResponse {
+                status: 200,
+                headers: Default::default(),
+                body: vec![],
+            })
         }
     }
 
-    fn load_test_json(file: &str) -> Result<Tests, FPError> {
+    #[tokio::test]
+    async fn test_usage_report_export() {
+        let mut repo = Repository::default();
+        repo.toggles.insert(
+            "toggle_1".to_owned(),
+            Toggle::new_for_test("toggle_1".to_owned(), json!(true)),
+        );
+        repo.version = Some(1);
+        let transport = Arc::new(Posts(repo, parking_lot::Mutex::new(vec![])));
+        let usage_url = url::Url::parse("http://127.0.0.1:1/usage").unwrap();
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            track_events: false,
+            refresh_interval: Duration::from_secs(60),
+            http_transport: Some(transport.clone()),
+            usage_report_url: Some(usage_url.clone()),
+            usage_report_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config);
+        assert!(fp.wait_until_initialized(Duration::from_secs(1)).await);
+        let u = FPUser::new();
+        fp.bool_value("toggle_1", &u, false);
+        fp.bool_value("toggle_1", &u, false);
+        fp.bool_value("missing", &u, false);
+
+        let report = fp.usage_report();
+        assert_eq!(report.toggles.len(), 1);
+        assert_eq!(report.toggles[0].key, "toggle_1");
+        assert_eq!(report.toggles[0].count, 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        fp.close();
+        let posts = transport.1.lock();
+        let (url, body) = posts.last().unwrap();
+        assert_eq!(url, &usage_url);
+        let exported: crate::UsageReport = serde_json::from_slice(body).unwrap();
+        assert_eq!(exported.toggles, report.toggles);
+    }
+
+    #[tokio::test]
+    async fn test_external_cancellation_token() {
+        let token = CancellationToken::new();
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            track_events: false,
+            cancellation_token: Some(token.clone()),
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config);
+        assert!(!fp.cancellation_token().is_cancelled());
+        token.cancel();
+        assert!(fp.cancellation_token().is_cancelled());
+
+        // closing the client leaves the application's token alone
+        let token = CancellationToken::new();
+        let config = FPConfig {
+            remote_url: url::Url::parse("http://127.0.0.1:1").unwrap(),
+            track_events: false,
+            cancellation_token: Some(token.clone()),
+            ..Default::default()
+        };
+        FeatureProbe::new(config).close();
+        assert!(!token.is_cancelled());
+    }
+
+    fn load_local_json(file: &str) -> Result<Repository, FPError> {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push(file);
-        let mut json_str = fs::read_to_string(path.clone());
-        if json_str.is_err() {
-            use std::process::Command;
-            Command::new("git")
-                .args(["submodule", "init"])
-                .status()
-                .expect("init");
-            Command::new("git")
-                .args(["submodule", "update"])
-                .status()
-                .expect("update");
-            json_str = fs::read_to_string(path);
-        }
-        assert!(json_str.is_ok(),
-                "contract test resource not found, run `git submodule init && git submodule update` to fetch");
-        let tests = load_tests_json(&json_str.unwrap());
-        assert!(tests.is_ok(), "err is {:?}", tests);
-        tests
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = crate::evaluate::load_json(&json_str);
+        assert!(repo.is_ok(), "err is {:?}", repo);
+        repo
     }
 }
@@ -0,0 +1,183 @@
+use crate::{Event, EventSink, FPError};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// appends one json event per line, rotated files are kept as path.1 .. path.N
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    current: Mutex<Option<Current>>,
+}
+
+#[derive(Debug)]
+struct Current {
+    writer: BufWriter<File>,
+    size: u64,
+    opened: Instant,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes: None,
+            max_age: None,
+            keep: 5,
+            current: Mutex::new(None),
+        }
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = Some(max_bytes);
+    }
+
+    pub fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = Some(max_age);
+    }
+
+    // rotated files beyond this count are deleted
+    pub fn set_keep(&mut self, keep: usize) {
+        self.keep = keep;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, events: &[Event]) -> Result<(), FPError> {
+        let mut current = self.current.lock();
+        for event in events {
+            let mut line = serde_json::to_vec(event).map_err(io_error)?;
+            line.push(b'\n');
+            if current.as_ref().is_some_and(|c| self.should_rotate(c)) {
+                if let Some(mut c) = current.take() {
+                    c.writer.flush().map_err(io_error)?;
+                }
+                self.rotate()?;
+            }
+            let c = match current.as_mut() {
+                Some(c) => c,
+                None => current.insert(self.open()?),
+            };
+            c.writer.write_all(&line).map_err(io_error)?;
+            c.size += line.len() as u64;
+        }
+        if let Some(c) = current.as_mut() {
+            c.writer.flush().map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self, current: &Current) -> bool {
+        self.max_bytes.is_some_and(|max| current.size >= max)
+            || self
+                .max_age
+                .is_some_and(|max| current.opened.elapsed() >= max)
+    }
+
+    fn open(&self) -> Result<Current, FPError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        let size = file.metadata().map_err(io_error)?.len();
+        Ok(Current {
+            writer: BufWriter::new(file),
+            size,
+            opened: Instant::now(),
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> Result<(), FPError> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path).map_err(io_error);
+        }
+        let _ = fs::remove_file(self.rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1)).map_err(io_error)?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1)).map_err(io_error)
+    }
+}
+
+fn io_error(e: impl std::fmt::Display) -> FPError {
+    FPError::EventSinkError(e.to_string())
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    async fn send(&self, events: &[Event]) -> Result<(), FPError> {
+        self.write(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CustomEvent;
+
+    fn custom(name: &str) -> Event {
+        Event::CustomEvent(CustomEvent {
+            kind: "custom".to_owned(),
+            time: 1,
+            user: "u".to_owned(),
+            name: name.to_owned(),
+            value: None,
+        })
+    }
+
+    #[test]
+    fn test_file_sink_rotation() {
+        let dir = std::env::temp_dir().join(format!("fp-sink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let mut sink = FileSink::new(&path);
+        sink.set_max_bytes(1);
+        sink.set_keep(2);
+
+        sink.write(&[custom("a"), custom("b"), custom("c")])
+            .unwrap();
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert!(read(path.clone()).contains("\"name\":\"c\""));
+        assert!(read(sink.rotated(1)).contains("\"name\":\"b\""));
+        assert!(read(sink.rotated(2)).contains("\"name\":\"a\""));
+
+        sink.write(&[custom("d")]).unwrap();
+        assert!(read(sink.rotated(2)).contains("\"name\":\"b\""));
+        assert!(!sink.rotated(3).exists());
+        assert_eq!(read(path.clone()).lines().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_sink_age() {
+        let path = std::env::temp_dir().join(format!("fp-sink-age-{}.jsonl", std::process::id()));
+        let mut sink = FileSink::new(&path);
+        sink.set_max_age(Duration::ZERO);
+        sink.set_keep(0);
+        sink.write(&[custom("a")]).unwrap();
+        sink.write(&[custom("b")]).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("\"name\":\"a\""));
+        assert!(content.contains("\"name\":\"b\""));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+// Arbitrary impls that keep generated repositories mostly well formed: keys,
+// subjects and objects come from small pools so rules, segments and
+// prerequisites reference each other, serves may still point out of range
+use crate::evaluate::{
+    BucketRange, Condition, ConditionType, Distribution, Prerequisites, Rule, SegmentRule, Serve,
+};
+use crate::hashing::BUCKET_SIZE;
+use crate::{FPUser, Repository, Segment, Toggle};
+use arbitrary::{Arbitrary, Result, Unstructured};
+use serde_json::Value;
+
+pub const TOGGLE_KEYS: [&str; 4] = ["t0", "t1", "t2", "t3"];
+pub const SEGMENT_KEYS: [&str; 3] = ["s0", "s1", "s2"];
+const SUBJECTS: [&str; 4] = ["city", "age", "version", "locale"];
+const OBJECTS: [&str; 10] = [
+    "1", "2", "10", "1.2.3", "2.0", "en-US", "zh", "a.*", "(", "%2",
+];
+
+fn pick<'a, T: Copy>(u: &mut Unstructured<'a>, items: &[T]) -> Result<T> {
+    u.choose(items).copied()
+}
+
+fn list<'a, T>(
+    u: &mut Unstructured<'a>,
+    max: usize,
+    f: impl Fn(&mut Unstructured<'a>) -> Result<T>,
+) -> Result<Vec<T>> {
+    let len = u.int_in_range(0..=max)?;
+    (0..len).map(|_| f(u)).collect()
+}
+
+pub fn arbitrary_value(u: &mut Unstructured) -> Result<Value> {
+    Ok(match u.int_in_range(0..=3)? {
+        0 => Value::Bool(u.arbitrary()?),
+        1 => Value::from(u.int_in_range(-10..=10)?),
+        2 => Value::from(pick(u, &OBJECTS)?),
+        _ => serde_json::json!({ "size": u.int_in_range(0..=3)? }),
+    })
+}
+
+// attributes from the same pools the conditions use
+pub fn arbitrary_user(u: &mut Unstructured) -> Result<FPUser> {
+    let mut user = FPUser::new().stable_rollout(pick(u, &OBJECTS)?.to_owned());
+    for subject in SUBJECTS {
+        if u.arbitrary()? {
+            user = user.with(subject, pick(u, &OBJECTS)?);
+        }
+    }
+    Ok(user)
+}
+
+fn condition_type(u: &mut Unstructured) -> Result<ConditionType> {
+    u.choose(&[
+        ConditionType::String,
+        ConditionType::Segment,
+        ConditionType::Datetime,
+        ConditionType::Number,
+        ConditionType::Semver,
+        ConditionType::Version,
+        ConditionType::Locale,
+        ConditionType::Unknown,
+    ])
+    .cloned()
+}
+
+fn predicate(u: &mut Unstructured, r#type: &ConditionType) -> Result<&'static str> {
+    let predicates: &[&str] = match r#type {
+        ConditionType::String => &[
+            "is one of",
+            "ends with",
+            "starts with",
+            "contains",
+            "matches regex",
+            "is not any of",
+            "does not end with",
+            "does not start with",
+            "does not contain",
+            "does not match regex",
+        ],
+        ConditionType::Segment => &["is in", "is not in"],
+        ConditionType::Datetime => &["after", "before"],
+        ConditionType::Number => &["=", "!=", ">", ">=", "<", "<=", "%2 ==", "%0 !="],
+        ConditionType::Semver | ConditionType::Version => &["=", "!=", ">", ">=", "<", "<="],
+        ConditionType::Locale => &["is one of", "in region", "is not any of", "not in region"],
+        ConditionType::Unknown => &["unknown"],
+    };
+    let predicate = pick(u, predicates)?;
+    match u.ratio(1, 20)? {
+        true => pick(u, &["exists", "does not exist", "bogus"]),
+        false => Ok(predicate),
+    }
+}
+
+impl<'a> Arbitrary<'a> for Condition {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let r#type = condition_type(u)?;
+        let predicate = predicate(u, &r#type)?.to_owned();
+        let objects = match r#type {
+            ConditionType::Segment => list(u, 2, |u| Ok(pick(u, &SEGMENT_KEYS)?.to_owned()))?,
+            _ => list(u, 3, |u| Ok(pick(u, &OBJECTS)?.to_owned()))?,
+        };
+        Ok(Condition {
+            r#type,
+            subject: pick(u, &SUBJECTS)?.to_owned(),
+            predicate,
+            objects,
+            regexes: Default::default(),
+        })
+    }
+}
+
+// ranges usually tile the bucket space, sometimes with gaps or overlaps
+impl<'a> Arbitrary<'a> for Distribution {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let groups = u.int_in_range(1..=3)?;
+        let size = BUCKET_SIZE as u32;
+        let mut cuts: Vec<u32> = (1..groups)
+            .map(|_| u.int_in_range(0..=size))
+            .collect::<Result<_>>()?;
+        cuts.sort();
+        let mut bounds = vec![0];
+        bounds.extend(cuts);
+        bounds.push(size);
+        let mut distribution: Vec<Vec<BucketRange>> = bounds
+            .windows(2)
+            .map(|w| vec![BucketRange((w[0], w[1]))])
+            .collect();
+        if u.ratio(1, 10)? {
+            let lower = u.int_in_range(0..=size + 10)?;
+            let upper = u.int_in_range(0..=size + 10)?;
+            distribution.push(vec![BucketRange((lower, upper))]);
+        }
+        Ok(Distribution {
+            distribution,
+            bucket_by: match u.ratio(1, 5)? {
+                true => Some(pick(u, &SUBJECTS)?.to_owned()),
+                false => None,
+            },
+            salt: match u.arbitrary()? {
+                true => Some(pick(u, &OBJECTS)?.to_owned()),
+                false => None,
+            },
+        })
+    }
+}
+
+fn serve(u: &mut Unstructured, variations: usize) -> Result<Serve> {
+    Ok(match u.ratio(1, 3)? {
+        true => Serve::Split(u.arbitrary()?),
+        false => Serve::Select(u.int_in_range(0..=variations)?),
+    })
+}
+
+fn window(u: &mut Unstructured) -> Result<Option<u64>> {
+    Ok(match u.ratio(1, 5)? {
+        true => Some(u.int_in_range(0..=4_000_000_000_000)?),
+        false => None,
+    })
+}
+
+impl<'a> Arbitrary<'a> for Segment {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let rules = list(u, 3, |u| {
+            Ok(SegmentRule {
+                conditions: list(u, 3, |u| {
+                    let mut c = Condition::arbitrary(u)?;
+                    // segments do not nest
+                    if c.r#type == ConditionType::Segment {
+                        c.r#type = ConditionType::String;
+                    }
+                    Ok(c)
+                })?,
+                start: window(u)?,
+                end: window(u)?,
+            })
+        })?;
+        Ok(Segment {
+            unique_id: pick(u, &SEGMENT_KEYS)?.to_owned(),
+            version: u.int_in_range(1..=5)?,
+            rules,
+            start: window(u)?,
+            end: window(u)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Toggle {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let key = pick(u, &TOGGLE_KEYS)?.to_owned();
+        let variations = list(u, 4, arbitrary_value)?;
+        let count = variations.len();
+        let rules = list(u, 4, |u| {
+            Ok(Rule {
+                serve: serve(u, count)?,
+                conditions: list(u, 3, Condition::arbitrary)?,
+            })
+        })?;
+        let prerequisites = match u.ratio(1, 4)? {
+            true => Some(list(u, 2, |u| {
+                Ok(Prerequisites {
+                    key: pick(u, &TOGGLE_KEYS)?.to_owned(),
+                    value: arbitrary_value(u)?,
+                })
+            })?),
+            false => None,
+        };
+        let mut toggle = Toggle::new_for_test(key, Value::Null);
+        toggle.enabled = u.ratio(4, 5)?;
+        toggle.version = u.int_in_range(1..=5)?;
+        toggle.default_serve = serve(u, count)?;
+        toggle.disabled_serve = serve(u, count)?;
+        toggle.rules = rules;
+        toggle.variations = variations;
+        toggle.prerequisites = prerequisites;
+        toggle.archived = u.ratio(1, 10)?;
+        Ok(toggle)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Repository {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut repo = Repository::default();
+        for toggle in list(u, TOGGLE_KEYS.len(), Toggle::arbitrary)? {
+            repo.toggles.insert(toggle.key.clone(), toggle);
+        }
+        for segment in list(u, SEGMENT_KEYS.len(), Segment::arbitrary)? {
+            repo.segments.insert(segment.unique_id.clone(), segment);
+        }
+        repo.version = Some(u.int_in_range(1..=100)?);
+        repo.rebuild_index();
+        Ok(repo)
+    }
+}
+
+// for fuzz targets taking raw bytes, runs out of data into small repositories
+pub fn repository_from_bytes(data: &[u8]) -> Repository {
+    Repository::arbitrary(&mut Unstructured::new(data)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FeatureProbe;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_eval_never_panics() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for _ in 0..300 {
+            let data: Vec<u8> = (0..512).map(|_| rng.gen()).collect();
+            let mut u = Unstructured::new(&data);
+            let repo = Repository::arbitrary(&mut u).unwrap();
+            let user = arbitrary_user(&mut u).unwrap();
+            let fp = FeatureProbe::new_with("secret key".to_owned(), repo);
+            for key in TOGGLE_KEYS {
+                let detail = fp.json_detail(key, &user, Value::Null);
+                assert_ne!(
+                    detail.reason_code,
+                    Some(crate::ReasonCode::EvaluationPanicked),
+                    "{key}: {}",
+                    detail.reason
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_repository_round_trips() {
+        let repo = repository_from_bytes(&[3; 256]);
+        let json = serde_json::to_string(&repo).unwrap();
+        let loaded: Repository = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.toggles, repo.toggles);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{FPConfig, FPError, FPUser, FeatureProbe};
+use serde_json::Value;
+use std::sync::OnceLock;
+use tracing::warn;
+
+static GLOBAL: OnceLock<FeatureProbe> = OnceLock::new();
+
+impl FeatureProbe {
+    pub fn init_global(config: FPConfig) -> &'static FeatureProbe {
+        if let Some(fp) = GLOBAL.get() {
+            warn!("global FeatureProbe already initialized");
+            return fp;
+        }
+        GLOBAL.get_or_init(|| FeatureProbe::new(config))
+    }
+
+    pub fn set_global(fp: FeatureProbe) -> Result<(), FPError> {
+        GLOBAL.set(fp).map_err(|_| {
+            FPError::InternalError("global FeatureProbe already initialized".to_owned())
+        })
+    }
+
+    pub fn global() -> Option<&'static FeatureProbe> {
+        GLOBAL.get()
+    }
+}
+
+fn with_global<T>(default: T, f: impl FnOnce(&FeatureProbe, T) -> T) -> T {
+    match GLOBAL.get() {
+        Some(fp) => f(fp, default),
+        None => {
+            warn!("global FeatureProbe not initialized, serve default");
+            default
+        }
+    }
+}
+
+pub fn bool_value(toggle: &str, user: &FPUser, default: bool) -> bool {
+    with_global(default, |fp, d| fp.bool_value(toggle, user, d))
+}
+
+pub fn string_value(toggle: &str, user: &FPUser, default: String) -> String {
+    with_global(default, |fp, d| fp.string_value(toggle, user, d))
+}
+
+pub fn number_value(toggle: &str, user: &FPUser, default: f64) -> f64 {
+    with_global(default, |fp, d| fp.number_value(toggle, user, d))
+}
+
+pub fn json_value(toggle: &str, user: &FPUser, default: Value) -> Value {
+    with_global(default, |fp, d| fp.json_value(toggle, user, d))
+}
+
+pub fn track(event_name: &str, user: &FPUser, value: Option<f64>) {
+    if let Some(fp) = GLOBAL.get() {
+        fp.track(event_name, user, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_global() {
+        let u = FPUser::new();
+        let fp = FeatureProbe::new_for_test("global_toggle", json!(true));
+        FeatureProbe::set_global(fp).unwrap();
+        assert!(FeatureProbe::global().is_some());
+        assert!(bool_value("global_toggle", &u, false));
+        assert_eq!(number_value("not_exist", &u, 1.0), 1.0);
+
+        let fp = FeatureProbe::new_for_test("global_toggle", json!(false));
+        assert!(FeatureProbe::set_global(fp).is_err());
+        assert!(bool_value("global_toggle", &u, false));
+    }
+}
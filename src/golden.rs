@@ -0,0 +1,270 @@
+use crate::evaluate::{EvalDetail, Repository};
+use crate::reason::ReasonCode;
+use crate::user::FPUser;
+use crate::FPError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use tracing::error;
+
+// records queued for the writer, evaluations drop records rather than wait when it is full
+const QUEUE_LEN: usize = 4096;
+
+// one line of a golden file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GoldenRecord {
+    pub toggle: String,
+    pub user: GoldenUser,
+    pub time: u128,
+    pub result: Option<GoldenResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GoldenUser {
+    pub key: String,
+    pub attrs: BTreeMap<String, String>,
+    #[serde(default)]
+    pub anonymous: bool,
+    // recorded private attributes, only present when they were opted in
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub private: BTreeSet<String>,
+}
+
+impl GoldenUser {
+    fn of(user: &FPUser, include_private: bool) -> Self {
+        let attrs: BTreeMap<String, String> = user
+            .get_all()
+            .iter()
+            .filter(|(k, _)| include_private || !user.is_private(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        Self {
+            key: user.key(),
+            private: attrs
+                .keys()
+                .filter(|k| user.is_private(k))
+                .cloned()
+                .collect(),
+            attrs,
+            anonymous: user.is_anonymous(),
+        }
+    }
+
+    pub fn to_user(&self) -> FPUser {
+        let user = match self.anonymous {
+            true => FPUser::anonymous_key(self.key.clone()),
+            false => FPUser::new().stable_rollout(self.key.clone()),
+        };
+        let user = self
+            .private
+            .iter()
+            .filter_map(|k| Some((k, self.attrs.get(k)?)))
+            .fold(user, |user, (k, v)| user.with_private(k, v));
+        user.copy_with(
+            self.attrs
+                .iter()
+                .filter(|(k, _)| !self.private.contains(*k))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GoldenResult {
+    pub value: Option<Value>,
+    pub variation_index: Option<usize>,
+    pub rule_index: Option<usize>,
+    pub version: Option<u64>,
+    pub reason_code: Option<ReasonCode>,
+}
+
+impl GoldenResult {
+    // toggle versions move with every publish, so they are not part of the decision
+    fn same_decision(&self, other: &GoldenResult) -> bool {
+        self.value == other.value
+            && self.variation_index == other.variation_index
+            && self.rule_index == other.rule_index
+            && self.reason_code == other.reason_code
+    }
+}
+
+impl From<&EvalDetail<Value>> for GoldenResult {
+    fn from(detail: &EvalDetail<Value>) -> Self {
+        Self {
+            value: detail.value.clone(),
+            variation_index: detail.variation_index,
+            rule_index: detail.rule_index,
+            version: detail.version,
+            reason_code: detail.reason_code,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GoldenDiff {
+    pub index: usize,
+    pub toggle: String,
+    pub user_key: String,
+    pub recorded: Option<GoldenResult>,
+    pub replayed: Option<GoldenResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GoldenReport {
+    pub records: usize,
+    pub diffs: Vec<GoldenDiff>,
+}
+
+impl GoldenReport {
+    pub fn is_clean(&self) -> bool {
+        self.diffs.is_empty()
+    }
+}
+
+// evaluations only queue records, a writer thread appends them in batches and
+// flushes whatever is left when the recorder is dropped
+#[derive(Debug)]
+pub(crate) struct GoldenRecorder {
+    sender: Option<SyncSender<GoldenRecord>>,
+    writer: Option<JoinHandle<()>>,
+    include_private: bool,
+}
+
+impl GoldenRecorder {
+    pub fn create(path: &Path, include_private: bool) -> Result<Self, FPError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        let (sender, receiver) = sync_channel(QUEUE_LEN);
+        let writer = std::thread::Builder::new()
+            .name("fp-golden".to_owned())
+            .spawn(move || write_records(receiver, BufWriter::new(file)))
+            .map_err(io_error)?;
+        Ok(Self {
+            sender: Some(sender),
+            writer: Some(writer),
+            include_private,
+        })
+    }
+
+    pub fn record(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        now: u128,
+        detail: Option<&EvalDetail<Value>>,
+    ) -> Result<(), FPError> {
+        let record = GoldenRecord {
+            toggle: toggle.to_owned(),
+            user: GoldenUser::of(user, self.include_private),
+            time: now,
+            result: detail.map(GoldenResult::from),
+        };
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        match sender.try_send(record) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(io_error("queue full, record dropped")),
+            Err(TrySendError::Disconnected(_)) => Err(io_error("writer stopped")),
+        }
+    }
+}
+
+impl Drop for GoldenRecorder {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_records(receiver: Receiver<GoldenRecord>, mut writer: BufWriter<File>) {
+    while let Ok(first) = receiver.recv() {
+        let mut batch = std::iter::once(first).chain(receiver.try_iter().take(QUEUE_LEN - 1));
+        let written = batch
+            .try_for_each(|record| {
+                serde_json::to_writer(&mut writer, &record).map_err(io_error)?;
+                writer.write_all(b"\n").map_err(io_error)
+            })
+            .and_then(|_| writer.flush().map_err(io_error));
+        if let Err(e) = written {
+            error!("golden writer failed: {}", e);
+            return;
+        }
+    }
+}
+
+pub fn load_golden(path: &Path) -> Result<Vec<GoldenRecord>, FPError> {
+    let file = File::open(path).map_err(io_error)?;
+    let mut records = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| FPError::JsonError(line, e))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+// evaluates against the repository only, client overrides and sticky buckets are not replayed
+pub(crate) fn replay(
+    records: &[GoldenRecord],
+    repo: &Repository,
+    max_prerequisites_deep: u8,
+) -> GoldenReport {
+    let mut diffs = vec![];
+    for (i, record) in records.iter().enumerate() {
+        let user = record.user.to_user();
+        let replayed = repo.toggles.get(&record.toggle).map(|t| {
+            let detail = t.eval_at(
+                &user,
+                &repo.segments,
+                &repo.toggles,
+                true,
+                max_prerequisites_deep,
+                repo.debug_until_time,
+                record.time,
+            );
+            GoldenResult::from(&detail)
+        });
+        let same = match (&record.result, &replayed) {
+            (Some(a), Some(b)) => a.same_decision(b),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            diffs.push(GoldenDiff {
+                index: i,
+                toggle: record.toggle.clone(),
+                user_key: record.user.key.clone(),
+                recorded: record.result.clone(),
+                replayed,
+            });
+        }
+    }
+    GoldenReport {
+        records: records.len(),
+        diffs,
+    }
+}
+
+fn io_error(e: impl std::fmt::Display) -> FPError {
+    FPError::GoldenFileError(e.to_string())
+}
@@ -0,0 +1,328 @@
+use crate::proto;
+use crate::sync::{SyncType, WeakSynchronizer};
+use crate::{FPError, Repository, Secret};
+use http::uri::PathAndQuery;
+use prost::Message;
+use std::time::Duration;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tonic::codec::ProstCodec;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tracing::{error, trace};
+use url::Url;
+
+pub const FETCH_PATH: &str = "/featureprobe.Toggles/Fetch";
+pub const SUBSCRIBE_PATH: &str = "/featureprobe.Toggles/Subscribe";
+pub const EVENTS_PATH: &str = "/featureprobe.Events/Send";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FetchRequest {
+    #[prost(uint64, optional, tag = "1")]
+    pub version: Option<u64>,
+}
+
+// events keep the json payload the http endpoint accepts
+#[derive(Clone, PartialEq, Message)]
+pub struct EventsRequest {
+    #[prost(string, tag = "1")]
+    pub payload: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EventsResponse {}
+
+#[derive(Debug, Clone)]
+pub struct GrpcClient {
+    inner: tonic::client::Grpc<Channel>,
+    auth: MetadataValue<Ascii>,
+}
+
+fn status_error(status: tonic::Status) -> FPError {
+    FPError::HttpError(format!("grpc {}: {}", status.code(), status.message()))
+}
+
+impl GrpcClient {
+    pub async fn connect(url: &Url, server_sdk_key: &str) -> Result<Self, FPError> {
+        let channel = Endpoint::from_shared(url.to_string())
+            .map_err(|e| FPError::UrlError(format!("{url}: {e}")))?
+            .connect()
+            .await
+            .map_err(|e| FPError::HttpError(e.to_string()))?;
+        Self::with_channel(channel, server_sdk_key)
+    }
+
+    pub fn with_channel(channel: Channel, server_sdk_key: &str) -> Result<Self, FPError> {
+        let mut auth: MetadataValue<Ascii> = server_sdk_key
+            .parse()
+            .map_err(|e| FPError::InternalError(format!("invalid sdk key metadata: {e}")))?;
+        auth.set_sensitive(true);
+        Ok(Self {
+            inner: tonic::client::Grpc::new(channel),
+            auth,
+        })
+    }
+
+    pub async fn fetch(&mut self, version: Option<u128>) -> Result<Repository, FPError> {
+        self.ready().await?;
+        let codec = ProstCodec::<FetchRequest, proto::Repository>::default();
+        let resp = self
+            .inner
+            .unary(
+                self.request(FetchRequest {
+                    version: version.map(|v| v as u64),
+                }),
+                PathAndQuery::from_static(FETCH_PATH),
+                codec,
+            )
+            .await
+            .map_err(status_error)?;
+        resp.into_inner().try_into()
+    }
+
+    // every message is a full repository, sent whenever the server side changes
+    pub async fn subscribe(
+        &mut self,
+        version: Option<u128>,
+    ) -> Result<impl Stream<Item = Result<Repository, FPError>>, FPError> {
+        self.ready().await?;
+        let codec = ProstCodec::<FetchRequest, proto::Repository>::default();
+        let resp = self
+            .inner
+            .server_streaming(
+                self.request(FetchRequest {
+                    version: version.map(|v| v as u64),
+                }),
+                PathAndQuery::from_static(SUBSCRIBE_PATH),
+                codec,
+            )
+            .await
+            .map_err(status_error)?;
+        Ok(resp
+            .into_inner()
+            .map(|r| r.map_err(status_error).and_then(TryInto::try_into)))
+    }
+
+    pub async fn send_events(&mut self, payload: String) -> Result<(), FPError> {
+        self.ready().await?;
+        let codec = ProstCodec::<EventsRequest, EventsResponse>::default();
+        self.inner
+            .unary(
+                self.request(EventsRequest { payload }),
+                PathAndQuery::from_static(EVENTS_PATH),
+                codec,
+            )
+            .await
+            .map_err(status_error)?;
+        Ok(())
+    }
+
+    async fn ready(&mut self) -> Result<(), FPError> {
+        self.inner
+            .ready()
+            .await
+            .map_err(|e| FPError::HttpError(e.to_string()))
+    }
+
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", self.auth.clone());
+        request
+    }
+}
+
+// streams repository updates into the synchronizer, reconnecting after retry
+pub(crate) async fn run_stream(
+    url: Url,
+    server_sdk_key: Secret,
+    syncer: WeakSynchronizer,
+    retry: Duration,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let version = match syncer.upgrade() {
+            Some(syncer) => syncer.version(),
+            None => break,
+        };
+        let result = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            result = stream_once(&url, server_sdk_key.expose(), &syncer, version, &shutdown) => result,
+        };
+        if let Err(e) = result {
+            error!(url = %url, error = %e, "grpc stream error");
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(retry) => {}
+        }
+    }
+}
+
+async fn stream_once(
+    url: &Url,
+    server_sdk_key: &str,
+    syncer: &WeakSynchronizer,
+    version: Option<u128>,
+    shutdown: &CancellationToken,
+) -> Result<(), FPError> {
+    let mut client = GrpcClient::connect(url, server_sdk_key).await?;
+    let mut updates = Box::pin(client.subscribe(version).await?);
+    while let Some(repo) = updates.next().await {
+        let repo = repo?;
+        trace!(version = ?repo.version, "grpc stream update");
+        match syncer.upgrade() {
+            Some(syncer) if !shutdown.is_cancelled() => syncer.apply(repo, SyncType::Realtime),
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Synchronizer;
+    use std::convert::Infallible;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tonic::body::BoxBody;
+    use tonic::codegen::{empty_body, BoxFuture, Service};
+    use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+    use tonic::transport::Body;
+    use tonic::{Request, Response, Status};
+
+    fn fixture() -> Repository {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let mut repo = crate::load_json(&json_str).unwrap();
+        repo.version = Some(7);
+        repo
+    }
+
+    fn authorized<T>(request: &Request<T>) -> bool {
+        matches!(request.metadata().get("authorization"), Some(v) if v == "sdk-key")
+    }
+
+    #[derive(Clone)]
+    struct Fetch;
+
+    impl UnaryService<FetchRequest> for Fetch {
+        type Response = proto::Repository;
+        type Future = BoxFuture<Response<Self::Response>, Status>;
+
+        fn call(&mut self, request: Request<FetchRequest>) -> Self::Future {
+            Box::pin(async move {
+                if !authorized(&request) {
+                    return Err(Status::unauthenticated("bad sdk key"));
+                }
+                Ok(Response::new((&fixture()).into()))
+            })
+        }
+    }
+
+    #[derive(Clone)]
+    struct Subscribe;
+
+    impl ServerStreamingService<FetchRequest> for Subscribe {
+        type Response = proto::Repository;
+        type ResponseStream =
+            tokio_stream::Iter<std::vec::IntoIter<Result<Self::Response, Status>>>;
+        type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+        fn call(&mut self, request: Request<FetchRequest>) -> Self::Future {
+            Box::pin(async move {
+                if !authorized(&request) {
+                    return Err(Status::unauthenticated("bad sdk key"));
+                }
+                let mut newer = fixture();
+                newer.version = Some(8);
+                let updates = vec![Ok((&fixture()).into()), Ok((&newer).into())];
+                Ok(Response::new(tokio_stream::iter(updates)))
+            })
+        }
+    }
+
+    #[derive(Clone)]
+    struct Server;
+
+    impl NamedService for Server {
+        const NAME: &'static str = "featureprobe.Toggles";
+    }
+
+    impl Service<http::Request<Body>> for Server {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+            let codec = ProstCodec::<proto::Repository, FetchRequest>::default();
+            match req.uri().path() {
+                FETCH_PATH => Box::pin(async move { Ok(Grpc::new(codec).unary(Fetch, req).await) }),
+                SUBSCRIBE_PATH => {
+                    Box::pin(
+                        async move { Ok(Grpc::new(codec).server_streaming(Subscribe, req).await) },
+                    )
+                }
+                _ => Box::pin(async move {
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .header("grpc-status", "12")
+                        .body(empty_body())
+                        .unwrap())
+                }),
+            }
+        }
+    }
+
+    async fn serve() -> Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(Server)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        Url::parse(&format!("http://{addr}")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_grpc_fetch() {
+        let url = serve().await;
+        let mut client = GrpcClient::connect(&url, "sdk-key").await.unwrap();
+        assert_eq!(client.fetch(None).await.unwrap(), fixture());
+
+        let mut client = GrpcClient::connect(&url, "wrong").await.unwrap();
+        assert!(client.fetch(None).await.is_err());
+        assert!(client.send_events("[]".to_owned()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_grpc_stream() {
+        let url = serve().await;
+        let syncer = Synchronizer::new(
+            Url::parse("http://127.0.0.1:0").unwrap(),
+            Duration::from_secs(60),
+            http::HeaderValue::from_static("sdk-key"),
+            String::new(),
+            Arc::new(reqwest::Client::default()),
+            Default::default(),
+            Default::default(),
+            Arc::new(crate::SystemClock),
+        );
+        let shutdown = CancellationToken::new();
+        stream_once(&url, "sdk-key", &syncer.downgrade(), None, &shutdown)
+            .await
+            .unwrap();
+        assert_eq!(syncer.version(), Some(8));
+        assert!(syncer.last_synced().is_some());
+    }
+}
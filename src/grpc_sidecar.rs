@@ -0,0 +1,339 @@
+use crate::{FPDetail, FPUser, FeatureProbe};
+use prost::Message;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{empty_body, BoxFuture, Service};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::transport::Body;
+use tonic::{Request, Response, Status};
+
+// messages mirror proto/sidecar.proto
+pub const EVALUATE_PATH: &str = "/featureprobe.sidecar.Evaluation/Evaluate";
+pub const EVALUATE_ALL_PATH: &str = "/featureprobe.sidecar.Evaluation/EvaluateAll";
+pub const TRACK_PATH: &str = "/featureprobe.sidecar.Evaluation/Track";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct User {
+    #[prost(string, optional, tag = "1")]
+    pub key: Option<String>,
+    #[prost(map = "string, string", tag = "2")]
+    pub attrs: HashMap<String, String>,
+}
+
+impl User {
+    fn to_user(&self) -> FPUser {
+        let mut user = FPUser::new();
+        if let Some(key) = &self.key {
+            user = user.stable_rollout(key.clone());
+        }
+        user.with_attrs(self.attrs.clone().into_iter())
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EvaluateRequest {
+    #[prost(string, tag = "1")]
+    pub toggle: String,
+    #[prost(message, optional, tag = "2")]
+    pub user: Option<User>,
+    #[prost(string, tag = "3")]
+    pub default_json: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Detail {
+    #[prost(string, tag = "1")]
+    pub toggle: String,
+    #[prost(string, tag = "2")]
+    pub value_json: String,
+    #[prost(uint32, optional, tag = "3")]
+    pub rule_index: Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub variation_index: Option<u32>,
+    #[prost(uint64, optional, tag = "5")]
+    pub version: Option<u64>,
+    #[prost(string, tag = "6")]
+    pub reason: String,
+    #[prost(string, optional, tag = "7")]
+    pub reason_code: Option<String>,
+}
+
+impl Detail {
+    fn new(toggle: String, detail: FPDetail<Value>) -> Self {
+        Self {
+            toggle,
+            value_json: detail.value.to_string(),
+            rule_index: detail.rule_index.map(|i| i as u32),
+            variation_index: detail.variation_index.map(|i| i as u32),
+            version: detail.version,
+            reason: detail.reason,
+            reason_code: detail.reason_code.map(|c| c.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EvaluateAllRequest {
+    #[prost(message, optional, tag = "1")]
+    pub user: Option<User>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EvaluateAllResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub details: Vec<Detail>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TrackRequest {
+    #[prost(string, tag = "1")]
+    pub event: String,
+    #[prost(message, optional, tag = "2")]
+    pub user: Option<User>,
+    #[prost(double, optional, tag = "3")]
+    pub value: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TrackResponse {}
+
+fn user_of(user: Option<User>) -> FPUser {
+    user.unwrap_or_default().to_user()
+}
+
+#[derive(Clone)]
+struct Evaluate(FeatureProbe);
+
+impl UnaryService<EvaluateRequest> for Evaluate {
+    type Response = Detail;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<EvaluateRequest>) -> Self::Future {
+        let fp = self.0.clone();
+        Box::pin(async move {
+            let req = request.into_inner();
+            let default = match req.default_json.as_str() {
+                "" => Value::Null,
+                json => serde_json::from_str(json)
+                    .map_err(|e| Status::invalid_argument(format!("default_json: {e}")))?,
+            };
+            let detail = fp.json_detail(&req.toggle, &user_of(req.user), default);
+            Ok(Response::new(Detail::new(req.toggle, detail)))
+        })
+    }
+}
+
+#[derive(Clone)]
+struct EvaluateAll(FeatureProbe);
+
+impl UnaryService<EvaluateAllRequest> for EvaluateAll {
+    type Response = EvaluateAllResponse;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<EvaluateAllRequest>) -> Self::Future {
+        let fp = self.0.clone();
+        Box::pin(async move {
+            let user = user_of(request.into_inner().user);
+            let mut toggles: Vec<String> = fp.repo.read().toggles.keys().cloned().collect();
+            toggles.sort();
+            let details = toggles
+                .into_iter()
+                .map(|t| {
+                    let detail = fp.json_detail(&t, &user, Value::Null);
+                    Detail::new(t, detail)
+                })
+                .collect();
+            Ok(Response::new(EvaluateAllResponse { details }))
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Track(FeatureProbe);
+
+impl UnaryService<TrackRequest> for Track {
+    type Response = TrackResponse;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<TrackRequest>) -> Self::Future {
+        let fp = self.0.clone();
+        Box::pin(async move {
+            let req = request.into_inner();
+            fp.track(&req.event, &user_of(req.user), req.value);
+            Ok(Response::new(TrackResponse {}))
+        })
+    }
+}
+
+// add to a tonic server, there is no authentication so serve it on a private address
+#[derive(Clone)]
+pub struct EvaluationServer {
+    fp: FeatureProbe,
+}
+
+impl EvaluationServer {
+    pub fn new(fp: FeatureProbe) -> Self {
+        Self { fp }
+    }
+}
+
+impl NamedService for EvaluationServer {
+    const NAME: &'static str = "featureprobe.sidecar.Evaluation";
+}
+
+impl Service<http::Request<Body>> for EvaluationServer {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let fp = self.fp.clone();
+        match req.uri().path() {
+            EVALUATE_PATH => Box::pin(async move {
+                let codec = ProstCodec::<Detail, EvaluateRequest>::default();
+                Ok(Grpc::new(codec).unary(Evaluate(fp), req).await)
+            }),
+            EVALUATE_ALL_PATH => Box::pin(async move {
+                let codec = ProstCodec::<EvaluateAllResponse, EvaluateAllRequest>::default();
+                Ok(Grpc::new(codec).unary(EvaluateAll(fp), req).await)
+            }),
+            TRACK_PATH => Box::pin(async move {
+                let codec = ProstCodec::<TrackResponse, TrackRequest>::default();
+                Ok(Grpc::new(codec).unary(Track(fp), req).await)
+            }),
+            _ => Box::pin(async move {
+                Ok(http::Response::builder()
+                    .status(200)
+                    .header("grpc-status", "12")
+                    .body(empty_body())
+                    .unwrap())
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::uri::PathAndQuery;
+    use serde_json::json;
+    use tonic::transport::Channel;
+
+    async fn unary<Req, Resp>(
+        client: &mut tonic::client::Grpc<Channel>,
+        path: &'static str,
+        message: Req,
+    ) -> Result<Resp, Status>
+    where
+        Req: Message + Send + Sync + 'static,
+        Resp: Message + Default + Send + Sync + 'static,
+    {
+        client.ready().await.unwrap();
+        let codec = ProstCodec::<Req, Resp>::default();
+        let resp = client
+            .unary(
+                Request::new(message),
+                PathAndQuery::from_static(path),
+                codec,
+            )
+            .await?;
+        Ok(resp.into_inner())
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sidecar() {
+        let fp = FeatureProbe::new_for_tests(HashMap::from([
+            ("toggle_1".to_owned(), json!(true)),
+            ("toggle_2".to_owned(), json!("v")),
+        ]));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(EvaluationServer::new(fp))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{addr}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = tonic::client::Grpc::new(channel);
+        let user = User {
+            key: Some("u1".to_owned()),
+            attrs: HashMap::from([("city".to_owned(), "1".to_owned())]),
+        };
+
+        let detail: Detail = unary(
+            &mut client,
+            EVALUATE_PATH,
+            EvaluateRequest {
+                toggle: "toggle_1".to_owned(),
+                user: Some(user.clone()),
+                default_json: String::new(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(detail.value_json, "true");
+
+        let detail: Detail = unary(
+            &mut client,
+            EVALUATE_PATH,
+            EvaluateRequest {
+                toggle: "missing".to_owned(),
+                user: None,
+                default_json: "3".to_owned(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(detail.value_json, "3");
+
+        let bad: Result<Detail, Status> = unary(
+            &mut client,
+            EVALUATE_PATH,
+            EvaluateRequest {
+                toggle: "toggle_1".to_owned(),
+                user: None,
+                default_json: "{".to_owned(),
+            },
+        )
+        .await;
+        assert_eq!(bad.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        let all: EvaluateAllResponse = unary(
+            &mut client,
+            EVALUATE_ALL_PATH,
+            EvaluateAllRequest { user: Some(user) },
+        )
+        .await
+        .unwrap();
+        let values: Vec<(&str, &str)> = all
+            .details
+            .iter()
+            .map(|d| (d.toggle.as_str(), d.value_json.as_str()))
+            .collect();
+        assert_eq!(values, vec![("toggle_1", "true"), ("toggle_2", "\"v\"")]);
+
+        let _: TrackResponse = unary(
+            &mut client,
+            TRACK_PATH,
+            TrackRequest {
+                event: "click".to_owned(),
+                user: None,
+                value: Some(1.0),
+            },
+        )
+        .await
+        .unwrap();
+    }
+}
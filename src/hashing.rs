@@ -0,0 +1,94 @@
+use crate::FPError;
+use byteorder::{BigEndian, ReadBytesExt};
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+
+pub const BUCKET_SIZE: u64 = 10000;
+
+pub fn salt_hash(key: &str, salt: &str, bucket_size: u64) -> u32 {
+    let size = 4;
+    let mut hasher = sha1::Sha1::new();
+    let data = format!("{key}{salt}");
+    hasher.update(data);
+    let hax_value = hasher.finalize();
+    let mut v = Vec::with_capacity(size);
+    for i in (hax_value.len() - size)..hax_value.len() {
+        v.push(hax_value[i]);
+    }
+    let mut v = v.as_slice();
+    // sha1 digest is always 20 bytes, so reading the last 4 never fails
+    let value = v.read_u32::<BigEndian>().unwrap_or_default();
+    value % bucket_size as u32
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TestVector {
+    pub key: String,
+    pub salt: String,
+    #[serde(default = "default_bucket_size")]
+    pub bucket_size: u64,
+    pub bucket: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VectorMismatch {
+    pub vector: TestVector,
+    pub actual: u32,
+}
+
+fn default_bucket_size() -> u64 {
+    BUCKET_SIZE
+}
+
+pub fn load_vectors(json_str: &str) -> Result<Vec<TestVector>, FPError> {
+    serde_json::from_str(json_str).map_err(|e| FPError::JsonError(json_str.to_owned(), e))
+}
+
+pub fn verify(vectors: &[TestVector]) -> Result<(), Vec<VectorMismatch>> {
+    let mismatches: Vec<VectorMismatch> = vectors
+        .iter()
+        .filter_map(|v| {
+            let actual = salt_hash(&v.key, &v.salt, v.bucket_size);
+            match actual == v.bucket {
+                true => None,
+                false => Some(VectorMismatch {
+                    vector: v.clone(),
+                    actual,
+                }),
+            }
+        })
+        .collect();
+    match mismatches.is_empty() {
+        true => Ok(()),
+        false => Err(mismatches),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_salt_hash() {
+        let bucket = salt_hash("key", "salt", 10000);
+        assert_eq!(2647, bucket);
+    }
+
+    #[test]
+    fn test_verify_vectors() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/hash_vectors.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let mut vectors = load_vectors(&json_str).unwrap();
+        assert!(verify(&vectors).is_ok());
+
+        vectors[0].bucket += 1;
+        let mismatches = verify(&vectors).unwrap_err();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].actual, 2647);
+    }
+}
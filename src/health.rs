@@ -0,0 +1,124 @@
+use crate::FeatureProbe;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    // initialized and not stale, the value readiness probes should gate on
+    pub healthy: bool,
+    pub initialized: bool,
+    pub stale: bool,
+    pub version: Option<u128>,
+    pub last_synced: Option<u128>,
+    pub sync_age_ms: Option<u128>,
+    pub track_events: bool,
+    pub realtime_connected: bool,
+    pub open_circuits: Vec<String>,
+}
+
+impl Health {
+    pub fn status_code(&self) -> u16 {
+        match self.healthy {
+            true => 200,
+            false => 503,
+        }
+    }
+}
+
+impl FeatureProbe {
+    pub fn health(&self) -> Health {
+        let now = self.config.clock.now();
+        let initialized = self.initialized();
+        let stale = self.is_stale();
+        let last_synced = self.last_synced();
+        Health {
+            healthy: initialized && !stale,
+            initialized,
+            stale,
+            version: self.version(),
+            last_synced,
+            sync_age_ms: last_synced.map(|t| now.saturating_sub(t)),
+            track_events: self.config.track_events,
+            realtime_connected: self.realtime_connected(),
+            open_circuits: self.open_circuits(),
+        }
+    }
+}
+
+// status code and json body, for wiring into any http framework
+pub fn health_response(fp: &FeatureProbe) -> (u16, String) {
+    let health = fp.health();
+    let body = serde_json::to_string(&health).unwrap_or_default();
+    (health.status_code(), body)
+}
+
+#[cfg(feature = "axum")]
+pub async fn axum_handler(
+    axum::extract::State(fp): axum::extract::State<FeatureProbe>,
+) -> (axum::http::StatusCode, axum::Json<Health>) {
+    let health = fp.health();
+    let status = axum::http::StatusCode::from_u16(health.status_code())
+        .unwrap_or(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    (status, axum::Json(health))
+}
+
+#[cfg(feature = "axum")]
+pub fn router(fp: FeatureProbe) -> axum::Router {
+    axum::Router::new()
+        .route("/healthz", axum::routing::get(axum_handler))
+        .with_state(fp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FPConfig, Repository};
+    use std::time::Duration;
+
+    #[test]
+    fn test_health_not_initialized() {
+        let fp = FeatureProbe::new_with("secret".to_string(), Repository::default());
+        let (status, body) = health_response(&fp);
+        assert_eq!(status, 503);
+        let health: Health = serde_json::from_str(&body).unwrap();
+        assert!(!health.initialized);
+        assert!(!health.realtime_connected);
+        assert_eq!(health.last_synced, None);
+    }
+
+    #[cfg(feature = "axum")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_health_initialized() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = std::fs::read_to_string(path).unwrap();
+        let repo: Repository = serde_json::from_str(&json_str).unwrap();
+
+        let app = axum::Router::new().route(
+            "/api/server-sdk/toggles",
+            axum::routing::get(move || async move { axum::Json(repo.clone()) }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let config = FPConfig {
+            remote_url: url::Url::parse(&format!("http://{addr}")).unwrap(),
+            server_sdk_key: "sdk-key".into(),
+            start_wait: Some(Duration::from_secs(5)),
+            track_events: false,
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config);
+        let health = fp.health();
+        assert!(health.healthy);
+        assert_eq!(health.status_code(), 200);
+        assert!(health.version.is_some());
+        assert!(health.sync_age_ms.is_some());
+        assert!(!health.track_events);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{Event, EventSink, FPError};
+use async_trait::async_trait;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::fmt::Debug;
+use std::time::Duration;
+
+// publishes access and custom events as json, keyed by user so one user's
+// events land on the same partition; debug events are not published
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+    timeout: Duration,
+}
+
+impl Debug for KafkaSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: impl Into<String>) -> Result<Self, FPError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()
+            .map_err(|e| FPError::EventSinkError(e.to_string()))?;
+        Ok(Self::with_producer(producer, topic))
+    }
+
+    pub fn with_producer(producer: FutureProducer, topic: impl Into<String>) -> Self {
+        Self {
+            producer,
+            topic: topic.into(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    // how long a send may wait for room in the producer queue
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+pub(crate) fn record_of(event: &Event) -> Option<(&str, Result<String, serde_json::Error>)> {
+    match event {
+        Event::AccessEvent(e) => Some((&e.user, serde_json::to_string(e))),
+        Event::CustomEvent(e) => Some((&e.user, serde_json::to_string(e))),
+        Event::DebugEvent(_) => None,
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn send(&self, events: &[Event]) -> Result<(), FPError> {
+        let mut deliveries = vec![];
+        for event in events {
+            let (key, payload) = match record_of(event) {
+                Some((key, Ok(payload))) => (key, payload),
+                Some((_, Err(e))) => return Err(FPError::EventSinkError(e.to_string())),
+                None => continue,
+            };
+            let record = FutureRecord::to(&self.topic).key(key).payload(&payload);
+            let delivery = self
+                .producer
+                .send_result(record)
+                .map_err(|(e, _)| FPError::EventSinkError(e.to_string()))?;
+            deliveries.push(delivery);
+        }
+
+        for delivery in deliveries {
+            match tokio::time::timeout(self.timeout, delivery).await {
+                Ok(Ok(Ok(_))) => {}
+                Ok(Ok(Err((e, _)))) => return Err(FPError::EventSinkError(e.to_string())),
+                Ok(Err(_)) => return Err(FPError::EventSinkError("delivery canceled".into())),
+                Err(_) => return Err(FPError::EventSinkError("delivery timed out".into())),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AccessEvent, DebugEvent};
+    use serde_json::json;
+
+    #[test]
+    fn test_record_keyed_by_user() {
+        let access = Event::AccessEvent(AccessEvent {
+            kind: "access".to_owned(),
+            time: 1,
+            key: "toggle".to_owned(),
+            user: "user-1".to_owned(),
+            value: json!(true),
+            variation_index: 0,
+            version: Some(1),
+            rule_index: None,
+            track_access_events: false,
+        });
+        let (key, payload) = record_of(&access).unwrap();
+        assert_eq!(key, "user-1");
+        assert!(payload.unwrap().contains("\"key\":\"toggle\""));
+
+        let debug = Event::DebugEvent(DebugEvent {
+            kind: "debug".to_owned(),
+            time: 1,
+            key: "toggle".to_owned(),
+            user: "user-1".to_owned(),
+            user_detail: json!({}),
+            value: json!(true),
+            variation_index: 0,
+            version: None,
+            rule_index: None,
+            reason: None,
+        });
+        assert!(record_of(&debug).is_none());
+    }
+}
@@ -1,22 +1,124 @@
+mod audit;
+mod breaker;
+mod clock;
+mod composition;
 mod config;
+mod context;
+mod diagnostics;
+mod diff;
 mod evaluate;
+mod events;
+mod experiment;
 mod feature_probe;
+mod file_sink;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+mod global;
+mod golden;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "grpc")]
+pub mod grpc_sidecar;
+pub mod hashing;
+pub mod health;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+mod limits;
+mod lint;
+#[cfg(feature = "loadtest")]
+pub mod loadtest;
+mod normalization;
+#[cfg(feature = "proto")]
+pub mod proto;
+mod reason;
+#[cfg(feature = "relay")]
+pub mod relay;
+mod scoped;
+mod secret;
+mod shadow;
+#[cfg(feature = "sidecar")]
+pub mod sidecar;
+#[cfg(feature = "signal")]
+pub mod signal;
+pub mod signing;
+mod simulate;
+mod snapshot;
+#[cfg(any(test, feature = "spec"))]
+pub mod spec;
+mod stats;
+mod sticky;
 mod sync;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+mod transport;
+mod usage;
 mod user;
+mod validation;
+mod value_type;
+mod warning;
 
-pub use crate::config::FPConfig;
-pub use crate::evaluate::{load_json, EvalDetail, Repository, Segment, Toggle};
+pub use crate::audit::{AuditCallback, AuditChange, AuditItem, AuditRecord, SaltRotation};
+pub use crate::breaker::CircuitBreakerConfig;
+pub use crate::clock::{Clock, MockClock, SystemClock};
+pub use crate::composition::RepoComposition;
+pub use crate::config::{FPConfig, InitPolicy, StalePolicy};
+pub use crate::context::IntoEvalContext;
+pub use crate::diagnostics::{ConfigDiagnostics, DiagnosticsSnapshot, RepoDiagnostics};
+pub use crate::diff::{Change, ChangeKind, RepoDiff};
+pub use crate::evaluate::{
+    load_async_reader, load_bytes, load_bytes_with_limits, load_json, load_reader, EvalDetail,
+    PrerequisiteResult, Repository, RolloutSchedule, RolloutStep, Segment, Toggle,
+};
+pub use crate::events::{EventSchema, EventSink, EVENT_SCHEMA_HEADER};
+pub use crate::experiment::{ExperimentClient, Exposure};
 pub use crate::feature_probe::FeatureProbe;
+pub use crate::file_sink::FileSink;
+pub use crate::global::{bool_value, json_value, number_value, string_value, track};
+pub use crate::golden::{
+    load_golden, GoldenDiff, GoldenRecord, GoldenReport, GoldenResult, GoldenUser,
+};
+pub use crate::health::Health;
+pub use crate::limits::{
+    EvalSoftLimits, LimitKind, LimitViolation, RepositoryLimits, SoftLimitBreach,
+};
+pub use crate::lint::{lint, LintOptions};
+pub use crate::normalization::UnicodeNormalization;
+pub use crate::reason::ReasonCode;
+pub use crate::scoped::ScopedEvaluator;
+pub use crate::secret::Secret;
+pub use crate::shadow::{ShadowCallback, ShadowMismatch, ShadowStats};
+pub use crate::simulate::Simulation;
+pub use crate::snapshot::Snapshot;
+pub use crate::stats::{MissingToggle, WarmUpReport};
+pub use crate::sticky::{InMemoryStickyBuckets, StickyAssignment, StickyBucketStore};
 pub use crate::sync::SyncType;
-pub use crate::user::FPUser;
+#[cfg(feature = "ureq")]
+pub use crate::transport::UreqTransport;
+pub use crate::transport::{HttpResponse, HttpTransport};
+pub use crate::usage::{ToggleUsage, UsageReport, VariationUsage};
+pub use crate::user::{
+    AnonymousKeyStore, FPUser, FPUserBuilder, InstanceKeyGenerator, KeyGenerator, LimitPolicy,
+    SequenceKeyGenerator, UserLimits, UuidKeyGenerator,
+};
+pub use crate::validation::{validate, IssueKind, ValidationIssue, ValidationReport};
+pub use crate::value_type::{TypeMismatch, ValueType};
+pub use crate::warning::{warning_counts, WarningCount, WarningKind};
+pub use feature_probe_event::event::{AccessEvent, CustomEvent, DebugEvent, Event};
+#[cfg(feature = "derive")]
+pub use feature_probe_server_sdk_derive::IntoEvalContext;
 use headers::{Error, Header, HeaderName, HeaderValue};
 use http::header::AUTHORIZATION;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::time::Duration;
 use thiserror::Error;
 pub use url::Url;
 
+// lets the derive macros name this crate from inside its own tests
+#[cfg(feature = "derive")]
+extern crate self as feature_probe_server_sdk;
+
 lazy_static! {
     pub(crate) static ref USER_AGENT: String = "Rust/".to_owned() + VERSION;
 }
@@ -31,6 +133,10 @@ pub struct FPDetail<T: Default + Debug> {
     pub variation_index: Option<usize>,
     pub version: Option<u64>,
     pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<ReasonCode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prerequisites: Vec<PrerequisiteResult>,
 }
 
 #[non_exhaustive]
@@ -48,22 +154,53 @@ pub enum FPError {
     EvalDetailError(String),
     #[error("internal error: {0}")]
     InternalError(String),
-}
-
-#[derive(Debug, Error)]
-enum PrerequisiteError {
+    #[error("toggle not exist: {0}")]
+    ToggleNotExist(String),
     #[error("prerequisite depth overflow")]
-    DepthOverflow,
+    PrerequisiteDepthOverflow,
     #[error("prerequisite not exist: {0}")]
-    NotExist(String),
+    PrerequisiteNotExist(String),
+    #[error("stale data: last synced {age_ms}ms ago")]
+    StaleData { age_ms: u128 },
+    #[error("not initialized within {0:?}")]
+    InitTimeout(Duration),
+    #[error("event sink error: {0}")]
+    EventSinkError(String),
+    #[error("evaluation budget exceeded")]
+    EvalTimeout,
+    #[error("invalid user: {0}")]
+    InvalidUser(String),
+    #[error("repository limit exceeded: {0}")]
+    LimitExceeded(LimitViolation),
+    #[error("golden file error: {0}")]
+    GoldenFileError(String),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
 pub struct SdkAuthorization(pub String);
 
+impl Debug for SdkAuthorization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SdkAuthorization({})", secret::REDACTED)
+    }
+}
+
 impl SdkAuthorization {
     pub fn encode(&self) -> HeaderValue {
-        HeaderValue::from_str(&self.0).expect("valid header value")
+        match self.try_encode() {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("{}", e);
+                HeaderValue::from_static("")
+            }
+        }
+    }
+
+    pub fn try_encode(&self) -> Result<HeaderValue, FPError> {
+        let mut value = HeaderValue::from_str(&self.0)
+            .map_err(|e| FPError::InternalError(format!("invalid sdk key: {e}")))?;
+        value.set_sensitive(true);
+        Ok(value)
     }
 }
 
@@ -96,8 +233,8 @@ impl Header for SdkAuthorization {
 pub fn unix_timestamp() -> u128 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .expect("Time went backwards!")
-        .as_millis()
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
@@ -105,11 +242,60 @@ mod tests {
     use super::*;
 
     #[test]
-    #[should_panic]
-    fn test_encode_panic() {
+    fn test_encode_invalid() {
         let v: Vec<u8> = vec![21, 20, 19, 18]; // not visible string
         let s = String::from_utf8(v).unwrap();
         let auth = SdkAuthorization(s);
-        let _ = auth.encode();
+        assert!(auth.try_encode().is_err());
+        assert!(auth.encode().is_empty());
+    }
+
+    #[test]
+    fn test_sdk_key_redacted() {
+        let auth = SdkAuthorization("server-abc".to_owned());
+        assert!(!format!("{auth:?}").contains("server-abc"));
+        assert!(!format!("{:?}", auth.encode()).contains("server-abc"));
+
+        let config = FPConfig {
+            server_sdk_key: "server-abc".into(),
+            signing_secret: Some("signing-abc".into()),
+            ..Default::default()
+        };
+        let debug = format!("{config:?} {:?}", config.build().unwrap());
+        assert!(!debug.contains("server-abc"));
+        assert!(!debug.contains("signing-abc"));
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_records() {
+        use parking_lot::Mutex;
+
+        struct Capture(Mutex<Vec<String>>);
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0
+                    .lock()
+                    .push(format!("{} {}", record.level(), record.args()));
+            }
+
+            fn flush(&self) {}
+        }
+
+        let capture: &'static Capture = Box::leak(Box::new(Capture(Mutex::new(vec![]))));
+        log::set_logger(capture).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let fp = FeatureProbe::new_for_test("toggle", serde_json::json!(true));
+        fp.emergency_disable("toggle");
+        let records = capture.0.lock();
+        assert!(records
+            .iter()
+            .any(|r| r.starts_with("WARN") && r.contains("emergency disable toggle")));
     }
 }
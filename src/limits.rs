@@ -0,0 +1,349 @@
+use dashmap::DashMap;
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::evaluate::{Condition, ConditionType};
+use crate::warning::{warn_throttled, WarningKind};
+use crate::{FPError, Repository};
+
+// evaluations per second above which a toggle is reported, nothing is rejected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalSoftLimits {
+    pub per_second: Option<u64>,
+    // overrides per_second for single toggles
+    pub toggles: BTreeMap<String, u64>,
+}
+
+impl EvalSoftLimits {
+    pub fn limit_of(&self, toggle: &str) -> Option<u64> {
+        self.toggles.get(toggle).copied().or(self.per_second)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SoftLimitBreach {
+    pub toggle: String,
+    pub limit: u64,
+    // highest evaluations seen in one second
+    pub peak: u64,
+    // seconds in which the limit was exceeded
+    pub seconds: u64,
+    pub last_seen: u128,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    second: u128,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct EvalRates {
+    windows: DashMap<String, Window>,
+    breaches: DashMap<String, SoftLimitBreach>,
+}
+
+impl EvalRates {
+    pub fn record(&self, limits: &EvalSoftLimits, toggle: &str, now: u128) {
+        let limit = match limits.limit_of(toggle) {
+            Some(limit) => limit,
+            None => return,
+        };
+        let count = {
+            let mut window = self.windows.entry(toggle.to_owned()).or_default();
+            let second = now / 1000;
+            if window.second != second {
+                window.second = second;
+                window.count = 0;
+            }
+            window.count += 1;
+            window.count
+        };
+        if count <= limit {
+            return;
+        }
+
+        let mut breach =
+            self.breaches
+                .entry(toggle.to_owned())
+                .or_insert_with(|| SoftLimitBreach {
+                    toggle: toggle.to_owned(),
+                    limit,
+                    peak: 0,
+                    seconds: 0,
+                    last_seen: now,
+                });
+        if count == limit + 1 {
+            breach.seconds += 1;
+            warn_throttled(WarningKind::EvalSoftLimit, toggle);
+        }
+        breach.limit = limit;
+        breach.peak = breach.peak.max(count);
+        breach.last_seen = now;
+    }
+
+    pub fn breaches(&self) -> Vec<SoftLimitBreach> {
+        let mut list: Vec<SoftLimitBreach> = self.breaches.iter().map(|b| b.clone()).collect();
+        list.sort_by(|a, b| b.peak.cmp(&a.peak).then_with(|| a.toggle.cmp(&b.toggle)));
+        list
+    }
+}
+
+// hard caps for repositories from untrusted sources, a repository over any
+// of them is rejected as a whole
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RepositoryLimits {
+    pub max_rules_per_toggle: usize,
+    // per toggle or segment rule
+    pub max_conditions: usize,
+    pub max_objects_per_condition: usize,
+    pub max_segments: usize,
+    // raw payload, checked before it is deserialized
+    pub max_payload_bytes: usize,
+    // compiled size of each regex object, see regex::RegexBuilder::size_limit
+    pub max_regex_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LimitKind {
+    RulesPerToggle,
+    Conditions,
+    ObjectsPerCondition,
+    Segments,
+    PayloadBytes,
+    // found is the pattern length, the compiled size is not reported
+    RegexBytes,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitViolation {
+    pub kind: LimitKind,
+    pub toggle: Option<String>,
+    pub segment: Option<String>,
+    pub limit: usize,
+    pub found: usize,
+}
+
+impl std::fmt::Display for LimitViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {} over limit {}",
+            self.kind, self.found, self.limit
+        )?;
+        if let Some(toggle) = &self.toggle {
+            write!(f, " in toggle {toggle}")?;
+        }
+        if let Some(segment) = &self.segment {
+            write!(f, " in segment {segment}")?;
+        }
+        Ok(())
+    }
+}
+
+impl RepositoryLimits {
+    pub fn check_payload(&self, len: usize) -> Result<(), FPError> {
+        match len > self.max_payload_bytes {
+            true => Err(FPError::LimitExceeded(LimitViolation {
+                kind: LimitKind::PayloadBytes,
+                toggle: None,
+                segment: None,
+                limit: self.max_payload_bytes,
+                found: len,
+            })),
+            false => Ok(()),
+        }
+    }
+
+    pub fn check(&self, repo: &Repository) -> Result<(), FPError> {
+        let violation = |kind, toggle: Option<&String>, segment: Option<&String>, limit, found| {
+            Err(FPError::LimitExceeded(LimitViolation {
+                kind,
+                toggle: toggle.cloned(),
+                segment: segment.cloned(),
+                limit,
+                found,
+            }))
+        };
+        if repo.segments.len() > self.max_segments {
+            return violation(
+                LimitKind::Segments,
+                None,
+                None,
+                self.max_segments,
+                repo.segments.len(),
+            );
+        }
+        for (key, toggle) in &repo.toggles {
+            if toggle.rules.len() > self.max_rules_per_toggle {
+                return violation(
+                    LimitKind::RulesPerToggle,
+                    Some(key),
+                    None,
+                    self.max_rules_per_toggle,
+                    toggle.rules.len(),
+                );
+            }
+            for rule in &toggle.rules {
+                if let Some((kind, limit, found)) = self.check_conditions(&rule.conditions) {
+                    return violation(kind, Some(key), None, limit, found);
+                }
+            }
+        }
+        for (key, segment) in &repo.segments {
+            for rule in &segment.rules {
+                if let Some((kind, limit, found)) = self.check_conditions(&rule.conditions) {
+                    return violation(kind, None, Some(key), limit, found);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_conditions(&self, conditions: &[Condition]) -> Option<(LimitKind, usize, usize)> {
+        if conditions.len() > self.max_conditions {
+            return Some((LimitKind::Conditions, self.max_conditions, conditions.len()));
+        }
+        let objects = conditions
+            .iter()
+            .map(|c| c.objects.len())
+            .find(|&found| found > self.max_objects_per_condition)
+            .map(|found| {
+                (
+                    LimitKind::ObjectsPerCondition,
+                    self.max_objects_per_condition,
+                    found,
+                )
+            });
+        objects.or_else(|| {
+            conditions
+                .iter()
+                .filter(|c| c.r#type == ConditionType::String && c.predicate.ends_with("regex"))
+                .flat_map(|c| c.objects.iter())
+                .find(|p| {
+                    matches!(
+                        RegexBuilder::new(p)
+                            .size_limit(self.max_regex_bytes)
+                            .build(),
+                        Err(regex::Error::CompiledTooBig(_))
+                    )
+                })
+                .map(|p| (LimitKind::RegexBytes, self.max_regex_bytes, p.len()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate::Toggle;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_repository_limits() {
+        let limits = RepositoryLimits {
+            max_rules_per_toggle: 2,
+            max_conditions: 2,
+            max_objects_per_condition: 3,
+            max_segments: 1,
+            max_payload_bytes: 64,
+            max_regex_bytes: 10_000,
+        };
+        let condition = |objects: usize| {
+            json!({"type": "string", "subject": "city", "predicate": "is one of",
+                   "objects": vec!["x"; objects]})
+        };
+        let repo = |rules: Value| {
+            let mut toggle = Toggle::new_for_test("t".to_owned(), json!(true));
+            toggle.rules = serde_json::from_value(rules).unwrap();
+            let mut repo = Repository::default();
+            repo.toggles.insert("t".to_owned(), toggle);
+            repo
+        };
+        let kind = |repo: &Repository| match limits.check(repo) {
+            Err(FPError::LimitExceeded(v)) => Some(v.kind),
+            _ => None,
+        };
+
+        let ok = repo(json!([{"serve": {"select": 0}, "conditions": [condition(3)]}]));
+        assert!(limits.check(&ok).is_ok());
+        let rule = json!({"serve": {"select": 0}, "conditions": []});
+        assert_eq!(
+            kind(&repo(json!([rule, rule, rule]))),
+            Some(LimitKind::RulesPerToggle)
+        );
+        let conditions = json!([{"serve": {"select": 0},
+            "conditions": [condition(1), condition(1), condition(1)]}]);
+        assert_eq!(kind(&repo(conditions)), Some(LimitKind::Conditions));
+        let objects = json!([{"serve": {"select": 0}, "conditions": [condition(4)]}]);
+        match limits.check(&repo(objects)) {
+            Err(FPError::LimitExceeded(v)) => {
+                assert_eq!(v.kind, LimitKind::ObjectsPerCondition);
+                assert_eq!(v.toggle.as_deref(), Some("t"));
+                assert_eq!((v.limit, v.found), (3, 4));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let mut segments = ok;
+        for key in ["a", "b"] {
+            segments.segments.insert(
+                key.to_owned(),
+                serde_json::from_value(json!({"uniqueId": key, "version": 1, "rules": []}))
+                    .unwrap(),
+            );
+        }
+        assert_eq!(kind(&segments), Some(LimitKind::Segments));
+
+        let regex = |pattern: &str| {
+            json!([{"serve": {"select": 0}, "conditions": [{"type": "string", "subject": "email",
+                "predicate": "matches regex", "objects": [pattern]}]}])
+        };
+        assert_eq!(kind(&repo(regex("^a{1,8}$"))), None);
+        assert_eq!(
+            kind(&repo(regex(r"^\w{1,1000}$"))),
+            Some(LimitKind::RegexBytes)
+        );
+
+        assert!(limits.check_payload(64).is_ok());
+        match limits.check_payload(65) {
+            Err(FPError::LimitExceeded(v)) => assert_eq!(v.kind, LimitKind::PayloadBytes),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_soft_limits() {
+        let limits = EvalSoftLimits {
+            per_second: Some(3),
+            toggles: BTreeMap::from([("hot".to_owned(), 5)]),
+        };
+        let rates = EvalRates::default();
+        for i in 0..4 {
+            rates.record(&limits, "t", 1000 + i);
+            rates.record(&limits, "hot", 1000 + i);
+        }
+        let breaches = rates.breaches();
+        assert_eq!(breaches.len(), 1);
+        assert_eq!(breaches[0].toggle, "t");
+        assert_eq!((breaches[0].peak, breaches[0].seconds), (4, 1));
+
+        // a new second starts a new window
+        for i in 0..5 {
+            rates.record(&limits, "t", 2000 + i);
+        }
+        let breach = &rates.breaches()[0];
+        assert_eq!(
+            (breach.peak, breach.seconds, breach.last_seen),
+            (5, 2, 2004)
+        );
+
+        rates.record(&EvalSoftLimits::default(), "other", 3000);
+        assert_eq!(rates.breaches().len(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::evaluate::{Condition, ConditionType};
+use crate::validation::{validate, IssueKind, ValidationIssue};
+use crate::Repository;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LintOptions {
+    // evaluations per toggle, e.g. from event statistics, empty skips the usage checks
+    pub evaluations: HashMap<String, u64>,
+    pub heavy_evaluations: u64,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            evaluations: HashMap::new(),
+            heavy_evaluations: 1000,
+        }
+    }
+}
+
+// suspicious but loadable configurations, stricter than validate
+pub fn lint(repo: &Repository, options: &LintOptions) -> Vec<ValidationIssue> {
+    let in_use: BTreeSet<&str> = repo
+        .toggles
+        .values()
+        .flat_map(|t| t.all_segment_ids())
+        .collect();
+    let mut issues: Vec<ValidationIssue> = validate(repo, 0)
+        .issues
+        .into_iter()
+        .filter(|i| match i.kind {
+            IssueKind::UnreachableRule | IssueKind::InvalidRange => true,
+            IssueKind::EmptySegment => i.segment.as_deref().is_some_and(|s| in_use.contains(s)),
+            _ => false,
+        })
+        .collect();
+
+    let mut toggles: Vec<_> = repo.toggles.values().collect();
+    toggles.sort_by(|a, b| a.key.cmp(&b.key));
+    for toggle in toggles {
+        let conditions = toggle.rules.iter().flat_map(|r| r.conditions.iter());
+        for pattern in unbounded_patterns(conditions) {
+            issues.push(unbounded_issue(Some(&toggle.key), None, pattern));
+        }
+        let evaluations = options.evaluations.get(&toggle.key).copied().unwrap_or(0);
+        if !toggle.enabled && evaluations >= options.heavy_evaluations {
+            issues.push(ValidationIssue {
+                kind: IssueKind::DisabledButEvaluated,
+                toggle: Some(toggle.key.clone()),
+                segment: None,
+                rule_index: None,
+                message: format!("disabled toggle evaluated {evaluations} times"),
+                hint: "remove the toggle from code or enable it".to_owned(),
+            });
+        }
+    }
+
+    let mut segments: Vec<_> = repo.segments.iter().collect();
+    segments.sort_by(|a, b| a.0.cmp(b.0));
+    for (key, segment) in segments {
+        let conditions = segment.rules.iter().flat_map(|r| r.conditions.iter());
+        for pattern in unbounded_patterns(conditions) {
+            issues.push(unbounded_issue(None, Some(key), pattern));
+        }
+    }
+    issues
+}
+
+fn unbounded_issue(toggle: Option<&str>, segment: Option<&str>, pattern: &str) -> ValidationIssue {
+    ValidationIssue {
+        kind: IssueKind::UnboundedRegex,
+        toggle: toggle.map(str::to_owned),
+        segment: segment.map(str::to_owned),
+        rule_index: None,
+        message: format!("regex {pattern} has an unbounded quantifier"),
+        hint: "bound the repetition, e.g. {1,64}, or use a plain string predicate".to_owned(),
+    }
+}
+
+fn unbounded_patterns<'a>(
+    conditions: impl Iterator<Item = &'a Condition>,
+) -> impl Iterator<Item = &'a str> {
+    conditions
+        .filter(|c| c.r#type == ConditionType::String && c.predicate.ends_with("regex"))
+        .flat_map(|c| c.objects.iter())
+        .map(String::as_str)
+        .filter(|p| has_unbounded_quantifier(p))
+}
+
+// *, + and {n,} outside of escapes and character classes
+fn has_unbounded_quantifier(pattern: &str) -> bool {
+    let mut chars = pattern.chars().peekable();
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '*' | '+' if !in_class => return true,
+            '{' if !in_class => {
+                let mut body = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    body.push(c);
+                }
+                if body.ends_with(',') {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Toggle;
+    use serde_json::json;
+
+    #[test]
+    fn test_unbounded_quantifier() {
+        assert!(has_unbounded_quantifier("^a.*$"));
+        assert!(has_unbounded_quantifier("a+"));
+        assert!(has_unbounded_quantifier("a{2,}"));
+        assert!(!has_unbounded_quantifier("a{2,5}"));
+        assert!(!has_unbounded_quantifier(r"a\+b\*"));
+        assert!(!has_unbounded_quantifier("[*+]"));
+    }
+
+    #[test]
+    fn test_lint() {
+        let mut toggle = Toggle::new_for_test("t".to_owned(), json!(1));
+        toggle.enabled = false;
+        toggle.rules = serde_json::from_value(json!([
+            {"serve": {"select": 0}, "conditions": [{
+                "type": "string", "subject": "email", "predicate": "matches regex",
+                "objects": [".*@example.com"]
+            }]},
+            {"serve": {"select": 0}, "conditions": [{
+                "type": "segment", "predicate": "is in", "objects": ["used"]
+            }]}
+        ]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("t".to_owned(), toggle);
+        for key in ["used", "unused"] {
+            repo.segments.insert(
+                key.to_owned(),
+                serde_json::from_value(json!({"uniqueId": key, "version": 1, "rules": []}))
+                    .unwrap(),
+            );
+        }
+
+        let kinds = |issues: Vec<ValidationIssue>| -> Vec<(IssueKind, Option<String>)> {
+            issues
+                .into_iter()
+                .map(|i| (i.kind, i.toggle.or(i.segment)))
+                .collect()
+        };
+        assert_eq!(
+            kinds(lint(&repo, &LintOptions::default())),
+            vec![
+                (IssueKind::EmptySegment, Some("used".to_owned())),
+                (IssueKind::UnboundedRegex, Some("t".to_owned())),
+            ]
+        );
+
+        let options = LintOptions {
+            evaluations: HashMap::from([("t".to_owned(), 5000)]),
+            ..Default::default()
+        };
+        let issues = kinds(lint(&repo, &options));
+        assert!(issues.contains(&(IssueKind::DisabledButEvaluated, Some("t".to_owned()))));
+    }
+}
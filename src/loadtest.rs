@@ -0,0 +1,129 @@
+use crate::{FPError, FPUser, FeatureProbe};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct LoadTestConfig {
+    pub workers: usize,
+    pub iterations_per_worker: usize,
+    // toggle keys evaluated round robin
+    pub toggles: Vec<String>,
+    pub users: usize,
+}
+
+impl Default for LoadTestConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            iterations_per_worker: 10_000,
+            toggles: vec![],
+            users: 1000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadTestReport {
+    pub evaluations: usize,
+    pub elapsed: Duration,
+    pub throughput: f64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+pub async fn run(fp: &FeatureProbe, config: &LoadTestConfig) -> Result<LoadTestReport, FPError> {
+    if config.toggles.is_empty() || config.users == 0 {
+        return Err(FPError::InternalError(
+            "loadtest needs at least one toggle and one user".to_owned(),
+        ));
+    }
+
+    let start = Instant::now();
+    let mut handles = Vec::with_capacity(config.workers);
+    for worker in 0..config.workers {
+        let fp = fp.clone();
+        let config = config.clone();
+        handles.push(tokio::task::spawn_blocking(move || {
+            work(&fp, &config, worker)
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(config.workers * config.iterations_per_worker);
+    for handle in handles {
+        let samples = handle
+            .await
+            .map_err(|e| FPError::InternalError(e.to_string()))?;
+        latencies.extend(samples);
+    }
+    let elapsed = start.elapsed();
+    Ok(report(latencies, elapsed))
+}
+
+fn work(fp: &FeatureProbe, config: &LoadTestConfig, worker: usize) -> Vec<Duration> {
+    let users: Vec<FPUser> = (0..config.users)
+        .map(|i| {
+            FPUser::new()
+                .stable_rollout(format!("loadtest-user-{i}"))
+                .with("index", &i.to_string())
+        })
+        .collect();
+    let mut latencies = Vec::with_capacity(config.iterations_per_worker);
+    for i in 0..config.iterations_per_worker {
+        let n = worker * config.iterations_per_worker + i;
+        let toggle = &config.toggles[n % config.toggles.len()];
+        let user = &users[n % users.len()];
+        let start = Instant::now();
+        fp.json_value(toggle, user, serde_json::Value::Null);
+        latencies.push(start.elapsed());
+    }
+    latencies
+}
+
+fn report(mut latencies: Vec<Duration>, elapsed: Duration) -> LoadTestReport {
+    latencies.sort();
+    let percentile = |p: f64| -> Duration {
+        match latencies.len() {
+            0 => Duration::ZERO,
+            n => latencies[((n as f64 * p) as usize).min(n - 1)],
+        }
+    };
+    LoadTestReport {
+        evaluations: latencies.len(),
+        elapsed,
+        throughput: match elapsed.as_secs_f64() {
+            s if s > 0.0 => latencies.len() as f64 / s,
+            _ => 0.0,
+        },
+        p50: percentile(0.5),
+        p90: percentile(0.9),
+        p99: percentile(0.99),
+        max: latencies.last().copied().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_loadtest() {
+        let fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        let config = LoadTestConfig {
+            workers: 2,
+            iterations_per_worker: 100,
+            toggles: vec!["toggle_1".to_owned(), "not_exist".to_owned()],
+            users: 10,
+        };
+        let report = run(&fp, &config).await.unwrap();
+        assert_eq!(report.evaluations, 200);
+        assert!(report.p50 <= report.p99);
+        assert!(report.p99 <= report.max);
+
+        let config = LoadTestConfig::default();
+        assert!(run(&fp, &config).await.is_err());
+    }
+}
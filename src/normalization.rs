@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization as _;
+
+// applied to user attributes and string condition objects before matching,
+// so composed and decomposed forms of the same text compare equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeNormalization {
+    Nfc,
+    Nfkc,
+}
+
+impl UnicodeNormalization {
+    pub fn normalize(&self, s: &str) -> String {
+        match self {
+            UnicodeNormalization::Nfc => s.nfc().collect(),
+            UnicodeNormalization::Nfkc => s.nfkc().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_eq!(UnicodeNormalization::Nfc.normalize(decomposed), composed);
+        assert_eq!(UnicodeNormalization::Nfkc.normalize(decomposed), composed);
+        // compatibility forms are only folded by nfkc
+        assert_eq!(UnicodeNormalization::Nfc.normalize("\u{fb01}"), "\u{fb01}");
+        assert_eq!(UnicodeNormalization::Nfkc.normalize("\u{fb01}"), "fi");
+    }
+}
@@ -0,0 +1,504 @@
+use crate::evaluate::{self, BucketRange, ConditionType};
+use crate::FPError;
+use prost::{Message, Oneof};
+use std::collections::HashMap;
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+// variation values and events stay json encoded, they are free form
+#[derive(Clone, PartialEq, Message)]
+pub struct Repository {
+    #[prost(map = "string, message", tag = "1")]
+    pub segments: HashMap<String, Segment>,
+    #[prost(map = "string, message", tag = "2")]
+    pub toggles: HashMap<String, Toggle>,
+    #[prost(string, optional, tag = "3")]
+    pub events: Option<String>,
+    #[prost(uint64, optional, tag = "4")]
+    pub version: Option<u64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub debug_until_time: Option<u64>,
+    #[prost(string, repeated, tag = "6")]
+    pub suppressed_events: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Segment {
+    #[prost(string, tag = "1")]
+    pub unique_id: String,
+    #[prost(uint64, tag = "2")]
+    pub version: u64,
+    #[prost(message, repeated, tag = "3")]
+    pub rules: Vec<SegmentRule>,
+    #[prost(uint64, optional, tag = "4")]
+    pub start: Option<u64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub end: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SegmentRule {
+    #[prost(message, repeated, tag = "1")]
+    pub conditions: Vec<Condition>,
+    #[prost(uint64, optional, tag = "2")]
+    pub start: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub end: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Toggle {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(bool, tag = "2")]
+    pub enabled: bool,
+    #[prost(bool, optional, tag = "3")]
+    pub track_access_events: Option<bool>,
+    #[prost(uint64, optional, tag = "4")]
+    pub last_modified: Option<u64>,
+    #[prost(uint64, tag = "5")]
+    pub version: u64,
+    #[prost(bool, tag = "6")]
+    pub for_client: bool,
+    #[prost(message, optional, tag = "7")]
+    pub disabled_serve: Option<Serve>,
+    #[prost(message, optional, tag = "8")]
+    pub default_serve: Option<Serve>,
+    #[prost(message, repeated, tag = "9")]
+    pub rules: Vec<Rule>,
+    #[prost(string, repeated, tag = "10")]
+    pub variations: Vec<String>,
+    #[prost(message, optional, tag = "11")]
+    pub prerequisites: Option<Prerequisites>,
+    #[prost(bool, tag = "12")]
+    pub archived: bool,
+    #[prost(bool, tag = "13")]
+    pub deprecated: bool,
+    #[prost(message, optional, tag = "14")]
+    pub archived_serve: Option<Serve>,
+    #[prost(message, optional, tag = "15")]
+    pub rollout_schedule: Option<RolloutSchedule>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RolloutSchedule {
+    #[prost(uint64, tag = "1")]
+    pub variation: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub steps: Vec<RolloutStep>,
+    #[prost(string, optional, tag = "3")]
+    pub bucket_by: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    pub salt: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct RolloutStep {
+    #[prost(uint64, tag = "1")]
+    pub time: u64,
+    #[prost(uint32, tag = "2")]
+    pub percentage: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Prerequisites {
+    #[prost(message, repeated, tag = "1")]
+    pub items: Vec<Prerequisite>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Prerequisite {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Rule {
+    #[prost(message, optional, tag = "1")]
+    pub serve: Option<Serve>,
+    #[prost(message, repeated, tag = "2")]
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Condition {
+    #[prost(string, tag = "1")]
+    pub r#type: String,
+    #[prost(string, tag = "2")]
+    pub subject: String,
+    #[prost(string, tag = "3")]
+    pub predicate: String,
+    #[prost(string, repeated, tag = "4")]
+    pub objects: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Serve {
+    #[prost(oneof = "ServeKind", tags = "1, 2")]
+    pub kind: Option<ServeKind>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum ServeKind {
+    #[prost(uint64, tag = "1")]
+    Select(u64),
+    #[prost(message, tag = "2")]
+    Split(Distribution),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Distribution {
+    #[prost(message, repeated, tag = "1")]
+    pub distribution: Vec<BucketRanges>,
+    #[prost(string, optional, tag = "2")]
+    pub bucket_by: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub salt: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BucketRanges {
+    #[prost(message, repeated, tag = "1")]
+    pub ranges: Vec<Range>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Range {
+    #[prost(uint32, tag = "1")]
+    pub start: u32,
+    #[prost(uint32, tag = "2")]
+    pub end: u32,
+}
+
+pub fn encode_repository(repo: &evaluate::Repository) -> Vec<u8> {
+    Repository::from(repo).encode_to_vec()
+}
+
+pub fn decode_repository(bytes: &[u8]) -> Result<evaluate::Repository, FPError> {
+    let message = Repository::decode(bytes)
+        .map_err(|e| FPError::InternalError(format!("invalid protobuf repository: {e}")))?;
+    message.try_into()
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(s: &str) -> Result<T, FPError> {
+    serde_json::from_str(s).map_err(|e| FPError::JsonError(s.to_owned(), e))
+}
+
+fn missing(field: &str) -> FPError {
+    FPError::InternalError(format!("invalid protobuf repository: missing {field}"))
+}
+
+impl From<&evaluate::Repository> for Repository {
+    fn from(repo: &evaluate::Repository) -> Self {
+        Self {
+            segments: repo
+                .segments
+                .iter()
+                .map(|(k, s)| (k.clone(), s.into()))
+                .collect(),
+            toggles: repo
+                .toggles
+                .iter()
+                .map(|(k, t)| (k.clone(), t.into()))
+                .collect(),
+            events: repo.events.as_ref().map(to_json),
+            version: repo.version.map(|v| v as u64),
+            debug_until_time: repo.debug_until_time,
+            suppressed_events: repo.suppressed_events.iter().cloned().collect(),
+        }
+    }
+}
+
+impl From<&evaluate::Segment> for Segment {
+    fn from(segment: &evaluate::Segment) -> Self {
+        Self {
+            unique_id: segment.unique_id.clone(),
+            version: segment.version,
+            rules: segment
+                .rules
+                .iter()
+                .map(|r| SegmentRule {
+                    conditions: r.conditions.iter().map(Condition::from).collect(),
+                    start: r.start,
+                    end: r.end,
+                })
+                .collect(),
+            start: segment.start,
+            end: segment.end,
+        }
+    }
+}
+
+impl From<&evaluate::Toggle> for Toggle {
+    fn from(toggle: &evaluate::Toggle) -> Self {
+        Self {
+            key: toggle.key.clone(),
+            enabled: toggle.enabled,
+            track_access_events: toggle.track_access_events,
+            last_modified: toggle.last_modified,
+            version: toggle.version,
+            for_client: toggle.for_client,
+            disabled_serve: Some((&toggle.disabled_serve).into()),
+            default_serve: Some((&toggle.default_serve).into()),
+            rules: toggle
+                .rules
+                .iter()
+                .map(|r| Rule {
+                    serve: Some((&r.serve).into()),
+                    conditions: r.conditions.iter().map(Condition::from).collect(),
+                })
+                .collect(),
+            variations: toggle.variations.iter().map(to_json).collect(),
+            prerequisites: toggle.prerequisites.as_ref().map(|p| Prerequisites {
+                items: p
+                    .iter()
+                    .map(|p| Prerequisite {
+                        key: p.key.clone(),
+                        value: to_json(&p.value),
+                    })
+                    .collect(),
+            }),
+            archived: toggle.archived,
+            deprecated: toggle.deprecated,
+            archived_serve: toggle.archived_serve.as_ref().map(Into::into),
+            rollout_schedule: toggle.rollout_schedule.as_ref().map(|s| RolloutSchedule {
+                variation: s.variation as u64,
+                steps: s
+                    .steps
+                    .iter()
+                    .map(|step| RolloutStep {
+                        time: step.time,
+                        percentage: step.percentage,
+                    })
+                    .collect(),
+                bucket_by: s.bucket_by.clone(),
+                salt: s.salt.clone(),
+            }),
+        }
+    }
+}
+
+impl From<&evaluate::Condition> for Condition {
+    fn from(condition: &evaluate::Condition) -> Self {
+        let r#type = match serde_json::to_value(&condition.r#type) {
+            Ok(serde_json::Value::String(s)) => s,
+            _ => String::new(),
+        };
+        Self {
+            r#type,
+            subject: condition.subject.clone(),
+            predicate: condition.predicate.clone(),
+            objects: condition.objects.clone(),
+        }
+    }
+}
+
+impl From<&evaluate::Serve> for Serve {
+    fn from(serve: &evaluate::Serve) -> Self {
+        let kind = match serve {
+            evaluate::Serve::Select(i) => ServeKind::Select(*i as u64),
+            evaluate::Serve::Split(d) => ServeKind::Split(Distribution {
+                distribution: d
+                    .distribution
+                    .iter()
+                    .map(|ranges| BucketRanges {
+                        ranges: ranges
+                            .iter()
+                            .map(|r| Range {
+                                start: r.0 .0,
+                                end: r.0 .1,
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+                bucket_by: d.bucket_by.clone(),
+                salt: d.salt.clone(),
+            }),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<Repository> for evaluate::Repository {
+    type Error = FPError;
+
+    fn try_from(message: Repository) -> Result<Self, Self::Error> {
+        let mut segments = HashMap::with_capacity(message.segments.len());
+        for (k, s) in message.segments {
+            segments.insert(k, s.try_into()?);
+        }
+        let mut toggles = HashMap::with_capacity(message.toggles.len());
+        for (k, t) in message.toggles {
+            toggles.insert(k, t.try_into()?);
+        }
+        let mut repo = evaluate::Repository::default();
+        repo.segments = segments;
+        repo.toggles = toggles;
+        repo.events = message.events.as_deref().map(from_json).transpose()?;
+        repo.version = message.version.map(|v| v as u128);
+        repo.debug_until_time = message.debug_until_time;
+        repo.suppressed_events = message.suppressed_events.into_iter().collect();
+        repo.rebuild_index();
+        Ok(repo)
+    }
+}
+
+impl TryFrom<Segment> for evaluate::Segment {
+    type Error = FPError;
+
+    fn try_from(message: Segment) -> Result<Self, Self::Error> {
+        Ok(Self {
+            unique_id: message.unique_id,
+            version: message.version,
+            rules: message
+                .rules
+                .into_iter()
+                .map(|r| evaluate::SegmentRule {
+                    conditions: r.conditions.into_iter().map(Into::into).collect(),
+                    start: r.start,
+                    end: r.end,
+                })
+                .collect(),
+            start: message.start,
+            end: message.end,
+        })
+    }
+}
+
+impl TryFrom<Toggle> for evaluate::Toggle {
+    type Error = FPError;
+
+    fn try_from(message: Toggle) -> Result<Self, Self::Error> {
+        let mut rules = Vec::with_capacity(message.rules.len());
+        for r in message.rules {
+            rules.push(evaluate::Rule {
+                serve: r.serve.ok_or_else(|| missing("rule serve"))?.try_into()?,
+                conditions: r.conditions.into_iter().map(Into::into).collect(),
+            });
+        }
+        let variations = message
+            .variations
+            .iter()
+            .map(|v| from_json(v))
+            .collect::<Result<Vec<_>, _>>()?;
+        let prerequisites = match message.prerequisites {
+            None => None,
+            Some(p) => Some(
+                p.items
+                    .into_iter()
+                    .map(|p| {
+                        Ok(evaluate::Prerequisites {
+                            value: from_json(&p.value)?,
+                            key: p.key,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, FPError>>()?,
+            ),
+        };
+        Ok(Self {
+            key: message.key,
+            enabled: message.enabled,
+            track_access_events: message.track_access_events,
+            last_modified: message.last_modified,
+            version: message.version,
+            for_client: message.for_client,
+            disabled_serve: message
+                .disabled_serve
+                .ok_or_else(|| missing("disabled serve"))?
+                .try_into()?,
+            default_serve: message
+                .default_serve
+                .ok_or_else(|| missing("default serve"))?
+                .try_into()?,
+            rules,
+            variations,
+            prerequisites,
+            archived: message.archived,
+            deprecated: message.deprecated,
+            archived_serve: message.archived_serve.map(TryInto::try_into).transpose()?,
+            rollout_schedule: message.rollout_schedule.map(|s| evaluate::RolloutSchedule {
+                variation: s.variation as usize,
+                steps: s
+                    .steps
+                    .into_iter()
+                    .map(|step| evaluate::RolloutStep {
+                        time: step.time,
+                        percentage: step.percentage,
+                    })
+                    .collect(),
+                bucket_by: s.bucket_by,
+                salt: s.salt,
+            }),
+        })
+    }
+}
+
+impl From<Condition> for evaluate::Condition {
+    fn from(message: Condition) -> Self {
+        let r#type = serde_json::from_value(serde_json::Value::String(message.r#type))
+            .unwrap_or(ConditionType::Unknown);
+        Self {
+            r#type,
+            subject: message.subject,
+            predicate: message.predicate,
+            objects: message.objects,
+            regexes: Default::default(),
+        }
+    }
+}
+
+impl TryFrom<Serve> for evaluate::Serve {
+    type Error = FPError;
+
+    fn try_from(message: Serve) -> Result<Self, Self::Error> {
+        match message.kind.ok_or_else(|| missing("serve kind"))? {
+            ServeKind::Select(i) => Ok(evaluate::Serve::Select(i as usize)),
+            ServeKind::Split(d) => Ok(evaluate::Serve::Split(evaluate::Distribution {
+                distribution: d
+                    .distribution
+                    .into_iter()
+                    .map(|r| {
+                        r.ranges
+                            .into_iter()
+                            .map(|r| BucketRange((r.start, r.end)))
+                            .collect()
+                    })
+                    .collect(),
+                bucket_by: d.bucket_by,
+                salt: d.salt,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_repository_round_trip() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = crate::load_json(&json_str).unwrap();
+
+        let bytes = encode_repository(&repo);
+        assert!(bytes.len() < json_str.len());
+        let decoded = decode_repository(&bytes).unwrap();
+        assert_eq!(decoded, repo);
+        assert_eq!(
+            decoded.dependent_toggles("some_segment1-fjoaefjaam"),
+            repo.dependent_toggles("some_segment1-fjoaefjaam")
+        );
+
+        assert!(decode_repository(b"\xff\xff").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+// codes are shared with the other FeatureProbe SDKs, never renumber them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReasonCode {
+    #[serde(rename = "FP001_RULE_MATCH")]
+    RuleMatch,
+    #[serde(rename = "FP002_DEFAULT_SERVE")]
+    DefaultServe,
+    #[serde(rename = "FP003_DISABLED")]
+    Disabled,
+    #[serde(rename = "FP004_PREREQUISITE_NOT_MATCH")]
+    PrerequisiteNotMatch,
+    #[serde(rename = "FP005_PREREQUISITE_ERROR")]
+    PrerequisiteError,
+    #[serde(rename = "FP006_TOGGLE_NOT_EXIST")]
+    ToggleNotExist,
+    #[serde(rename = "FP007_TYPE_MISMATCH")]
+    TypeMismatch,
+    #[serde(rename = "FP008_SERVE_ERROR")]
+    ServeError,
+    #[serde(rename = "FP009_OVERRIDDEN")]
+    Overridden,
+    #[serde(rename = "FP010_EMERGENCY_DISABLED")]
+    EmergencyDisabled,
+    #[serde(rename = "FP011_STALE_DEFAULT")]
+    StaleDefault,
+    #[serde(rename = "FP012_CIRCUIT_OPEN")]
+    CircuitOpen,
+    #[serde(rename = "FP013_EVALUATION_PANICKED")]
+    EvaluationPanicked,
+    #[serde(rename = "FP014_ARCHIVED")]
+    Archived,
+    #[serde(rename = "FP015_SCHEDULED_ROLLOUT")]
+    ScheduledRollout,
+    #[serde(rename = "FP016_EVAL_TIMEOUT")]
+    EvalTimeout,
+}
+
+impl ReasonCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasonCode::RuleMatch => "FP001_RULE_MATCH",
+            ReasonCode::DefaultServe => "FP002_DEFAULT_SERVE",
+            ReasonCode::Disabled => "FP003_DISABLED",
+            ReasonCode::PrerequisiteNotMatch => "FP004_PREREQUISITE_NOT_MATCH",
+            ReasonCode::PrerequisiteError => "FP005_PREREQUISITE_ERROR",
+            ReasonCode::ToggleNotExist => "FP006_TOGGLE_NOT_EXIST",
+            ReasonCode::TypeMismatch => "FP007_TYPE_MISMATCH",
+            ReasonCode::ServeError => "FP008_SERVE_ERROR",
+            ReasonCode::Overridden => "FP009_OVERRIDDEN",
+            ReasonCode::EmergencyDisabled => "FP010_EMERGENCY_DISABLED",
+            ReasonCode::StaleDefault => "FP011_STALE_DEFAULT",
+            ReasonCode::CircuitOpen => "FP012_CIRCUIT_OPEN",
+            ReasonCode::EvaluationPanicked => "FP013_EVALUATION_PANICKED",
+            ReasonCode::Archived => "FP014_ARCHIVED",
+            ReasonCode::ScheduledRollout => "FP015_SCHEDULED_ROLLOUT",
+            ReasonCode::EvalTimeout => "FP016_EVAL_TIMEOUT",
+        }
+    }
+}
+
+impl Display for ReasonCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason_code_serde() {
+        for code in [
+            ReasonCode::RuleMatch,
+            ReasonCode::PrerequisiteNotMatch,
+            ReasonCode::EvaluationPanicked,
+        ] {
+            let json = serde_json::to_string(&code).unwrap();
+            assert_eq!(json, format!("\"{code}\""));
+            assert_eq!(serde_json::from_str::<ReasonCode>(&json).unwrap(), code);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::events::EventEndpoints;
+use crate::{FeatureProbe, Repository, SdkAuthorization};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, StatusCode},
+    routing::{get, post},
+    Json, Router, TypedHeader,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::{error, trace};
+use url::Url;
+
+// relayed batches stay raw json, only events are looked into for their kind
+#[derive(Debug, Serialize, Deserialize)]
+struct RelayedBatch {
+    #[serde(default)]
+    events: Vec<Value>,
+    access: Value,
+}
+
+pub fn router(fp: FeatureProbe) -> Router {
+    Router::new()
+        .route("/api/server-sdk/toggles", get(server_sdk_toggles))
+        .route("/api/events", post(events))
+        .with_state(fp)
+}
+
+async fn server_sdk_toggles(
+    State(fp): State<FeatureProbe>,
+    TypedHeader(SdkAuthorization(sdk_key)): TypedHeader<SdkAuthorization>,
+) -> Result<Json<Repository>, StatusCode> {
+    if sdk_key != fp.config.server_sdk_key.expose() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let repo = fp.repo.read().clone();
+    Ok(Json(repo))
+}
+
+async fn events(
+    State(fp): State<FeatureProbe>,
+    TypedHeader(SdkAuthorization(sdk_key)): TypedHeader<SdkAuthorization>,
+    body: Bytes,
+) -> StatusCode {
+    if sdk_key != fp.config.server_sdk_key.expose() {
+        return StatusCode::UNAUTHORIZED;
+    }
+    let batches: Vec<RelayedBatch> = match serde_json::from_slice(&body) {
+        Ok(batches) => batches,
+        Err(e) => {
+            error!("relay events invalid: {}", e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+    // split by event type so per type events urls are honored
+    let mut status = StatusCode::OK;
+    for (url, batches) in route_batches(&fp.config.event_endpoints, batches) {
+        let body = match serde_json::to_vec(&batches) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("relay events serialize error: {}", e);
+                status = StatusCode::INTERNAL_SERVER_ERROR;
+                continue;
+            }
+        };
+        if let Err(e) = relay_events(&fp, url.clone(), body).await {
+            status = e;
+        }
+    }
+    status
+}
+
+// splits batches the way the recorder routes events, access counters go with
+// the access events
+fn route_batches(
+    endpoints: &EventEndpoints,
+    batches: Vec<RelayedBatch>,
+) -> BTreeMap<&Url, Vec<RelayedBatch>> {
+    let mut routed: BTreeMap<&Url, Vec<RelayedBatch>> = BTreeMap::new();
+    for batch in batches {
+        let mut by_url: BTreeMap<&Url, Vec<Value>> = BTreeMap::new();
+        by_url.entry(&endpoints.access).or_default();
+        for event in batch.events {
+            let kind = event
+                .get("kind")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            by_url
+                .entry(endpoints.route_kind(kind))
+                .or_default()
+                .push(event);
+        }
+        for (url, events) in by_url {
+            let mut access = batch.access.clone();
+            if url != &endpoints.access {
+                if let Some(access) = access.as_object_mut() {
+                    access.insert("counters".to_owned(), Value::Object(Default::default()));
+                }
+            }
+            let has_counters = access
+                .get("counters")
+                .and_then(Value::as_object)
+                .is_some_and(|c| !c.is_empty());
+            if events.is_empty() && !has_counters {
+                continue;
+            }
+            routed
+                .entry(url)
+                .or_default()
+                .push(RelayedBatch { events, access });
+        }
+    }
+    routed
+}
+
+async fn relay_events(fp: &FeatureProbe, url: Url, body: Vec<u8>) -> Result<(), StatusCode> {
+    trace!("relay events to {}", url);
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        SdkAuthorization(fp.config.server_sdk_key.expose().to_owned()).encode(),
+    );
+    if let Ok(ua) = header::HeaderValue::from_str(&fp.config.user_agent) {
+        headers.insert(header::USER_AGENT, ua);
+    }
+    if let Some(secret) = &fp.config.signing_secret {
+        let now = fp.config.clock.now();
+        let signature = crate::signing::sign(secret.expose().as_bytes(), now, &body);
+        if let Ok(signature) = header::HeaderValue::from_str(&signature) {
+            headers.insert(crate::signing::SIGNATURE_HEADER, signature);
+        }
+    }
+    let resp = fp
+        .config
+        .http_transport
+        .post_json(url, headers, body, fp.config.refresh_interval)
+        .await;
+    match resp {
+        Ok(resp) if resp.is_success() => Ok(()),
+        Ok(resp) => {
+            error!("relay events status: {}", resp.status);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+        Err(e) => {
+            error!("relay events error: {}", e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FPConfig, FPUser};
+    use serde_json::json;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_relay_toggles() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = crate::load_json(&json_str).unwrap();
+        let upstream = FeatureProbe::new_with("relay-key".to_owned(), repo);
+
+        let port = 9011;
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let app = router(upstream);
+        tokio::spawn(async move {
+            let _ = axum::Server::bind(&addr)
+                .serve(app.into_make_service())
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let config = FPConfig {
+            remote_url: Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap(),
+            server_sdk_key: "relay-key".into(),
+            refresh_interval: Duration::from_secs(10),
+            start_wait: Some(Duration::from_secs(2)),
+            track_events: false,
+            ..Default::default()
+        };
+        let downstream = FeatureProbe::new(config);
+        let user = FPUser::new().with("city", "1");
+        assert!(downstream.initialized());
+        assert!(downstream.bool_value("bool_toggle", &user, false));
+
+        let resp = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{}/api/server-sdk/toggles", port))
+            .header(header::AUTHORIZATION, "wrong-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[derive(Debug, Default)]
+    struct Upstream {
+        posts: parking_lot::Mutex<Vec<(Url, Value)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::HttpTransport for Upstream {
+        async fn get(
+            &self,
+            _url: Url,
+            _headers: header::HeaderMap,
+            _timeout: Duration,
+        ) -> Result<crate::HttpResponse, crate::FPError> {
+            Err(crate::FPError::HttpError("events only".to_owned()))
+        }
+
+        async fn post_json(
+            &self,
+            url: Url,
+            _headers: header::HeaderMap,
+            body: Vec<u8>,
+            _timeout: Duration,
+        ) -> Result<crate::HttpResponse, crate::FPError> {
+            let body = serde_json::from_slice(&body).unwrap();
+            self.posts.lock().push((url, body));
+            Ok(crate::HttpResponse {
+                status: 200,
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_relay_events() {
+        let transport = Arc::new(Upstream::default());
+        let access_url = Url::parse("http://events.test/api/events").unwrap();
+        let debug_url = Url::parse("http://debug.test/api/events").unwrap();
+        let custom_url = Url::parse("http://custom.test/api/events").unwrap();
+        let upstream = FeatureProbe::new(FPConfig {
+            server_sdk_key: "relay-key".into(),
+            http_transport: Some(transport.clone()),
+            events_url: Some(access_url.clone()),
+            debug_events_url: Some(debug_url.clone()),
+            custom_events_url: Some(custom_url.clone()),
+            track_events: false,
+            ..Default::default()
+        });
+
+        let port = 9018;
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let app = router(upstream);
+        tokio::spawn(axum::Server::bind(&addr).serve(app.into_make_service()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let batches = json!([{
+            "events": [
+                {"kind": "access", "time": 10, "key": "toggle", "user": "u", "value": true,
+                 "variationIndex": 0, "version": 1, "ruleIndex": null},
+                {"kind": "debug", "time": 11, "key": "toggle", "user": "u", "userDetail": {},
+                 "value": true, "variationIndex": 0, "version": 1, "ruleIndex": null,
+                 "reason": null},
+                {"kind": "custom", "time": 12, "user": "u", "name": "buy", "value": null},
+            ],
+            "access": {"startTime": 10, "endTime": 10, "counters": {
+                "toggle": [{"value": true, "version": 1, "index": 0, "count": 1}],
+            }},
+        }]);
+        let resp = reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}/api/events", port))
+            .header(header::AUTHORIZATION, "relay-key")
+            .json(&batches)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let posts = transport.posts.lock();
+        assert_eq!(posts.len(), 3);
+        let posted = |url: &Url| &posts.iter().find(|(u, _)| u == url).unwrap().1[0];
+        assert_eq!(posted(&access_url)["events"][0]["kind"], "access");
+        assert_eq!(
+            posted(&access_url)["access"]["counters"]["toggle"][0]["count"],
+            1
+        );
+        assert_eq!(posted(&debug_url)["events"][0]["kind"], "debug");
+        assert_eq!(posted(&debug_url)["access"]["counters"], json!({}));
+        assert_eq!(posted(&custom_url)["events"][0]["kind"], "custom");
+        assert_eq!(posted(&custom_url)["events"].as_array().unwrap().len(), 1);
+    }
+}
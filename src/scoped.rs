@@ -0,0 +1,226 @@
+use crate::evaluate::{EvalDetail, Repository};
+use crate::feature_probe::record_event;
+use crate::{FPDetail, FPUser, FeatureProbe, ValueType};
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use tracing::error;
+
+pub struct ScopedEvaluator {
+    fp: FeatureProbe,
+    user: FPUser,
+    repo: Arc<Repository>,
+    now: u128,
+    cache: Mutex<HashMap<String, Option<EvalDetail<Value>>>>,
+    // toggles evaluated from the repository, recorded once at drop
+    pending_events: Mutex<Vec<String>>,
+}
+
+impl Debug for ScopedEvaluator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ScopedEvaluator")
+            .field(&self.user)
+            .field(&self.repo.version)
+            .finish()
+    }
+}
+
+impl ScopedEvaluator {
+    pub(crate) fn new(fp: FeatureProbe, user: &FPUser, repo: Arc<Repository>) -> Self {
+        user.key_with(&*fp.config.key_generator);
+        let user = user.copy_with([]);
+        let now = fp.config.clock.now();
+        Self {
+            fp,
+            user,
+            repo,
+            now,
+            cache: Default::default(),
+            pending_events: Default::default(),
+        }
+    }
+
+    pub fn user(&self) -> &FPUser {
+        &self.user
+    }
+
+    // every evaluation in the scope sees this repository version
+    pub fn version(&self) -> Option<u128> {
+        self.repo.version
+    }
+
+    pub fn bool_value(&self, toggle: &str, default: bool) -> bool {
+        self.bool_detail(toggle, default).value
+    }
+
+    pub fn string_value(&self, toggle: &str, default: String) -> String {
+        self.string_detail(toggle, default).value
+    }
+
+    pub fn number_value(&self, toggle: &str, default: f64) -> f64 {
+        self.number_detail(toggle, default).value
+    }
+
+    pub fn json_value(&self, toggle: &str, default: Value) -> Value {
+        self.json_detail(toggle, default).value
+    }
+
+    pub fn bool_detail(&self, toggle: &str, default: bool) -> FPDetail<bool> {
+        let detail = self.eval(toggle);
+        self.fp
+            .typed_detail(toggle, detail, default, ValueType::Bool, |v| v.as_bool())
+    }
+
+    pub fn string_detail(&self, toggle: &str, default: String) -> FPDetail<String> {
+        let detail = self.eval(toggle);
+        self.fp
+            .typed_detail(toggle, detail, default, ValueType::String, |v| {
+                v.as_str().map(|s| s.to_owned())
+            })
+    }
+
+    pub fn number_detail(&self, toggle: &str, default: f64) -> FPDetail<f64> {
+        let detail = self.eval(toggle);
+        self.fp
+            .typed_detail(toggle, detail, default, ValueType::Number, |v| v.as_f64())
+    }
+
+    pub fn json_detail(&self, toggle: &str, default: Value) -> FPDetail<Value> {
+        let detail = self.eval(toggle);
+        self.fp
+            .typed_detail(toggle, detail, default, ValueType::Json, Some)
+    }
+
+    fn eval(&self, toggle: &str) -> Option<EvalDetail<Value>> {
+        if let Some(detail) = self.cache.lock().get(toggle) {
+            return detail.clone();
+        }
+
+        let detail = match self.fp.eval_override(toggle) {
+            Some(d) => Some(d),
+            None => {
+                let detail = catch_unwind(AssertUnwindSafe(|| {
+                    self.fp
+                        .eval_checked(toggle, &self.repo, &self.user, true, self.now)
+                }))
+                .unwrap_or_else(|_| {
+                    error!(toggle, "evaluate toggle panicked");
+                    Some(FeatureProbe::panicked_detail(toggle))
+                });
+                self.pending_events.lock().push(toggle.to_owned());
+                detail
+            }
+        };
+        self.cache.lock().insert(toggle.to_owned(), detail.clone());
+        detail
+    }
+}
+
+impl Drop for ScopedEvaluator {
+    fn drop(&mut self) {
+        let recorder = match &self.fp.event_recorder {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        let cache = self.cache.lock();
+        for toggle in self.pending_events.lock().drain(..) {
+            if self.fp.events_suppressed(&self.repo, &toggle) {
+                continue;
+            }
+            let track_access_events = self
+                .repo
+                .toggles
+                .get(&toggle)
+                .map(|t| t.track_access_events())
+                .unwrap_or(false);
+            record_event(
+                recorder.clone(),
+                track_access_events,
+                &toggle,
+                &self.user,
+                cache.get(&toggle).cloned().flatten(),
+                self.repo.debug_until_time,
+                self.now,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_scoped_evaluator() {
+        let fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        let u = FPUser::new();
+        let scope = fp.scoped(&u);
+        assert!(scope.bool_value("toggle_1", false));
+        assert_eq!(scope.user().key(), u.key());
+
+        // cached result survives a repository change inside the scope
+        fp.repo.write().toggles.clear();
+        assert!(scope.bool_value("toggle_1", false));
+        assert_eq!(scope.pending_events.lock().len(), 1);
+
+        let detail = scope.number_detail("toggle_1", 1.0);
+        assert_eq!(detail.value, 1.0);
+        assert!(detail.reason.contains("mismatch"));
+        assert!(!fp.bool_value("toggle_1", &u, false));
+    }
+
+    #[test]
+    fn test_scoped_checks_and_usage() {
+        let clock = crate::MockClock::new(10_000);
+        let mut fp = FeatureProbe::new_for_test("toggle_1", json!(true));
+        fp.set_clock(Arc::new(clock.clone()));
+        fp.config.max_staleness = Some(std::time::Duration::from_secs(5));
+        fp.config.stale_policy = crate::StalePolicy::ServeDefault;
+        *fp.last_synced.write() = Some(10_000);
+        let u = FPUser::new();
+
+        let scope = fp.scoped(&u);
+        assert!(!scope.bool_value("missing", false));
+        assert!(fp.missing_toggles().iter().any(|m| m.key == "missing"));
+        assert!(scope.bool_value("toggle_1", false));
+        let usage = fp.usage_report();
+        assert_eq!(usage.toggles[0].key, "toggle_1");
+        assert_eq!(usage.toggles[0].count, 1);
+
+        clock.advance(std::time::Duration::from_secs(6));
+        let scope = fp.scoped(&u);
+        let detail = scope.bool_detail("toggle_1", false);
+        assert!(!detail.value);
+        assert_eq!(detail.value, fp.bool_value("toggle_1", &u, false));
+        assert!(detail.reason.starts_with("Stale"));
+    }
+
+    #[test]
+    fn test_scoped_consistent_version() {
+        let mut toggles = HashMap::new();
+        toggles.insert("toggle_1".to_owned(), json!(true));
+        toggles.insert("toggle_2".to_owned(), json!(true));
+        let fp = FeatureProbe::new_for_tests(toggles);
+        let u = FPUser::new();
+        let scope = fp.scoped(&u);
+        assert_eq!(scope.version(), Some(0));
+        assert!(scope.bool_value("toggle_1", false));
+
+        // a sync lands mid-request
+        {
+            let mut repo = fp.repo.write();
+            repo.version = Some(1);
+            for t in repo.toggles.values_mut() {
+                t.variations = vec![json!(false)];
+            }
+        }
+        assert!(!fp.bool_value("toggle_2", &u, true));
+        assert!(scope.bool_value("toggle_2", false));
+        assert_eq!(scope.version(), Some(0));
+        assert_eq!(fp.scoped(&u).version(), Some(1));
+    }
+}
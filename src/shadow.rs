@@ -0,0 +1,82 @@
+use crate::evaluate::{EvalDetail, Repository};
+use crate::user::FPUser;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub type ShadowCallback = Box<dyn Fn(ShadowMismatch) + Send>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowMismatch {
+    pub toggle: String,
+    pub user_key: String,
+    pub primary: Option<Value>,
+    pub shadow: Option<Value>,
+    pub primary_version: Option<u64>,
+    pub shadow_version: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadowStats {
+    pub evaluations: u64,
+    pub mismatches: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Shadow {
+    pub repo: RwLock<Option<Repository>>,
+    callback: Mutex<Option<ShadowCallback>>,
+    evaluations: AtomicU64,
+    mismatches: AtomicU64,
+}
+
+impl std::fmt::Debug for Shadow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Shadow").field(&self.stats()).finish()
+    }
+}
+
+impl Shadow {
+    pub fn set_callback(&self, callback: ShadowCallback) {
+        let mut lock = self.callback.lock();
+        *lock = Some(callback);
+    }
+
+    pub fn stats(&self) -> ShadowStats {
+        ShadowStats {
+            evaluations: self.evaluations.load(Ordering::Relaxed),
+            mismatches: self.mismatches.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn compare(
+        &self,
+        toggle: &str,
+        user: &FPUser,
+        primary: Option<&EvalDetail<Value>>,
+        shadow: Option<EvalDetail<Value>>,
+    ) {
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+        let primary_value = primary.and_then(|d| d.value.clone());
+        let shadow_value = shadow.as_ref().and_then(|d| d.value.clone());
+        if primary_value == shadow_value {
+            return;
+        }
+
+        self.mismatches.fetch_add(1, Ordering::Relaxed);
+        let lock = self.callback.lock();
+        if let Some(cb) = &*lock {
+            cb(ShadowMismatch {
+                toggle: toggle.to_owned(),
+                user_key: user.key(),
+                primary: primary_value,
+                shadow: shadow_value,
+                primary_version: primary.and_then(|d| d.version),
+                shadow_version: shadow.and_then(|d| d.version),
+            })
+        }
+    }
+}
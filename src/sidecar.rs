@@ -0,0 +1,132 @@
+use crate::{FPDetail, FPUser, FeatureProbe};
+use axum::{
+    extract::{Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+// local evaluation service for processes on the same host, there is no authentication
+// so bind it to a loopback or otherwise private address
+pub fn router(fp: FeatureProbe) -> Router {
+    Router::new()
+        .route("/evaluate", post(evaluate))
+        .route("/all", get(all))
+        .with_state(fp)
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SidecarUser {
+    pub key: Option<String>,
+    pub attrs: HashMap<String, String>,
+}
+
+impl SidecarUser {
+    fn to_user(&self) -> FPUser {
+        let mut user = FPUser::new();
+        if let Some(key) = &self.key {
+            user = user.stable_rollout(key.clone());
+        }
+        user.with_attrs(self.attrs.clone().into_iter())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EvaluateRequest {
+    pub toggle: String,
+    #[serde(default)]
+    pub user: SidecarUser,
+    #[serde(default)]
+    pub default: Value,
+}
+
+async fn evaluate(
+    State(fp): State<FeatureProbe>,
+    Json(req): Json<EvaluateRequest>,
+) -> Json<FPDetail<Value>> {
+    let user = req.user.to_user();
+    Json(fp.json_detail(&req.toggle, &user, req.default))
+}
+
+// GET /all?key=u1&city=1, every parameter but key is a user attribute
+async fn all(
+    State(fp): State<FeatureProbe>,
+    Query(mut params): Query<HashMap<String, String>>,
+) -> Json<BTreeMap<String, FPDetail<Value>>> {
+    let user = SidecarUser {
+        key: params.remove("key"),
+        attrs: params,
+    }
+    .to_user();
+    let toggles: Vec<String> = fp.repo.read().toggles.keys().cloned().collect();
+    let details = toggles
+        .into_iter()
+        .map(|t| {
+            let detail = fp.json_detail(&t, &user, Value::Null);
+            (t, detail)
+        })
+        .collect();
+    Json(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sidecar() {
+        let fp = FeatureProbe::new_for_tests(HashMap::from([
+            ("toggle_1".to_owned(), json!(true)),
+            ("toggle_2".to_owned(), json!("v")),
+        ]));
+        let port = 9013;
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let app = router(fp);
+        tokio::spawn(async move {
+            let _ = axum::Server::bind(&addr)
+                .serve(app.into_make_service())
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let client = reqwest::Client::new();
+        let detail: Value = client
+            .post(format!("http://127.0.0.1:{port}/evaluate"))
+            .json(&json!({"toggle": "toggle_1", "user": {"key": "u1", "attrs": {"city": "1"}}}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(detail["value"], json!(true));
+
+        let detail: Value = client
+            .post(format!("http://127.0.0.1:{port}/evaluate"))
+            .json(&json!({"toggle": "missing", "default": 3}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(detail["value"], json!(3));
+
+        let all: Value = client
+            .get(format!("http://127.0.0.1:{port}/all?key=u1&city=1"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(all["toggle_1"]["value"], json!(true));
+        assert_eq!(all["toggle_2"]["value"], json!("v"));
+    }
+}
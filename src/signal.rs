@@ -0,0 +1,39 @@
+use crate::FeatureProbe;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// resolves on the first SIGTERM or SIGINT (ctrl-c elsewhere)
+pub async fn terminated() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = term.recv() => Ok(()),
+            r = tokio::signal::ctrl_c() => r,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+// await alongside the application's own shutdown, the process keeps running
+// after the client is closed so the application decides when to exit
+pub async fn shutdown_on_signal(fp: FeatureProbe, grace: Duration) {
+    match terminated().await {
+        Ok(()) => {
+            info!("termination signal received, flushing events");
+            fp.shutdown(grace).await;
+        }
+        Err(e) => warn!(error = %e, "signal handler not installed"),
+    }
+}
+
+// for applications without their own handling: flush, close and exit
+pub fn install(fp: &FeatureProbe, grace: Duration) -> tokio::task::JoinHandle<()> {
+    let fp = fp.clone();
+    tokio::spawn(async move {
+        shutdown_on_signal(fp, grace).await;
+        std::process::exit(0);
+    })
+}
@@ -0,0 +1,80 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const SIGNATURE_HEADER: &str = "X-FP-Signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// header value is `t=<timestamp ms>,v1=<hex hmac-sha256 of "<timestamp>.<body>">`
+pub fn sign(secret: &[u8], timestamp: u128, body: &[u8]) -> String {
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex(&digest(secret, timestamp, body))
+    )
+}
+
+pub fn verify(secret: &[u8], header: &str, body: &[u8]) -> bool {
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<u128>().ok(),
+            Some(("v1", s)) => signature = unhex(s),
+            _ => {}
+        }
+    }
+    match (timestamp, signature) {
+        (Some(t), Some(s)) => mac(secret, t, body).verify_slice(&s).is_ok(),
+        _ => false,
+    }
+}
+
+fn mac(secret: &[u8], timestamp: u128, body: &[u8]) -> HmacSha256 {
+    // hmac accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+fn digest(secret: &[u8], timestamp: u128, body: &[u8]) -> Vec<u8> {
+    mac(secret, timestamp, body)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let header = sign(b"secret", 1000, b"{\"a\":1}");
+        assert!(header.starts_with("t=1000,v1="));
+        assert_eq!(header.len(), "t=1000,v1=".len() + 64);
+        assert!(verify(b"secret", &header, b"{\"a\":1}"));
+        assert!(!verify(b"secret", &header, b"{\"a\":2}"));
+        assert!(!verify(b"other", &header, b"{\"a\":1}"));
+        assert!(!verify(
+            b"secret",
+            &header.replace("t=1000", "t=1001"),
+            b"{\"a\":1}"
+        ));
+        assert!(!verify(b"secret", "t=1000", b""));
+        assert!(!verify(b"secret", &format!("{header}0"), b"{\"a\":1}"));
+    }
+}
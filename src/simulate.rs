@@ -0,0 +1,96 @@
+use crate::evaluate::{Segment, Toggle};
+use crate::user::FPUser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const MAX_PREREQUISITES_DEEP: u8 = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Simulation {
+    pub sample_size: usize,
+    // count per variation index
+    pub variations: Vec<u64>,
+    // hit count per rule index
+    pub rules: Vec<u64>,
+    pub default_hits: u64,
+    pub errors: u64,
+}
+
+impl Simulation {
+    pub fn variation_ratio(&self, index: usize) -> f64 {
+        match (self.variations.get(index), self.sample_size) {
+            (Some(_), 0) | (None, _) => 0.0,
+            (Some(count), n) => *count as f64 / n as f64,
+        }
+    }
+}
+
+impl Toggle {
+    // evaluates as if enabled, so a rollout can be checked before turning it on
+    pub fn simulate(
+        &self,
+        segments: &HashMap<String, Segment>,
+        toggles: &HashMap<String, Toggle>,
+        sample_size: usize,
+    ) -> Simulation {
+        let mut toggle = self.clone();
+        toggle.enabled = true;
+        let mut simulation = Simulation {
+            sample_size,
+            variations: vec![0; toggle.variations.len()],
+            rules: vec![0; toggle.rules.len()],
+            ..Default::default()
+        };
+
+        let now = crate::unix_timestamp();
+        for i in 0..sample_size {
+            let user = FPUser::new().stable_rollout(format!("simulate-{i}"));
+            let detail = toggle.eval_at(
+                &user,
+                segments,
+                toggles,
+                true,
+                MAX_PREREQUISITES_DEEP,
+                None,
+                now,
+            );
+            match detail.variation_index {
+                Some(v) if v < simulation.variations.len() => simulation.variations[v] += 1,
+                _ => simulation.errors += 1,
+            }
+            match detail.rule_index {
+                Some(r) if r < simulation.rules.len() => simulation.rules[r] += 1,
+                _ => simulation.default_hits += 1,
+            }
+        }
+        simulation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluate::load_json;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_simulate() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = fs::read_to_string(path).unwrap();
+        let repo = load_json(&json_str).unwrap();
+        let toggle = repo.toggles.get("json_toggle").unwrap();
+
+        let simulation = toggle.simulate(&repo.segments, &repo.toggles, 1000);
+        assert_eq!(simulation.sample_size, 1000);
+        assert_eq!(simulation.variations.iter().sum::<u64>(), 1000);
+        assert_eq!(
+            simulation.rules.iter().sum::<u64>() + simulation.default_hits,
+            1000
+        );
+        assert_eq!(simulation.errors, 0);
+        assert!(simulation.variation_ratio(0) > 0.0);
+        assert_eq!(simulation.variation_ratio(99), 0.0);
+    }
+}
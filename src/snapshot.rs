@@ -0,0 +1,63 @@
+use crate::evaluate::Repository;
+use crate::FPError;
+use serde::{Deserialize, Serialize};
+
+const SNAPSHOT_FORMAT: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub format: u8,
+    pub time: u128,
+    pub repository: Repository,
+}
+
+impl Snapshot {
+    pub fn new(repository: Repository, time: u128) -> Self {
+        Self {
+            format: SNAPSHOT_FORMAT,
+            time,
+            repository,
+        }
+    }
+
+    pub fn version(&self) -> Option<u128> {
+        self.repository.version
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FPError> {
+        let snapshot = serde_json::from_slice::<Snapshot>(bytes)
+            .map_err(|e| FPError::JsonError(String::from_utf8_lossy(bytes).into_owned(), e))?;
+        if snapshot.format != SNAPSHOT_FORMAT {
+            return Err(FPError::InternalError(format!(
+                "unsupported snapshot format {}",
+                snapshot.format
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_bytes() {
+        let snapshot = Snapshot::new(Repository::default(), 100);
+        let bytes = snapshot.to_bytes();
+        let loaded = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.version(), Some(0));
+
+        assert!(Snapshot::from_bytes(b"{invalid}").is_err());
+
+        let mut unknown = snapshot;
+        unknown.format = 99;
+        assert!(Snapshot::from_bytes(&unknown.to_bytes()).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+// runner for the official server sdk contract spec (toggle_simple_spec.json),
+// public so wrappers of this sdk can check their integration against it
+use crate::{FPDetail, FPError, FPUser, FeatureProbe, Repository};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::Debug;
+
+pub fn load_tests_json(json_str: &str) -> Result<Tests, FPError> {
+    serde_json::from_str::<Tests>(json_str).map_err(|e| FPError::JsonError(json_str.to_owned(), e))
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Tests {
+    pub tests: Vec<Scenario>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Scenario {
+    pub scenario: String,
+    pub cases: Vec<Case>,
+    pub fixture: Repository,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Case {
+    pub name: String,
+    pub user: User,
+    pub function: Function,
+    pub expect_result: ExpectResult,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub key: String,
+    pub custom_values: Vec<KeyValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub toggle: String,
+    pub default: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectResult {
+    pub value: Value,
+    pub reason: Option<String>,
+    pub rule_index: Option<usize>,
+    pub no_rule_index: Option<bool>,
+    pub version: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CaseFailure {
+    pub scenario: String,
+    pub case: String,
+    pub message: String,
+}
+
+impl User {
+    pub fn to_user(&self) -> FPUser {
+        let mut user = FPUser::new().stable_rollout(self.key.clone());
+        for custom_value in &self.custom_values {
+            user = user.with(custom_value.key.clone(), custom_value.value.clone());
+        }
+        user
+    }
+}
+
+// build turns each scenario's fixture into the client under test
+pub fn run(tests: &Tests, build: impl Fn(Repository) -> FeatureProbe) -> Vec<CaseFailure> {
+    let mut failures = vec![];
+    for scenario in &tests.tests {
+        let fp = build(scenario.fixture.clone());
+        for case in &scenario.cases {
+            if let Err(message) = run_case(&fp, case) {
+                failures.push(CaseFailure {
+                    scenario: scenario.scenario.clone(),
+                    case: case.name.clone(),
+                    message,
+                });
+            }
+        }
+    }
+    failures
+}
+
+pub fn run_case(fp: &FeatureProbe, case: &Case) -> Result<(), String> {
+    let user = case.user.to_user();
+    let toggle = case.function.toggle.as_str();
+    let default = &case.function.default;
+    let invalid_default = || format!("invalid default {default} for {}", case.function.name);
+    let as_bool = || default.as_bool().ok_or_else(invalid_default);
+    let as_str = || {
+        default
+            .as_str()
+            .map(|s| s.to_owned())
+            .ok_or_else(invalid_default)
+    };
+    let as_f64 = || default.as_f64().ok_or_else(invalid_default);
+
+    let expected = &case.expect_result.value;
+    match case.function.name.as_str() {
+        "bool_value" => check_value(fp.bool_value(toggle, &user, as_bool()?), expected),
+        "string_value" => check_value(fp.string_value(toggle, &user, as_str()?), expected),
+        "number_value" => check_value(fp.number_value(toggle, &user, as_f64()?), expected),
+        "json_value" => check_value(fp.json_value(toggle, &user, default.clone()), expected),
+        "bool_detail" => check_detail(case, fp.bool_detail(toggle, &user, as_bool()?)),
+        "string_detail" => check_detail(case, fp.string_detail(toggle, &user, as_str()?)),
+        "number_detail" => check_detail(case, fp.number_detail(toggle, &user, as_f64()?)),
+        "json_detail" => check_detail(case, fp.json_detail(toggle, &user, default.clone())),
+        name => Err(format!("function name {name} not found.")),
+    }
+}
+
+// numbers compare as f64, so 1 and 1.0 are the same value
+fn check_value<T: Serialize>(actual: T, expected: &Value) -> Result<(), String> {
+    let actual = serde_json::to_value(actual).map_err(|e| e.to_string())?;
+    let same = match (actual.as_f64(), expected.as_f64()) {
+        (Some(a), Some(e)) => a == e,
+        _ => &actual == expected,
+    };
+    match same {
+        true => Ok(()),
+        false => Err(format!("value {actual} does not equal {expected}")),
+    }
+}
+
+fn check_detail<T: Default + Debug + Serialize>(
+    case: &Case,
+    ret: FPDetail<T>,
+) -> Result<(), String> {
+    let expect = &case.expect_result;
+    check_value(&ret.value, &expect.value)?;
+    if let Some(r) = &expect.reason {
+        if !ret.reason.contains(r.as_str()) {
+            return Err(format!(
+                "reason: \"{}\" does not contains \"{r}\"",
+                ret.reason
+            ));
+        }
+    }
+    if expect.rule_index.is_some() && expect.rule_index != ret.rule_index {
+        return Err(format!(
+            "rule index {:?} does not match {:?}",
+            ret.rule_index, expect.rule_index
+        ));
+    }
+    if expect.no_rule_index.is_some() && expect.rule_index.is_some() {
+        return Err("should not have rule index.".to_owned());
+    }
+    if expect.version.is_some() && expect.version != ret.version {
+        return Err(format!(
+            "version {:?} does not match {:?}",
+            ret.version, expect.version
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_contract() {
+        let tests = load_test_json("resources/fixtures/spec/spec/toggle_simple_spec.json");
+        for scenario in &tests.tests {
+            assert!(!scenario.cases.is_empty(), "{}", scenario.scenario);
+        }
+        let failures = run(&tests, |fixture| {
+            FeatureProbe::new_with("secret key".to_string(), fixture)
+        });
+        assert!(failures.is_empty(), "{failures:#?}");
+    }
+
+    #[test]
+    fn test_run_reports_failures() {
+        let mut fixture = Repository::default();
+        fixture.toggles.insert(
+            "t".to_owned(),
+            crate::Toggle::new_for_test("t".to_owned(), json!(true)),
+        );
+        let case = |name: &str, function: &str, default: Value, value: Value| Case {
+            name: name.to_owned(),
+            user: User {
+                key: "u".to_owned(),
+                custom_values: vec![],
+            },
+            function: Function {
+                name: function.to_owned(),
+                toggle: "t".to_owned(),
+                default,
+            },
+            expect_result: ExpectResult {
+                value,
+                ..Default::default()
+            },
+        };
+        let tests = Tests {
+            tests: vec![Scenario {
+                scenario: "simple".to_owned(),
+                cases: vec![
+                    case("pass", "bool_value", json!(false), json!(true)),
+                    case("detail", "bool_detail", json!(false), json!(true)),
+                    case("wrong", "bool_value", json!(false), json!(false)),
+                    case("unknown", "nope", json!(false), json!(true)),
+                ],
+                fixture,
+            }],
+        };
+        let failures = run(&tests, |fixture| {
+            FeatureProbe::new_with("secret key".to_string(), fixture)
+        });
+        let names: Vec<&str> = failures.iter().map(|f| f.case.as_str()).collect();
+        assert_eq!(names, vec!["wrong", "unknown"]);
+    }
+
+    fn load_test_json(file: &str) -> Tests {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push(file);
+        let mut json_str = fs::read_to_string(path.clone());
+        if json_str.is_err() {
+            use std::process::Command;
+            Command::new("git")
+                .args(["submodule", "init"])
+                .status()
+                .expect("init");
+            Command::new("git")
+                .args(["submodule", "update"])
+                .status()
+                .expect("update");
+            json_str = fs::read_to_string(path);
+        }
+        assert!(json_str.is_ok(),
+                "contract test resource not found, run `git submodule init && git submodule update` to fetch");
+        let tests = load_tests_json(&json_str.unwrap());
+        assert!(tests.is_ok(), "err is {:?}", tests);
+        tests.unwrap()
+    }
+}
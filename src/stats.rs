@@ -0,0 +1,108 @@
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Weak;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingToggle {
+    pub key: String,
+    pub count: u64,
+    pub first_seen: u128,
+    pub last_seen: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WarmUpReport {
+    pub toggles: usize,
+    pub patterns: usize,
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MissingToggles {
+    toggles: RwLock<HashMap<String, MissingToggle>>,
+}
+
+impl MissingToggles {
+    pub fn record(&self, key: &str, now: u128) {
+        let mut toggles = self.toggles.write();
+        let t = toggles
+            .entry(key.to_owned())
+            .or_insert_with(|| MissingToggle {
+                key: key.to_owned(),
+                count: 0,
+                first_seen: now,
+                last_seen: now,
+            });
+        t.count += 1;
+        t.last_seen = now;
+    }
+
+    pub fn snapshot(&self) -> Vec<MissingToggle> {
+        let toggles = self.toggles.read();
+        let mut list: Vec<MissingToggle> = toggles.values().cloned().collect();
+        list.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        list
+    }
+
+    pub fn clear(&self) {
+        self.toggles.write().clear();
+    }
+
+    pub fn start_report(
+        tracker: Weak<MissingToggles>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = ticker.tick() => {}
+                }
+                let tracker = match tracker.upgrade() {
+                    Some(tracker) => tracker,
+                    None => break,
+                };
+                let list = tracker.snapshot();
+                if !list.is_empty() {
+                    let keys: Vec<String> = list
+                        .iter()
+                        .map(|t| format!("{}({})", t.key, t.count))
+                        .collect();
+                    warn!("toggles not found: {}", keys.join(", "));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_toggles() {
+        let tracker = MissingToggles::default();
+        tracker.record("typo_toggle", 10);
+        tracker.record("dead_toggle", 11);
+        tracker.record("typo_toggle", 20);
+
+        let list = tracker.snapshot();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].key, "typo_toggle");
+        assert_eq!(list[0].count, 2);
+        assert_eq!(list[0].first_seen, 10);
+        assert_eq!(list[0].last_seen, 20);
+
+        tracker.clear();
+        assert!(tracker.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StickyAssignment {
+    pub variation: usize,
+    pub salt: String,
+}
+
+// remembers split assignments per toggle and user, an assignment made under
+// an older salt is served again after the salt rotates
+pub trait StickyBucketStore: Debug + Send + Sync {
+    fn get(&self, toggle: &str, user: &str) -> Option<StickyAssignment>;
+
+    fn set(&self, toggle: &str, user: &str, assignment: StickyAssignment);
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryStickyBuckets {
+    assignments: DashMap<(String, String), StickyAssignment>,
+}
+
+impl InMemoryStickyBuckets {
+    pub fn len(&self) -> usize {
+        self.assignments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assignments.is_empty()
+    }
+}
+
+impl StickyBucketStore for InMemoryStickyBuckets {
+    fn get(&self, toggle: &str, user: &str) -> Option<StickyAssignment> {
+        self.assignments
+            .get(&(toggle.to_owned(), user.to_owned()))
+            .map(|a| a.clone())
+    }
+
+    fn set(&self, toggle: &str, user: &str, assignment: StickyAssignment) {
+        self.assignments
+            .insert((toggle.to_owned(), user.to_owned()), assignment);
+    }
+}
@@ -1,12 +1,21 @@
+use crate::audit::{AuditCallback, AuditRecord};
+use crate::clock::Clock;
+use crate::composition::RepoComposition;
+use crate::evaluate::check_legacy_format;
+use crate::validation::{validate_in_background, ReportSlot};
 use crate::FPError;
-use crate::Repository;
+use crate::{HttpResponse, HttpTransport};
+use crate::{Repository, RepositoryLimits, Secret, UnicodeNormalization};
 use headers::HeaderValue;
-use parking_lot::{Mutex, RwLock};
-use reqwest::{header::AUTHORIZATION, Client, Method};
-use std::{sync::mpsc::sync_channel, time::Instant};
-use std::{sync::Arc, time::Duration};
-use tracing::trace;
-use tracing::{debug, error};
+use http::header::{HeaderMap, AUTHORIZATION, USER_AGENT};
+use parking_lot::{Condvar, Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::{watch, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, debug_span, error, field, trace, warn, Instrument, Span};
 use url::Url;
 
 pub type UpdateCallback = Box<dyn Fn(Repository, Repository, SyncType) + Send>;
@@ -16,7 +25,12 @@ pub struct Synchronizer {
     inner: Arc<Inner>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub(crate) struct WeakSynchronizer {
+    inner: Weak<Inner>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SyncType {
     Realtime,
     Polling,
@@ -26,10 +40,62 @@ struct Inner {
     toggles_url: Url,
     refresh_interval: Duration,
     auth: HeaderValue,
-    client: Client,
+    user_agent: String,
+    transport: Arc<dyn HttpTransport>,
     repo: Arc<RwLock<Repository>>,
-    is_init: Arc<RwLock<bool>>,
+    init: InitSignal,
+    last_synced: Arc<RwLock<Option<u128>>>,
     update_callback: Arc<Mutex<Option<UpdateCallback>>>,
+    audit_callback: Arc<Mutex<Option<AuditCallback>>>,
+    signing_secret: Mutex<Option<Secret>>,
+    normalization: Mutex<Option<UnicodeNormalization>>,
+    limits: Mutex<Option<RepositoryLimits>>,
+    validation: Mutex<Option<ReportSlot>>,
+    // version of every newer repository applied
+    updates: watch::Sender<Option<u128>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Default)]
+struct InitSignal {
+    done: Mutex<bool>,
+    cond: Condvar,
+    notify: Notify,
+}
+
+impl InitSignal {
+    fn is_done(&self) -> bool {
+        *self.done.lock()
+    }
+
+    fn set(&self) {
+        let mut done = self.done.lock();
+        if !*done {
+            *done = true;
+            self.cond.notify_all();
+            self.notify.notify_waiters();
+        }
+    }
+
+    fn wait_blocking(&self, timeout: Duration) -> bool {
+        let mut done = self.done.lock();
+        self.cond.wait_while_for(&mut done, |done| !*done, timeout);
+        *done
+    }
+
+    async fn wait(&self, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                // register before checking, so a set() in between is not missed
+                let notified = self.notify.notified();
+                if self.is_done() {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
 }
 
 impl std::fmt::Debug for Inner {
@@ -38,65 +104,106 @@ impl std::fmt::Debug for Inner {
             .field(&self.toggles_url)
             .field(&self.refresh_interval)
             .field(&self.repo)
-            .field(&self.is_init)
+            .field(&self.init)
             .finish()
     }
 }
 
-//TODO: graceful shutdown
 impl Synchronizer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         toggles_url: Url,
         refresh_interval: Duration,
         auth: HeaderValue,
-        client: Client,
+        user_agent: String,
+        transport: Arc<dyn HttpTransport>,
         repo: Arc<RwLock<Repository>>,
+        last_synced: Arc<RwLock<Option<u128>>>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         Self {
             inner: Arc::new(Inner {
                 toggles_url,
                 refresh_interval,
                 auth,
-                client,
+                user_agent,
+                transport,
                 repo,
-                is_init: Default::default(),
+                init: Default::default(),
+                last_synced,
                 update_callback: Arc::new(Mutex::new(None)),
+                audit_callback: Arc::new(Mutex::new(None)),
+                signing_secret: Mutex::new(None),
+                normalization: Mutex::new(None),
+                limits: Mutex::new(None),
+                validation: Mutex::new(None),
+                updates: watch::channel(None).0,
+                clock,
             }),
         }
     }
 
     pub fn initialized(&self) -> bool {
-        let lock = self.inner.is_init.read();
-        *lock
+        self.inner.init.is_done()
     }
 
-    pub fn start_sync(&self, start_wait: Option<Duration>, should_stop: Arc<RwLock<bool>>) {
-        let inner = self.inner.clone();
-        let (tx, rx) = sync_channel(1);
-        let start = Instant::now();
-        let mut is_send = false;
-        let interval_duration = inner.refresh_interval;
-        let is_timeout = Self::init_timeout_fn(start_wait, interval_duration, start);
+    pub async fn wait_until_initialized(&self, timeout: Duration) -> bool {
+        self.inner.init.wait(timeout).await
+    }
+
+    pub fn wait_until_initialized_blocking(&self, timeout: Duration) -> bool {
+        self.inner.init.wait_blocking(timeout)
+    }
+
+    pub fn start_sync(&self, start_wait: Option<Duration>, shutdown: CancellationToken) {
+        let weak = self.downgrade();
+        let interval_duration = self.inner.refresh_interval;
 
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(inner.refresh_interval);
+            let mut interval = tokio::time::interval(interval_duration);
             loop {
-                let result = inner.sync_now(SyncType::Polling).await;
-
-                if let Some(r) = Self::should_send(result, &is_timeout, is_send) {
-                    is_send = true;
-                    let _ = tx.try_send(r);
+                // stop polling once every synchronizer handle is dropped
+                let syncer = match weak.upgrade() {
+                    Some(syncer) => syncer,
+                    None => break,
+                };
+                let result = tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    result = syncer.inner.sync_now(SyncType::Polling) => result,
+                };
+                drop(syncer);
+
+                if let Err(e) = result {
+                    error!(error = %e, "sync error");
                 }
 
-                if *should_stop.read() {
-                    break;
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = interval.tick() => {}
                 }
-                interval.tick().await;
             }
         });
 
-        if start_wait.is_some() {
-            let _ = rx.recv();
+        if let Some(timeout) = start_wait {
+            // don't stall a multi-thread runtime worker while waiting
+            let initialized = match Handle::try_current() {
+                Ok(h) if h.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                    tokio::task::block_in_place(|| self.wait_until_initialized_blocking(timeout))
+                }
+                _ => self.wait_until_initialized_blocking(timeout),
+            };
+            if !initialized {
+                warn!(
+                    timeout_ms = timeout.as_millis() as u64,
+                    "not initialized after waiting"
+                );
+            }
+        }
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakSynchronizer {
+        WeakSynchronizer {
+            inner: Arc::downgrade(&self.inner),
         }
     }
 
@@ -105,11 +212,46 @@ impl Synchronizer {
         *lock = Some(update_callback);
     }
 
+    pub fn set_audit_callback(&mut self, audit_callback: AuditCallback) {
+        let mut lock = self.inner.audit_callback.lock();
+        *lock = Some(audit_callback);
+    }
+
+    pub fn set_signing_secret(&mut self, secret: Secret) {
+        let mut lock = self.inner.signing_secret.lock();
+        *lock = Some(secret);
+    }
+
+    pub fn set_unicode_normalization(&mut self, form: UnicodeNormalization) {
+        let mut lock = self.inner.normalization.lock();
+        *lock = Some(form);
+    }
+
+    pub fn set_repository_limits(&mut self, limits: RepositoryLimits) {
+        let mut lock = self.inner.limits.lock();
+        *lock = Some(limits);
+    }
+
+    // every newer repository is validated in the background into the slot
+    pub(crate) fn set_validation(&mut self, slot: ReportSlot) {
+        let mut lock = self.inner.validation.lock();
+        *lock = Some(slot);
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Option<u128>> {
+        self.inner.updates.subscribe()
+    }
+
     pub fn version(&self) -> Option<u128> {
         let repo = self.inner.repo.read();
         repo.version
     }
 
+    #[cfg(test)]
+    pub fn last_synced(&self) -> Option<u128> {
+        *self.inner.last_synced.read()
+    }
+
     #[cfg(test)]
     pub fn repository(&self) -> Arc<RwLock<Repository>> {
         self.inner.repo.clone()
@@ -120,36 +262,10 @@ impl Synchronizer {
         self.inner.notify_update(old_repo, new_repo, t)
     }
 
-    fn init_timeout_fn(
-        start_wait: Option<Duration>,
-        interval: Duration,
-        start: Instant,
-    ) -> Option<Box<dyn Fn() -> bool + Send>> {
-        match start_wait {
-            Some(timeout) => Some(Box::new(move || start.elapsed() + interval > timeout)),
-            None => None,
-        }
-    }
-
-    fn should_send(
-        result: Result<(), FPError>,
-        is_timeout: &Option<Box<dyn Fn() -> bool + Send>>,
-        is_send: bool,
-    ) -> Option<Result<(), FPError>> {
-        if let Some(is_timeout) = is_timeout {
-            match result {
-                Ok(_) if !is_send => {
-                    return Some(Ok(()));
-                }
-                Err(e) if !is_send && is_timeout() => {
-                    error!("sync error: {}", e);
-                    return Some(Err(e));
-                }
-                Err(e) => error!("sync error: {}", e),
-                _ => {}
-            }
-        }
-        None
+    // repositories pushed by a streaming transport skip the http fetch
+    #[cfg(feature = "grpc")]
+    pub(crate) fn apply(&self, repo: Repository, t: SyncType) {
+        self.inner.apply(repo, t)
     }
 
     pub fn sync_now(&self, t: SyncType) {
@@ -158,53 +274,148 @@ impl Synchronizer {
     }
 }
 
+impl WeakSynchronizer {
+    pub fn upgrade(&self) -> Option<Synchronizer> {
+        self.inner.upgrade().map(|inner| Synchronizer { inner })
+    }
+}
+
 impl Inner {
     pub async fn sync_now(&self, t: SyncType) -> Result<(), FPError> {
-        use http::header::USER_AGENT;
+        let span = debug_span!(
+            "fp.sync",
+            sync_type = ?t,
+            url = %self.toggles_url,
+            version = field::Empty,
+            duration_ms = field::Empty,
+        );
+        let start = Instant::now();
+        let result = self.fetch(t).instrument(span.clone()).await;
+        let duration_ms = start.elapsed().as_millis() as u64;
+        span.record("duration_ms", duration_ms);
+        span.in_scope(|| match &result {
+            Ok(()) => debug!(duration_ms, "sync finished"),
+            Err(e) => debug!(duration_ms, error = %e, "sync failed"),
+        });
+        result
+    }
 
-        trace!("sync_now {:?} {:?}", self.auth, t);
-        let mut request = self
-            .client
-            .request(Method::GET, self.toggles_url.clone())
-            .header(AUTHORIZATION, self.auth.clone())
-            .header(USER_AGENT, &*crate::USER_AGENT)
-            .timeout(self.refresh_interval);
+    async fn fetch(&self, t: SyncType) -> Result<(), FPError> {
+        let mut url = self.toggles_url.clone();
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, self.auth.clone());
+        if let Ok(ua) = HeaderValue::from_str(&self.user_agent) {
+            headers.insert(USER_AGENT, ua);
+        }
 
         {
             let repo = self.repo.read();
             if let Some(version) = &repo.version {
-                request = request.query(&[("version", &version.to_string())]);
+                url.query_pairs_mut()
+                    .append_pair("version", &version.to_string());
             }
         } // drop repo lock
 
+        #[cfg(feature = "proto")]
+        if let Ok(accept) = HeaderValue::from_str(&format!(
+            "{}, application/json;q=0.9",
+            crate::proto::CONTENT_TYPE
+        )) {
+            headers.insert(http::header::ACCEPT, accept);
+        }
+
+        if let Some(secret) = &*self.signing_secret.lock() {
+            // sync requests carry no body, so only the timestamp is signed
+            let signature = crate::signing::sign(secret.expose().as_bytes(), self.clock.now(), b"");
+            if let Ok(signature) = HeaderValue::from_str(&signature) {
+                headers.insert(crate::signing::SIGNATURE_HEADER, signature);
+            }
+        }
+
         //TODO: report failure
-        match request.send().await {
-            Err(e) => Err(FPError::HttpError(e.to_string())),
-            Ok(resp) => match resp.text().await {
-                Err(e) => Err(FPError::HttpError(e.to_string())),
-                Ok(body) => match serde_json::from_str::<Repository>(&body) {
-                    Err(e) => Err(FPError::JsonError(body, e)),
-                    Ok(r) => {
-                        // TODO: validate repo
-                        // TODO: diff change, notify subscriber
-                        debug!("sync success {:?}", r);
-                        let mut repo = self.repo.write();
-                        if r.version > repo.version {
-                            let old = (*repo).clone();
-                            let new = r.clone();
-                            *repo = r;
-                            self.notify_update(old, new, t);
-                        }
-                        let mut is_init = self.is_init.write();
-                        *is_init = true;
-                        Ok(())
-                    }
-                },
-            },
+        let limits = *self.limits.lock();
+        let resp = match &limits {
+            Some(limits) => {
+                self.transport
+                    .get_with_limits(url, headers, self.refresh_interval, limits)
+                    .await?
+            }
+            None => {
+                self.transport
+                    .get(url, headers, self.refresh_interval)
+                    .await?
+            }
+        };
+        let r = Self::parse_repository(resp, limits.is_some())?;
+        if let Some(limits) = &limits {
+            limits.check(&r)?;
+        }
+        self.apply(r, t);
+        Ok(())
+    }
+
+    fn apply(&self, mut r: Repository, t: SyncType) {
+        if let Some(form) = *self.normalization.lock() {
+            r.normalize_strings(form);
+        }
+        Span::current().record("version", field::debug(&r.version));
+        trace!(repository = ?r, "sync success");
+        let mut repo = self.repo.write();
+        if r.version > repo.version {
+            debug!(
+                sync_type = ?t,
+                old_version = ?repo.version,
+                new_version = ?r.version,
+                toggles = r.toggles.len(),
+                segments = r.segments.len(),
+                "repository updated"
+            );
+            let old = (*repo).clone();
+            let new = r.clone();
+            *repo = r;
+            self.updates.send_replace(new.version);
+            if let Some(slot) = &*self.validation.lock() {
+                validate_in_background(new.clone(), slot.clone(), self.clock.now());
+            }
+            drop(repo);
+            RepoComposition::of(&new).log();
+            self.notify_update(old, new, t);
+        }
+        *self.last_synced.write() = Some(self.clock.now());
+        self.init.set();
+    }
+
+    // an untrusted body is not kept for the error, like load_bytes_with_limits
+    fn parse_repository(resp: HttpResponse, untrusted: bool) -> Result<Repository, FPError> {
+        #[cfg(feature = "proto")]
+        if resp
+            .content_type()
+            .map(|v| v.starts_with(crate::proto::CONTENT_TYPE))
+            .unwrap_or(false)
+        {
+            return crate::proto::decode_repository(&resp.body);
+        }
+
+        match serde_json::from_slice::<Repository>(&resp.body) {
+            Err(e) if untrusted => Err(FPError::JsonError("<untrusted>".to_owned(), e)),
+            Err(e) => Err(FPError::JsonError(
+                String::from_utf8_lossy(&resp.body).into_owned(),
+                e,
+            )),
+            Ok(r) => {
+                check_legacy_format(&resp.body);
+                Ok(r)
+            }
         }
     }
 
     fn notify_update(&self, old_repo: Repository, new_repo: Repository, t: SyncType) {
+        {
+            let lock = self.audit_callback.lock();
+            if let Some(cb) = &*lock {
+                cb(AuditRecord::new(&old_repo, &new_repo, t, self.clock.now()))
+            }
+        }
         let lock = self.update_callback.lock();
         if let Some(cb) = &*lock {
             cb(old_repo, new_repo, t)
@@ -220,6 +431,29 @@ mod tests {
     use headers::UserAgent;
     use std::{fs, net::SocketAddr, path::PathBuf, sync::mpsc::channel};
 
+    #[test]
+    fn test_background_validation() {
+        let mut syncer = build_synchronizer(9000);
+        let slot: ReportSlot = Default::default();
+        syncer.set_validation(slot.clone());
+        let mut repo = Repository::default();
+        repo.version = Some(3);
+        repo.segments.insert(
+            "empty".to_owned(),
+            serde_json::from_str(r#"{"uniqueId": "empty", "version": 1, "rules": []}"#).unwrap(),
+        );
+        syncer.inner.apply(repo, SyncType::Polling);
+        for _ in 0..100 {
+            if slot.read().is_some() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let report = slot.read().clone().unwrap();
+        assert_eq!(report.version, Some(3));
+        assert_eq!(report.issues[0].segment.as_deref(), Some("empty"));
+    }
+
     #[test]
     fn test_update_callback() {
         let mut syncer = build_synchronizer(9000);
@@ -233,75 +467,55 @@ mod tests {
         assert!(rx.try_recv().is_ok())
     }
 
-    #[tokio::test]
-    async fn test_init_timeout_fn() {
-        let now = Instant::now();
-        let now = now - Duration::from_millis(10);
-
-        let is_timeout_fn = Synchronizer::init_timeout_fn(None, Duration::from_millis(1), now);
-        assert!(is_timeout_fn.is_none());
+    #[test]
+    fn test_audit_callback() {
+        let mut syncer = build_synchronizer(9000);
+        let (tx, rx) = channel();
 
-        let is_timeout_fn = Synchronizer::init_timeout_fn(
-            Some(Duration::from_millis(20)),
-            Duration::from_millis(1),
-            now,
-        );
-        assert!(!is_timeout_fn.unwrap()());
+        syncer.set_audit_callback(Box::new(move |record| tx.send(record).unwrap()));
+        let old = Repository::default();
+        let mut new = Repository::default();
+        new.version = Some(1);
+        syncer.notify_update(old, new, SyncType::Realtime);
+
+        let record = rx.try_recv().unwrap();
+        assert_eq!(record.sync_type, SyncType::Realtime);
+        assert_eq!(record.new_version, Some(1));
+        assert!(record.changes.is_empty());
+    }
 
-        let is_timeout_fn = Synchronizer::init_timeout_fn(
-            Some(Duration::from_millis(5)),
-            Duration::from_millis(1),
-            now,
-        );
-        assert!(is_timeout_fn.unwrap()());
+    #[test]
+    fn test_weak_synchronizer() {
+        let syncer = build_synchronizer(9000);
+        let weak = syncer.downgrade();
+        assert!(weak.upgrade().is_some());
+        drop(syncer);
+        assert!(weak.upgrade().is_none());
     }
 
     #[test]
-    fn test_should_send() {
-        let is_timeout_fn = None;
-        let r = Synchronizer::should_send(Ok(()), &is_timeout_fn, false);
-        assert!(r.is_none(), "no need send because not set timeout");
-
-        let is_timeout_fn: Option<Box<dyn Fn() -> bool + Send>> = Some(Box::new(|| false));
-        let r = Synchronizer::should_send(Ok(()), &is_timeout_fn, false);
-        assert!(r.is_some(), "need send because not timeout, and return Ok");
-        let r = r.unwrap();
-        assert!(r.is_ok());
-
-        let is_timeout_fn: Option<Box<dyn Fn() -> bool + Send>> = Some(Box::new(|| false));
-        let r = Synchronizer::should_send(Ok(()), &is_timeout_fn, true);
-        assert!(
-            r.is_none(),
-            "no need send because not timeout, and return error, wait next loop"
-        );
+    fn test_init_signal_blocking() {
+        let syncer = build_synchronizer(9000);
+        assert!(!syncer.wait_until_initialized_blocking(Duration::from_millis(10)));
 
-        let is_timeout_fn: Option<Box<dyn Fn() -> bool + Send>> = Some(Box::new(|| false));
-        let is_send = true;
-        let r = Synchronizer::should_send(
-            Err(FPError::InternalError("unknown".to_owned())),
-            &is_timeout_fn,
-            is_send,
-        );
-        assert!(r.is_none(), "no need send because already send before");
+        let signal = syncer.clone();
+        std::thread::spawn(move || signal.inner.init.set());
+        assert!(syncer.wait_until_initialized_blocking(Duration::from_secs(5)));
+        assert!(syncer.initialized());
+    }
 
-        let is_timeout_fn: Option<Box<dyn Fn() -> bool + Send>> = Some(Box::new(|| true));
-        let r = Synchronizer::should_send(
-            Err(FPError::InternalError("unknown".to_owned())),
-            &is_timeout_fn,
-            is_send,
-        );
-        assert!(r.is_none(), "no need send because already send before");
-
-        let is_send = false;
-        let is_timeout_fn: Option<Box<dyn Fn() -> bool + Send>> = Some(Box::new(|| true));
-        let r = Synchronizer::should_send(
-            Err(FPError::InternalError("unknown".to_owned())),
-            &is_timeout_fn,
-            is_send,
+    #[tokio::test]
+    async fn test_init_signal_async() {
+        let syncer = build_synchronizer(9000);
+        assert!(
+            !syncer
+                .wait_until_initialized(Duration::from_millis(10))
+                .await
         );
-        assert!(r.is_some(), "need send because already timeout");
-        let r = r.unwrap();
-        assert!(r.is_err());
+
+        let signal = syncer.clone();
+        tokio::spawn(async move { signal.inner.init.set() });
+        assert!(syncer.wait_until_initialized(Duration::from_secs(5)).await);
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -311,13 +525,84 @@ mod tests {
         let port = 9009;
         setup_mock_api(port).await;
         let syncer = build_synchronizer(port);
-        let should_stop = Arc::new(RwLock::new(false));
-        syncer.start_sync(Some(Duration::from_secs(5)), should_stop);
+        let shutdown = CancellationToken::new();
+        syncer.start_sync(Some(Duration::from_secs(5)), shutdown);
 
         let repo = syncer.repository();
         let repo = repo.read();
         assert!(!repo.toggles.is_empty());
         assert!(syncer.initialized());
+        assert!(syncer.last_synced().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_sync_signed() {
+        let port = 9010;
+        let app = Router::new().route(
+            "/api/server-sdk/toggles",
+            get(|headers: http::HeaderMap| async move {
+                let signature = headers
+                    .get(crate::signing::SIGNATURE_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default();
+                assert!(crate::signing::verify(b"secret", signature, b""));
+                Json(Repository::default())
+            }),
+        );
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        tokio::spawn(axum::Server::bind(&addr).serve(app.into_make_service()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut syncer = build_synchronizer(port);
+        syncer.set_signing_secret("secret".into());
+        assert!(syncer.inner.sync_now(SyncType::Polling).await.is_ok());
+        assert!(syncer.initialized());
+    }
+
+    #[tokio::test]
+    async fn test_sync_payload_limits() {
+        let chunked = || {
+            let chunks = (0..1024).map(|_| Ok::<_, std::io::Error>(vec![b' '; 1024]));
+            axum::body::StreamBody::new(futures_util::stream::iter(chunks))
+        };
+        let apps = [
+            (9014, get(move || async move { chunked() })),
+            (9015, get(|| async { vec![b' '; 1 << 20] })),
+            (9016, get(|| async { "{\"toggles\": secret" })),
+        ];
+        for (port, handler) in apps {
+            let app = Router::new().route("/api/server-sdk/toggles", handler);
+            let addr = SocketAddr::from(([0, 0, 0, 0], port));
+            tokio::spawn(axum::Server::bind(&addr).serve(app.into_make_service()));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let limits = RepositoryLimits {
+            max_rules_per_toggle: 10,
+            max_conditions: 10,
+            max_objects_per_condition: 10,
+            max_segments: 10,
+            max_payload_bytes: 64,
+            max_regex_bytes: 1024,
+        };
+        let fetch = |port| {
+            let mut syncer = build_synchronizer(port);
+            syncer.set_repository_limits(limits);
+            async move { syncer.inner.fetch(SyncType::Polling).await }
+        };
+        // the chunked body is dropped once the cap is passed, not read to the end
+        match fetch(9014).await {
+            Err(FPError::LimitExceeded(v)) => assert!(v.found < 1 << 20),
+            r => panic!("unexpected {r:?}"),
+        }
+        match fetch(9015).await {
+            Err(FPError::LimitExceeded(v)) => assert_eq!(v.found, 1 << 20),
+            r => panic!("unexpected {r:?}"),
+        }
+        match fetch(9016).await {
+            Err(FPError::JsonError(body, _)) => assert_eq!(body, "<untrusted>"),
+            r => panic!("unexpected {r:?}"),
+        }
     }
 
     fn build_synchronizer(port: u16) -> Synchronizer {
@@ -330,10 +615,19 @@ mod tests {
                 toggles_url,
                 refresh_interval,
                 auth,
-                client: Default::default(),
+                user_agent: crate::USER_AGENT.clone(),
+                transport: Arc::new(reqwest::Client::default()),
                 repo: Default::default(),
-                is_init: Default::default(),
+                init: Default::default(),
+                last_synced: Default::default(),
                 update_callback: Default::default(),
+                audit_callback: Default::default(),
+                signing_secret: Default::default(),
+                normalization: Default::default(),
+                limits: Default::default(),
+                validation: Default::default(),
+                updates: watch::channel(None).0,
+                clock: Arc::new(crate::clock::SystemClock),
             }),
         }
     }
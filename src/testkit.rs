@@ -0,0 +1,484 @@
+use crate::evaluate::{
+    BucketRange, Condition, ConditionType, Distribution, Repository, Rule, Serve, Toggle,
+    KEY_SUBJECT,
+};
+use crate::{FPError, FPUser, FeatureProbe};
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Json, Router,
+};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::Arc;
+use url::Url;
+
+#[derive(Debug, Clone, Default)]
+pub struct TestData {
+    repo: Arc<RwLock<Repository>>,
+    flags: Arc<RwLock<HashMap<String, FlagBuilder>>>,
+}
+
+impl TestData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flag(&self, key: &str) -> FlagBuilder {
+        let flags = self.flags.read();
+        match flags.get(key) {
+            Some(builder) => builder.clone(),
+            None => FlagBuilder::new(key),
+        }
+    }
+
+    pub fn update(&self, builder: FlagBuilder) {
+        let mut repo = self.repo.write();
+        let version = repo.toggles.get(&builder.key).map(|t| t.version + 1);
+        let toggle = builder.build(version.unwrap_or(1));
+        repo.toggles.insert(builder.key.clone(), toggle);
+        repo.version = Some(repo.version.unwrap_or_default() + 1);
+        repo.rebuild_index();
+        self.flags.write().insert(builder.key.clone(), builder);
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut repo = self.repo.write();
+        if repo.toggles.remove(key).is_some() {
+            repo.version = Some(repo.version.unwrap_or_default() + 1);
+            repo.rebuild_index();
+        }
+        self.flags.write().remove(key);
+    }
+
+    pub fn repository(&self) -> Repository {
+        self.repo.read().clone()
+    }
+
+    pub fn feature_probe(&self) -> FeatureProbe {
+        FeatureProbe::new_with_repo(self.repo.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlagBuilder {
+    key: String,
+    enabled: bool,
+    variations: Vec<Value>,
+    default_serve: Serve,
+    disabled_serve: Serve,
+    user_targets: HashMap<String, usize>,
+    rules: Vec<Rule>,
+}
+
+impl FlagBuilder {
+    fn new(key: &str) -> Self {
+        Self {
+            key: key.to_owned(),
+            enabled: true,
+            variations: vec![Value::Bool(true), Value::Bool(false)],
+            default_serve: Serve::Select(0),
+            disabled_serve: Serve::Select(1),
+            user_targets: HashMap::new(),
+            rules: vec![],
+        }
+    }
+
+    pub fn variations(self, first: Value, second: Value) -> Self {
+        self.variation_list(vec![first, second])
+    }
+
+    pub fn variation_list(mut self, variations: Vec<Value>) -> Self {
+        self.variations = variations;
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn default_variation(mut self, index: usize) -> Self {
+        self.default_serve = Serve::Select(index);
+        self
+    }
+
+    pub fn disabled_variation(mut self, index: usize) -> Self {
+        self.disabled_serve = Serve::Select(index);
+        self
+    }
+
+    pub fn variation_for_user(mut self, user_key: &str, index: usize) -> Self {
+        self.user_targets.insert(user_key.to_owned(), index);
+        self
+    }
+
+    pub fn rule(mut self, subject: &str, predicate: &str, objects: &[&str], index: usize) -> Self {
+        self.rules.push(Rule {
+            serve: Serve::Select(index),
+            conditions: vec![Condition {
+                r#type: ConditionType::String,
+                subject: subject.to_owned(),
+                predicate: predicate.to_owned(),
+                objects: objects.iter().map(|o| o.to_string()).collect(),
+                regexes: Default::default(),
+            }],
+        });
+        self
+    }
+
+    // weights are in basis points of 10000, one per variation.
+    pub fn rollout(mut self, weights: &[u32]) -> Self {
+        let mut lower = 0;
+        let distribution = weights
+            .iter()
+            .map(|w| {
+                let range = BucketRange((lower, lower + w));
+                lower += w;
+                vec![range]
+            })
+            .collect();
+        self.default_serve = Serve::Split(Distribution {
+            distribution,
+            bucket_by: None,
+            salt: None,
+        });
+        self
+    }
+
+    pub fn clear_rules(mut self) -> Self {
+        self.user_targets.clear();
+        self.rules.clear();
+        self
+    }
+
+    // user targets become leading rules on the user key, one per variation
+    fn build(&self, version: u64) -> Toggle {
+        let mut targets: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for (user_key, index) in &self.user_targets {
+            targets.entry(*index).or_default().push(user_key.clone());
+        }
+        let mut rules: Vec<Rule> = targets
+            .into_iter()
+            .map(|(index, mut keys)| {
+                keys.sort();
+                Rule {
+                    serve: Serve::Select(index),
+                    conditions: vec![Condition {
+                        r#type: ConditionType::String,
+                        subject: KEY_SUBJECT.to_owned(),
+                        predicate: "is one of".to_owned(),
+                        objects: keys,
+                        regexes: Default::default(),
+                    }],
+                }
+            })
+            .collect();
+        rules.extend(self.rules.iter().cloned());
+
+        Toggle {
+            key: self.key.clone(),
+            enabled: self.enabled,
+            track_access_events: None,
+            last_modified: None,
+            version,
+            for_client: false,
+            disabled_serve: self.disabled_serve.clone(),
+            default_serve: self.default_serve.clone(),
+            rules,
+            variations: self.variations.clone(),
+            prerequisites: None,
+            archived: false,
+            deprecated: false,
+            archived_serve: None,
+            rollout_schedule: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScenarioUser {
+    pub key: String,
+    #[serde(default)]
+    pub attrs: HashMap<String, String>,
+}
+
+impl ScenarioUser {
+    pub fn to_user(&self) -> FPUser {
+        FPUser::new()
+            .stable_rollout(self.key.clone())
+            .with_attrs(self.attrs.clone().into_iter())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Expectation {
+    pub user: String,
+    pub toggle: String,
+    pub value: Value,
+    pub variation_index: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub expectation: Expectation,
+    pub value: Value,
+    pub variation_index: Option<usize>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Scenarios {
+    pub users: HashMap<String, ScenarioUser>,
+    pub expectations: Vec<Expectation>,
+}
+
+impl Scenarios {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, FPError> {
+        let path = path.as_ref();
+        let json_str = fs::read_to_string(path)
+            .map_err(|e| FPError::InternalError(format!("read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&json_str).map_err(|e| FPError::JsonError(json_str, e))
+    }
+
+    pub fn user(&self, name: &str) -> Option<FPUser> {
+        self.users.get(name).map(|u| u.to_user())
+    }
+
+    pub fn verify(&self, fp: &FeatureProbe) -> Result<(), Vec<Mismatch>> {
+        let mut mismatches = vec![];
+        for expectation in &self.expectations {
+            let user = match self.user(&expectation.user) {
+                Some(user) => user,
+                None => {
+                    mismatches.push(Mismatch {
+                        expectation: expectation.clone(),
+                        value: Value::Null,
+                        variation_index: None,
+                        reason: format!("user [{}] not defined", expectation.user),
+                    });
+                    continue;
+                }
+            };
+            let detail = fp.json_detail(&expectation.toggle, &user, Value::Null);
+            let index_match = match expectation.variation_index {
+                Some(index) => detail.variation_index == Some(index),
+                None => true,
+            };
+            if detail.value != expectation.value || !index_match {
+                mismatches.push(Mismatch {
+                    expectation: expectation.clone(),
+                    value: detail.value,
+                    variation_index: detail.variation_index,
+                    reason: detail.reason,
+                });
+            }
+        }
+
+        match mismatches.is_empty() {
+            true => Ok(()),
+            false => Err(mismatches),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<MockState>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    repo: RwLock<Repository>,
+    posted: Mutex<Vec<Value>>,
+}
+
+impl MockServer {
+    pub fn start(repo: Repository) -> Result<Self, FPError> {
+        let state = Arc::new(MockState {
+            repo: RwLock::new(repo),
+            posted: Default::default(),
+        });
+        let app = Router::new()
+            .route("/api/server-sdk/toggles", get(server_sdk_toggles))
+            .route("/api/events", post(events))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|e| FPError::InternalError(format!("mock server bind: {}", e)))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| FPError::InternalError(format!("mock server addr: {}", e)))?;
+        let server = axum::Server::from_tcp(listener)
+            .map_err(|e| FPError::InternalError(format!("mock server: {}", e)))?
+            .serve(app.into_make_service());
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                tracing::error!("mock server error: {}", e);
+            }
+        });
+
+        Ok(Self { addr, state })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn remote_url(&self) -> Url {
+        Url::parse(&format!("http://{}/", self.addr)).expect("valid mock server url")
+    }
+
+    pub fn repository(&self) -> Repository {
+        self.state.repo.read().clone()
+    }
+
+    pub fn set_repository(&self, repo: Repository) {
+        let mut lock = self.state.repo.write();
+        *lock = repo;
+    }
+
+    pub fn update_repository<F: FnOnce(&mut Repository)>(&self, f: F) {
+        let mut repo = self.state.repo.write();
+        f(&mut repo);
+        repo.version = Some(repo.version.unwrap_or_default() + 1);
+        repo.rebuild_index();
+    }
+
+    pub fn posted_events(&self) -> Vec<Value> {
+        self.state.posted.lock().clone()
+    }
+
+    pub fn clear_events(&self) {
+        self.state.posted.lock().clear();
+    }
+}
+
+async fn server_sdk_toggles(State(state): State<Arc<MockState>>) -> Json<Repository> {
+    Json(state.repo.read().clone())
+}
+
+async fn events(State(state): State<Arc<MockState>>, Json(data): Json<Vec<Value>>) {
+    state.posted.lock().extend(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_variation_for_user() {
+        let td = TestData::new();
+        td.update(
+            td.flag("f1")
+                .variations(json!(true), json!(false))
+                .variation_for_user("u1", 1),
+        );
+        let fp = td.feature_probe();
+
+        let u1 = FPUser::new().stable_rollout("u1".to_owned());
+        let u2 = FPUser::new().stable_rollout("u2".to_owned());
+        assert!(!fp.bool_value("f1", &u1, true));
+        assert!(fp.bool_value("f1", &u2, false));
+        assert_eq!(td.repository().toggles["f1"].rules.len(), 1);
+        let served = FeatureProbe::new_with("secret".to_owned(), td.repository());
+        assert!(!served.bool_value("f1", &u1, true));
+
+        td.update(td.flag("f1").enabled(false));
+        assert!(!fp.bool_value("f1", &u1, true));
+        td.update(td.flag("f1").enabled(true).clear_rules());
+        assert!(fp.bool_value("f1", &u1, false));
+    }
+
+    #[test]
+    fn test_update_after_create() {
+        let td = TestData::new();
+        let fp = td.feature_probe();
+        let user = FPUser::new().with("city", "1");
+
+        td.update(td.flag("f1").variations(json!("a"), json!("b")));
+        assert_eq!(fp.string_value("f1", &user, "x".to_owned()), "a");
+
+        td.update(td.flag("f1").rule("city", "is one of", &["1"], 1));
+        assert_eq!(fp.string_value("f1", &user, "x".to_owned()), "b");
+        assert_eq!(
+            fp.string_detail("f1", &user, "x".to_owned()).version,
+            Some(2)
+        );
+
+        td.update(td.flag("f1").enabled(false).disabled_variation(0));
+        assert_eq!(fp.string_value("f1", &user, "x".to_owned()), "a");
+
+        td.remove("f1");
+        assert_eq!(fp.string_value("f1", &user, "x".to_owned()), "x");
+    }
+
+    #[test]
+    fn test_rollout() {
+        let td = TestData::new();
+        td.update(td.flag("f1").rollout(&[0, 10000]));
+        let fp = td.feature_probe();
+
+        let user = FPUser::new();
+        assert!(!fp.bool_value("f1", &user, true));
+    }
+
+    #[test]
+    fn test_scenarios_verify() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let fp = FeatureProbe::from_fixture(path).unwrap();
+
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/scenarios.json");
+        let mut scenarios = Scenarios::load(path).unwrap();
+        assert!(scenarios.user("beijing").is_some());
+        assert!(scenarios.verify(&fp).is_ok());
+
+        scenarios.expectations[0].value = json!(false);
+        let mismatches = scenarios.verify(&fp).unwrap_err();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].value, json!(true));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_mock_server() {
+        let td = TestData::new();
+        td.update(td.flag("f1"));
+        let server = MockServer::start(td.repository()).unwrap();
+
+        let config = crate::FPConfig {
+            remote_url: server.remote_url(),
+            server_sdk_key: "server-sdk-key".into(),
+            refresh_interval: std::time::Duration::from_millis(100),
+            start_wait: Some(std::time::Duration::from_secs(2)),
+            #[cfg(feature = "realtime")]
+            realtime_url: Some(server.remote_url()),
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config);
+        let user = FPUser::new();
+        assert!(fp.initialized());
+        assert!(fp.bool_value("f1", &user, false));
+
+        server.update_repository(|repo| {
+            repo.toggles.get_mut("f1").unwrap().enabled = false;
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(!fp.bool_value("f1", &user, true));
+        assert!(!server.posted_events().is_empty());
+
+        server.clear_events();
+        assert!(server.posted_events().is_empty());
+        fp.close();
+    }
+}
@@ -0,0 +1,505 @@
+use crate::{FPError, RepositoryLimits};
+use async_trait::async_trait;
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok())
+    }
+}
+
+// implement to route sdk traffic through a custom client or a test double,
+// non 2xx responses are returned as Ok and left to the caller
+#[async_trait]
+pub trait HttpTransport: Debug + Send + Sync {
+    async fn get(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        timeout: Duration,
+    ) -> Result<HttpResponse, FPError>;
+
+    async fn post_json(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        body: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<HttpResponse, FPError>;
+
+    // get with the body capped at limits.max_payload_bytes, the default reads
+    // the whole body first, override to stop reading once the cap is passed
+    async fn get_with_limits(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        timeout: Duration,
+        limits: &RepositoryLimits,
+    ) -> Result<HttpResponse, FPError> {
+        let resp = self.get(url, headers, timeout).await?;
+        limits.check_payload(resp.body.len())?;
+        Ok(resp)
+    }
+
+    // a copy resolving the given hosts to fixed addresses, None when this
+    // transport can't, config build then fails instead of dropping them
+    fn with_dns_overrides(
+        &self,
+        _overrides: &BTreeMap<String, Vec<SocketAddr>>,
+    ) -> Option<Arc<dyn HttpTransport>> {
+        None
+    }
+}
+
+#[async_trait]
+impl HttpTransport for reqwest::Client {
+    async fn get(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        timeout: Duration,
+    ) -> Result<HttpResponse, FPError> {
+        let resp = self
+            .get(url)
+            .headers(headers)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(|e| FPError::HttpError(e.to_string()))?;
+        read_reqwest(resp, None).await
+    }
+
+    async fn get_with_limits(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        timeout: Duration,
+        limits: &RepositoryLimits,
+    ) -> Result<HttpResponse, FPError> {
+        let resp = self
+            .get(url)
+            .headers(headers)
+            .timeout(timeout)
+            .send()
+            .await
+            .map_err(|e| FPError::HttpError(e.to_string()))?;
+        read_reqwest(resp, Some(limits)).await
+    }
+
+    async fn post_json(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        body: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<HttpResponse, FPError> {
+        let resp = self
+            .post(url)
+            .headers(headers)
+            .header(CONTENT_TYPE, "application/json")
+            .timeout(timeout)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| FPError::HttpError(e.to_string()))?;
+        read_reqwest(resp, None).await
+    }
+}
+
+// with limits, content-length is checked up front and the read stops at the
+// first chunk past the cap
+async fn read_reqwest(
+    mut resp: reqwest::Response,
+    limits: Option<&RepositoryLimits>,
+) -> Result<HttpResponse, FPError> {
+    let status = resp.status().as_u16();
+    let headers = resp.headers().clone();
+    let limits = match limits {
+        Some(limits) => limits,
+        None => {
+            let body = resp
+                .bytes()
+                .await
+                .map_err(|e| FPError::HttpError(e.to_string()))?;
+            return Ok(HttpResponse {
+                status,
+                headers,
+                body: body.to_vec(),
+            });
+        }
+    };
+    if let Some(len) = resp.content_length() {
+        limits.check_payload(len as usize)?;
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| FPError::HttpError(e.to_string()))?
+    {
+        body.extend_from_slice(&chunk);
+        limits.check_payload(body.len())?;
+    }
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+#[cfg(feature = "ureq")]
+type AgentBuilderFn = Arc<dyn Fn() -> ureq::AgentBuilder + Send + Sync>;
+
+// blocking ureq calls run on tokio's blocking pool
+#[cfg(feature = "ureq")]
+#[derive(Clone)]
+pub struct UreqTransport {
+    agent: ureq::Agent,
+    builder: Option<AgentBuilderFn>,
+}
+
+#[cfg(feature = "ureq")]
+impl Debug for UreqTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UreqTransport")
+            .field("agent", &self.agent)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl UreqTransport {
+    // a prebuilt agent can't take a resolver, dns_overrides are rejected
+    pub fn new(agent: ureq::Agent) -> Self {
+        Self {
+            agent,
+            builder: None,
+        }
+    }
+
+    // the builder is kept so dns_overrides can be applied as a resolver
+    pub fn from_builder(builder: impl Fn() -> ureq::AgentBuilder + Send + Sync + 'static) -> Self {
+        Self {
+            agent: builder().build(),
+            builder: Some(Arc::new(builder)),
+        }
+    }
+
+    fn call(
+        agent: &ureq::Agent,
+        method: &str,
+        url: &Url,
+        headers: &HeaderMap,
+        body: Option<&[u8]>,
+        timeout: Duration,
+        limits: Option<RepositoryLimits>,
+    ) -> Result<HttpResponse, FPError> {
+        use std::io::Read;
+
+        let mut request = agent.request_url(method, url).timeout(timeout);
+        for (name, value) in headers {
+            if let Ok(value) = value.to_str() {
+                request = request.set(name.as_str(), value);
+            }
+        }
+        let resp = match body {
+            Some(body) => request
+                .set(CONTENT_TYPE.as_str(), "application/json")
+                .send_bytes(body),
+            None => request.call(),
+        };
+        let resp = match resp {
+            Ok(resp) | Err(ureq::Error::Status(_, resp)) => resp,
+            Err(e) => return Err(FPError::HttpError(e.to_string())),
+        };
+
+        let mut headers = HeaderMap::new();
+        for name in resp.headers_names() {
+            let value = resp.header(&name).unwrap_or_default();
+            if let (Ok(name), Ok(value)) = (
+                http::HeaderName::from_bytes(name.as_bytes()),
+                http::HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        let status = resp.status();
+        let mut body = Vec::new();
+        match limits {
+            Some(limits) => {
+                let len = resp.header(http::header::CONTENT_LENGTH.as_str());
+                if let Some(len) = len.and_then(|v| v.parse().ok()) {
+                    limits.check_payload(len)?;
+                }
+                // one byte past the cap is enough to reject the payload
+                resp.into_reader()
+                    .take(limits.max_payload_bytes as u64 + 1)
+                    .read_to_end(&mut body)
+                    .map_err(|e| FPError::HttpError(e.to_string()))?;
+                limits.check_payload(body.len())?;
+            }
+            None => {
+                resp.into_reader()
+                    .read_to_end(&mut body)
+                    .map_err(|e| FPError::HttpError(e.to_string()))?;
+            }
+        }
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    async fn spawn(
+        &self,
+        method: &'static str,
+        url: Url,
+        headers: HeaderMap,
+        body: Option<Vec<u8>>,
+        timeout: Duration,
+        limits: Option<RepositoryLimits>,
+    ) -> Result<HttpResponse, FPError> {
+        let agent = self.agent.clone();
+        tokio::task::spawn_blocking(move || {
+            Self::call(
+                &agent,
+                method,
+                &url,
+                &headers,
+                body.as_deref(),
+                timeout,
+                limits,
+            )
+        })
+        .await
+        .map_err(|e| FPError::InternalError(e.to_string()))?
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl Default for UreqTransport {
+    fn default() -> Self {
+        Self::from_builder(ureq::AgentBuilder::new)
+    }
+}
+
+// ports in the override addresses are ignored, the url port is used
+#[cfg(feature = "ureq")]
+fn resolve_with_overrides(
+    overrides: &BTreeMap<String, Vec<SocketAddr>>,
+    netloc: &str,
+) -> std::io::Result<Vec<SocketAddr>> {
+    use std::net::ToSocketAddrs;
+
+    if let Some((host, port)) = netloc.rsplit_once(':') {
+        if let (Some(addrs), Ok(port)) = (overrides.get(host), port.parse()) {
+            return Ok(addrs
+                .iter()
+                .map(|a| SocketAddr::new(a.ip(), port))
+                .collect());
+        }
+    }
+    netloc.to_socket_addrs().map(Iterator::collect)
+}
+
+#[cfg(feature = "ureq")]
+#[async_trait]
+impl HttpTransport for UreqTransport {
+    async fn get(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        timeout: Duration,
+    ) -> Result<HttpResponse, FPError> {
+        self.spawn("GET", url, headers, None, timeout, None).await
+    }
+
+    async fn get_with_limits(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        timeout: Duration,
+        limits: &RepositoryLimits,
+    ) -> Result<HttpResponse, FPError> {
+        self.spawn("GET", url, headers, None, timeout, Some(*limits))
+            .await
+    }
+
+    async fn post_json(
+        &self,
+        url: Url,
+        headers: HeaderMap,
+        body: Vec<u8>,
+        timeout: Duration,
+    ) -> Result<HttpResponse, FPError> {
+        self.spawn("POST", url, headers, Some(body), timeout, None)
+            .await
+    }
+
+    fn with_dns_overrides(
+        &self,
+        overrides: &BTreeMap<String, Vec<SocketAddr>>,
+    ) -> Option<Arc<dyn HttpTransport>> {
+        let builder = self.builder.clone()?;
+        let overrides = overrides.clone();
+        let agent = builder()
+            .resolver(move |netloc: &str| resolve_with_overrides(&overrides, netloc))
+            .build();
+        Some(Arc::new(Self {
+            agent,
+            builder: Some(builder),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FPConfig, FPUser, FeatureProbe};
+
+    #[derive(Debug)]
+    struct Fixture;
+
+    #[async_trait]
+    impl HttpTransport for Fixture {
+        async fn get(
+            &self,
+            url: Url,
+            headers: HeaderMap,
+            _timeout: Duration,
+        ) -> Result<HttpResponse, FPError> {
+            assert!(url.path().ends_with("/api/server-sdk/toggles"));
+            assert_eq!(headers.get(http::header::AUTHORIZATION).unwrap(), "sdk-key");
+            let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+            path.push("resources/fixtures/repo.json");
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            Ok(HttpResponse {
+                status: 200,
+                headers,
+                body: std::fs::read(path).unwrap(),
+            })
+        }
+
+        async fn post_json(
+            &self,
+            _url: Url,
+            _headers: HeaderMap,
+            _body: Vec<u8>,
+            _timeout: Duration,
+        ) -> Result<HttpResponse, FPError> {
+            Ok(HttpResponse {
+                status: 200,
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_custom_transport() {
+        let config = FPConfig {
+            server_sdk_key: "sdk-key".into(),
+            http_transport: Some(Arc::new(Fixture)),
+            start_wait: Some(Duration::from_secs(5)),
+            track_events: false,
+            ..Default::default()
+        };
+        let fp = FeatureProbe::new(config);
+        assert!(fp.initialized());
+        let user = FPUser::new().with("city", "1");
+        assert!(fp.bool_value("bool_toggle", &user, false));
+    }
+
+    #[test]
+    fn test_custom_transport_rejects_dns_overrides() {
+        let mut config = FPConfig {
+            server_sdk_key: "sdk-key".into(),
+            http_transport: Some(Arc::new(Fixture)),
+            ..Default::default()
+        };
+        assert!(config.build().is_ok());
+        config
+            .dns_overrides
+            .insert("example.com".into(), vec!["127.0.0.1:0".parse().unwrap()]);
+        assert!(matches!(config.build(), Err(FPError::InternalError(_))));
+    }
+
+    #[cfg(feature = "ureq")]
+    #[test]
+    fn test_ureq_dns_overrides() {
+        let overrides = BTreeMap::from([(
+            "example.com".to_owned(),
+            vec!["127.0.0.2:1".parse().unwrap()],
+        )]);
+        let addrs = resolve_with_overrides(&overrides, "example.com:9012").unwrap();
+        assert_eq!(addrs, vec!["127.0.0.2:9012".parse().unwrap()]);
+        let addrs = resolve_with_overrides(&overrides, "127.0.0.3:80").unwrap();
+        assert_eq!(addrs, vec!["127.0.0.3:80".parse().unwrap()]);
+
+        assert!(UreqTransport::new(ureq::Agent::new())
+            .with_dns_overrides(&overrides)
+            .is_none());
+        let config = FPConfig {
+            server_sdk_key: "sdk-key".into(),
+            http_transport: Some(Arc::new(UreqTransport::default())),
+            dns_overrides: overrides,
+            ..Default::default()
+        };
+        assert!(config.build().is_ok());
+    }
+
+    #[cfg(feature = "ureq")]
+    #[tokio::test]
+    async fn test_ureq_payload_limits() {
+        use axum::{routing::get, Router};
+
+        let chunks = || (0..1024).map(|_| Ok::<_, std::io::Error>(vec![b' '; 1024]));
+        let app = Router::new().route(
+            "/",
+            get(move || async move {
+                axum::body::StreamBody::new(futures_util::stream::iter(chunks()))
+            }),
+        );
+        let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 9017));
+        tokio::spawn(axum::Server::bind(&addr).serve(app.into_make_service()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let limits = RepositoryLimits {
+            max_rules_per_toggle: 10,
+            max_conditions: 10,
+            max_objects_per_condition: 10,
+            max_segments: 10,
+            max_payload_bytes: 64,
+            max_regex_bytes: 1024,
+        };
+        let url = Url::parse("http://127.0.0.1:9017/").unwrap();
+        let timeout = Duration::from_secs(5);
+        let transport = UreqTransport::default();
+        let resp = transport
+            .get_with_limits(url, HeaderMap::new(), timeout, &limits)
+            .await;
+        match resp {
+            Err(FPError::LimitExceeded(v)) => assert_eq!(v.found, 65),
+            r => panic!("unexpected {r:?}"),
+        }
+    }
+}
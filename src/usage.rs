@@ -0,0 +1,167 @@
+use crate::transport::HttpTransport;
+use dashmap::DashMap;
+use http::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+use url::Url;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct VariationUsage {
+    // none when the default passed by the caller was served
+    pub variation_index: Option<usize>,
+    pub count: u64,
+    pub last_served: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ToggleUsage {
+    pub key: String,
+    pub count: u64,
+    pub first_served: u128,
+    pub last_served: u128,
+    pub variations: Vec<VariationUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub generated_at: u128,
+    pub toggles: Vec<ToggleUsage>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    first_served: u128,
+    last_served: u128,
+    variations: BTreeMap<Option<usize>, (u64, u128)>,
+}
+
+// served toggles since startup, kept apart from the event pipeline
+#[derive(Debug, Default)]
+pub(crate) struct UsageTracker {
+    toggles: DashMap<String, Entry>,
+}
+
+impl UsageTracker {
+    pub fn record(&self, toggle: &str, variation_index: Option<usize>, now: u128) {
+        let mut entry = match self.toggles.get_mut(toggle) {
+            Some(entry) => entry,
+            None => self.toggles.entry(toggle.to_owned()).or_insert(Entry {
+                first_served: now,
+                ..Default::default()
+            }),
+        };
+        entry.last_served = now;
+        let variation = entry.variations.entry(variation_index).or_default();
+        variation.0 += 1;
+        variation.1 = now;
+    }
+
+    pub fn report(&self, now: u128) -> UsageReport {
+        let mut toggles: Vec<ToggleUsage> = self
+            .toggles
+            .iter()
+            .map(|e| ToggleUsage {
+                key: e.key().clone(),
+                count: e.variations.values().map(|v| v.0).sum(),
+                first_served: e.first_served,
+                last_served: e.last_served,
+                variations: e
+                    .variations
+                    .iter()
+                    .map(|(index, (count, last_served))| VariationUsage {
+                        variation_index: *index,
+                        count: *count,
+                        last_served: *last_served,
+                    })
+                    .collect(),
+            })
+            .collect();
+        toggles.sort_by(|a, b| a.key.cmp(&b.key));
+        UsageReport {
+            generated_at: now,
+            toggles,
+        }
+    }
+
+    pub fn start_export(
+        tracker: Weak<UsageTracker>,
+        url: Url,
+        interval: Duration,
+        transport: Arc<dyn HttpTransport>,
+        headers: HeaderMap,
+        shutdown: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = ticker.tick() => {}
+                }
+                let body = match tracker.upgrade() {
+                    Some(tracker) => serde_json::to_vec(&tracker.report(crate::unix_timestamp())),
+                    None => break,
+                };
+                let body = match body {
+                    Ok(body) => body,
+                    Err(e) => {
+                        error!(error = %e, "serialize usage report failed");
+                        continue;
+                    }
+                };
+                match transport
+                    .post_json(url.clone(), headers.clone(), body, interval)
+                    .await
+                {
+                    Ok(resp) if resp.is_success() => debug!(%url, "usage report exported"),
+                    Ok(resp) => error!(%url, status = resp.status, "usage report rejected"),
+                    Err(e) => error!(%url, error = %e, "usage report export error"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_report() {
+        let tracker = UsageTracker::default();
+        tracker.record("b", Some(1), 10);
+        tracker.record("a", Some(0), 11);
+        tracker.record("b", Some(1), 12);
+        tracker.record("b", None, 13);
+
+        let report = tracker.report(20);
+        assert_eq!(report.generated_at, 20);
+        let keys: Vec<&str> = report.toggles.iter().map(|t| t.key.as_str()).collect();
+        assert_eq!(keys, vec!["a", "b"]);
+        let b = &report.toggles[1];
+        assert_eq!((b.count, b.first_served, b.last_served), (3, 10, 13));
+        assert_eq!(
+            b.variations,
+            vec![
+                VariationUsage {
+                    variation_index: None,
+                    count: 1,
+                    last_served: 13,
+                },
+                VariationUsage {
+                    variation_index: Some(1),
+                    count: 2,
+                    last_served: 12,
+                },
+            ]
+        );
+    }
+}
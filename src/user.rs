@@ -1,12 +1,169 @@
+use crate::normalization::UnicodeNormalization;
+use crate::warning::{warn_throttled, WarningKind};
+use crate::FPError;
 use parking_lot::RwLock;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+// what a client or FPUserBuilder does with attributes over the limits,
+// every policy logs a throttled warning
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LimitPolicy {
+    // keep the attribute as is
+    #[default]
+    Warn,
+    // cut long values, drop attributes past the count
+    Truncate,
+    // drop the offending attribute
+    Reject,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserLimits {
+    pub max_attrs: usize,
+    // in bytes, truncation keeps whole characters
+    pub max_value_len: usize,
+    pub policy: LimitPolicy,
+}
+
+impl UserLimits {
+    // none when the attribute is dropped
+    fn admit(&self, attrs: &HashMap<String, String>, k: &str, mut v: String) -> Option<String> {
+        if !attrs.contains_key(k) && attrs.len() >= self.max_attrs {
+            warn_throttled(WarningKind::UserAttrLimit, k);
+            if self.policy != LimitPolicy::Warn {
+                return None;
+            }
+        }
+        if v.len() > self.max_value_len {
+            warn_throttled(WarningKind::UserAttrLimit, k);
+            match self.policy {
+                LimitPolicy::Warn => {}
+                LimitPolicy::Truncate => {
+                    let mut end = self.max_value_len;
+                    while !v.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    v.truncate(end);
+                }
+                LimitPolicy::Reject => return None,
+            }
+        }
+        Some(v)
+    }
+}
+
+pub trait KeyGenerator: Debug + Send + Sync {
+    fn generate(&self) -> String;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UuidKeyGenerator;
+
+impl KeyGenerator for UuidKeyGenerator {
+    fn generate(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SequenceKeyGenerator {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl SequenceKeyGenerator {
+    pub fn new<T: Into<String>>(prefix: T) -> Self {
+        Self {
+            prefix: prefix.into(),
+            next: AtomicU64::new(0),
+        }
+    }
+}
+
+impl KeyGenerator for SequenceKeyGenerator {
+    fn generate(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::SeqCst);
+        format!("{}{}", self.prefix, n)
+    }
+}
+
+// the same key for every keyless user, so a whole client instance buckets
+// one way, e.g. for canary rollouts across instances
+#[derive(Debug, Clone)]
+pub struct InstanceKeyGenerator {
+    key: String,
+}
+
+impl InstanceKeyGenerator {
+    pub fn new<T: Into<String>>(key: T) -> Self {
+        Self { key: key.into() }
+    }
+
+    // reads the key kept at path, generating and saving one the first time
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<Self, FPError> {
+        let path = path.as_ref();
+        if let Ok(key) = fs::read_to_string(path) {
+            let key = key.trim();
+            if !key.is_empty() {
+                return Ok(Self::new(key));
+            }
+        }
+        let key = UuidKeyGenerator.generate();
+        fs::write(path, &key).map_err(|e| {
+            FPError::InternalError(format!("save instance key to {}: {e}", path.display()))
+        })?;
+        Ok(Self::new(key))
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl KeyGenerator for InstanceKeyGenerator {
+    fn generate(&self) -> String {
+        self.key.clone()
+    }
+}
+
+// keeps the anonymous key of this device across process restarts
+pub trait AnonymousKeyStore: Debug + Send + Sync {
+    fn load(&self) -> Option<String>;
+    fn save(&self, key: &str);
+}
+
+#[derive(Default, Debug, Deserialize)]
 pub struct FPUser {
     key: RwLock<Option<String>>,
     attrs: HashMap<String, String>,
+    #[serde(default)]
+    anonymous: bool,
+    // names of attributes left out when the user is serialized
+    #[serde(skip)]
+    private: BTreeSet<String>,
+}
+
+impl Serialize for FPUser {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let attrs: HashMap<&String, &String> = self
+            .attrs
+            .iter()
+            .filter(|(k, _)| !self.private.contains(*k))
+            .collect();
+        let mut s = serializer.serialize_struct("FPUser", 3)?;
+        s.serialize_field("key", &*self.key.read())?;
+        s.serialize_field("attrs", &attrs)?;
+        s.serialize_field("anonymous", &self.anonymous)?;
+        s.end()
+    }
 }
 
 impl FPUser {
@@ -18,21 +175,76 @@ impl FPUser {
         }
     }
 
+    // a fresh uuid key on every call, use anonymous_with to reuse one
+    pub fn anonymous() -> Self {
+        Self::anonymous_key(UuidKeyGenerator.generate())
+    }
+
+    // reuses the stored key, a new one is generated and saved the first time
+    pub fn anonymous_with(store: &dyn AnonymousKeyStore) -> Self {
+        let key = store.load().unwrap_or_else(|| {
+            let key = UuidKeyGenerator.generate();
+            store.save(&key);
+            key
+        });
+        Self::anonymous_key(key)
+    }
+
+    pub(crate) fn anonymous_key(key: String) -> Self {
+        FPUser {
+            key: RwLock::new(Some(key)),
+            anonymous: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn builder() -> FPUserBuilder {
+        FPUserBuilder::new()
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.anonymous
+    }
+
     pub fn stable_rollout(mut self, key: String) -> Self {
         self.key = RwLock::new(Some(key));
         self
     }
 
     pub fn with<T: Into<String>>(mut self, k: T, v: T) -> Self {
-        self.attrs.insert(k.into(), v.into());
+        self.insert(k.into(), v.into(), None);
         self
     }
 
     pub fn with_attrs(mut self, attrs: impl Iterator<Item = (String, String)>) -> Self {
-        self.attrs.extend(attrs);
+        for (k, v) in attrs {
+            self.insert(k, v, None);
+        }
         self
     }
 
+    // evaluated like any other attribute but never serialized
+    pub fn with_private<T: Into<String>>(mut self, k: T, v: T) -> Self {
+        let k = k.into();
+        self.private.insert(k.clone());
+        self.with(k, v.into())
+    }
+
+    pub fn is_private(&self, k: &str) -> bool {
+        self.private.contains(k)
+    }
+
+    fn insert(&mut self, k: String, v: String, limits: Option<&UserLimits>) {
+        let v = match limits {
+            Some(limits) => match limits.admit(&self.attrs, &k, v) {
+                Some(v) => v,
+                None => return,
+            },
+            None => v,
+        };
+        self.attrs.insert(k, v);
+    }
+
     pub fn get(&self, k: &str) -> Option<&String> {
         self.attrs.get(k)
     }
@@ -41,7 +253,65 @@ impl FPUser {
         &self.attrs
     }
 
+    // copy with the extra attributes added, later ones win; key, anonymity and
+    // private names are kept and no limits are applied
+    pub(crate) fn copy_with(&self, extra: impl IntoIterator<Item = (String, String)>) -> FPUser {
+        let mut attrs = self.attrs.clone();
+        attrs.extend(extra);
+        FPUser {
+            key: RwLock::new(self.key.read().clone()),
+            attrs,
+            anonymous: self.anonymous,
+            private: self.private.clone(),
+        }
+    }
+
+    // copy with every attribute value normalized, the key is kept as is
+    pub(crate) fn normalized(&self, form: UnicodeNormalization) -> FPUser {
+        FPUser {
+            key: RwLock::new(self.key.read().clone()),
+            attrs: self
+                .attrs
+                .iter()
+                .map(|(k, v)| (k.clone(), form.normalize(v)))
+                .collect(),
+            anonymous: self.anonymous,
+            private: self.private.clone(),
+        }
+    }
+
+    // copy within the limits, none when the user fits or the policy only warns,
+    // attributes are admitted in name order so the outcome is stable
+    pub(crate) fn limited(&self, limits: &UserLimits) -> Option<FPUser> {
+        let fits = self.attrs.len() <= limits.max_attrs
+            && self.attrs.values().all(|v| v.len() <= limits.max_value_len);
+        if fits {
+            return None;
+        }
+        let mut keys: Vec<&String> = self.attrs.keys().collect();
+        keys.sort();
+        let mut attrs = HashMap::new();
+        for k in keys {
+            if let Some(v) = limits.admit(&attrs, k, self.attrs[k].clone()) {
+                attrs.insert(k.clone(), v);
+            }
+        }
+        if limits.policy == LimitPolicy::Warn {
+            return None;
+        }
+        Some(FPUser {
+            key: RwLock::new(self.key.read().clone()),
+            attrs,
+            anonymous: self.anonymous,
+            private: self.private.clone(),
+        })
+    }
+
     pub fn key(&self) -> String {
+        self.key_with(&UuidKeyGenerator)
+    }
+
+    pub fn key_with(&self, generator: &dyn KeyGenerator) -> String {
         let key = {
             let key = self.key.read();
             (*key).clone()
@@ -50,20 +320,86 @@ impl FPUser {
             Some(key) => key,
             None => {
                 let mut guard = self.key.write();
-                let key = generate_key();
-                *guard = Some(key.clone());
-                key
+                // another thread may generate key between read and write lock
+                let key = guard.get_or_insert_with(|| generator.generate());
+                key.clone()
             }
         }
     }
 }
 
-fn generate_key() -> String {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went before epoch");
-    format!("{}", since_the_epoch.as_micros())
+// same limits the server applies to user keys
+pub const MAX_USER_KEY_LEN: usize = 256;
+const RESERVED_ATTRS: [&str; 2] = ["key", "anonymous"];
+
+#[derive(Debug, Clone, Default)]
+pub struct FPUserBuilder {
+    key: Option<String>,
+    attrs: Vec<(String, String)>,
+    limits: Option<UserLimits>,
+}
+
+impl FPUserBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key<T: Into<String>>(mut self, key: T) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    pub fn attr<T: Into<String>>(mut self, k: T, v: T) -> Self {
+        self.attrs.push((k.into(), v.into()));
+        self
+    }
+
+    // applied while building, in the order the attributes were added
+    pub fn limits(mut self, limits: UserLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn build(self) -> Result<FPUser, FPError> {
+        if let Some(key) = &self.key {
+            validate_key(key)?;
+        }
+        if let Some((k, _)) = self
+            .attrs
+            .iter()
+            .find(|(k, _)| RESERVED_ATTRS.contains(&k.as_str()))
+        {
+            return Err(FPError::InvalidUser(format!(
+                "attribute name {k} is reserved"
+            )));
+        }
+        let mut user = FPUser::new();
+        for (k, v) in self.attrs {
+            user.insert(k, v, self.limits.as_ref());
+        }
+        Ok(match self.key {
+            Some(key) => user.stable_rollout(key),
+            None => user,
+        })
+    }
+}
+
+// malformed keys still bucket, just not the way the server does
+fn validate_key(key: &str) -> Result<(), FPError> {
+    if key.is_empty() {
+        return Err(FPError::InvalidUser("empty key".to_owned()));
+    }
+    if key.len() > MAX_USER_KEY_LEN {
+        return Err(FPError::InvalidUser(format!(
+            "key longer than {MAX_USER_KEY_LEN} bytes"
+        )));
+    }
+    if key.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(FPError::InvalidUser(format!(
+            "key {key:?} contains whitespace or control characters"
+        )));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -80,6 +416,34 @@ mod tests {
         assert_eq!(u.key(), u.key());
     }
 
+    #[test]
+    fn test_user_key_with_generator() {
+        let generator = SequenceKeyGenerator::new("user-");
+        let u1 = FPUser::new();
+        let u2 = FPUser::new();
+        assert_eq!(u1.key_with(&generator), "user-0");
+        assert_eq!(u2.key_with(&generator), "user-1");
+        assert_eq!(u1.key(), "user-0");
+
+        let u3 = FPUser::new().stable_rollout("fixed".to_owned());
+        assert_eq!(u3.key_with(&generator), "fixed");
+    }
+
+    #[test]
+    fn test_instance_key_persisted() {
+        let path = std::env::temp_dir().join(format!("fp-instance-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let first = InstanceKeyGenerator::load_or_create(&path).unwrap();
+        let second = InstanceKeyGenerator::load_or_create(&path).unwrap();
+        assert_eq!(first.key(), second.key());
+        assert_eq!(FPUser::new().key_with(&first), first.key());
+        assert_eq!(
+            FPUser::new().key_with(&first),
+            FPUser::new().key_with(&second)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_user_with_attrs() {
         let mut attrs: HashMap<String, String> = Default::default();
@@ -88,4 +452,121 @@ mod tests {
         let u = FPUser::new().with_attrs(attrs.into_iter());
         assert_eq!(u.get_all().len(), 2);
     }
+
+    #[test]
+    fn test_private_attrs_not_serialized() {
+        let u = FPUser::new()
+            .stable_rollout("u1".to_owned())
+            .with("city", "paris")
+            .with_private("email", "a@b.c");
+        assert!(u.is_private("email"));
+        assert_eq!(u.get("email").unwrap(), "a@b.c");
+        let json = serde_json::to_value(&u).unwrap();
+        assert_eq!(json["key"], "u1");
+        assert_eq!(json["attrs"]["city"], "paris");
+        assert!(json["attrs"].get("email").is_none());
+    }
+
+    #[test]
+    fn test_user_builder() {
+        let u = FPUser::builder()
+            .key("user-1")
+            .attr("city", "paris")
+            .build()
+            .unwrap();
+        assert_eq!(u.key(), "user-1");
+        assert_eq!(u.get("city").unwrap(), "paris");
+        assert!(FPUser::builder().build().is_ok());
+
+        let invalid = |b: FPUserBuilder| matches!(b.build(), Err(FPError::InvalidUser(_)));
+        assert!(invalid(FPUser::builder().key("")));
+        assert!(invalid(FPUser::builder().key("a b")));
+        assert!(invalid(FPUser::builder().key("a\n")));
+        assert!(invalid(
+            FPUser::builder().key("k".repeat(MAX_USER_KEY_LEN + 1))
+        ));
+        assert!(invalid(FPUser::builder().attr("key", "x")));
+    }
+
+    #[derive(Debug, Default)]
+    struct MemoryStore(RwLock<Option<String>>);
+
+    impl AnonymousKeyStore for MemoryStore {
+        fn load(&self) -> Option<String> {
+            self.0.read().clone()
+        }
+
+        fn save(&self, key: &str) {
+            *self.0.write() = Some(key.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_copy_with() {
+        let u = FPUser::anonymous()
+            .with("city", "1")
+            .with_private("email", "a@b.c");
+        let copy = u.copy_with([("city".to_owned(), "2".to_owned())]);
+        assert_eq!(copy.key(), u.key());
+        assert!(copy.is_anonymous());
+        assert!(copy.is_private("email"));
+        assert_eq!(copy.get("city"), Some(&"2".to_owned()));
+        assert_eq!(u.get("city"), Some(&"1".to_owned()));
+    }
+
+    #[test]
+    fn test_anonymous_user() {
+        let u = FPUser::anonymous();
+        assert!(u.is_anonymous());
+        assert!(!FPUser::new().is_anonymous());
+        assert_ne!(u.key(), FPUser::anonymous().key());
+
+        let store = MemoryStore::default();
+        let first = FPUser::anonymous_with(&store);
+        assert_eq!(store.load(), Some(first.key()));
+        // a restarted process reads the same key back
+        let second = FPUser::anonymous_with(&store);
+        assert_eq!(first.key(), second.key());
+        assert!(second.is_anonymous());
+    }
+
+    #[test]
+    fn test_user_limits() {
+        let limits = |policy| UserLimits {
+            max_attrs: 2,
+            max_value_len: 4,
+            policy,
+        };
+        let build = |limits: UserLimits| {
+            let mut builder = FPUser::builder().limits(limits);
+            for (k, v) in [("a", "1"), ("b", "héllo"), ("c", "3"), ("a", "11")] {
+                builder = builder.attr(k, v);
+            }
+            builder.build().unwrap()
+        };
+
+        let u = build(limits(LimitPolicy::Warn));
+        assert_eq!(u.get_all().len(), 3);
+        assert_eq!(u.get("b").unwrap(), "héllo");
+
+        let u = build(limits(LimitPolicy::Truncate));
+        assert_eq!(u.get_all().len(), 2);
+        assert_eq!(u.get("b").unwrap(), "hél");
+        assert_eq!(u.get("a").unwrap(), "11");
+
+        let u = build(limits(LimitPolicy::Reject));
+        assert_eq!(u.get_all().len(), 2);
+        assert_eq!(u.get("b"), None);
+        assert_eq!(u.get("c").unwrap(), "3");
+
+        let u = FPUser::new()
+            .with("b", "héllo")
+            .with("a", "1")
+            .with("c", "3");
+        assert!(u.limited(&limits(LimitPolicy::Warn)).is_none());
+        let limited = u.limited(&limits(LimitPolicy::Reject)).unwrap();
+        assert_eq!(limited.get_all().len(), 2);
+        assert_eq!(limited.get("b"), None);
+        assert_eq!(u.get_all().len(), 3);
+    }
 }
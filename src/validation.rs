@@ -0,0 +1,290 @@
+use crate::evaluate::{ConditionType, Serve, Toggle};
+use crate::hashing::BUCKET_SIZE;
+use crate::Repository;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum IssueKind {
+    InvalidRange,
+    VariationOutOfRange,
+    UnreachableRule,
+    EmptySegment,
+    MissingSegment,
+    MissingPrerequisite,
+    UnboundedRegex,
+    DisabledButEvaluated,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    pub toggle: Option<String>,
+    pub segment: Option<String>,
+    pub rule_index: Option<usize>,
+    pub message: String,
+    // what to change on the platform to fix it
+    pub hint: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    pub version: Option<u128>,
+    pub checked_at: u128,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub(crate) fn log(&self) {
+        for issue in &self.issues {
+            warn!(
+                version = ?self.version,
+                kind = ?issue.kind,
+                toggle = issue.toggle.as_deref(),
+                segment = issue.segment.as_deref(),
+                hint = %issue.hint,
+                "{}",
+                issue.message
+            );
+        }
+    }
+}
+
+pub fn validate(repo: &Repository, now: u128) -> ValidationReport {
+    let mut issues = vec![];
+    let mut toggles: Vec<&Toggle> = repo.toggles.values().collect();
+    toggles.sort_by(|a, b| a.key.cmp(&b.key));
+    for toggle in toggles {
+        validate_toggle(repo, toggle, &mut issues);
+    }
+
+    let mut segments: Vec<_> = repo.segments.iter().collect();
+    segments.sort_by(|a, b| a.0.cmp(b.0));
+    for (key, segment) in segments {
+        if segment.rules.iter().all(|r| r.conditions.is_empty()) {
+            issues.push(ValidationIssue {
+                kind: IssueKind::EmptySegment,
+                toggle: None,
+                segment: Some(key.clone()),
+                rule_index: None,
+                message: format!("segment {key} has no conditions and matches nobody"),
+                hint: "add rules to the segment or remove it from toggles".to_owned(),
+            });
+        }
+    }
+
+    ValidationReport {
+        version: repo.version,
+        checked_at: now,
+        issues,
+    }
+}
+
+pub(crate) type ReportSlot = Arc<RwLock<Option<ValidationReport>>>;
+
+// runs on its own thread so syncing and evaluation never wait for it
+pub(crate) fn validate_in_background(repo: Repository, slot: ReportSlot, now: u128) {
+    let spawned = std::thread::Builder::new()
+        .name("fp-validate".to_owned())
+        .spawn(move || {
+            let report = validate(&repo, now);
+            report.log();
+            *slot.write() = Some(report);
+        });
+    if let Err(e) = spawned {
+        warn!("repository validation not started: {}", e);
+    }
+}
+
+fn validate_toggle(repo: &Repository, toggle: &Toggle, issues: &mut Vec<ValidationIssue>) {
+    let issue = |kind, rule_index, message: String, hint: &str| ValidationIssue {
+        kind,
+        toggle: Some(toggle.key.clone()),
+        segment: None,
+        rule_index,
+        message,
+        hint: hint.to_owned(),
+    };
+
+    let serves = [
+        (None, "default serve", &toggle.default_serve),
+        (None, "disabled serve", &toggle.disabled_serve),
+    ];
+    let rule_serves = toggle
+        .rules
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (Some(i), "rule serve", &r.serve));
+    for (rule_index, name, serve) in serves.into_iter().chain(rule_serves) {
+        for (kind, message) in serve_problems(serve, toggle.variations.len()) {
+            let hint = match kind {
+                IssueKind::InvalidRange => "make split percentages add up to 100%",
+                _ => "pick one of the toggle's variations",
+            };
+            issues.push(issue(kind, rule_index, format!("{name}: {message}"), hint));
+        }
+    }
+
+    if let Some(catch_all) = toggle.rules.iter().position(|r| r.conditions.is_empty()) {
+        for i in catch_all + 1..toggle.rules.len() {
+            issues.push(issue(
+                IssueKind::UnreachableRule,
+                Some(i),
+                format!("rule {i} follows rule {catch_all} which has no conditions"),
+                "move the rule above the catch-all rule or delete it",
+            ));
+        }
+    }
+
+    for (i, rule) in toggle.rules.iter().enumerate() {
+        for condition in &rule.conditions {
+            if condition.r#type != ConditionType::Segment {
+                continue;
+            }
+            for segment in &condition.objects {
+                if !repo.segments.contains_key(segment) {
+                    issues.push(issue(
+                        IssueKind::MissingSegment,
+                        Some(i),
+                        format!("segment {segment} is not in the repository"),
+                        "publish the segment or remove it from the rule",
+                    ));
+                }
+            }
+        }
+    }
+
+    for prerequisite in toggle.prerequisites.iter().flatten() {
+        if !repo.toggles.contains_key(&prerequisite.key) {
+            issues.push(issue(
+                IssueKind::MissingPrerequisite,
+                None,
+                format!("prerequisite toggle {} does not exist", prerequisite.key),
+                "restore the prerequisite toggle or remove the prerequisite",
+            ));
+        }
+    }
+}
+
+fn serve_problems(serve: &Serve, variations: usize) -> Vec<(IssueKind, String)> {
+    let mut problems = vec![];
+    match serve {
+        Serve::Select(index) => {
+            if *index >= variations {
+                problems.push((
+                    IssueKind::VariationOutOfRange,
+                    format!("variation {index} of {variations}"),
+                ));
+            }
+        }
+        Serve::Split(distribution) => {
+            if distribution.distribution.len() > variations {
+                problems.push((
+                    IssueKind::VariationOutOfRange,
+                    format!(
+                        "{} split groups for {variations} variations",
+                        distribution.distribution.len()
+                    ),
+                ));
+            }
+            let mut ranges: Vec<(u32, u32)> = distribution
+                .distribution
+                .iter()
+                .flatten()
+                .map(|r| r.0)
+                .collect();
+            ranges.sort();
+            let mut covered = 0;
+            for (start, end) in ranges {
+                if start > end || end as u64 > BUCKET_SIZE {
+                    problems.push((IssueKind::InvalidRange, format!("range {start}..{end}")));
+                } else if start < covered {
+                    problems.push((
+                        IssueKind::InvalidRange,
+                        format!("range {start}..{end} overlaps"),
+                    ));
+                } else if start > covered {
+                    problems.push((IssueKind::InvalidRange, format!("gap {covered}..{start}")));
+                }
+                covered = covered.max(end);
+            }
+            if (covered as u64) < BUCKET_SIZE {
+                problems.push((
+                    IssueKind::InvalidRange,
+                    format!("buckets {covered}..{BUCKET_SIZE} are not covered"),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_fixture() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources/fixtures/repo.json");
+        let json_str = std::fs::read_to_string(path).unwrap();
+        let repo = crate::load_json(&json_str).unwrap();
+        let report = validate(&repo, 10);
+        assert_eq!(report.checked_at, 10);
+        assert_eq!(report.version, repo.version);
+    }
+
+    #[test]
+    fn test_validate_issues() {
+        let mut toggle = Toggle::new_for_test("t".to_owned(), json!(1));
+        toggle.variations = vec![json!(1), json!(2)];
+        toggle.default_serve = serde_json::from_value(json!({"split": {
+            "distribution": [[[0, 4000]], [[5000, 10000]]]
+        }}))
+        .unwrap();
+        toggle.disabled_serve = Serve::Select(2);
+        toggle.rules = serde_json::from_value(json!([
+            {"serve": {"select": 0}, "conditions": []},
+            {"serve": {"select": 1}, "conditions": [{
+                "type": "segment", "predicate": "is in", "objects": ["gone"]
+            }]}
+        ]))
+        .unwrap();
+        let mut repo = Repository::default();
+        repo.toggles.insert("t".to_owned(), toggle);
+        repo.segments.insert(
+            "empty".to_owned(),
+            serde_json::from_value(json!({"uniqueId": "empty", "version": 1, "rules": []}))
+                .unwrap(),
+        );
+
+        let report = validate(&repo, 0);
+        let kinds: Vec<(IssueKind, Option<usize>)> = report
+            .issues
+            .iter()
+            .map(|i| (i.kind, i.rule_index))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (IssueKind::InvalidRange, None),
+                (IssueKind::VariationOutOfRange, None),
+                (IssueKind::UnreachableRule, Some(1)),
+                (IssueKind::MissingSegment, Some(1)),
+                (IssueKind::EmptySegment, None),
+            ]
+        );
+        assert!(report.issues[0].message.contains("gap 4000..5000"));
+        assert!(!report.is_clean());
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum ValueType {
+    Bool,
+    Number,
+    String,
+    Json,
+}
+
+impl ValueType {
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Bool(_) => ValueType::Bool,
+            Value::Number(_) => ValueType::Number,
+            Value::String(_) => ValueType::String,
+            _ => ValueType::Json,
+        }
+    }
+
+    // json accepts any variation
+    pub fn accepts(&self, value: &Value) -> bool {
+        *self == ValueType::Json || *self == Self::of(value)
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ValueType::Bool => "bool",
+            ValueType::Number => "number",
+            ValueType::String => "string",
+            ValueType::Json => "json",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeMismatch {
+    pub toggle: String,
+    pub version: u64,
+    pub expected: ValueType,
+    pub found: ValueType,
+}
+
+impl std::fmt::Display for TypeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Toggle:[{}] version {} serves {}, expected {}",
+            self.toggle, self.version, self.found, self.expected
+        )
+    }
+}
+
+// "500ms", "2s", "1.5m", units are us, ms, s, m and h
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .ok_or_else(|| format!("duration {s:?} has no unit"))?;
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("duration {s:?} has no valid number"))?;
+    let secs = match unit {
+        "us" => number / 1_000_000.0,
+        "ms" => number / 1_000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3_600.0,
+        _ => return Err(format!("duration {s:?} has unknown unit {unit:?}")),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("duration {s:?}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_value_type() {
+        assert_eq!(ValueType::of(&json!(true)), ValueType::Bool);
+        assert_eq!(ValueType::of(&json!(1.5)), ValueType::Number);
+        assert_eq!(ValueType::of(&json!("s")), ValueType::String);
+        assert_eq!(ValueType::of(&json!({"a": 1})), ValueType::Json);
+        assert!(ValueType::Json.accepts(&json!("s")));
+        assert!(!ValueType::Bool.accepts(&json!("s")));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration(" 1.5m "), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("2d").is_err());
+    }
+}
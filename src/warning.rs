@@ -0,0 +1,190 @@
+use crate::clock::{Clock, SystemClock};
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+lazy_static! {
+    // warnings raised outside any client, e.g. while loading a repository
+    static ref THROTTLE: Arc<WarningThrottle> = Arc::default();
+}
+
+thread_local! {
+    // the throttle of the client evaluating on this thread
+    static CURRENT: RefCell<Option<Arc<WarningThrottle>>> = const { RefCell::new(None) };
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum WarningKind {
+    UserAttrMissing,
+    UnknownPredicate,
+    SegmentNotFound,
+    LegacyFormat,
+    EvalSoftLimit,
+    EvalTimeout,
+    UserAttrLimit,
+}
+
+impl std::fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            WarningKind::UserAttrMissing => "user attr missing",
+            WarningKind::UnknownPredicate => "unknown predicate",
+            WarningKind::SegmentNotFound => "segment not found",
+            WarningKind::LegacyFormat => "legacy snake_case payload",
+            WarningKind::EvalSoftLimit => "evaluations per second over soft limit",
+            WarningKind::EvalTimeout => "evaluation budget exceeded",
+            WarningKind::UserAttrLimit => "user attribute over limit",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WarningCount {
+    pub kind: WarningKind,
+    pub subject: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    count: u64,
+    suppressed: u64,
+    last_logged: Option<u128>,
+}
+
+// one per client, so intervals and counts never mix between clients
+#[derive(Debug)]
+pub(crate) struct WarningThrottle {
+    entries: DashMap<(WarningKind, String), Entry>,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for WarningThrottle {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(60), Arc::new(SystemClock))
+    }
+}
+
+impl WarningThrottle {
+    pub fn new(interval: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            entries: DashMap::new(),
+            interval,
+            clock,
+        }
+    }
+
+    fn warn(&self, kind: WarningKind, subject: &str) -> bool {
+        let now = self.clock.now();
+        let mut entry = self.entries.entry((kind, subject.to_owned())).or_default();
+        entry.count += 1;
+        let should_log = match entry.last_logged {
+            Some(t) => now.saturating_sub(t) >= self.interval.as_millis(),
+            None => true,
+        };
+        if !should_log {
+            entry.suppressed += 1;
+            return false;
+        }
+
+        match kind {
+            WarningKind::SegmentNotFound
+            | WarningKind::LegacyFormat
+            | WarningKind::EvalSoftLimit
+            | WarningKind::EvalTimeout
+            | WarningKind::UserAttrLimit => {
+                warn!("{} {} ({} suppressed)", kind, subject, entry.suppressed)
+            }
+            _ => info!("{}: {} ({} suppressed)", kind, subject, entry.suppressed),
+        }
+        entry.suppressed = 0;
+        entry.last_logged = Some(now);
+        true
+    }
+
+    pub fn counts(&self) -> Vec<WarningCount> {
+        let mut counts: Vec<WarningCount> = self
+            .entries
+            .iter()
+            .map(|e| WarningCount {
+                kind: e.key().0,
+                subject: e.key().1.clone(),
+                count: e.value().count,
+            })
+            .collect();
+        counts.sort_by(|a, b| (a.kind, &a.subject).cmp(&(b.kind, &b.subject)));
+        counts
+    }
+}
+
+pub(crate) fn warn_throttled(kind: WarningKind, subject: &str) {
+    let current = CURRENT.with(|c| c.borrow().clone());
+    current.as_ref().unwrap_or(&THROTTLE).warn(kind, subject);
+}
+
+// warnings raised by f on this thread go to throttle
+pub(crate) fn scoped<R>(throttle: &Arc<WarningThrottle>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Arc<WarningThrottle>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|c| *c.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|c| c.borrow_mut().replace(throttle.clone())));
+    f()
+}
+
+// counts of warnings raised outside any client, see FeatureProbe::warning_counts
+pub fn warning_counts() -> Vec<WarningCount> {
+    THROTTLE.counts()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warning_throttle() {
+        let clock = crate::MockClock::new(0);
+        let throttle = WarningThrottle::new(Duration::from_secs(60), Arc::new(clock.clone()));
+        assert!(throttle.warn(WarningKind::UserAttrMissing, "city"));
+        assert!(!throttle.warn(WarningKind::UserAttrMissing, "city"));
+        assert!(throttle.warn(WarningKind::SegmentNotFound, "s1"));
+
+        clock.advance(Duration::from_secs(60));
+        assert!(throttle.warn(WarningKind::UserAttrMissing, "city"));
+
+        let counts = throttle.counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0].kind, WarningKind::UserAttrMissing);
+        assert_eq!(counts[0].count, 3);
+        assert_eq!(counts[1].subject, "s1");
+        assert_eq!(counts[1].count, 1);
+    }
+
+    #[test]
+    fn test_scoped_throttle() {
+        let throttle = Arc::new(WarningThrottle::default());
+        scoped(&throttle, || {
+            warn_throttled(WarningKind::UnknownPredicate, "scoped-only");
+        });
+        warn_throttled(WarningKind::UnknownPredicate, "unscoped-only");
+        let subjects: Vec<String> = throttle.counts().into_iter().map(|c| c.subject).collect();
+        assert_eq!(subjects, vec!["scoped-only"]);
+        assert!(warning_counts()
+            .iter()
+            .any(|c| c.subject == "unscoped-only"));
+        assert!(!warning_counts().iter().any(|c| c.subject == "scoped-only"));
+    }
+}
@@ -26,7 +26,7 @@ async fn integration_test() {
 
     let config = FPConfig {
         remote_url: Url::parse(&format!("http://127.0.0.1:{}", server_port)).unwrap(),
-        server_sdk_key: "server-sdk-key1".to_owned(),
+        server_sdk_key: "server-sdk-key1".into(),
         refresh_interval: Duration::from_secs(2),
         start_wait: Some(Duration::from_secs(5)),
         #[cfg(feature = "realtime")]